@@ -38,6 +38,8 @@ Source111: metricdog.service
 Source112: metricdog.timer
 Source113: send-boot-success.service
 Source114: bootstrap-containers@.service
+Source115: update-scheduler.service
+Source116: update-scheduler.timer
 
 # 2xx sources: tmpfilesd configs
 Source200: migration-tmpfiles.conf
@@ -73,6 +75,7 @@ Requires: %{_cross_os}sundog
 Requires: %{_cross_os}thar-be-settings
 Requires: %{_cross_os}thar-be-updates
 Requires: %{_cross_os}updog
+Requires: %{_cross_os}update-scheduler
 
 %if %{_is_k8s_variant}
 %if %{_is_aws_variant}
@@ -154,7 +157,7 @@ Requires: %{_cross_os}host-ctr
 %{summary}.
 
 %package -n %{_cross_os}storewolf
-Summary: Data store creator
+Summary: Data store creator and checker
 %description -n %{_cross_os}storewolf
 %{summary}.
 
@@ -187,6 +190,12 @@ Summary: Bottlerocket updater CLI
 %description -n %{_cross_os}updog
 not much what's up with you
 
+%package -n %{_cross_os}update-scheduler
+Summary: Updates the host during its maintenance windows
+Requires: %{_cross_os}updog
+%description -n %{_cross_os}update-scheduler
+%{summary}.
+
 %package -n %{_cross_os}metricdog
 Summary: Bottlerocket health metrics sender
 %description -n %{_cross_os}metricdog
@@ -294,10 +303,12 @@ echo "** Output from non-static builds:"
     -p servicedog \
     -p host-containers \
     -p storewolf \
+    -p datastore-fsck \
     -p settings-committer \
     -p migrator \
     -p signpost \
     -p updog \
+    -p update-scheduler \
     -p logdog \
     -p metricdog \
     -p ghostdog \
@@ -334,9 +345,9 @@ for p in \
   apiserver \
   early-boot-config netdog sundog schnauzer bork corndog \
   thar-be-settings thar-be-updates servicedog host-containers \
-  storewolf settings-committer \
+  storewolf datastore-fsck settings-committer \
   migrator prairiedog certdog \
-  signpost updog metricdog logdog \
+  signpost updog update-scheduler metricdog logdog \
   ghostdog bootstrap-containers \
 %if "%{_cross_variant}" == "aws-ecs-1"
   ecs-settings-applier \
@@ -401,7 +412,7 @@ install -d %{buildroot}%{_cross_unitdir}
 install -p -m 0644 \
   %{S:100} %{S:101} %{S:102} %{S:103} %{S:105} \
   %{S:106} %{S:107} %{S:110} %{S:111} %{S:112} \
-  %{S:113} %{S:114} \
+  %{S:113} %{S:114} %{S:115} %{S:116} \
   %{buildroot}%{_cross_unitdir}
 
 install -d %{buildroot}%{_cross_tmpfilesdir}
@@ -470,6 +481,7 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 
 %files -n %{_cross_os}storewolf
 %{_cross_bindir}/storewolf
+%{_cross_bindir}/datastore-fsck
 %{_cross_unitdir}/storewolf.service
 
 %files -n %{_cross_os}migration
@@ -500,6 +512,11 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 %dir %{_cross_templatedir}
 %{_cross_templatedir}/updog-toml
 
+%files -n %{_cross_os}update-scheduler
+%{_cross_bindir}/update-scheduler
+%{_cross_unitdir}/update-scheduler.service
+%{_cross_unitdir}/update-scheduler.timer
+
 %files -n %{_cross_os}metricdog
 %{_cross_bindir}/metricdog
 %dir %{_cross_templatedir}
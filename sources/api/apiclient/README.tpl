@@ -5,7 +5,7 @@ Current version: {{version}}
 ## apiclient binary
 
 The `apiclient` binary provides high-level methods to interact with the Bottlerocket API.
-There's a [set](#set-mode) subcommand for changing settings, an [update](#update-mode) subcommand for updating the host, and an [exec](#exec-mode) subcommand for running commands in host containers.
+There's a [get](#get-mode) subcommand for reading settings, a [set](#set-mode) subcommand for changing settings, a [rollback](#rollback-mode) subcommand for undoing changes, a [watch](#watch-mode) subcommand for following changes, an [update](#update-mode) subcommand for updating the host, and an [exec](#exec-mode) subcommand for running commands in host containers.
 There's also a low-level [raw](#raw-mode) subcommand for direct interaction with the HTTP API.
 
 It talks to the Bottlerocket socket by default.
@@ -28,6 +28,108 @@ Or, request all settings whose names start with a given `prefix`.
 apiclient -u /settings?prefix=host-containers.admin
 ```
 
+### Get mode
+
+This mode is a friendlier way to read settings, without needing to know the API's query parameters or reach for a JSON tool.
+
+Give it the settings you want to see; each can be a single setting or a whole section of settings.
+As with `set`, the "settings." prefix is optional.
+
+```
+apiclient get settings.kubernetes.node-labels settings.network
+```
+
+If you don't give any settings, all settings are shown.
+You can also request settings whose names start with a given string using `-p` or `--prefix`, which can be repeated:
+
+```
+apiclient get --prefix host-containers.ad
+```
+
+The output is JSON by default.
+You can request TOML instead, in the same format accepted by `apiclient apply` and user data, with `-f toml` or `--format toml`.
+
+If you'd rather work with flat `key=value` lines, for example in a shell script, use `--format pairs`:
+
+```
+apiclient get motd kernel.lockdown --format pairs
+```
+
+```
+settings.kernel.lockdown="integrity"
+settings.motd="hi there"
+```
+
+Values in this form are JSON, so strings keep their quotes.
+
+### History mode
+
+Every time settings are committed or rolled back, the API server records the change: the transaction name, the resulting [generation](#rollback-mode), the time, the user, group, and process ID of the client that made the commit, and the old and new value of each changed setting.
+Values of sensitive settings, like `settings.kubernetes.bootstrap-token`, aren't recorded.
+
+You can see this history, oldest first:
+
+```
+apiclient history
+```
+
+```
+2026-10-18T17:02:31.123456789Z apiclient-set-Zb3xO6RkKyvYdbNs -> generation 4 (uid 0, gid 0, pid 2314)
+    settings.motd: "hello" -> "hi there"
+```
+
+To only see the most recent commits, use `-n` or `--last` with a count.
+You can also get the history in JSON form, as returned by the API, with `--json`.
+
+### Render mode
+
+To check a configuration file template, you can render the file without changing settings or writing anything to disk:
+
+```
+apiclient render kubelet-config
+```
+
+The output is printed as the file would be written.
+If the template has a problem, like invalid syntax or a reference to a setting that isn't set, you'll see the error with its line and column in the template.
+
+By default, the live settings are used.
+To see the file as it would be after committing a transaction, use `--tx` with the transaction name, and to try out settings, give them with `-j` in the same JSON form as [set mode](#json-input):
+
+```
+apiclient render kubelet-config --tx FOO -j '{"kubernetes": {"max-pods": 20}}'
+```
+
+### Rollback mode
+
+Each commit that changes settings creates a new generation of settings, and the API server keeps the settings of the last few generations.
+If a change didn't work out, you can restore the settings of an earlier generation, and they'll be applied to the system like any other change:
+
+```
+apiclient rollback 3
+```
+
+The generation created by each commit is shown in the [history](#history-mode).
+To undo the change that created generation 4, roll back to generation 3.
+A rollback creates a new generation too, so it can be undone the same way.
+
+To see the current generation and the generations you can restore:
+
+```
+apiclient rollback --list
+```
+
+### Services mode
+
+After settings change, the services that use them are restarted.
+To see how that went, and the current state of the services' systemd units:
+
+```
+apiclient services
+```
+
+This shows, for each service, the last time settings were applied to it since boot, the changed settings that caused it, and whether each restart command and action succeeded.
+You can give the names of specific services, like `apiclient services kubernetes`, and you can get the status in JSON form, as returned by the API, with `--json`.
+
 ### Set mode
 
 This allows you to change settings on the system.
@@ -76,6 +178,63 @@ You can use JSON form to set it:
 apiclient set --json '{"motd": "42"}'
 ```
 
+#### Dry run
+
+To see what a change would do before making it, add `--dry-run`:
+
+```
+apiclient set --dry-run motd="hi there"
+```
+
+This shows each changed setting with its current and new value, the services that would be restarted, and the configuration files that would be rewritten.
+It also renders those configuration files with the new settings and shows the changes as a unified diff.
+Nothing is committed, written, or restarted.
+
+`apiclient apply --dry-run` does the same for settings files.
+
+#### Avoiding conflicting changes
+
+If several clients change settings on the same host, one could overwrite a change another just made without knowing it.
+To avoid this, note the current [generation](#rollback-mode) of settings before you read them:
+
+```
+apiclient rollback --list
+```
+
+Then give that generation when you make your change:
+
+```
+apiclient set --if-generation 12 motd="hi there"
+```
+
+If any of the settings you're changing were changed since that generation, nothing is changed and the command fails, so you can read the settings again and decide what to do.
+Changes to other settings don't matter.
+`apiclient apply --if-generation` works the same way.
+
+API clients can get the generation from the `ETag` header of `GET /settings`, and give it back in an `If-Match` header when they change settings or commit a transaction; conflicting requests fail with status 409.
+
+#### Validating settings
+
+To check settings without changing anything, add `--validate`:
+
+```
+apiclient set --validate --json '{"kubernetes": {"node-labels": {"example.com/role": "web"}}}'
+```
+
+The settings are checked against a [JSON Schema](https://json-schema.org/) generated from the API model, which includes the checks made on each setting, like the format of Kubernetes label keys.
+Each problem found is printed, and the command fails if there are any.
+This doesn't use the API, so it works anywhere apiclient runs, for example to check user data in CI before launching instances with it.
+
+By default, the schema for the variant apiclient was built for is used.
+To check against another variant, save its schema from the API, and give it with `--schema`:
+
+```
+apiclient raw -u /schema > schema.json
+apiclient set --validate --schema schema.json motd="hi there"
+```
+
+Some checks, like whether a URL can be parsed, can't be described in a schema, so settings can still be rejected by the API after passing validation.
+
 ### Update mode
 
 To start, you can check what updates are available:
@@ -93,6 +252,8 @@ apiclient update apply
 ```
 
 This downloads and writes the update to the alternate partition set, then marks it as active.
+While the update downloads, apiclient shows how much of it is done; it keeps waiting as long as the download is making progress.
+If the download is interrupted, the next attempt continues where it left off.
 The next time you reboot, for example with `apiclient reboot`, the update will take effect.
 
 If you're confident that you want to update immediately to the latest version, you can do all of the above in one step:
@@ -101,8 +262,37 @@ If you're confident that you want to update immediately to the latest version, y
 apiclient update apply --check --reboot
 ```
 
+Hosts that can't reach the configured update repository, like those in air-gapped networks, can update from a repository stored on the host instead.
+Make a tarball of the repository written by `pubsys repo`, copy it to the host, and apply the update in it:
+
+```
+apiclient update apply --from-archive /local/bottlerocket-repo.tar.gz
+```
+
+The repository can also be a directory, a block device holding one, or a `file://` URL; check it with `apiclient update check --repository LOCATION`, and `apiclient update apply` will prepare the update from the same repository.
+Paths are on the host, and must be absolute.
+The repository is checked against the same root.json as the configured repository, so it must be signed by the same keys.
+
 > Note that available updates are controlled by your settings under `settings.updates`; see [README](../../../README.md#updates-settings) for details.
 
+### Watch mode
+
+This mode prints changes to settings and to the update state as they happen, so you don't have to poll for them.
+It runs until you stop it, or until the API server closes the connection, for example because it's restarting.
+
+```
+apiclient watch settings.kubernetes
+```
+
+Each time a commit or rollback changes settings you're watching, this prints the resulting generation and the transaction or rollback that made the change, followed by the current values of the changed settings.
+(If settings change again quickly, the values shown may already include the later change.)
+Settings that no longer have a value are shown as unset.
+
+If you don't give any settings, all settings changes are shown, along with changes to the update state, like `Update state: Available -> Staged`.
+To watch the update state along with specific settings, add `--updates`.
+
+For use in scripts, `--json` prints each change as a line of JSON, as sent by the API, instead of the current values.
+
 ### Reboot mode
 
 This will reboot the system.
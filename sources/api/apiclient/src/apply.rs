@@ -2,7 +2,7 @@
 //! TOML settings files, in the same format as user data, or the JSON equivalent.  The inputs are
 //! pulled and applied to the API server in a single transaction.
 
-use crate::rando;
+use crate::{diff, rando};
 use futures::future::{join, ready, TryFutureExt};
 use futures::stream::{self, StreamExt};
 use reqwest::Url;
@@ -13,7 +13,56 @@ use tokio::io::AsyncReadExt;
 
 /// Reads settings in TOML or JSON format from files at the requested URIs (or from stdin, if given
 /// "-"), then commits them in a single transaction and applies them to the system.
-pub async fn apply<P>(socket_path: P, input_sources: Vec<String>) -> Result<()>
+///
+/// If `generation` is given, the change fails if any of the given settings changed since that
+/// generation of settings, for example because another client changed them after you read them.
+pub async fn apply<P>(
+    socket_path: P,
+    input_sources: Vec<String>,
+    generation: Option<u64>,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, input_sources, generation).await?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let headers = crate::if_generation_headers(generation);
+    let (_status, _body) =
+        crate::raw_request_with_headers(&socket_path, &uri, method, None, &headers)
+            .await
+            .context(error::CommitApply { uri })?;
+
+    Ok(())
+}
+
+/// Reads settings from the requested URIs into a new transaction, like `apply`, but instead of
+/// committing the transaction, returns a description of what committing it would change, and
+/// deletes it.  The description is the raw response body, which can be given to `diff::parse`.
+pub async fn apply_dry_run<P>(
+    socket_path: P,
+    input_sources: Vec<String>,
+    generation: Option<u64>,
+) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, input_sources, generation).await?;
+    diff::diff_and_discard(&socket_path, &transaction)
+        .await
+        .context(error::Diff)
+}
+
+/// Reads settings from the requested URIs and sends them to the server in a new transaction,
+/// returning the name of the transaction.  If `generation` is given, the server checks that the
+/// settings are unchanged since that generation.
+async fn stage<P>(
+    socket_path: P,
+    input_sources: Vec<String>,
+    generation: Option<u64>,
+) -> Result<String>
 where
     P: AsRef<Path>,
 {
@@ -44,26 +93,21 @@ where
 
     // Send the settings changes to the server in the same transaction.  (They're quick local
     // requests, so don't add the complexity of making them run concurrently.)
+    let headers = crate::if_generation_headers(generation);
     for (input_source, json) in changes {
         let uri = format!("/settings?tx={}", transaction);
         let method = "PATCH";
-        let (_status, _body) = crate::raw_request(&socket_path, &uri, method, Some(json))
-            .await
-            .context(error::Patch {
-                input_source,
-                uri,
-                method,
-            })?;
+        let (_status, _body) =
+            crate::raw_request_with_headers(&socket_path, &uri, method, Some(json), &headers)
+                .await
+                .context(error::Patch {
+                    input_source,
+                    uri,
+                    method,
+                })?;
     }
 
-    // Commit the transaction and apply it to the system.
-    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
-    let method = "POST";
-    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
-        .await
-        .context(error::CommitApply { uri })?;
-
-    Ok(())
+    Ok(transaction)
 }
 
 /// Retrieves the given source location and returns the result in a String.
@@ -156,6 +200,9 @@ mod error {
         #[snafu(display("Failed to commit combined settings to '{}': {}", uri, source))]
         CommitApply { uri: String, source: crate::Error },
 
+        #[snafu(display("Failed to describe pending changes: {}", source))]
+        Diff { source: crate::diff::Error },
+
         #[snafu(display("Failed to read given file '{}': {}", input_source, source))]
         FileRead {
             input_source: String,
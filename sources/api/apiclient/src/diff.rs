@@ -0,0 +1,140 @@
+//! This module retrieves a description of what committing a pending transaction would change, and
+//! formats it for display.  It's used to preview settings changes before making them.
+
+use serde::Deserialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::fmt::Write;
+use std::path::Path;
+
+/// A description of what committing a transaction would change, as returned by the API.
+#[derive(Debug, Deserialize)]
+pub struct TransactionDiff {
+    pub changes: Vec<PendingChange>,
+    pub services: Vec<String>,
+    pub configuration_files: Vec<String>,
+    pub rendered_files: Option<Vec<ConfigFileDiff>>,
+}
+
+/// The live and pending value of a single key in a transaction.
+#[derive(Debug, Deserialize)]
+pub struct PendingChange {
+    pub key: String,
+    pub live_value: Option<Value>,
+    pub pending_value: Value,
+}
+
+/// The change that re-rendering a configuration file would make, as a unified diff.
+#[derive(Debug, Deserialize)]
+pub struct ConfigFileDiff {
+    pub path: String,
+    pub diff: String,
+}
+
+/// Retrieves a description of what committing the given transaction would change.  Returns the
+/// raw response body, which can be given to `parse` or shown directly.
+pub async fn diff<P, S>(socket_path: P, transaction: S) -> Result<String>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let uri = format!("/tx/diff?tx={}", transaction.as_ref());
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+    Ok(body)
+}
+
+/// Retrieves a description of what committing the given transaction would change, like `diff`,
+/// then deletes the transaction.  This is used to preview changes staged only for the preview.
+pub async fn diff_and_discard<P, S>(socket_path: P, transaction: S) -> Result<String>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    // Delete the transaction even if we couldn't get the diff, so we don't leave it pending.
+    let diff_result = diff(&socket_path, &transaction).await;
+
+    let uri = format!("/tx?tx={}", transaction.as_ref());
+    let method = "DELETE";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    diff_result
+}
+
+/// Parses a response from `diff` into a TransactionDiff.
+pub fn parse(response: &str) -> Result<TransactionDiff> {
+    serde_json::from_str(response).context(error::ResponseJson)
+}
+
+/// Formats a transaction diff in a human-readable form: each changed key with its live and pending
+/// value, the affected services and configuration files, and the changes to each rendered file.
+pub fn format_diff(diff: &TransactionDiff) -> String {
+    let mut output = String::new();
+    if diff.changes.is_empty() {
+        output.push_str("No pending changes\n");
+        return output;
+    }
+
+    // (Writing to a String can't fail.)
+    output.push_str("Settings:\n");
+    for change in &diff.changes {
+        let live = change
+            .live_value
+            .as_ref()
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "(unset)".to_string());
+        let _ = writeln!(
+            output,
+            "    {}: {} -> {}",
+            change.key, live, change.pending_value
+        );
+    }
+
+    let _ = writeln!(output, "Services to restart: {}", list(&diff.services));
+    let _ = writeln!(
+        output,
+        "Configuration files to render: {}",
+        list(&diff.configuration_files)
+    );
+
+    if let Some(rendered_files) = &diff.rendered_files {
+        for rendered_file in rendered_files {
+            output.push('\n');
+            output.push_str(&rendered_file.diff);
+        }
+    }
+    output
+}
+
+/// Joins the given names for display, or returns "(none)" if there are none.
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        "(none)".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Transaction diff response was not in the expected format: {}", source))]
+        ResponseJson { source: serde_json::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,322 @@
+//! This module allows retrieval of settings from the API, optionally limited to given key paths or
+//! prefixes, and formatting of the result in a few forms that are easy to read or to reuse.
+
+use datastore::serialization::to_pairs;
+use datastore::{Key, KeyType};
+use serde_json::{Map, Value};
+use snafu::ResultExt;
+use std::path::Path;
+use std::str::FromStr;
+use url::form_urlencoded;
+
+/// The output formats supported for retrieved settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A JSON object including the outer "settings" layer.
+    Json,
+    /// A TOML document including the outer "settings" table, as accepted by `apiclient apply`.
+    Toml,
+    /// Flat `key=value` lines, with datastore key names and JSON-serialized values.
+    Pairs,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        match input {
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            "pairs" => Ok(Format::Pairs),
+            _ => error::UnknownFormat { input }.fail(),
+        }
+    }
+}
+
+/// Retrieves the settings at the given key paths, or all settings if no key paths or prefixes are
+/// given.  Key paths name a setting or a subtree of settings, for example
+/// "settings.kubernetes.node-labels"; the "settings." prefix is optional.  Prefixes are matched
+/// against setting names as strings by the server, for example "host-containers.ad".
+///
+/// Returns the merged settings as a JSON object, without the outer "settings" layer.
+pub async fn get<P>(socket_path: P, key_paths: Vec<String>, prefixes: Vec<String>) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    let mut merged = Map::new();
+
+    if key_paths.is_empty() && prefixes.is_empty() {
+        return get_uri(&socket_path, "/settings".to_string()).await;
+    }
+
+    for key_path in key_paths {
+        let segments = settings_segments(&key_path)?;
+        // An empty path means the user asked for "settings" itself, i.e. everything.
+        if segments.is_empty() {
+            let response = get_uri(&socket_path, "/settings".to_string()).await?;
+            merge_json(&mut merged, response);
+            continue;
+        }
+
+        // The server matches prefixes as strings, so "motd" would also return "motdfoo"; we use
+        // it to limit the response, then pull out exactly the requested path.
+        let prefix = Key::from_segments(KeyType::Data, &segments)
+            .context(error::InvalidKey { input: &key_path })?;
+        let uri = prefix_uri(prefix.name());
+        let response = get_uri(&socket_path, uri).await?;
+        if let Some(found) = extract_path(&response, &segments) {
+            merge_json(&mut merged, found);
+        }
+    }
+
+    for prefix in prefixes {
+        let prefix = prefix.strip_prefix("settings.").unwrap_or(&prefix);
+        let uri = prefix_uri(prefix);
+        let response = get_uri(&socket_path, uri).await?;
+        merge_json(&mut merged, response);
+    }
+
+    Ok(Value::Object(merged))
+}
+
+/// Formats settings retrieved by `get` for display in the requested format.  The outer "settings"
+/// layer is added back so the output has full setting names.
+pub fn format_settings(settings: Value, format: Format) -> Result<String> {
+    match format {
+        Format::Json => {
+            let mut output = Map::new();
+            output.insert("settings".to_string(), settings);
+            serde_json::to_string_pretty(&output).context(error::FormatJson)
+        }
+        Format::Toml => {
+            let mut output = Map::new();
+            output.insert("settings".to_string(), settings);
+            // Converting to a toml::Value first lets the serializer order scalars before tables,
+            // as TOML requires.
+            let toml_value = toml::Value::try_from(&output).context(error::FormatToml)?;
+            toml::to_string(&toml_value).context(error::FormatToml)
+        }
+        Format::Pairs => {
+            // The pairs serializer needs type information that generic JSON doesn't have, for
+            // example to know the size of integers, so we go through the model.
+            let settings: model::Settings =
+                serde_json::from_value(settings).context(error::ModelDeserialize)?;
+            let pairs = to_pairs(&settings).context(error::FormatPairs)?;
+            let mut lines: Vec<String> = pairs
+                .into_iter()
+                .map(|(key, value)| format!("{}={}", key.name(), value))
+                .collect();
+            lines.sort();
+            Ok(lines.join("\n"))
+        }
+    }
+}
+
+/// Makes a GET request to the given settings URI and parses the response as JSON.
+async fn get_uri<P>(socket_path: P, uri: String) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri: &uri, method })?;
+
+    serde_json::from_str(&body).context(error::ResponseJson { uri })
+}
+
+/// Builds the URI for a settings request limited to the given prefix.  Quoted key segments, as in
+/// `kubernetes.node-labels."my.label"`, need to be encoded to be valid in a URI.
+fn prefix_uri(prefix: &str) -> String {
+    let encoded: String = form_urlencoded::byte_serialize(prefix.as_bytes()).collect();
+    format!("/settings?prefix={}", encoded)
+}
+
+/// Parses a user-given key path into its segments, removing the "settings" prefix if present.
+fn settings_segments(key_path: &str) -> Result<Vec<String>> {
+    let key = Key::new(KeyType::Data, key_path).context(error::InvalidKey { input: key_path })?;
+    let mut segments = key.segments().clone();
+    if segments.first().map(|s| s.as_str()) == Some("settings") {
+        segments.remove(0);
+    }
+    Ok(segments)
+}
+
+/// Finds the value at the given path of segments within `settings` and returns it nested at the
+/// same path in an otherwise empty object.  Returns None if the path isn't populated.
+fn extract_path(settings: &Value, segments: &[String]) -> Option<Value> {
+    let mut found = settings;
+    for segment in segments {
+        found = found.as_object()?.get(segment)?;
+    }
+
+    let mut output = found.clone();
+    for segment in segments.iter().rev() {
+        let mut map = Map::new();
+        map.insert(segment.clone(), output);
+        output = Value::Object(map);
+    }
+    Some(output)
+}
+
+/// Recursively merges the JSON object `from` into `into`.  Objects are merged key by key; other
+/// values from `from` replace those in `into`.
+fn merge_json(into: &mut Map<String, Value>, from: Value) {
+    let from = match from {
+        Value::Object(map) => map,
+        // Settings responses are always objects; anything else has nothing to merge.
+        _ => return,
+    };
+
+    for (key, from_value) in from {
+        match (into.get_mut(&key), from_value) {
+            (Some(Value::Object(into_map)), from_value @ Value::Object(_)) => {
+                merge_json(into_map, from_value)
+            }
+            (_, from_value) => {
+                into.insert(key, from_value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!(Format::from_str("json").unwrap(), Format::Json);
+        assert_eq!(Format::from_str("toml").unwrap(), Format::Toml);
+        assert_eq!(Format::from_str("pairs").unwrap(), Format::Pairs);
+        assert!(Format::from_str("JSON").is_err());
+        assert!(Format::from_str("").is_err());
+    }
+
+    #[test]
+    fn segments_without_settings() {
+        assert_eq!(
+            settings_segments("settings.motd").unwrap(),
+            vec!["motd".to_string()]
+        );
+        assert_eq!(
+            settings_segments("kubernetes.node-labels").unwrap(),
+            vec!["kubernetes".to_string(), "node-labels".to_string()]
+        );
+        assert!(settings_segments("settings").unwrap().is_empty());
+    }
+
+    #[test]
+    fn segments_quoted() {
+        assert_eq!(
+            settings_segments("settings.kubernetes.node-labels.\"my.label\"").unwrap(),
+            vec![
+                "kubernetes".to_string(),
+                "node-labels".to_string(),
+                "my.label".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_invalid() {
+        assert!(settings_segments("settings..motd").is_err());
+    }
+
+    #[test]
+    fn extract_nested() {
+        let settings = json!({
+            "motd": "hi",
+            "kubernetes": {"node-labels": {"a": "1"}, "cluster-name": "c"}
+        });
+        let segments = vec!["kubernetes".to_string(), "node-labels".to_string()];
+        assert_eq!(
+            extract_path(&settings, &segments),
+            Some(json!({"kubernetes": {"node-labels": {"a": "1"}}}))
+        );
+    }
+
+    #[test]
+    fn extract_missing() {
+        let settings = json!({"motd": "hi"});
+        assert_eq!(extract_path(&settings, &["motdfoo".to_string()]), None);
+        // A path can't continue through a value that isn't an object.
+        let segments = vec!["motd".to_string(), "more".to_string()];
+        assert_eq!(extract_path(&settings, &segments), None);
+    }
+
+    #[test]
+    fn merge_nested() {
+        let mut into = json!({"kubernetes": {"node-labels": {"a": "1"}}, "motd": "hi"})
+            .as_object()
+            .unwrap()
+            .clone();
+        merge_json(
+            &mut into,
+            json!({"kubernetes": {"node-labels": {"b": "2"}}, "motd": "bye"}),
+        );
+        assert_eq!(
+            Value::Object(into),
+            json!({"kubernetes": {"node-labels": {"a": "1", "b": "2"}}, "motd": "bye"})
+        );
+    }
+
+    #[test]
+    fn merge_replaces_non_objects() {
+        let mut into = json!({"a": {"b": 1}}).as_object().unwrap().clone();
+        merge_json(&mut into, json!({"a": 2}));
+        assert_eq!(Value::Object(into), json!({"a": 2}));
+
+        // Non-object input has nothing to merge.
+        let mut into = json!({"a": 1}).as_object().unwrap().clone();
+        merge_json(&mut into, json!("string"));
+        assert_eq!(Value::Object(into), json!({"a": 1}));
+    }
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Unable to format settings as JSON: {}", source))]
+        FormatJson { source: serde_json::Error },
+
+        #[snafu(display("Unable to format settings as key=value pairs: {}", source))]
+        FormatPairs {
+            source: datastore::serialization::Error,
+        },
+
+        #[snafu(display("Unable to format settings as TOML: {}", source))]
+        FormatToml { source: toml::ser::Error },
+
+        #[snafu(display("Given key '{}' is not a valid format: {}", input, source))]
+        InvalidKey {
+            input: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Unable to deserialize settings into model: {}", source))]
+        ModelDeserialize { source: serde_json::Error },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Response from '{}' was not valid JSON: {}", uri, source))]
+        ResponseJson {
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unknown output format '{}'; expected json, toml, or pairs", input))]
+        UnknownFormat { input: String },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
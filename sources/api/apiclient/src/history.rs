@@ -0,0 +1,122 @@
+//! This module retrieves the history of committed settings changes from the API and formats it
+//! for display.
+
+use serde::Deserialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::fmt::Write;
+use std::path::Path;
+
+/// A record of a single committed transaction or rollback, as returned by the API.
+#[derive(Debug, Deserialize)]
+pub struct HistoryEntry {
+    pub transaction: Option<String>,
+    pub rollback_to: Option<u64>,
+    pub generation: Option<u64>,
+    pub timestamp: String,
+    pub client: Option<Client>,
+    pub changes: Vec<SettingChange>,
+}
+
+/// The credentials of the API client that committed a transaction.
+#[derive(Debug, Deserialize)]
+pub struct Client {
+    pub pid: Option<i32>,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// A record of the change to a single key in a committed transaction.
+#[derive(Debug, Deserialize)]
+pub struct SettingChange {
+    pub key: String,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    #[serde(default)]
+    pub redacted: bool,
+}
+
+/// Retrieves the history of committed settings changes, oldest first.  Returns the raw response
+/// body, which can be given to `parse` or shown directly.
+pub async fn history<P>(socket_path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings/history";
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+    Ok(body)
+}
+
+/// Parses a response from `history` into history entries.
+pub fn parse(response: &str) -> Result<Vec<HistoryEntry>> {
+    serde_json::from_str(response).context(error::ResponseJson)
+}
+
+/// Formats history entries in a human-readable form, one line per commit followed by an indented
+/// line per changed key.
+pub fn format_entries(entries: &[HistoryEntry]) -> String {
+    let mut output = String::new();
+    for entry in entries {
+        // (Writing to a String can't fail.)
+        let _ = write!(output, "{}", entry.timestamp);
+        if let Some(transaction) = &entry.transaction {
+            let _ = write!(output, " {}", transaction);
+        }
+        if let Some(rollback_to) = entry.rollback_to {
+            let _ = write!(output, " rollback to generation {}", rollback_to);
+        }
+        if let Some(generation) = entry.generation {
+            let _ = write!(output, " -> generation {}", generation);
+        }
+        if let Some(client) = &entry.client {
+            let _ = write!(output, " (uid {}, gid {}", client.uid, client.gid);
+            if let Some(pid) = client.pid {
+                let _ = write!(output, ", pid {}", pid);
+            }
+            output.push(')');
+        }
+        output.push('\n');
+
+        for change in &entry.changes {
+            if change.redacted {
+                let _ = writeln!(output, "    {}: (redacted)", change.key);
+                continue;
+            }
+            let old = change
+                .old_value
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "(unset)".to_string());
+            let new = change
+                .new_value
+                .as_ref()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "(unset)".to_string());
+            let _ = writeln!(output, "    {}: {} -> {}", change.key, old, new);
+        }
+    }
+    output
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Settings history response was not in the expected format: {}", source))]
+        ResponseJson { source: serde_json::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -1,8 +1,9 @@
 #![deny(rust_2018_idioms)]
 
 //! The apiclient library provides high-level methods to interact with the Bottlerocket API.  See
-//! the documentation for submodules [`apply`], [`exec`], [`reboot`], [`set`], and [`update`] for
-//! high-level helpers.
+//! the documentation for submodules [`apply`], [`diff`], [`exec`], [`get`], [`history`],
+//! [`reboot`], [`render`], [`rollback`], [`services`], [`set`], [`update`], [`validate`], and
+//! [`watch`] for high-level helpers.
 //!
 //! For more control, and to handle APIs without high-level wrappers, there are also 'raw' methods
 //! to query an HTTP API over a Unix-domain socket.
@@ -22,10 +23,18 @@ use snafu::{ensure, ResultExt};
 use std::path::Path;
 
 pub mod apply;
+pub mod diff;
 pub mod exec;
+pub mod get;
+pub mod history;
 pub mod reboot;
+pub mod render;
+pub mod rollback;
+pub mod services;
 pub mod set;
 pub mod update;
+pub mod validate;
+pub mod watch;
 
 mod error {
     use snafu::Snafu;
@@ -99,6 +108,38 @@ where
     Ok((status, body))
 }
 
+/// Works exactly like raw_request in making an HTTP request over a Unix-domain socket, but also
+/// sends the given headers, as (name, value) pairs.  For example, you can send the generation of
+/// settings you read in an If-Match header to make sure you don't overwrite changes you haven't
+/// seen.
+pub async fn raw_request_with_headers<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+    headers: &[(&str, String)],
+) -> Result<(http::StatusCode, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let (status, body) = request(&socket_path, &uri, &method, data, headers).await?;
+
+    // Error if the response status is in not in the 2xx range.
+    ensure!(
+        status.is_success(),
+        error::ResponseStatus {
+            method: method.as_ref(),
+            code: status,
+            uri: uri.as_ref(),
+            body,
+        }
+    );
+
+    Ok((status, body))
+}
+
 /// Works exactly like raw_request in making an HTTP request over a Unix-domain socket, but doesn't
 /// check that the returned status code represents success.  This can be useful if you have to
 /// handle specific error codes, rather than inspecting the Error type of raw_request.
@@ -108,6 +149,23 @@ pub async fn raw_request_unchecked<P, S1, S2>(
     method: S2,
     data: Option<String>,
 ) -> Result<(http::StatusCode, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    request(socket_path, uri, method, data, &[]).await
+}
+
+/// Makes an HTTP request over a Unix-domain socket with the given headers, without checking the
+/// returned status code.
+async fn request<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+    headers: &[(&str, String)],
+) -> Result<(http::StatusCode, String)>
 where
     P: AsRef<Path>,
     S1: AsRef<str>,
@@ -125,10 +183,14 @@ where
     } else {
         Body::empty()
     };
-    let request = Request::builder()
+    let mut request = Request::builder()
         .method(method)
         .uri(&uri)
-        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_TYPE, "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, value);
+    }
+    let request = request
         .body(Body::from(request_data))
         .context(error::RequestSetup)?;
 
@@ -145,6 +207,15 @@ where
     Ok((status, body))
 }
 
+/// Returns the headers for a request that should only succeed if the settings it changes are
+/// unchanged since the given generation, if any.
+pub(crate) fn if_generation_headers(generation: Option<u64>) -> Vec<(&'static str, String)> {
+    match generation {
+        Some(generation) => vec![("If-Match", format!("\"{}\"", generation))],
+        None => Vec::new(),
+    }
+}
+
 /// Generates a random ID, affectionately known as a 'rando'.
 pub(crate) fn rando() -> String {
     thread_rng()
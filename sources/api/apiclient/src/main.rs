@@ -6,17 +6,21 @@
 // library calls based on the given flags, etc.)  The library modules contain the code for talking
 // to the API, which is intended to be reusable by other crates.
 
-use apiclient::{apply, exec, reboot, set, update};
+use apiclient::{
+    apply, diff, exec, get, history, reboot, render, rollback, services, set, update, validate,
+    watch,
+};
 use constants;
 use datastore::{serialize_scalar, Key, KeyType};
 use log::{info, log_enabled, trace, warn};
 use simplelog::{
     ColorChoice, ConfigBuilder as LogConfigBuilder, LevelFilter, TermLogger, TerminalMode,
 };
-use snafu::ResultExt;
+use snafu::{ensure, ResultExt};
 use std::collections::HashMap;
 use std::env;
 use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 use unindent::unindent;
@@ -44,16 +48,24 @@ impl Default for Args {
 enum Subcommand {
     Apply(ApplyArgs),
     Exec(ExecArgs),
+    Get(GetArgs),
+    History(HistoryArgs),
     Raw(RawArgs),
     Reboot(RebootArgs),
+    Render(RenderArgs),
+    Rollback(RollbackArgs),
+    Services(ServicesArgs),
     Set(SetArgs),
     Update(UpdateSubcommand),
+    Watch(WatchArgs),
 }
 
 /// Stores user-supplied arguments for the 'apply' subcommand.
 #[derive(Debug)]
 struct ApplyArgs {
     input_sources: Vec<String>,
+    dry_run: bool,
+    if_generation: Option<u64>,
 }
 
 /// Stores user-supplied arguments for the 'exec' subcommand.
@@ -64,6 +76,21 @@ struct ExecArgs {
     tty: Option<bool>,
 }
 
+/// Stores user-supplied arguments for the 'get' subcommand.
+#[derive(Debug)]
+struct GetArgs {
+    key_paths: Vec<String>,
+    prefixes: Vec<String>,
+    format: get::Format,
+}
+
+/// Stores user-supplied arguments for the 'history' subcommand.
+#[derive(Debug)]
+struct HistoryArgs {
+    json: bool,
+    last: Option<usize>,
+}
+
 /// Stores user-supplied arguments for the 'raw' subcommand.
 #[derive(Debug)]
 struct RawArgs {
@@ -76,13 +103,53 @@ struct RawArgs {
 #[derive(Debug)]
 struct RebootArgs {}
 
+/// Stores user-supplied arguments for the 'render' subcommand.
+#[derive(Debug)]
+struct RenderArgs {
+    name: String,
+    transaction: Option<String>,
+    settings: Option<serde_json::Value>,
+}
+
+/// Stores user-supplied arguments for the 'rollback' subcommand.
+#[derive(Debug)]
+enum RollbackArgs {
+    List,
+    To(u64),
+}
+
+/// Stores user-supplied arguments for the 'services' subcommand.
+#[derive(Debug)]
+struct ServicesArgs {
+    names: Vec<String>,
+    json: bool,
+}
+
 /// Stores user-supplied arguments for the 'set' subcommand.
 #[derive(Debug)]
-enum SetArgs {
+struct SetArgs {
+    input: SetInput,
+    dry_run: bool,
+    if_generation: Option<u64>,
+    validate: bool,
+    schema: Option<PathBuf>,
+}
+
+/// Stores the settings given to the 'set' subcommand, in whichever form the user gave them.
+#[derive(Debug)]
+enum SetInput {
     Simple(HashMap<Key, String>),
     Json(serde_json::Value),
 }
 
+/// Stores user-supplied arguments for the 'watch' subcommand.
+#[derive(Debug)]
+struct WatchArgs {
+    key_paths: Vec<String>,
+    updates: bool,
+    json: bool,
+}
+
 /// Stores the 'update' subcommand specified by the user.
 #[derive(Debug)]
 enum UpdateSubcommand {
@@ -93,13 +160,16 @@ enum UpdateSubcommand {
 
 /// Stores user-supplied arguments for the 'update check' subcommand.
 #[derive(Debug)]
-struct UpdateCheckArgs {}
+struct UpdateCheckArgs {
+    repository: Option<String>,
+}
 
 /// Stores user-supplied arguments for the 'update apply' subcommand.
 #[derive(Debug)]
 struct UpdateApplyArgs {
     check: bool,
     reboot: bool,
+    archive: Option<String>,
 }
 
 /// Stores user-supplied arguments for the 'update cancel' subcommand.
@@ -122,12 +192,21 @@ fn usage() -> ! {
                                        'raw' is the default subcommand and may be omitted.
             apply                      Applies settings from TOML/JSON files at given URIs,
                                        or from stdin.
+            get                        Retrieves settings and prints them in the chosen format.
             set                        Changes settings and applies them to the system.
+            history                    Shows the history of committed settings changes.
+            rollback                   Restores settings from a previous generation.
+            render                     Renders a configuration file from its template and
+                                       prints it, without writing it.
+            services                   Shows the status of services: the outcome of the last time
+                                       settings were applied to them, and the state of their units.
             update check               Prints information about available updates.
             update apply               Applies available updates.
             update cancel              Deactivates an applied update.
             reboot                     Reboots the host.
             exec                       Execute a command in a host container.
+            watch                      Prints changes to settings and the update state as they
+                                       happen.
 
         raw options:
             -u, --uri URI              Required; URI to request from the server, e.g. /tx
@@ -138,10 +217,49 @@ fn usage() -> ! {
             [ URI ...]                 The list of URIs to TOML or JSON settings files that you
                                        want to apply to the system.  If no URI is specified, or
                                        if "-" is given, reads from stdin.
+            --dry-run                  Show what applying the settings would change, including
+                                       changes to configuration files, without changing anything.
+            --if-generation GENERATION Only apply the settings if none of them changed since
+                                       the given generation, for example because another client
+                                       changed them after you read them.
+
+        get options:
+            [ KEY ...]                 The settings you want to see, for example:
+                                          settings.kubernetes.node-labels settings.network
+                                       The "settings." prefix is optional.  If no keys or
+                                       prefixes are given, all settings are shown.
+            -p, --prefix PREFIX        Also show settings whose names start with the given string,
+                                       for example 'host-containers.ad'.  Can be repeated.
+            -f, --format FORMAT        Output format; json|toml|pairs.  Default: json
+                                       'pairs' prints one key=value line per setting.
+
+        history options:
+            -n, --last COUNT           Only show the most recent COUNT commits.
+            --json                     Print the history as JSON, as returned by the API.
 
         reboot options:
             None.
 
+        render options:
+            NAME                       Required; the name of the configuration file, for example
+                                       'kubelet-config'.  Names are listed by the API at
+                                       /configuration-files.
+            --tx TRANSACTION           Render with the pending settings of the given transaction
+                                       on top of the live settings.
+            -j, --json JSON            Render with the given settings on top of the live settings,
+                                       and the pending settings if --tx is given.  For example:
+                                          -j '{{"kubernetes": {{"max-pods": 20}}}}'
+
+        rollback options:
+            GENERATION                 The generation of settings to restore.  The generation
+                                       created by each commit is shown by 'apiclient history'.
+            -l, --list                 List the current and restorable generations instead.
+
+        services options:
+            [ NAME ...]                The services you want to see, for example 'kubernetes'.
+                                       If no names are given, all services are shown.
+            --json                     Print the status as JSON, as returned by the API.
+
         set options:
             KEY=VALUE [KEY=VALUE ...]  The settings you want to set.  For example:
                                           settings.motd="hi there" settings.ecs.cluster=example
@@ -152,17 +270,42 @@ fn usage() -> ! {
                                        which can simplify setting multiple values, and is necessary
                                        for some numeric settings.  For example:
                                           -j '{{"kernel": {{"sysctl": {{"vm.max_map_count": "262144"}}}}}}'
+            --dry-run                  Show what setting the values would change, including
+                                       changes to configuration files, without changing anything.
+            --if-generation GENERATION Only change the settings if none of them changed since
+                                       the given generation, for example because another client
+                                       changed them after you read them.
+            --validate                 Check the settings against the settings schema instead of
+                                       changing them.  This doesn't use the API, so it can be used
+                                       anywhere, for example to check user data in CI.
+            --schema FILE              The schema to use with --validate, as served by the API at
+                                       /schema.  Default: the schema apiclient was built with.
 
         update check options:
-            None.
+            --repository LOCATION      Check the repository stored on the host at this file://
+                                       URL or absolute path, to a directory, tarball, or block
+                                       device, instead of the configured repository.  A later
+                                       `update apply` uses the same repository.
 
         update apply options:
             -c, --check                Automatically `update check` and apply whatever is found.
             -r, --reboot               Automatically reboot if an update was found and applied.
+            --from-archive PATH        Check and apply the update in a tarball of the repository
+                                       written by `pubsys repo`.  PATH is an absolute path on the
+                                       host.  Implies --check.
 
         update cancel options:
             None.
 
+        watch options:
+            [ KEY ...]                 The settings you want to watch, for example:
+                                          settings.kubernetes settings.network
+                                       The "settings." prefix is optional.  If no keys are given,
+                                       all settings and the update state are watched.
+            -u, --updates              Also watch the update state when giving keys.
+            --json                     Print each change as JSON, one per line, as sent by the
+                                       API, rather than printing the new values.
+
         exec options:
             -t, --tty                  Force the server to run the program in a pseudoterminal.
             -T, --no-tty               Force the server not to run the program in a pseudoterminal.
@@ -217,7 +360,8 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
             }
 
             // Subcommands
-            "raw" | "apply" | "exec" | "reboot" | "set" | "update"
+            "raw" | "apply" | "exec" | "get" | "history" | "reboot" | "render" | "rollback"
+            | "services" | "set" | "update" | "watch"
                 if subcommand.is_none() && !arg.starts_with('-') =>
             {
                 subcommand = Some(arg)
@@ -233,9 +377,15 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
         None | Some("raw") => return (global_args, parse_raw_args(subcommand_args)),
         Some("apply") => return (global_args, parse_apply_args(subcommand_args)),
         Some("exec") => return (global_args, parse_exec_args(subcommand_args)),
+        Some("get") => return (global_args, parse_get_args(subcommand_args)),
+        Some("history") => return (global_args, parse_history_args(subcommand_args)),
         Some("reboot") => return (global_args, parse_reboot_args(subcommand_args)),
+        Some("render") => return (global_args, parse_render_args(subcommand_args)),
+        Some("rollback") => return (global_args, parse_rollback_args(subcommand_args)),
+        Some("services") => return (global_args, parse_services_args(subcommand_args)),
         Some("set") => return (global_args, parse_set_args(subcommand_args)),
         Some("update") => return (global_args, parse_update_args(subcommand_args)),
+        Some("watch") => return (global_args, parse_watch_args(subcommand_args)),
         _ => usage_msg("Missing or unknown subcommand"),
     }
 }
@@ -285,14 +435,21 @@ fn parse_raw_args(args: Vec<String>) -> Subcommand {
 /// Parses arguments for the 'apply' subcommand.
 fn parse_apply_args(args: Vec<String>) -> Subcommand {
     let mut input_sources = Vec::new();
+    let mut dry_run = false;
+    let mut if_generation = None;
 
     let mut iter = args.into_iter();
     while let Some(arg) = iter.next() {
         match arg {
+            x if x == "--dry-run" => dry_run = true,
+
+            x if x == "--if-generation" => if_generation = Some(parse_if_generation(iter.next())),
+
             // Allow "-" for stdin, but we have no other parameters.
-            x if x.starts_with("-") && x != "-" => {
-                usage_msg("apiclient apply takes no parameters, just a list of URIs.")
-            }
+            x if x.starts_with("-") && x != "-" => usage_msg(
+                "apiclient apply takes no parameters other than --dry-run and --if-generation, \
+                 just a list of URIs.",
+            ),
 
             x => input_sources.push(x),
         }
@@ -303,7 +460,11 @@ fn parse_apply_args(args: Vec<String>) -> Subcommand {
         input_sources.push("-".to_string());
     }
 
-    Subcommand::Apply(ApplyArgs { input_sources })
+    Subcommand::Apply(ApplyArgs {
+        input_sources,
+        dry_run,
+        if_generation,
+    })
 }
 
 /// Parses arguments for the 'exec' subcommand.
@@ -347,6 +508,92 @@ fn parse_exec_args(args: Vec<String>) -> Subcommand {
     })
 }
 
+/// Parses arguments for the 'get' subcommand.
+fn parse_get_args(args: Vec<String>) -> Subcommand {
+    let mut key_paths = Vec::new();
+    let mut prefixes = Vec::new();
+    let mut format = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-p" | "--prefix" => {
+                let prefix = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -p | --prefix"));
+                if prefix.is_empty() {
+                    usage_msg("Prefix given to -p | --prefix must not be empty");
+                }
+                prefixes.push(prefix);
+            }
+
+            "-f" | "--format" => {
+                let format_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -f | --format"));
+                format = Some(
+                    get::Format::from_str(&format_str).unwrap_or_else(|e| usage_msg(e.to_string())),
+                );
+            }
+
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+
+            _ => key_paths.push(arg),
+        }
+    }
+
+    Subcommand::Get(GetArgs {
+        key_paths,
+        prefixes,
+        format: format.unwrap_or(get::Format::Json),
+    })
+}
+
+/// Parses arguments for the 'history' subcommand.
+fn parse_history_args(args: Vec<String>) -> Subcommand {
+    let mut json = false;
+    let mut last = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--json" => json = true,
+
+            "-n" | "--last" => {
+                let last_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -n | --last"));
+                last = Some(last_str.parse().unwrap_or_else(|e| {
+                    usage_msg(format!(
+                        "Invalid count '{}' given to --last: {}",
+                        last_str, e
+                    ))
+                }));
+            }
+
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    Subcommand::History(HistoryArgs { json, last })
+}
+
+/// Parses arguments for the 'services' subcommand.
+fn parse_services_args(args: Vec<String>) -> Subcommand {
+    let mut names = Vec::new();
+    let mut json = false;
+
+    for arg in args {
+        match arg.as_ref() {
+            "--json" => json = true,
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+            _ => names.push(arg),
+        }
+    }
+
+    Subcommand::Services(ServicesArgs { names, json })
+}
+
 /// Parses arguments for the 'reboot' subcommand.
 fn parse_reboot_args(args: Vec<String>) -> Subcommand {
     if !args.is_empty() {
@@ -355,6 +602,89 @@ fn parse_reboot_args(args: Vec<String>) -> Subcommand {
     Subcommand::Reboot(RebootArgs {})
 }
 
+/// Parses arguments for the 'render' subcommand.
+fn parse_render_args(args: Vec<String>) -> Subcommand {
+    let mut name = None;
+    let mut transaction = None;
+    let mut settings = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--tx" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --tx")),
+                )
+            }
+
+            "-j" | "--json" => {
+                let raw_json = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -j | --json"));
+                let input_val: serde_json::Value =
+                    serde_json::from_str(&raw_json).unwrap_or_else(|e| {
+                        usage_msg(format!("Couldn't parse given JSON input: {}", e))
+                    });
+                let mut input_map = match input_val {
+                    serde_json::Value::Object(map) => map,
+                    _ => usage_msg("JSON input must be an object (map)"),
+                };
+                // Like 'set', accept settings with or without a "settings" layer around them.
+                if let Some(val) = input_map.remove("settings") {
+                    match val {
+                        serde_json::Value::Object(map) => input_map.extend(map),
+                        _ => usage_msg("JSON 'settings' value must be an object (map)"),
+                    };
+                }
+                settings = Some(input_map.into());
+            }
+
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+
+            x if name.is_none() => name = Some(x.to_string()),
+
+            _ => usage_msg("Can only render one configuration file"),
+        }
+    }
+
+    Subcommand::Render(RenderArgs {
+        name: name.unwrap_or_else(|| usage_msg("Must give the name of a configuration file")),
+        transaction,
+        settings,
+    })
+}
+
+/// Parses arguments for the 'rollback' subcommand.
+fn parse_rollback_args(args: Vec<String>) -> Subcommand {
+    let mut list = false;
+    let mut generation = None;
+
+    for arg in args {
+        match arg.as_ref() {
+            "-l" | "--list" => list = true,
+
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+
+            x if generation.is_none() => {
+                generation =
+                    Some(x.parse().unwrap_or_else(|e| {
+                        usage_msg(format!("Invalid generation '{}': {}", x, e))
+                    }))
+            }
+
+            _ => usage_msg("Can only roll back to one generation"),
+        }
+    }
+
+    match (list, generation) {
+        (true, None) => Subcommand::Rollback(RollbackArgs::List),
+        (false, Some(generation)) => Subcommand::Rollback(RollbackArgs::To(generation)),
+        (true, Some(_)) => usage_msg("Cannot specify a generation with --list"),
+        (false, None) => usage_msg("Must specify a generation, or --list, with 'rollback'"),
+    }
+}
+
 /// Parses arguments for the 'set' subcommand.
 // Note: the API doesn't allow setting non-settings keys, e.g. services, configuration-files, and
 // metadata.  If we allow it in the future, we should revisit this 'set' parsing code and decide
@@ -363,10 +693,28 @@ fn parse_reboot_args(args: Vec<String>) -> Subcommand {
 fn parse_set_args(args: Vec<String>) -> Subcommand {
     let mut simple = HashMap::new();
     let mut json = None;
+    let mut dry_run = false;
+    let mut if_generation = None;
+    let mut validate = false;
+    let mut schema = None;
 
     let mut iter = args.into_iter();
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
+            "--dry-run" => dry_run = true,
+
+            "--if-generation" => if_generation = Some(parse_if_generation(iter.next())),
+
+            "--validate" => validate = true,
+
+            "--schema" => {
+                schema = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --schema"))
+                        .into(),
+                )
+            }
+
             "-j" | "--json" if json.is_some() => {
                 usage_msg(
                     "Can't specify the --json argument multiple times.  You can set as many \
@@ -427,15 +775,66 @@ fn parse_set_args(args: Vec<String>) -> Subcommand {
         }
     }
 
-    if json.is_some() && !simple.is_empty() {
+    let input = if json.is_some() && !simple.is_empty() {
         usage_msg("Cannot specify key=value pairs and --json settings with 'set'");
     } else if let Some(json) = json {
-        Subcommand::Set(SetArgs::Json(json))
+        SetInput::Json(json)
     } else if !simple.is_empty() {
-        Subcommand::Set(SetArgs::Simple(simple))
+        SetInput::Simple(simple)
     } else {
         usage_msg("Must specify key=value settings or --json settings with 'set'");
+    };
+
+    if validate && (dry_run || if_generation.is_some()) {
+        usage_msg("--validate doesn't change settings, so it can't be used with --dry-run or --if-generation");
+    }
+    if schema.is_some() && !validate {
+        usage_msg("--schema can only be used with --validate");
+    }
+
+    Subcommand::Set(SetArgs {
+        input,
+        dry_run,
+        if_generation,
+        validate,
+        schema,
+    })
+}
+
+/// Parses the argument given to --if-generation.
+fn parse_if_generation(arg: Option<String>) -> u64 {
+    let generation_str =
+        arg.unwrap_or_else(|| usage_msg("Did not give argument to --if-generation"));
+    generation_str.parse().unwrap_or_else(|e| {
+        usage_msg(format!(
+            "Invalid generation '{}' given to --if-generation: {}",
+            generation_str, e
+        ))
+    })
+}
+
+/// Parses arguments for the 'watch' subcommand.
+fn parse_watch_args(args: Vec<String>) -> Subcommand {
+    let mut key_paths = Vec::new();
+    let mut updates = false;
+    let mut json = false;
+
+    for arg in args {
+        match arg.as_ref() {
+            "-u" | "--updates" => updates = true,
+            "--json" => json = true,
+
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+
+            _ => key_paths.push(arg),
+        }
     }
+
+    Subcommand::Watch(WatchArgs {
+        key_paths,
+        updates,
+        json,
+    })
 }
 
 /// Parses the desired subcommand of 'update'.
@@ -468,28 +867,60 @@ fn parse_update_args(args: Vec<String>) -> Subcommand {
 
 /// Parses arguments for the 'update check' subcommand.
 fn parse_update_check_args(args: Vec<String>) -> UpdateSubcommand {
-    if !args.is_empty() {
-        usage_msg(&format!("Unknown arguments: {}", args.join(", ")));
+    let mut repository = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--repository" => {
+                let location = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --repository"));
+                // The API reads the repository, so relative paths wouldn't mean what the user
+                // expects.
+                if !location.starts_with("file://") && !Path::new(&location).is_absolute() {
+                    usage_msg("--repository must be a file:// URL or an absolute path");
+                }
+                repository = Some(location);
+            }
+
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
     }
-    UpdateSubcommand::Check(UpdateCheckArgs {})
+
+    UpdateSubcommand::Check(UpdateCheckArgs { repository })
 }
 
 /// Parses arguments for the 'update apply' subcommand.
 fn parse_update_apply_args(args: Vec<String>) -> UpdateSubcommand {
     let mut check = false;
     let mut reboot = false;
+    let mut archive = None;
 
     let mut iter = args.into_iter();
     while let Some(arg) = iter.next() {
         match arg.as_ref() {
             "-c" | "--check" => check = true,
             "-r" | "--reboot" => reboot = true,
+            "--from-archive" => {
+                let path = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --from-archive"));
+                if !Path::new(&path).is_absolute() {
+                    usage_msg("--from-archive must be an absolute path");
+                }
+                archive = Some(path);
+            }
 
             x => usage_msg(&format!("Unknown argument '{}'", x)),
         }
     }
 
-    UpdateSubcommand::Apply(UpdateApplyArgs { check, reboot })
+    UpdateSubcommand::Apply(UpdateApplyArgs {
+        check,
+        reboot,
+        archive,
+    })
 }
 
 /// Parses arguments for the 'update cancel' subcommand.
@@ -505,8 +936,8 @@ fn parse_update_cancel_args(args: Vec<String>) -> UpdateSubcommand {
 
 /// Requests an update status check through the API, printing the updated status, in a pretty
 /// format if possible.
-async fn check(args: &Args) -> Result<String> {
-    let output = update::check(&args.socket_path)
+async fn check(args: &Args, repository: Option<&str>) -> Result<String> {
+    let output = update::check(&args.socket_path, repository)
         .await
         .context(error::UpdateCheck)?;
 
@@ -517,6 +948,9 @@ async fn check(args: &Args) -> Result<String> {
             println!("{}", output);
         }
     }
+    for reason in update::skipped(&output) {
+        info!("{}", reason);
+    }
 
     Ok(output)
 }
@@ -556,6 +990,127 @@ fn massage_set_input(input_map: HashMap<Key, String>) -> Result<HashMap<Key, Str
     Ok(massaged_map)
 }
 
+/// Removes all but the last `last` items from the list, if `last` is specified.
+fn keep_last<T>(items: &mut Vec<T>, last: Option<usize>) {
+    if let Some(last) = last {
+        let skip = items.len().saturating_sub(last);
+        items.drain(..skip);
+    }
+}
+
+/// Checks the settings given to 'set' against the settings schema, without using the API, and
+/// prints each problem found.
+fn validate_settings(set: SetArgs) -> Result<()> {
+    let schema = validate::schema(set.schema.as_ref()).context(error::Validate)?;
+    let settings = match set.input {
+        SetInput::Simple(input_map) => {
+            validate::settings_from_pairs(&input_map).context(error::Validate)?
+        }
+        SetInput::Json(json) => json,
+    };
+
+    let problems = validate::validate(&schema, &settings);
+    ensure!(
+        problems.is_empty(),
+        error::InvalidSettings {
+            problems: problems
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    );
+    println!("Settings are valid");
+    Ok(())
+}
+
+/// Prints a description of pending changes, as returned by a dry run, in human-readable form.
+fn print_diff(response: &str) -> Result<()> {
+    let diff = diff::parse(response).context(error::Diff)?;
+    print!("{}", diff::format_diff(&diff));
+    Ok(())
+}
+
+/// Prints changes from the server's event stream as they arrive, until the stream ends.  Settings
+/// changes are printed with the current values of the changed settings that match the requested
+/// keys.
+async fn watch_events(args: &Args, watch_args: WatchArgs) -> Result<()> {
+    let filters = watch::key_filters(&watch_args.key_paths).context(error::Watch)?;
+    let show_updates = watch_args.key_paths.is_empty() || watch_args.updates;
+
+    let mut events = watch::events(&args.socket_path)
+        .await
+        .context(error::Watch)?;
+    while let Some(event) = events.next().await.context(error::Watch)? {
+        // Only show the parts of the event the user is interested in.
+        let event = match event {
+            watch::Event::Settings(mut settings_event) => {
+                settings_event.keys = watch::matching_keys(&settings_event, &filters);
+                if settings_event.keys.is_empty() {
+                    continue;
+                }
+                watch::Event::Settings(settings_event)
+            }
+            watch::Event::UpdateState(_) if !show_updates => continue,
+            other => other,
+        };
+
+        if watch_args.json {
+            let line = serde_json::to_string(&event).context(error::FormatEvent)?;
+            println!("{}", line);
+            continue;
+        }
+
+        match event {
+            watch::Event::Settings(settings_event) => {
+                print_settings_event(args, settings_event).await?
+            }
+            watch::Event::UpdateState(update_event) => match update_event.previous {
+                Some(previous) => println!("Update state: {} -> {}", previous, update_event.state),
+                None => println!("Update state: {}", update_event.state),
+            },
+            watch::Event::Lagged { missed } => warn!(
+                "Missed {} changes because we fell behind; settings may have changed",
+                missed
+            ),
+        }
+    }
+
+    error::WatchClosed.fail()
+}
+
+/// Prints a description of a settings change, along with the current values of the changed keys.
+/// Keys that no longer have a value, for example because a rollback removed them, are shown as
+/// unset.
+async fn print_settings_event(args: &Args, event: watch::SettingsEvent) -> Result<()> {
+    match (event.transaction, event.rollback_to) {
+        (_, Some(rollback_to)) => println!(
+            "Generation {}, rollback to generation {}:",
+            event.generation, rollback_to
+        ),
+        (Some(transaction), None) => println!(
+            "Generation {}, transaction {}:",
+            event.generation, transaction
+        ),
+        (None, None) => println!("Generation {}:", event.generation),
+    }
+
+    let settings = get::get(&args.socket_path, event.keys.clone(), Vec::new())
+        .await
+        .context(error::Get)?;
+    let pairs = get::format_settings(settings, get::Format::Pairs).context(error::Get)?;
+    for line in pairs.lines() {
+        println!("    {}", line);
+    }
+    for key in &event.keys {
+        let prefix = format!("{}=", key);
+        if !pairs.lines().any(|line| line.starts_with(&prefix)) {
+            println!("    {} (unset)", key);
+        }
+    }
+    Ok(())
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 // Main dispatch
 
@@ -596,9 +1151,20 @@ async fn run() -> Result<()> {
         }
 
         Subcommand::Apply(apply) => {
-            apply::apply(&args.socket_path, apply.input_sources)
+            if apply.dry_run {
+                let response = apply::apply_dry_run(
+                    &args.socket_path,
+                    apply.input_sources,
+                    apply.if_generation,
+                )
                 .await
                 .context(error::Apply)?;
+                print_diff(&response)?;
+            } else {
+                apply::apply(&args.socket_path, apply.input_sources, apply.if_generation)
+                    .await
+                    .context(error::Apply)?;
+            }
         }
 
         Subcommand::Exec(exec) => {
@@ -607,16 +1173,99 @@ async fn run() -> Result<()> {
                 .context(error::Exec)?;
         }
 
+        Subcommand::Get(get) => {
+            let settings = get::get(&args.socket_path, get.key_paths, get.prefixes)
+                .await
+                .context(error::Get)?;
+            let output = get::format_settings(settings, get.format).context(error::Get)?;
+            println!("{}", output);
+        }
+
+        Subcommand::History(history_args) => {
+            let response = history::history(&args.socket_path)
+                .await
+                .context(error::History)?;
+
+            if history_args.json {
+                // Show the entries as the server sent them, rather than only the fields we know.
+                let mut entries: Vec<serde_json::Value> =
+                    serde_json::from_str(&response).context(error::DeserializeJson)?;
+                keep_last(&mut entries, history_args.last);
+                println!("{:#}", serde_json::Value::from(entries));
+            } else {
+                let mut entries = history::parse(&response).context(error::History)?;
+                keep_last(&mut entries, history_args.last);
+                print!("{}", history::format_entries(&entries));
+            }
+        }
+
+        Subcommand::Services(services_args) => {
+            let response = services::status(&args.socket_path, &services_args.names)
+                .await
+                .context(error::Services)?;
+
+            if services_args.json {
+                // Show the status as the server sent it, rather than only the fields we know.
+                let status: serde_json::Value =
+                    serde_json::from_str(&response).context(error::DeserializeJson)?;
+                println!("{:#}", status);
+            } else {
+                let statuses = services::parse(&response).context(error::Services)?;
+                print!("{}", services::format_status(&statuses));
+            }
+        }
+
         Subcommand::Reboot(_reboot) => {
             reboot::reboot(&args.socket_path)
                 .await
                 .context(error::Reboot)?;
         }
 
+        Subcommand::Render(render_args) => {
+            let rendered = render::render(
+                &args.socket_path,
+                &render_args.name,
+                render_args.transaction.as_deref(),
+                render_args.settings.as_ref(),
+            )
+            .await
+            .context(error::Render)?;
+            info!("Rendered '{}' for {}", rendered.name, rendered.path);
+            print!("{}", rendered.rendered);
+        }
+
+        Subcommand::Rollback(RollbackArgs::List) => {
+            let generations = rollback::generations(&args.socket_path)
+                .await
+                .context(error::Rollback)?;
+            let saved: Vec<String> = generations.saved.iter().map(|g| g.to_string()).collect();
+            println!("Current generation: {}", generations.current);
+            println!("Saved generations: {}", saved.join(" "));
+        }
+
+        Subcommand::Rollback(RollbackArgs::To(generation)) => {
+            let changed = rollback::rollback(&args.socket_path, generation)
+                .await
+                .context(error::Rollback)?;
+            if changed.is_empty() {
+                info!("Settings already match generation {}", generation);
+            } else {
+                info!(
+                    "Restored settings from generation {}, changing: {}",
+                    generation,
+                    changed.join(", ")
+                );
+            }
+        }
+
+        Subcommand::Set(set) if set.validate => {
+            validate_settings(set)?;
+        }
+
         Subcommand::Set(set) => {
             let settings: model::Settings;
-            match set {
-                SetArgs::Simple(input_map) => {
+            match set.input {
+                SetInput::Simple(input_map) => {
                     // For key=val, we need some type information to deserialize into a Settings.
                     trace!("Original key=value input: {:#?}", input_map);
                     let massaged_map = massage_set_input(input_map)?;
@@ -627,26 +1276,39 @@ async fn run() -> Result<()> {
                     settings = datastore::deserialization::from_map(&massaged_map)
                         .context(error::DeserializeMap)?;
                 }
-                SetArgs::Json(json) => {
+                SetInput::Json(json) => {
                     // No processing to do on JSON input; the format determines the types.  serde
                     // can turn a Value into the nested Settings structure itself.
                     settings = serde_json::from_value(json).context(error::DeserializeJson)?;
                 }
             };
 
-            set::set(&args.socket_path, &settings)
-                .await
-                .context(error::Set)?;
+            if set.dry_run {
+                let response = set::set_dry_run(&args.socket_path, &settings, set.if_generation)
+                    .await
+                    .context(error::Set)?;
+                print_diff(&response)?;
+            } else {
+                set::set(&args.socket_path, &settings, set.if_generation)
+                    .await
+                    .context(error::Set)?;
+            }
+        }
+
+        Subcommand::Watch(watch_args) => {
+            watch_events(&args, watch_args).await?;
         }
 
         Subcommand::Update(subcommand) => match subcommand {
-            UpdateSubcommand::Check(_check) => {
-                check(&args).await?;
+            UpdateSubcommand::Check(check_args) => {
+                check(&args, check_args.repository.as_deref()).await?;
             }
 
             UpdateSubcommand::Apply(apply) => {
-                if apply.check {
-                    let output = check(&args).await?;
+                // An archive has to be checked first, so the update is found in it and prepared
+                // from it.
+                if apply.check || apply.archive.is_some() {
+                    let output = check(&args, apply.archive.as_deref()).await?;
                     // Exit early if no update is required, either because none is available or one
                     // is already applied and ready.
                     if !update::required(&output) {
@@ -692,7 +1354,10 @@ async fn main() {
 }
 
 mod error {
-    use apiclient::{apply, exec, reboot, set, update};
+    use apiclient::{
+        apply, diff, exec, get, history, reboot, render, rollback, services, set, update, validate,
+        watch,
+    };
     use snafu::Snafu;
 
     #[derive(Debug, Snafu)]
@@ -715,6 +1380,24 @@ mod error {
         #[snafu(display("Failed to exec: {}", source))]
         Exec { source: exec::Error },
 
+        #[snafu(display("Failed to get settings: {}", source))]
+        Get { source: get::Error },
+
+        #[snafu(display("Failed to describe pending changes: {}", source))]
+        Diff { source: diff::Error },
+
+        #[snafu(display("Unable to format event as JSON: {}", source))]
+        FormatEvent { source: serde_json::Error },
+
+        #[snafu(display("Failed to get settings history: {}", source))]
+        History { source: history::Error },
+
+        #[snafu(display("Failed to get service status: {}", source))]
+        Services { source: services::Error },
+
+        #[snafu(display("Settings are not valid:\n{}", problems))]
+        InvalidSettings { problems: String },
+
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
 
@@ -728,6 +1411,12 @@ mod error {
             source: apiclient::Error,
         },
 
+        #[snafu(display("Failed to render configuration file: {}", source))]
+        Render { source: render::Error },
+
+        #[snafu(display("Failed to roll back settings: {}", source))]
+        Rollback { source: rollback::Error },
+
         #[snafu(display("Unable to serialize data: {}", source))]
         Serialize { source: serde_json::Error },
 
@@ -742,6 +1431,15 @@ mod error {
 
         #[snafu(display("Failed to check for updates: {}", source))]
         UpdateCheck { source: update::Error },
+
+        #[snafu(display("Failed to validate settings: {}", source))]
+        Validate { source: validate::Error },
+
+        #[snafu(display("Failed to watch for changes: {}", source))]
+        Watch { source: watch::Error },
+
+        #[snafu(display("Server closed the event stream; it may be restarting"))]
+        WatchClosed,
     }
 }
 type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,87 @@
+//! This module renders a configuration file from its template through the API, so you can see
+//! what it would contain without changing settings or writing anything to disk.
+
+use serde::Deserialize;
+use snafu::{ensure, ResultExt};
+use std::path::Path;
+
+/// A configuration file rendered from its template, as returned by the API.
+#[derive(Debug, Deserialize)]
+pub struct RenderedConfigurationFile {
+    pub name: String,
+    pub path: String,
+    pub rendered: String,
+}
+
+/// Renders the named configuration file with the live settings, with the pending settings of the
+/// given transaction on top, if one is given, and then the given settings, if any.  The settings
+/// are a JSON object in the same form as the settings given to `set`.
+pub async fn render<P>(
+    socket_path: P,
+    name: &str,
+    transaction: Option<&str>,
+    settings: Option<&serde_json::Value>,
+) -> Result<RenderedConfigurationFile>
+where
+    P: AsRef<Path>,
+{
+    let mut uri = format!("/configuration-files/render?name={}", name);
+    if let Some(transaction) = transaction {
+        uri = format!("{}&tx={}", uri, transaction);
+    }
+    let method = "POST";
+    let data = settings.map(|s| s.to_string());
+
+    let (status, body) = crate::raw_request_unchecked(&socket_path, &uri, method, data)
+        .await
+        .context(error::Request { uri: &uri, method })?;
+    // The server returns problems with the template itself, like a syntax error or a missing
+    // setting, separately from other failures, so we can show them as they are.
+    ensure!(
+        status != http::StatusCode::UNPROCESSABLE_ENTITY,
+        error::Template { message: body }
+    );
+    ensure!(
+        status.is_success(),
+        error::Response {
+            uri: &uri,
+            code: status,
+            body
+        }
+    );
+
+    serde_json::from_str(&body).context(error::ResponseJson { uri })
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Status {} when requesting '{}': {}", code, uri, body))]
+        Response {
+            uri: String,
+            code: http::StatusCode,
+            body: String,
+        },
+
+        #[snafu(display("Response from '{}' was not in the expected format: {}", uri, source))]
+        ResponseJson {
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("{}", message))]
+        Template { message: String },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,69 @@
+//! This module lets you see which generations of settings can be restored, and roll back live
+//! settings to one of them.
+
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::path::Path;
+
+/// The generation of the live settings, and the saved prior generations, as returned by the API.
+#[derive(Debug, Deserialize)]
+pub struct Generations {
+    pub current: u64,
+    pub saved: Vec<u64>,
+}
+
+/// Retrieves the generation of the live settings and the prior generations that can be restored.
+pub async fn generations<P>(socket_path: P) -> Result<Generations>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/tx/generations";
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    serde_json::from_str(&body).context(error::ResponseJson { uri })
+}
+
+/// Restores live settings to those of the given saved generation, and applies the changes to the
+/// system.  Returns the names of the changed settings, sorted; the list is empty if the live
+/// settings already matched.
+pub async fn rollback<P>(socket_path: P, generation: u64) -> Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let uri = format!("/tx/rollback?generation={}", generation);
+    let method = "POST";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri: &uri, method })?;
+
+    let mut changed: Vec<String> =
+        serde_json::from_str(&body).context(error::ResponseJson { uri })?;
+    changed.sort();
+    Ok(changed)
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Response from '{}' was not in the expected format: {}", uri, source))]
+        ResponseJson {
+            uri: String,
+            source: serde_json::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
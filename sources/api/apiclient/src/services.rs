@@ -0,0 +1,116 @@
+//! This module retrieves the runtime status of services from the API and formats it for display.
+
+use serde::Deserialize;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The runtime status of a service, as returned by the API.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServiceStatus {
+    pub last_applied: Option<String>,
+    pub changed_settings: Option<Vec<String>>,
+    #[serde(default)]
+    pub restart_results: Vec<ActionResult>,
+    #[serde(default)]
+    pub units: BTreeMap<String, String>,
+}
+
+/// The outcome of one restart command or action of a service.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ActionResult {
+    pub action: String,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// Retrieves the status of the given services, or all services if none are given.  Returns the
+/// raw response body, which can be given to `parse` or shown directly.
+pub async fn status<P>(socket_path: P, names: &[String]) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let mut uri = "/services/status".to_string();
+    if !names.is_empty() {
+        uri = format!("{}?names={}", uri, names.join(","));
+    }
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+    Ok(body)
+}
+
+/// Parses a response from `status` into the status of each service, by name.
+pub fn parse(response: &str) -> Result<BTreeMap<String, ServiceStatus>> {
+    serde_json::from_str(response).context(error::ResponseJson)
+}
+
+/// Formats the status of services in a human-readable form: a line per service with the state of
+/// its units, followed by indented lines describing the last time settings were applied to it.
+pub fn format_status(statuses: &BTreeMap<String, ServiceStatus>) -> String {
+    let mut output = String::new();
+    for (name, status) in statuses {
+        // (Writing to a String can't fail.)
+        let _ = write!(output, "{}:", name);
+        for (unit, state) in &status.units {
+            let _ = write!(output, " {} {}", unit, state);
+        }
+        output.push('\n');
+
+        let last_applied = match &status.last_applied {
+            Some(last_applied) => last_applied,
+            None => {
+                let _ = writeln!(output, "    not applied since boot");
+                continue;
+            }
+        };
+        match &status.changed_settings {
+            Some(changed) => {
+                let _ = writeln!(
+                    output,
+                    "    applied {} for {}",
+                    last_applied,
+                    changed.join(", ")
+                );
+            }
+            None => {
+                let _ = writeln!(output, "    applied {} for all settings", last_applied);
+            }
+        }
+        for result in &status.restart_results {
+            match &result.error {
+                None => {
+                    let _ = writeln!(output, "    ok: {}", result.action);
+                }
+                Some(error) => {
+                    let _ = writeln!(output, "    failed: {}: {}", result.action, error);
+                }
+            }
+        }
+    }
+    output
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Response was not in the expected format: {}", source))]
+        ResponseJson { source: serde_json::Error },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -1,4 +1,4 @@
-use crate::rando;
+use crate::{diff, rando};
 use snafu::ResultExt;
 use std::path::Path;
 
@@ -6,7 +6,56 @@ use std::path::Path;
 /// containing those changes.  The given Settings only has to be populated (i.e. Option::Some) with
 /// the settings you want to change.  If you're deserializing a request from a user, for example,
 /// the created Settings will only have the requested keys populated.
-pub async fn set<P>(socket_path: P, settings: &model::Settings) -> Result<()>
+///
+/// If `generation` is given, the change fails if any of the requested settings changed since that
+/// generation of settings, for example because another client changed them after you read them.
+pub async fn set<P>(
+    socket_path: P,
+    settings: &model::Settings,
+    generation: Option<u64>,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, settings, generation).await?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let headers = crate::if_generation_headers(generation);
+    let (_status, _body) =
+        crate::raw_request_with_headers(&socket_path, &uri, method, None, &headers)
+            .await
+            .context(error::Request { uri, method })?;
+
+    Ok(())
+}
+
+/// Changes the requested settings through the API in a new transaction, like `set`, but instead
+/// of committing the transaction, returns a description of what committing it would change, and
+/// deletes it.  The description is the raw response body, which can be given to `diff::parse`.
+pub async fn set_dry_run<P>(
+    socket_path: P,
+    settings: &model::Settings,
+    generation: Option<u64>,
+) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let transaction = stage(&socket_path, settings, generation).await?;
+    diff::diff_and_discard(&socket_path, &transaction)
+        .await
+        .context(error::Diff)
+}
+
+/// Sends the requested settings changes to the server in a new transaction, returning the name of
+/// the transaction.  If `generation` is given, the server checks that the settings are unchanged
+/// since that generation.
+async fn stage<P>(
+    socket_path: P,
+    settings: &model::Settings,
+    generation: Option<u64>,
+) -> Result<String>
 where
     P: AsRef<Path>,
 {
@@ -17,18 +66,13 @@ where
     let uri = format!("/settings?tx={}", transaction);
     let method = "PATCH";
     let request_body = serde_json::to_string(&settings).context(error::Serialize)?;
-    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, Some(request_body))
-        .await
-        .context(error::Request { uri, method })?;
-
-    // Commit the transaction and apply it to the system.
-    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
-    let method = "POST";
-    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
-        .await
-        .context(error::Request { uri, method })?;
+    let headers = crate::if_generation_headers(generation);
+    let (_status, _body) =
+        crate::raw_request_with_headers(&socket_path, &uri, method, Some(request_body), &headers)
+            .await
+            .context(error::Request { uri, method })?;
 
-    Ok(())
+    Ok(transaction)
 }
 
 mod error {
@@ -37,6 +81,9 @@ mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub(super)")]
     pub enum Error {
+        #[snafu(display("Failed to describe pending changes: {}", source))]
+        Diff { source: crate::diff::Error },
+
         #[snafu(display("Unable to serialize data: {}", source))]
         Serialize { source: serde_json::Error },
 
@@ -26,16 +26,29 @@ use snafu::{ensure, ResultExt};
 use std::path::Path;
 use std::time::Duration;
 use tokio::time;
+use url::form_urlencoded;
 
-/// Refresh the list of available updates and return the current status.
-pub async fn check<P>(socket_path: P) -> Result<String>
+/// Refresh the list of available updates and return the current status.  If given a repository
+/// stored on the host, as a file:// URL or path, updates are found in it instead of the configured
+/// repository, and a later apply() prepares the update from it.
+pub async fn check<P>(socket_path: P, repository: Option<&str>) -> Result<String>
 where
     P: AsRef<Path>,
 {
-    info!("Refreshing updates...");
+    let url = match repository {
+        Some(repository) => {
+            info!("Refreshing updates from {}...", repository);
+            let encoded: String = form_urlencoded::byte_serialize(repository.as_bytes()).collect();
+            format!("/actions/refresh-updates?repository={}", encoded)
+        }
+        None => {
+            info!("Refreshing updates...");
+            "/actions/refresh-updates".to_string()
+        }
+    };
     let (_body, status) = wait_request(
         socket_path,
-        "/actions/refresh-updates",
+        url,
         "POST",
         None,
         "refresh",
@@ -69,6 +82,19 @@ pub fn required(check_output: &str) -> bool {
     }
 }
 
+/// Returns updog's explanations of the updates it skipped because of the `denied-versions` and
+/// `allowed-versions` update settings, from the output of check().
+pub fn skipped(check_output: &str) -> Vec<String> {
+    match response_field(&["most_recent_command", "stderr"], check_output) {
+        Some(stderr) => stderr
+            .lines()
+            .filter(|line| line.starts_with("Skipping update"))
+            .map(str::to_string)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
 /// Applies the update shown as selected in the output of check(), and makes it active.
 pub async fn apply<P>(socket_path: P) -> Result<()>
 where
@@ -146,6 +172,16 @@ fn response_field(
     result.as_str().map(|s| s.to_string())
 }
 
+/// Returns the bytes done and total of the download progress in an update status response, if any.
+fn download_progress(response_str: &str) -> Option<(u64, u64)> {
+    let response: serde_json::Value = serde_json::from_str(response_str).ok()?;
+    let progress = response.get("download_progress")?;
+    Some((
+        progress.get("bytes_done")?.as_u64()?,
+        progress.get("bytes_total")?.as_u64()?,
+    ))
+}
+
 /// Represents how a caller wants wait_request to handle waiting for status updates after
 /// requesting an action from an API call.  After `max_attempts` checks with `between_attempts`
 /// duration between them, the call will be timed out and fail.
@@ -220,6 +256,8 @@ where
     let notify_every = Duration::from_secs(5);
     // Counter so we can tell whether we should notify.
     let mut waited = Duration::from_millis(0);
+    // The last download progress we saw, in bytes done and total, if the command downloads.
+    let mut last_progress = None;
 
     loop {
         // Check if we've timed out or failed too many requests.
@@ -244,10 +282,16 @@ where
         // Let the user know what's going on every once in a while, as we wait.
         if attempt > 1 && waited >= notify_every {
             waited = Duration::from_millis(0);
-            info!(
-                "Still waiting for updated status, will wait up to {:?} longer...",
-                (wait.max_attempts * wait.between_attempts) - (attempt * wait.between_attempts)
-            );
+            match last_progress {
+                Some((bytes_done, bytes_total)) => info!(
+                    "Still waiting for updated status, downloaded {} of {} bytes...",
+                    bytes_done, bytes_total
+                ),
+                None => info!(
+                    "Still waiting for updated status, will wait up to {:?} longer...",
+                    (wait.max_attempts * wait.between_attempts) - (attempt * wait.between_attempts)
+                ),
+            }
         }
         time::sleep(wait.between_attempts).await;
         waited += wait.between_attempts;
@@ -269,6 +313,13 @@ where
         // Mutating actions will return a LOCKED status if they're not yet complete.
         if code == StatusCode::LOCKED {
             trace!("Lock still held, presumably by our request...");
+            // While an update is being prepared, the status includes its download progress.  As
+            // long as that's moving, the command is working, so we keep waiting.
+            let progress = download_progress(&status_body);
+            if progress.is_some() && progress != last_progress {
+                attempt = 0;
+                last_progress = progress;
+            }
             continue;
         } else if !code.is_success() {
             failures += 1;
@@ -0,0 +1,115 @@
+//! This module checks settings against the JSON Schema describing the settings of a variant,
+//! without using the API.  This lets you check settings before you use them, for example checking
+//! user data in CI before launching instances with it.
+
+use datastore::Key;
+use model::schema::ValidationError;
+use serde_json::{Map, Value};
+use snafu::{ensure, ResultExt};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Returns the JSON Schema from the given file, as served by the API at `/schema`, or the schema
+/// for the variant apiclient was built for if no file is given.
+pub fn schema<P>(path: Option<P>) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    match path {
+        Some(path) => {
+            let path = path.as_ref();
+            let data = fs::read_to_string(path).context(error::SchemaRead { path })?;
+            serde_json::from_str(&data).context(error::SchemaParse { path })
+        }
+        // The generated schema is always valid JSON.
+        None => Ok(serde_json::to_value(model::schema::settings_schema())
+            .expect("generated schema can't be represented as JSON")),
+    }
+}
+
+/// Checks the given settings against the schema, returning each problem found.  The settings are
+/// the contents of the "settings" object, e.g. `{"motd": "hi"}`.
+pub fn validate(schema: &Value, settings: &Value) -> Vec<ValidationError> {
+    model::schema::validate(schema, settings)
+}
+
+/// Builds the settings object for `validate` from key=value pairs, like those given to `set`.
+/// Keys may start with "settings."; other prefixes aren't settings.  Values are given the type
+/// that `set` would give them: true and false are booleans, numbers are numbers, and anything else
+/// is a string.
+pub fn settings_from_pairs(pairs: &HashMap<Key, String>) -> Result<Value> {
+    let mut settings = Map::new();
+    for (key, value) in pairs {
+        let mut segments = key.segments().as_slice();
+        if segments.first().map(String::as_str) == Some("settings") {
+            segments = &segments[1..];
+        }
+        ensure!(!segments.is_empty(), error::NotASetting { key: key.name() });
+
+        // Find the object holding the last segment, creating objects along the way.
+        let mut object = &mut settings;
+        for segment in &segments[..segments.len() - 1] {
+            object = match object
+                .entry(segment.clone())
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                Value::Object(map) => map,
+                _ => return error::PairConflict { key: key.name() }.fail(),
+            };
+        }
+
+        let last = &segments[segments.len() - 1];
+        ensure!(
+            !object.contains_key(last),
+            error::PairConflict { key: key.name() }
+        );
+        object.insert(last.clone(), typed_value(value));
+    }
+    Ok(Value::Object(settings))
+}
+
+/// Gives the value the type `set` would: a boolean or number if it parses as one, else a string.
+fn typed_value(value: &str) -> Value {
+    if let Ok(b) = serde_json::from_str::<bool>(value) {
+        Value::Bool(b)
+    } else if let Ok(u) = serde_json::from_str::<u64>(value) {
+        Value::from(u)
+    } else if let Ok(f) = serde_json::from_str::<f64>(value) {
+        Value::from(f)
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("'{}' is not a setting", key))]
+        NotASetting { key: String },
+
+        #[snafu(display(
+            "Setting '{}' conflicts with another given setting; a setting can't have both a value and settings inside it",
+            key
+        ))]
+        PairConflict { key: String },
+
+        #[snafu(display("Schema file '{}' is not valid JSON: {}", path.display(), source))]
+        SchemaParse {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to read schema file '{}': {}", path.display(), source))]
+        SchemaRead {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
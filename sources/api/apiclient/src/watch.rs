@@ -0,0 +1,223 @@
+//! This module watches the API's event stream for changes to settings and to the update state.
+//! The server sends an event each time a commit or rollback changes settings, and each time the
+//! update state changes, as server-sent events.
+
+use datastore::{Key, KeyType};
+use hyper::body::HttpBody;
+use hyper::{header, Body, Client, Request};
+use hyper_unix_connector::{UnixClient, Uri};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, ResultExt};
+use std::path::Path;
+
+/// A change reported by the server.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Event {
+    Settings(SettingsEvent),
+    UpdateState(UpdateStateEvent),
+    /// We fell behind and the server dropped this many events.  Whatever was being watched should
+    /// be reread.
+    Lagged {
+        missed: u64,
+    },
+}
+
+/// Sent when a commit or rollback changes live settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsEvent {
+    /// The generation of the live settings after the change.
+    pub generation: u64,
+    /// The committed transaction; None for a rollback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+    /// The saved generation whose settings were restored, for a rollback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollback_to: Option<u64>,
+    /// The names of the changed keys, sorted.
+    pub keys: Vec<String>,
+}
+
+/// Sent when the update state changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateStateEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+    pub state: String,
+}
+
+#[derive(Deserialize)]
+struct LaggedData {
+    missed: u64,
+}
+
+/// An open stream of events from the server.  Use `next` to wait for each event.
+pub struct Events {
+    body: Body,
+    // Received data that doesn't make up a full event yet.  We keep bytes rather than text
+    // because a chunk can end in the middle of a character.
+    buffer: Vec<u8>,
+}
+
+/// Connects to the server's event stream.  Only changes made after this returns are reported.
+pub async fn events<P>(socket_path: P) -> Result<Events>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/events";
+    let client = Client::builder().build::<_, Body>(UnixClient);
+    let hyper_uri: hyper::Uri = Uri::new(socket_path, uri).into();
+    let request = Request::builder()
+        .method("GET")
+        .uri(&hyper_uri)
+        .header(header::ACCEPT, "text/event-stream")
+        .body(Body::empty())
+        .context(error::RequestSetup)?;
+
+    let response = client.request(request).await.context(error::RequestSend)?;
+    let status = response.status();
+    ensure!(
+        status.is_success(),
+        error::ResponseStatus { uri, code: status }
+    );
+
+    Ok(Events {
+        body: response.into_body(),
+        buffer: Vec::new(),
+    })
+}
+
+impl Events {
+    /// Waits for the next event from the server.  Returns None if the server closed the stream,
+    /// for example because it's restarting.
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        loop {
+            // Events end with a blank line.
+            while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+                let raw: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                let raw = String::from_utf8(raw).context(error::NonUtf8Response)?;
+                if let Some(event) = parse_event(&raw)? {
+                    return Ok(Some(event));
+                }
+            }
+
+            match self.body.data().await {
+                Some(chunk) => {
+                    let chunk = chunk.context(error::ResponseBodyRead)?;
+                    self.buffer.extend_from_slice(&chunk);
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Parses a single server-sent event.  Returns None for comments, like heartbeats, and for event
+/// types we don't know, which a newer server might send.
+fn parse_event(raw: &str) -> Result<Option<Event>> {
+    let mut event_type = None;
+    let mut data = Vec::new();
+    for line in raw.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            event_type = Some(value.trim());
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.trim_start());
+        }
+    }
+    let data = data.join("\n");
+
+    let event = match event_type {
+        Some("settings") => {
+            Event::Settings(serde_json::from_str(&data).context(error::EventJson { data })?)
+        }
+        Some("update-state") => {
+            Event::UpdateState(serde_json::from_str(&data).context(error::EventJson { data })?)
+        }
+        Some("lagged") => {
+            let lagged: LaggedData =
+                serde_json::from_str(&data).context(error::EventJson { data })?;
+            Event::Lagged {
+                missed: lagged.missed,
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Parses user-given key paths, like those given to `get`, into filters for `matching_keys`.  The
+/// "settings." prefix is optional.
+pub fn key_filters(key_paths: &[String]) -> Result<Vec<Key>> {
+    key_paths
+        .iter()
+        .map(|key_path| {
+            let key =
+                Key::new(KeyType::Data, key_path).context(error::InvalidKey { input: key_path })?;
+            if key.segments()[0] == "settings" {
+                return Ok(key);
+            }
+            let mut segments = key.segments().clone();
+            segments.insert(0, "settings".to_string());
+            Key::from_segments(KeyType::Data, &segments)
+                .context(error::InvalidKey { input: key_path })
+        })
+        .collect()
+}
+
+/// Returns the names of the changed keys in the event that are at or under any of the given
+/// filters, or all changed keys if there are no filters.
+pub fn matching_keys(event: &SettingsEvent, filters: &[Key]) -> Vec<String> {
+    if filters.is_empty() {
+        return event.keys.clone();
+    }
+    event
+        .keys
+        .iter()
+        .filter(|name| match Key::new(KeyType::Data, name) {
+            Ok(key) => filters
+                .iter()
+                .any(|filter| key.segments().starts_with(filter.segments())),
+            // The server only sends valid key names, but if one isn't, we can't tell if the
+            // user is interested; show it rather than hiding a change.
+            Err(_) => true,
+        })
+        .cloned()
+        .collect()
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Event data was not in the expected format: {}: {}", source, data))]
+        EventJson {
+            data: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Given key '{}' is not a valid format: {}", input, source))]
+        InvalidKey {
+            input: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Event stream was not UTF-8: {}", source))]
+        NonUtf8Response { source: std::string::FromUtf8Error },
+
+        #[snafu(display("Failed to build request: {}", source))]
+        RequestSetup { source: http::Error },
+
+        #[snafu(display("Failed to send request: {}", source))]
+        RequestSend { source: hyper::Error },
+
+        #[snafu(display("Failed to read event stream: {}", source))]
+        ResponseBodyRead { source: hyper::Error },
+
+        #[snafu(display("Status {} when requesting {}", code.as_str(), uri))]
+        ResponseStatus { uri: String, code: http::StatusCode },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -10,7 +10,7 @@ use nix::unistd::Gid;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::{ensure, ResultExt};
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 
@@ -21,6 +21,9 @@ const DEFAULT_BIND_PATH: &str = "/run/api.sock";
 /// By default, when the user requests that we run a process via /exec, we run the process through
 /// this containerd socket.
 const DEFAULT_EXEC_SOCKET: &str = "/run/host-containerd/containerd.sock";
+/// By default, this is where we keep the history of committed settings changes.  It's outside the
+/// data store so it isn't lost when the data store is migrated.
+const DEFAULT_HISTORY_PATH: &str = "/var/lib/bottlerocket/settings-history.json";
 
 type Result<T> = std::result::Result<T, error::Error>;
 
@@ -48,6 +51,8 @@ struct Args {
     socket_gid: Option<Gid>,
     socket_path: String,
     exec_socket_path: String,
+    history_path: String,
+    policy_path: Option<PathBuf>,
 }
 
 /// Informs the user about proper usage of the program and exits.
@@ -59,12 +64,16 @@ fn usage() -> ! {
             [ --socket-path PATH ]
             [ --socket-gid GROUP_ID ]
             [ --exec-socket-path PATH ]
+            [ --history-path PATH ]
+            [ --policy-path PATH ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]
 
     --socket-path defaults to {}
-    --exec-socket-path (for apiclient exec) defaults to {}",
-        program_name, DEFAULT_BIND_PATH, DEFAULT_EXEC_SOCKET
+    --exec-socket-path (for apiclient exec) defaults to {}
+    --history-path (for settings change history) defaults to {}
+    --policy-path gives an access policy for API clients; without it, all clients have full access",
+        program_name, DEFAULT_BIND_PATH, DEFAULT_EXEC_SOCKET, DEFAULT_HISTORY_PATH
     );
     process::exit(2);
 }
@@ -82,6 +91,8 @@ fn parse_args(args: env::Args) -> Args {
     let mut socket_gid = None;
     let mut socket_path = None;
     let mut exec_socket_path = None;
+    let mut history_path = None;
+    let mut policy_path = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -129,6 +140,20 @@ fn parse_args(args: env::Args) -> Args {
                     }))
             }
 
+            "--history-path" => {
+                history_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --history-path")),
+                )
+            }
+
+            "--policy-path" => {
+                policy_path =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --policy-path")
+                    })))
+            }
+
             _ => usage(),
         }
     }
@@ -139,6 +164,8 @@ fn parse_args(args: env::Args) -> Args {
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_BIND_PATH.to_string()),
         exec_socket_path: exec_socket_path.unwrap_or_else(|| DEFAULT_EXEC_SOCKET.to_string()),
+        history_path: history_path.unwrap_or_else(|| DEFAULT_HISTORY_PATH.to_string()),
+        policy_path,
     }
 }
 
@@ -176,6 +203,8 @@ async fn run() -> Result<()> {
         threads,
         args.socket_gid,
         args.exec_socket_path,
+        args.history_path,
+        args.policy_path,
     )
     .await
     .context(error::Server)
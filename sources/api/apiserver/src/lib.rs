@@ -7,6 +7,7 @@ It's intended to be the primary way to read and modify OS settings, to update se
 The server listens to HTTP requests on a Unix-domain socket.
 There is no built-in authentication - local access to the socket should be limited to processes and containers that should be able to configure the system.
 Remote access should only be allowed through an authenticated control channel such as SSH or SSM.
+Clients that can reach the socket can be given less than full access with an access policy, described below.
 
 # Design
 
@@ -24,6 +25,8 @@ Pending settings can be retrieved from `/tx` to see what will change.
 Upon making a `/tx/commit` POST call, the pending transaction is made live.
 Upon making an `/tx/apply` POST call, an external settings applier tool is called to apply the changes to the system and restart services as necessary.
 There's also `/tx/commit_and_apply` to do both, which is the most common case.
+The outcome of restarting each service, and the current state of its systemd units, can be retrieved from `/services/status`.
+The outcome of running each bootstrap container this boot can be retrieved from `/bootstrap-containers/status`.
 
 If you don't specify a transaction, the "default" transaction is used, so you usually don't have to think about it.
 If you want to group changes into transactions yourself, you can add a `tx` parameter to the APIs mentioned above.
@@ -54,6 +57,33 @@ The default data store location is `/var/lib/bottlerocket/datastore/current`, an
 
 For more detail, see [datastore](../datastore).
 
+## Access policy
+
+If the server is started with `--policy-path`, each request is checked against the access policy in that file.
+Clients are identified by the uid and gid of the connecting process, and its security label (like an SELinux context) if an LSM that supports labels is active, all as reported by the kernel.
+The policy is a TOML file with a list of rules; the first rule that matches a client decides which routes it can call and which settings it can change:
+
+```toml
+[[rule]]
+name = "root"
+uid = 0
+routes = ["*"]
+settings = ["settings"]
+
+[[rule]]
+name = "monitoring"
+label = "system_u:system_r:container_t:*"
+routes = ["GET /settings", "GET /os", "GET /updates/status", "GET /events"]
+```
+
+Routes are a method and a path pattern, or just a path pattern to allow any method.
+Settings are key prefixes, checked when settings are changed and when a transaction is committed.
+Clients that don't match any rule can't do anything.
+Denied requests fail with 403 Forbidden, and are logged.
+Note that a rollback can change any setting, so it should only be allowed for trusted clients.
+
+Without a policy, any client that can reach the socket has full access.
+
 # Current limitations
 
 * Data store locking is coarse; read requests can happen in parallel, but a write request will block everything else.
@@ -3,19 +3,29 @@
 
 use bottlerocket_release::BottlerocketRelease;
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
 use std::io::Write;
 use std::process::{Command, Stdio};
 
 use crate::server::error::{self, Result};
+use crate::server::history::{self, SettingChange};
 use actix_web::HttpResponse;
+use chrono::{DateTime, Utc};
 use datastore::deserialization::{from_map, from_map_with_prefix};
 use datastore::serialization::to_pairs;
 use datastore::{deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value};
-use model::{ConfigurationFiles, Services, Settings};
+use model::modeled_types::ServiceActionKind;
+use model::{
+    ConfigurationFile, ConfigurationFiles, CustomFile, Model, RestartAction, Service, Services,
+    Settings,
+};
 use num::FromPrimitive;
 use std::os::unix::process::ExitStatusExt;
+use thar_be_settings::service::ActionResult;
+use thar_be_settings::status::ServiceStatuses;
 use thar_be_updates::error::TbuErrorStatus;
 
 /// List the open transactions from the data store.
@@ -82,9 +92,9 @@ pub(crate) fn get_os_info() -> Result<BottlerocketRelease> {
     BottlerocketRelease::new().context(error::ReleaseData)
 }
 
-/// Build a Services based on the data in the datastore.
+/// Build a Services based on the data in the datastore, including custom services.
 pub(crate) fn get_services<D: DataStore>(datastore: &D) -> Result<Services> {
-    get_prefix(
+    let mut services: Services = get_prefix(
         datastore,
         &Committed::Live,
         "services.",
@@ -92,12 +102,16 @@ pub(crate) fn get_services<D: DataStore>(datastore: &D) -> Result<Services> {
     )
     .transpose()
     // None is not OK here - we always have services
-    .context(error::MissingData { prefix: "services" })?
+    .context(error::MissingData { prefix: "services" })??;
+    let custom_files = get_custom_files(datastore, &Committed::Live)?;
+    services.extend(custom_services(custom_files));
+    Ok(services)
 }
 
-/// Build a ConfigurationFiles based on the data in the datastore.
+/// Build a ConfigurationFiles based on the data in the datastore, including custom configuration
+/// files.
 pub(crate) fn get_configuration_files<D: DataStore>(datastore: &D) -> Result<ConfigurationFiles> {
-    get_prefix(
+    let mut configuration_files: ConfigurationFiles = get_prefix(
         datastore,
         &Committed::Live,
         "configuration-files",
@@ -107,7 +121,94 @@ pub(crate) fn get_configuration_files<D: DataStore>(datastore: &D) -> Result<Con
     // None is not OK here - we always have configuration files
     .context(error::MissingData {
         prefix: "configuration-files",
-    })?
+    })??;
+    let custom_files = get_custom_files(datastore, &Committed::Live)?;
+    configuration_files.extend(custom_configuration_files(custom_files));
+    Ok(configuration_files)
+}
+
+/// Custom files are given in settings, and each one is both a configuration file and a service,
+/// with the name of the custom file plus this prefix, so they can't replace built-in ones.
+const CUSTOM_NAME_PREFIX: &str = "custom-";
+
+/// Gets the custom files from settings, by name.  Custom files can be given over several
+/// requests, so ones without a path or template aren't ready to be used yet, and are left out.
+fn get_custom_files<D: DataStore>(
+    datastore: &D,
+    committed: &Committed,
+) -> Result<HashMap<String, CustomFile>> {
+    let custom_files: HashMap<String, CustomFile> = get_prefix(
+        datastore,
+        committed,
+        "settings.custom-files.",
+        Some("settings.custom-files".to_string()),
+    )?
+    .unwrap_or_default();
+    Ok(custom_files
+        .into_iter()
+        .filter(|(_, custom_file)| custom_file.path.is_some() && custom_file.template.is_some())
+        .collect())
+}
+
+/// Returns the name of the configuration file and service for the named custom file.
+fn custom_name(name: &str) -> String {
+    format!("{}{}", CUSTOM_NAME_PREFIX, name)
+}
+
+/// Returns the service for each of the given custom files, which runs the file's restart
+/// actions.  The model only accepts custom file actions on the units custom files are allowed to
+/// act on, and every one of those is also a valid service action.
+fn custom_services(custom_files: HashMap<String, CustomFile>) -> Services {
+    custom_files
+        .into_iter()
+        .filter_map(|(name, custom_file)| {
+            let name = custom_name(&name);
+            let restart_actions = custom_file
+                .restart_actions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|restart_action| {
+                    Some(RestartAction {
+                        action: restart_action.action.as_ref().try_into().ok()?,
+                        timeout_seconds: restart_action.timeout_seconds,
+                    })
+                })
+                .collect::<Option<_>>()?;
+            let service = Service {
+                configuration_files: vec![name.as_str().try_into().ok()?],
+                restart_commands: Vec::new(),
+                restart_actions,
+            };
+            Some((name, service))
+        })
+        .collect()
+}
+
+/// Returns the configuration file for each of the given custom files.
+fn custom_configuration_files(custom_files: HashMap<String, CustomFile>) -> ConfigurationFiles {
+    custom_files
+        .into_iter()
+        .filter_map(|(name, custom_file)| {
+            let configuration_file = ConfigurationFile {
+                path: custom_file.path.as_deref()?.try_into().ok()?,
+                template_path: None,
+                template: custom_file.template,
+                mode: match custom_file.mode {
+                    Some(mode) => Some(mode.as_ref().try_into().ok()?),
+                    None => None,
+                },
+                owner: custom_file.owner,
+                group: custom_file.group,
+                render_if: None,
+            };
+            Some((custom_name(&name), configuration_file))
+        })
+        .collect()
+}
+
+/// Returns whether the given data key is the given setting, or is under it.
+fn key_is_under(key: &str, setting: &str) -> bool {
+    matches!(key.strip_prefix(setting), Some(rest) if rest.is_empty() || rest.starts_with('.'))
 }
 
 /// Helper to get data from the datastore, starting with the given find_prefix, and deserialize it
@@ -169,30 +270,60 @@ pub(crate) fn get_settings_keys<D: DataStore>(
     Ok(settings)
 }
 
-/// Build a collection of Service items with the given names using data from the datastore.
+/// Build a collection of Service items with the given names using data from the datastore,
+/// including custom services.
 pub(crate) fn get_services_names<'a, D: DataStore>(
     datastore: &D,
     names: &'a HashSet<&str>,
     committed: &Committed,
 ) -> Result<Services> {
-    get_map_from_prefix(datastore, "services.".to_string(), names, committed)
+    let custom = custom_services(get_custom_files(datastore, committed)?);
+    get_map_from_prefix_with(datastore, "services.".to_string(), names, committed, custom)
 }
 
 /// Build a collection of ConfigurationFile items with the given names using data from the
-/// datastore.
+/// datastore, including custom configuration files.
 pub(crate) fn get_configuration_files_names<D: DataStore>(
     datastore: &D,
     names: &HashSet<&str>,
     committed: &Committed,
 ) -> Result<ConfigurationFiles> {
-    get_map_from_prefix(
+    let custom = custom_configuration_files(get_custom_files(datastore, committed)?);
+    get_map_from_prefix_with(
         datastore,
         "configuration-files.".to_string(),
         names,
         committed,
+        custom,
     )
 }
 
+/// Like get_map_from_prefix, but items with names in `extra` are taken from there instead of the
+/// datastore.
+fn get_map_from_prefix_with<D: DataStore, T>(
+    datastore: &D,
+    prefix: String,
+    names: &HashSet<&str>,
+    committed: &Committed,
+    mut extra: HashMap<String, T>,
+) -> Result<HashMap<String, T>>
+where
+    T: DeserializeOwned,
+{
+    let stored_names = names
+        .iter()
+        .filter(|name| !extra.contains_key(**name))
+        .copied()
+        .collect();
+    let mut result = get_map_from_prefix(datastore, prefix, &stored_names, committed)?;
+    for name in names {
+        if let Some(item) = extra.remove(*name) {
+            result.insert(name.to_string(), item);
+        }
+    }
+    Ok(result)
+}
+
 /// Helper to get data from the datastore for a collection of requested items under a given prefix.  For
 /// example, a collection of Service items under "services" that have the requested names.
 /// Returns Err if we couldn't pull expected data, including the case where a name was specified
@@ -242,11 +373,99 @@ pub(crate) fn set_settings<D: DataStore>(
     let pending = Committed::Pending {
         tx: transaction.into(),
     };
+    if settings.custom.is_some() || settings.custom_schema.is_some() {
+        check_custom_settings(datastore, &pending, &pairs)?;
+    }
     datastore
         .set_keys(&pairs, &pending)
         .context(error::DataStore { op: "set_keys" })
 }
 
+/// The prefixes of the keys for custom settings and their declared types.
+const CUSTOM_SETTINGS_PREFIXES: &[&str] = &["settings.custom.", "settings.custom-schema."];
+
+/// Checks that the custom settings would match their declared types after the given pairs are
+/// set in the given transaction.  The values and the schema are both checked together, with
+/// live settings, then pending settings, then the given pairs layered on top, so they can be set
+/// in either order, or in the same request.  Custom settings without a declared type can have
+/// any type.
+fn check_custom_settings<D: DataStore>(
+    datastore: &D,
+    pending: &Committed,
+    pairs: &HashMap<Key, String>,
+) -> Result<()> {
+    let mut data = HashMap::new();
+    for prefix in CUSTOM_SETTINGS_PREFIXES {
+        data.extend(
+            datastore
+                .get_prefix(prefix, &Committed::Live)
+                .context(error::DataStore {
+                    op: "get_prefix for live",
+                })?,
+        );
+        data.extend(
+            datastore
+                .get_prefix(prefix, pending)
+                .context(error::DataStore {
+                    op: "get_prefix for pending",
+                })?,
+        );
+        data.extend(
+            pairs
+                .iter()
+                .filter(|(key, _)| key.name().starts_with(prefix))
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+    }
+    check_custom_data(&data)
+}
+
+/// Checks that the custom settings in the given settings data match their declared types.  Keys
+/// other than custom settings are ignored.
+fn check_custom_data(data: &HashMap<Key, String>) -> Result<()> {
+    let data: HashMap<Key, String> = data
+        .iter()
+        .filter(|(key, _)| {
+            CUSTOM_SETTINGS_PREFIXES
+                .iter()
+                .any(|prefix| key.name().starts_with(prefix))
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let settings: Settings = from_map_with_prefix(None, &data).context(error::Deserialization {
+        given: "custom settings",
+    })?;
+
+    let (values, schema) = match (settings.custom, settings.custom_schema) {
+        (Some(values), Some(schema)) => (values, schema),
+        _ => return Ok(()),
+    };
+    let mut problems: Vec<String> = schema
+        .iter()
+        .filter_map(|(name, kind)| {
+            let value = values.get(name)?;
+            if kind.accepts(value) {
+                None
+            } else {
+                Some(format!(
+                    "settings.custom.{} is {}, expected a {}",
+                    name,
+                    serde_json::to_string(value).unwrap_or_default(),
+                    kind
+                ))
+            }
+        })
+        .collect();
+    problems.sort();
+    ensure!(
+        problems.is_empty(),
+        error::InvalidCustomSettings {
+            problems: problems.join("; ")
+        }
+    );
+    Ok(())
+}
+
 // This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
 // data model and check types.
 /// Gets the value of a metadata key for the requested list of data keys.
@@ -286,6 +505,50 @@ pub(crate) fn get_metadata_for_data_keys<D: DataStore, S: AsRef<str>>(
     Ok(result)
 }
 
+/// Gets the services affected by each of the given data keys.  This is the "affected-services"
+/// metadata of the keys, plus the custom services affected by them: each custom service is
+/// affected by its own settings and the settings listed in its `affected-by`.
+pub(crate) fn get_affected_services<D: DataStore>(
+    datastore: &D,
+    data_keys: &HashSet<&str>,
+) -> Result<HashMap<String, Value>> {
+    let mut result = get_metadata_for_data_keys(datastore, "affected-services", data_keys)?;
+    let custom_files = get_custom_files(datastore, &Committed::Live)?;
+    if custom_files.is_empty() {
+        return Ok(result);
+    }
+
+    for data_key in data_keys {
+        let mut custom_names: Vec<String> = custom_files
+            .iter()
+            .filter(|(name, custom_file)| {
+                let own_settings = format!("settings.custom-files.{}", name);
+                key_is_under(data_key, &own_settings)
+                    || custom_file
+                        .affected_by
+                        .iter()
+                        .flatten()
+                        .any(|setting| key_is_under(data_key, setting))
+            })
+            .map(|(name, _)| custom_name(name))
+            .collect();
+        if custom_names.is_empty() {
+            continue;
+        }
+        custom_names.sort();
+
+        let mut services: Vec<String> = match result.remove(*data_key) {
+            Some(value) => serde_json::from_value(value).context(error::InvalidMetadata {
+                key: "affected-services",
+            })?,
+            None => Vec::new(),
+        };
+        services.extend(custom_names);
+        result.insert(data_key.to_string(), services.into());
+    }
+    Ok(result)
+}
+
 /// Gets the value of a metadata key everywhere it's found in the data store.  Returns a mapping
 /// of data key to the metadata value associated with the requested key.
 pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
@@ -314,16 +577,582 @@ pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
     Ok(result)
 }
 
+/// Gets the policies of setting generators, from the metadata of the settings that have them.
+/// Returns a mapping of data key to an object holding each policy metadata value found for it,
+/// under the short name from `SETTING_GENERATOR_POLICY_METADATA`.
+pub(crate) fn get_setting_generator_policies<D: DataStore>(
+    datastore: &D,
+) -> Result<HashMap<String, Value>> {
+    let mut result: HashMap<String, serde_json::Map<String, Value>> = HashMap::new();
+    for (metadata_name, policy_name) in constants::SETTING_GENERATOR_POLICY_METADATA {
+        for (data_key, value) in get_metadata_for_all_data_keys(datastore, metadata_name)? {
+            result
+                .entry(data_key)
+                .or_default()
+                .insert(policy_name.to_string(), value);
+        }
+    }
+
+    Ok(result
+        .into_iter()
+        .map(|(data_key, policies)| (data_key, Value::Object(policies)))
+        .collect())
+}
+
+/// A configuration file rendered from its template, for previewing it without writing it.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct RenderedConfigurationFile {
+    pub(crate) name: String,
+    pub(crate) path: String,
+    pub(crate) rendered: String,
+}
+
+/// Renders the template of the named configuration file with the live settings, with the pending
+/// settings of the given transaction on top, if one is given, and then the given settings, if
+/// any.  This shows what the file would contain after those changes; nothing is written.
+pub(crate) fn render_configuration_file<D: DataStore>(
+    datastore: &D,
+    name: &str,
+    transaction: Option<&str>,
+    extra_settings: Option<&Settings>,
+) -> Result<RenderedConfigurationFile> {
+    let mut names = HashSet::new();
+    names.insert(name);
+    let configuration_file = get_configuration_files_names(datastore, &names, &Committed::Live)?
+        .remove(name)
+        .context(error::MissingData {
+            prefix: format!("configuration-files.{}", name),
+        })?;
+
+    // Layer the settings key by key, so each layer only replaces the keys it sets.
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStore {
+            op: "get_prefix for live",
+        })?;
+    if let Some(transaction) = transaction {
+        let pending = Committed::Pending {
+            tx: transaction.into(),
+        };
+        data.extend(
+            datastore
+                .get_prefix("settings.", &pending)
+                .context(error::DataStore {
+                    op: "get_prefix for pending",
+                })?,
+        );
+    }
+    if let Some(extra_settings) = extra_settings {
+        data.extend(
+            to_pairs(extra_settings)
+                .context(error::DataStoreSerialization { given: "Settings" })?,
+        );
+    }
+    let settings: Settings =
+        from_map_with_prefix(None, &data).context(error::Deserialization { given: "settings" })?;
+
+    // Templates see the same model that thar-be-settings gives them.
+    let model = Model {
+        settings: Some(settings),
+        services: Some(get_services(datastore)?),
+        configuration_files: Some(get_configuration_files(datastore)?),
+        os: Some(get_os_info()?),
+    };
+
+    let mut registry = schnauzer::build_template_registry().context(error::TemplateRegistry)?;
+    schnauzer::register_configuration_template(&mut registry, name, &configuration_file)
+        .context(error::TemplateRender)?;
+    let rendered =
+        schnauzer::render_template(&registry, name, &model).context(error::TemplateRender)?;
+
+    Ok(RenderedConfigurationFile {
+        name: name.to_string(),
+        path: configuration_file.path.to_string(),
+        rendered,
+    })
+}
+
+/// The runtime state of a service: the outcome of the last time settings were applied to it, if
+/// they have been since boot, and the current state of its systemd units.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ServiceStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) last_applied: Option<DateTime<Utc>>,
+    /// The changed settings that caused the service to be restarted; missing if all services
+    /// were restarted, like at boot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) changed_settings: Option<Vec<String>>,
+    pub(crate) restart_results: Vec<ActionResult>,
+    /// The systemd ActiveState of each unit of the service, like "active" or "failed".
+    pub(crate) units: BTreeMap<String, String>,
+}
+
+/// Combines the given services with the recorded outcome of the last time settings were applied
+/// to them, and the state of their systemd units, given by `unit_state`.
+///
+/// A service's units are the ones named in its restart actions.  If it has none, the unit with the
+/// same name as the service is used, if it exists.
+pub(crate) fn get_services_status<F>(
+    services: Services,
+    mut applied: ServiceStatuses,
+    unit_state: F,
+) -> BTreeMap<String, ServiceStatus>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = BTreeMap::new();
+    for (name, service) in services {
+        let mut units = BTreeMap::new();
+        for restart_action in &service.restart_actions {
+            let unit = match restart_action.action.kind() {
+                ServiceActionKind::RestartUnit { unit }
+                | ServiceActionKind::ReloadUnit { unit }
+                | ServiceActionKind::SignalUnit { unit, .. } => unit,
+                ServiceActionKind::Run { .. } => continue,
+            };
+            if let Some(state) = unit_state(unit) {
+                units.insert(unit.to_string(), state);
+            }
+        }
+        if service.restart_actions.is_empty() {
+            let unit = format!("{}.service", name);
+            if let Some(state) = unit_state(&unit) {
+                units.insert(unit, state);
+            }
+        }
+
+        let status = match applied.remove(&name) {
+            Some(applied) => ServiceStatus {
+                last_applied: Some(applied.last_applied),
+                changed_settings: applied.changed_settings,
+                restart_results: applied.restart_results,
+                units,
+            },
+            None => ServiceStatus {
+                last_applied: None,
+                changed_settings: None,
+                restart_results: Vec::new(),
+                units,
+            },
+        };
+        result.insert(name, status);
+    }
+    result
+}
+
+/// Returns the systemd ActiveState of the given unit, or None if there's no such unit.  If
+/// systemd can't be asked, the state is "unknown".
+pub(crate) fn systemd_unit_state(unit: &str) -> Option<String> {
+    let output = Command::new(constants::SYSTEMCTL_BIN)
+        .args([
+            "show",
+            "--property=LoadState",
+            "--property=ActiveState",
+            unit,
+        ])
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            warn!(
+                "Failed to get state of unit '{}': {}",
+                unit,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return Some("unknown".to_string());
+        }
+        Err(e) => {
+            warn!("Failed to get state of unit '{}': {}", unit, e);
+            return Some("unknown".to_string());
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut active_state = None;
+    for line in stdout.lines() {
+        match line.split_once('=') {
+            Some(("LoadState", "not-found")) => return None,
+            Some(("ActiveState", state)) => active_state = Some(state.to_string()),
+            _ => {}
+        }
+    }
+    Some(active_state.unwrap_or_else(|| "unknown".to_string()))
+}
+
+/// Returns each key that committing the given transaction would change, sorted by key, with its
+/// current live value, if any, and its pending value.  Keys whose pending value is the same as
+/// their live value aren't changed by the commit, so they're left out.
+fn changed_keys<D>(datastore: &D, transaction: &str) -> Result<Vec<(Key, Option<String>, String)>>
+where
+    D: DataStore,
+{
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let pending_data = datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStore {
+            op: "get_prefix for pending",
+        })?;
+
+    let mut changed = Vec::with_capacity(pending_data.len());
+    for (key, pending_value) in pending_data {
+        let live_value = datastore
+            .get_key(&key, &Committed::Live)
+            .context(error::DataStore { op: "get_key" })?;
+        if live_value.as_ref() == Some(&pending_value) {
+            continue;
+        }
+        changed.push((key, live_value, pending_value));
+    }
+    changed.sort_by(|a, b| a.0.name().cmp(b.0.name()));
+
+    Ok(changed)
+}
+
+/// Describes the changes that committing the given transaction would make, as they're recorded in
+/// the history; see `changed_keys`.
+pub(crate) fn pending_changes<D>(datastore: &D, transaction: &str) -> Result<Vec<SettingChange>>
+where
+    D: DataStore,
+{
+    Ok(changed_keys(datastore, transaction)?
+        .into_iter()
+        .map(|(key, live_value, pending_value)| {
+            SettingChange::new(&key, live_value.as_deref(), Some(&pending_value))
+        })
+        .collect())
+}
+
+/// Describes what committing a transaction would change, for previewing it before a commit.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct TransactionDiff {
+    /// Each pending key with its current live value, if any, and its pending value.
+    pub(crate) changes: Vec<PendingChange>,
+    /// The services that would be restarted.
+    pub(crate) services: BTreeSet<String>,
+    /// The configuration files that would be re-rendered.
+    pub(crate) configuration_files: BTreeSet<String>,
+    /// The changes that re-rendering would make to each configuration file, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rendered_files: Option<Vec<ConfigFileDiff>>,
+}
+
+/// The live and pending value of a single key in a transaction.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct PendingChange {
+    pub(crate) key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) live_value: Option<Value>,
+    pub(crate) pending_value: Value,
+}
+
+/// The change that re-rendering a configuration file would make to the file on disk, as a unified
+/// diff.  This is produced by the config applier.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ConfigFileDiff {
+    pub(crate) path: String,
+    pub(crate) diff: String,
+}
+
+/// Describes what committing the given transaction would change: the pending keys with their live
+/// and pending values, and the services and configuration files affected by those keys.  Keys
+/// whose pending value is the same as their live value aren't changed by a commit, so they're left
+/// out, along with any services and configuration files that only they affect.
+/// The rendered files aren't included; see `render_pending`.
+pub(crate) fn transaction_diff<D>(datastore: &D, transaction: &str) -> Result<TransactionDiff>
+where
+    D: DataStore,
+{
+    let changes: Vec<PendingChange> = changed_keys(datastore, transaction)?
+        .into_iter()
+        .map(|(key, live_value, pending_value)| PendingChange {
+            key: key.name().to_string(),
+            live_value: live_value.as_deref().map(history::parse_value),
+            pending_value: history::parse_value(&pending_value),
+        })
+        .collect();
+    let key_names: HashSet<&str> = changes.iter().map(|change| change.key.as_str()).collect();
+
+    let affected = get_affected_services(datastore, &key_names)?;
+    let mut services = BTreeSet::new();
+    for service_list in affected.values() {
+        let service_list: Vec<String> =
+            serde_json::from_value(service_list.clone()).context(error::InvalidMetadata {
+                key: "affected-services",
+            })?;
+        services.extend(service_list);
+    }
+
+    let mut configuration_files = BTreeSet::new();
+    if !services.is_empty() {
+        let service_names = services.iter().map(|s| s.as_str()).collect();
+        for service in get_services_names(datastore, &service_names, &Committed::Live)?.values() {
+            configuration_files.extend(service.configuration_files.iter().map(|f| f.to_string()));
+        }
+    }
+
+    Ok(TransactionDiff {
+        changes,
+        services,
+        configuration_files,
+        rendered_files: None,
+    })
+}
+
+/// Runs the config applier in dry-run mode to render configuration files affected by the given
+/// keys as if the given transaction were committed.  Returns the changes that would be made to
+/// each configuration file.  Nothing is written or restarted.
+///
+/// The config applier reads data from the API, so this must not be called while holding the data
+/// store lock, and must not block the server's event loop.
+pub(crate) fn render_pending<S>(transaction: &str, keys: &[S]) -> Result<Vec<ConfigFileDiff>>
+where
+    S: AsRef<str>,
+{
+    let keys: Vec<&str> = keys.iter().map(|s| s.as_ref()).collect();
+    trace!("Serializing the transaction's pending keys: {:?}", keys);
+    let cmd_input = serde_json::to_string(&keys).context(error::CommandSerialization {
+        given: "transaction's pending keys",
+    })?;
+
+    debug!("Launching thar-be-settings to render pending changes");
+    let mut cmd = Command::new("/usr/bin/thar-be-settings")
+        .arg("--dry-run")
+        .arg("--tx")
+        .arg(transaction)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(error::ConfigApplierStart)?;
+
+    trace!("Sending pending keys");
+    cmd.stdin
+        .take()
+        .context(error::ConfigApplierStdin)?
+        .write_all(cmd_input.as_bytes())
+        .context(error::ConfigApplierWrite)?;
+
+    let output = cmd.wait_with_output().context(error::ConfigApplierWait)?;
+    ensure!(
+        output.status.success(),
+        error::ConfigRender {
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+
+    serde_json::from_slice(&output.stdout).context(error::ConfigRenderOutput)
+}
+
 /// Makes live any pending settings in the datastore, returning the changed keys.
+///
+/// Custom settings are checked against their declared types again first, since other
+/// transactions may have changed the live values or schema since these were set.
 pub(crate) fn commit_transaction<D>(datastore: &mut D, transaction: &str) -> Result<HashSet<Key>>
 where
     D: DataStore,
 {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    check_custom_settings(datastore, &pending, &HashMap::new())?;
     datastore
         .commit_transaction(transaction)
         .context(error::DataStore { op: "commit" })
 }
 
+/// The generations of the live settings, for choosing a generation to roll back to.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Generations {
+    /// The generation of the current live settings.
+    pub(crate) current: u64,
+    /// Prior generations whose settings are saved and can be restored, oldest first.
+    pub(crate) saved: Vec<u64>,
+}
+
+/// Returns the generation of the live settings.
+pub(crate) fn get_generation<D: DataStore>(datastore: &D) -> Result<u64> {
+    datastore
+        .generation()
+        .context(error::DataStore { op: "generation" })
+}
+
+/// Returns the keys that setting the given Settings would change.
+pub(crate) fn settings_keys(settings: &Settings) -> Result<HashSet<Key>> {
+    let pairs = to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    Ok(pairs.into_keys().collect())
+}
+
+/// Returns the keys with pending changes in the given transaction.
+pub(crate) fn pending_keys<D: DataStore>(datastore: &D, transaction: &str) -> Result<HashSet<Key>> {
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    let pending_data = datastore
+        .get_prefix("settings.", &pending)
+        .context(error::DataStore {
+            op: "get_prefix for pending",
+        })?;
+    Ok(pending_data.into_keys().collect())
+}
+
+/// Checks that none of the given keys has changed in live settings since the given generation.
+/// Clients give the generation they read settings from, to make sure they don't overwrite changes
+/// they haven't seen.  Changes to other keys since that generation don't matter.
+///
+/// If the generation isn't saved, we can't tell what changed, so this fails with
+/// GenerationUnknown and the client should read settings again.
+pub(crate) fn check_unchanged_since<D: DataStore>(
+    datastore: &D,
+    generation: u64,
+    keys: &HashSet<Key>,
+) -> Result<()> {
+    let current = get_generation(datastore)?;
+    if generation == current {
+        return Ok(());
+    }
+    ensure!(
+        generation < current,
+        error::GenerationUnknown {
+            generation,
+            current
+        }
+    );
+
+    let saved = match datastore.get_generation_settings(generation) {
+        Ok(saved) => saved,
+        Err(datastore::Error::GenerationNotFound { .. }) => {
+            return error::GenerationUnknown {
+                generation,
+                current,
+            }
+            .fail()
+        }
+        Err(e) => {
+            return Err(e).context(error::DataStore {
+                op: "get_generation_settings",
+            })
+        }
+    };
+
+    let mut changed = Vec::new();
+    for key in keys {
+        let live_value = datastore
+            .get_key(key, &Committed::Live)
+            .context(error::DataStore { op: "get_key" })?;
+        if live_value.as_ref() != saved.get(key) {
+            changed.push(key.name().to_string());
+        }
+    }
+    changed.sort();
+
+    ensure!(
+        changed.is_empty(),
+        error::GenerationConflict {
+            generation,
+            current,
+            keys: changed,
+        }
+    );
+    Ok(())
+}
+
+/// Returns the current generation and the saved prior generations.
+pub(crate) fn list_generations<D: DataStore>(datastore: &D) -> Result<Generations> {
+    Ok(Generations {
+        current: get_generation(datastore)?,
+        saved: datastore.list_generations().context(error::DataStore {
+            op: "list_generations",
+        })?,
+    })
+}
+
+/// Returns the keys that rolling back to the given prior generation would change, so they can be
+/// checked against the client's access policy before the rollback is made.
+pub(crate) fn rollback_keys<D: DataStore>(datastore: &D, generation: u64) -> Result<HashSet<Key>> {
+    let saved = list_generations(datastore)?.saved;
+    ensure!(
+        saved.contains(&generation),
+        error::GenerationNotFound { generation }
+    );
+
+    let saved = datastore
+        .get_generation_settings(generation)
+        .context(error::DataStore {
+            op: "get_generation_settings",
+        })?;
+    let live = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStore {
+            op: "get_prefix for live",
+        })?;
+
+    // Keys set since the generation are removed, and keys with other values are restored.
+    let mut keys: HashSet<Key> = live
+        .keys()
+        .filter(|key| !saved.contains_key(key))
+        .cloned()
+        .collect();
+    keys.extend(
+        saved
+            .into_iter()
+            .filter(|(key, value)| live.get(key) != Some(value))
+            .map(|(key, _)| key),
+    );
+    Ok(keys)
+}
+
+/// Restores live settings to those saved for the given prior generation.  Returns the changed
+/// keys, along with a description of each change for the settings history.
+pub(crate) fn rollback<D>(
+    datastore: &mut D,
+    generation: u64,
+) -> Result<(HashSet<Key>, Vec<SettingChange>)>
+where
+    D: DataStore,
+{
+    let saved = list_generations(datastore)?.saved;
+    ensure!(
+        saved.contains(&generation),
+        error::GenerationNotFound { generation }
+    );
+
+    // The saved settings replace the live settings entirely, so their custom settings are checked
+    // on their own.
+    let saved = datastore
+        .get_generation_settings(generation)
+        .context(error::DataStore {
+            op: "get_generation_settings",
+        })?;
+    check_custom_data(&saved)?;
+
+    let before = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStore {
+            op: "get_prefix for live",
+        })?;
+    let changed = datastore
+        .rollback_to_generation(generation)
+        .context(error::DataStore { op: "rollback" })?;
+
+    let mut changes = Vec::with_capacity(changed.len());
+    for key in &changed {
+        let new_value = datastore
+            .get_key(key, &Committed::Live)
+            .context(error::DataStore { op: "get_key" })?;
+        changes.push(SettingChange::new(
+            key,
+            before.get(key).map(|v| v.as_str()),
+            new_value.as_deref(),
+        ));
+    }
+    changes.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok((changed, changes))
+}
+
 /// Launches the config applier to make appropriate changes to the system based on any settings
 /// that have been committed.  Can be called after a commit, with the keys that changed in that
 /// commit, or called on its own to reset configuration state with all known keys.
@@ -516,6 +1345,12 @@ mod test {
             &Committed::Live,
         )
         .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.foo.restart-actions").unwrap(),
+            "[{\"action\": \"restart-unit foo.service\", \"timeout-seconds\": 30}]",
+            &Committed::Live,
+        )
+        .unwrap();
 
         // Retrieve built service
         let names = hashset!("foo");
@@ -524,11 +1359,167 @@ mod test {
             services,
             hashmap!("foo".to_string() => Service {
                 configuration_files: vec!["file1".try_into().unwrap()],
-                restart_commands: vec!["echo hi".to_string()]
+                restart_commands: vec!["echo hi".to_string()],
+                restart_actions: vec![RestartAction {
+                    action: "restart-unit foo.service".try_into().unwrap(),
+                    timeout_seconds: Some(30),
+                }],
             })
         );
     }
 
+    #[test]
+    fn custom_files_work() {
+        let mut ds = MemoryDataStore::new();
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom-files": {
+                "app": {
+                    "path": "/etc/custom/app.conf",
+                    // fleet={{settings.custom.fleet}}
+                    "template": "ZmxlZXQ9e3tzZXR0aW5ncy5jdXN0b20uZmxlZXR9fQ==",
+                    "mode": "0640",
+                    "restart-actions": [{"action": "restart-unit host-containers@app.service"}],
+                    "affected-by": ["settings.custom.fleet"],
+                },
+                // Incomplete custom files aren't used yet.
+                "partial": {"path": "/etc/custom/partial.conf"},
+            }
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, "tx").unwrap();
+        ds.commit_transaction("tx").unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.foo.configuration-files").unwrap(),
+            "[\"file1\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        let services = get_services(&ds).unwrap();
+        assert_eq!(services.len(), 2);
+        let names = hashset!("foo", "custom-app");
+        let services = get_services_names(&ds, &names, &Committed::Live).unwrap();
+        let app = &services["custom-app"];
+        assert_eq!(app.configuration_files.len(), 1);
+        assert_eq!(&*app.configuration_files[0], "custom-app");
+        assert_eq!(
+            app.restart_actions,
+            vec![RestartAction {
+                action: "restart-unit host-containers@app.service"
+                    .try_into()
+                    .unwrap(),
+                timeout_seconds: None,
+            }]
+        );
+        let names = hashset!("custom-partial");
+        get_services_names(&ds, &names, &Committed::Live).unwrap_err();
+
+        let names = hashset!("custom-app");
+        let files = get_configuration_files_names(&ds, &names, &Committed::Live).unwrap();
+        let file = &files["custom-app"];
+        assert_eq!(&*file.path, "/etc/custom/app.conf");
+        assert_eq!(file.template_path, None);
+        assert_eq!(file.mode, Some("0640".try_into().unwrap()));
+
+        // Custom services are affected by their own settings and the ones they list.
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &Key::new(KeyType::Data, "settings.custom").unwrap(),
+            "[\"foo\"]",
+        )
+        .unwrap();
+        let keys = hashset!(
+            "settings.custom.fleet",
+            "settings.custom.fleet-size",
+            "settings.custom-files.app.template",
+            "settings.motd"
+        );
+        let affected = get_affected_services(&ds, &keys).unwrap();
+        assert_eq!(
+            affected,
+            hashmap!(
+                "settings.custom.fleet".to_string() => serde_json::json!(["foo", "custom-app"]),
+                "settings.custom.fleet-size".to_string() => serde_json::json!(["foo"]),
+                "settings.custom-files.app.template".to_string() => serde_json::json!(["custom-app"]),
+            )
+        );
+    }
+
+    #[test]
+    fn custom_files_limited() {
+        // Custom files can't run programs, act on units outside the allow-list, or be setuid.
+        for custom_file in &[
+            serde_json::json!({"restart-actions": [{"action": "run /bin/sh -c reboot"}]}),
+            serde_json::json!({"restart-actions": [{"action": "restart-unit kubelet.service"}]}),
+            serde_json::json!({"mode": "4755"}),
+        ] {
+            let settings = serde_json::json!({"custom-files": {"app": custom_file}});
+            serde_json::from_value::<Settings>(settings).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn get_services_status_works() {
+        let service = |restart_actions: &[&str]| Service {
+            configuration_files: vec![],
+            restart_commands: vec![],
+            restart_actions: restart_actions
+                .iter()
+                .map(|action| RestartAction {
+                    action: (*action).try_into().unwrap(),
+                    timeout_seconds: None,
+                })
+                .collect(),
+        };
+        let services = hashmap!(
+            "foo".to_string() => service(&["run /bin/foo-setup", "restart-unit foo.service", "signal-unit bar.service SIGHUP"]),
+            "baz".to_string() => service(&[]),
+            "missing".to_string() => service(&[]),
+        );
+        let last_applied = chrono::Utc::now();
+        let results = vec![ActionResult {
+            action: "run /bin/foo-setup".to_string(),
+            exit_code: Some(1),
+            error: Some("failed".to_string()),
+        }];
+        let mut applied = ServiceStatuses::new();
+        applied.insert(
+            "foo".to_string(),
+            thar_be_settings::status::ServiceStatus {
+                last_applied,
+                changed_settings: Some(vec!["settings.foo".to_string()]),
+                restart_results: results.clone(),
+            },
+        );
+
+        let unit_state = |unit: &str| match unit {
+            "foo.service" => Some("active".to_string()),
+            "bar.service" => Some("failed".to_string()),
+            "baz.service" => Some("inactive".to_string()),
+            _ => None,
+        };
+        let status = get_services_status(services, applied, unit_state);
+        assert_eq!(status.len(), 3);
+        assert_eq!(
+            status["foo"],
+            ServiceStatus {
+                last_applied: Some(last_applied),
+                changed_settings: Some(vec!["settings.foo".to_string()]),
+                restart_results: results,
+                units: vec![
+                    ("bar.service".to_string(), "failed".to_string()),
+                    ("foo.service".to_string(), "active".to_string()),
+                ]
+                .into_iter()
+                .collect(),
+            }
+        );
+        // Services without restart actions use the unit with their name, if it exists.
+        assert_eq!(status["baz"].last_applied, None);
+        assert_eq!(status["baz"].units.len(), 1);
+        assert!(status["missing"].units.is_empty());
+    }
+
     #[test]
     fn set_settings_works() {
         let mut settings = Settings::default();
@@ -548,6 +1539,87 @@ mod test {
         );
     }
 
+    #[test]
+    fn set_custom_settings() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        // Values can be set without a schema, with any type.
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom": {"replicas": 3, "fleet": "blue", "debug": true}
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, tx).unwrap();
+        let key = Key::new(KeyType::Data, "settings.custom.replicas").unwrap();
+        assert_eq!(Some("3".to_string()), ds.get_key(&key, &pending).unwrap());
+
+        // The schema is checked against the values already pending.
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom-schema": {"replicas": "string"}
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, tx).unwrap_err();
+
+        // Values and schema given together are checked together.
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom": {"replicas": "3"},
+            "custom-schema": {"replicas": "string", "debug": "bool"}
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, tx).unwrap();
+        ds.commit_transaction(tx).unwrap();
+
+        // Later values are checked against the committed schema.
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom": {"debug": "yes"}
+        }))
+        .unwrap();
+        let err = set_settings(&mut ds, &settings, tx).unwrap_err();
+        assert!(err.to_string().contains("settings.custom.debug"));
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom": {"debug": false, "new": 1.5}
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, tx).unwrap();
+    }
+
+    #[test]
+    fn commit_checks_custom_settings() {
+        let mut ds = MemoryDataStore::new();
+
+        // Each transaction is fine on its own, but not once the other is committed.
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom": {"x": "a"}
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, "A").unwrap();
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "custom-schema": {"x": "number"}
+        }))
+        .unwrap();
+        set_settings(&mut ds, &settings, "B").unwrap();
+
+        commit_transaction(&mut ds, "A").unwrap();
+        let err = commit_transaction(&mut ds, "B").unwrap_err();
+        assert!(err.to_string().contains("settings.custom.x"));
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert!(settings.custom_schema.is_none());
+
+        // Rolling back checks the restored settings, too.  Here the schema is committed directly to
+        // the data store, as if it were saved before the schema checks, and then fixed.
+        let pending = Committed::Pending { tx: "C".into() };
+        let schema = Key::new(KeyType::Data, "settings.custom-schema.x").unwrap();
+        ds.set_key(&schema, "\"number\"", &pending).unwrap();
+        ds.commit_transaction("C").unwrap();
+        let x = Key::new(KeyType::Data, "settings.custom.x").unwrap();
+        ds.set_key(&x, "1", &pending).unwrap();
+        ds.commit_transaction("C").unwrap();
+        let generation = get_generation(&ds).unwrap();
+        rollback(&mut ds, generation - 1).unwrap_err();
+        rollback(&mut ds, generation - 2).unwrap();
+    }
+
     #[test]
     fn get_metadata_keys_works() {
         let mut ds = MemoryDataStore::new();
@@ -595,6 +1667,31 @@ mod test {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn pending_changes_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        ds.set_key(&motd, "\"old\"", &Committed::Live).unwrap();
+        ds.set_key(&motd, "\"new\"", &pending).unwrap();
+        ds.set_key(&hostname, "\"example\"", &pending).unwrap();
+        // Setting a key to its current value doesn't change it.
+        let timezone = Key::new(KeyType::Data, "settings.timezone").unwrap();
+        ds.set_key(&timezone, "\"UTC\"", &Committed::Live).unwrap();
+        ds.set_key(&timezone, "\"UTC\"", &pending).unwrap();
+
+        let changes = pending_changes(&ds, tx).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                SettingChange::new(&motd, Some("\"old\""), Some("\"new\"")),
+                SettingChange::new(&hostname, None, Some("\"example\"")),
+            ]
+        );
+    }
+
     #[test]
     fn commit_works() {
         // Set directly with data store
@@ -623,4 +1720,150 @@ mod test {
         let settings = get_settings(&ds, &Committed::Live).unwrap();
         assert_eq!(settings.motd, Some("json string".try_into().unwrap()));
     }
+
+    #[test]
+    fn rollback_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        ds.set_key(&motd, "\"old\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        ds.set_key(&motd, "\"new\"", &pending).unwrap();
+        ds.set_key(&hostname, "\"example\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        assert_eq!(
+            list_generations(&ds).unwrap(),
+            Generations {
+                current: 2,
+                saved: vec![0, 1]
+            }
+        );
+
+        assert_eq!(
+            rollback_keys(&ds, 1).unwrap(),
+            hashset!(motd.clone(), hostname.clone())
+        );
+        let (changed, changes) = rollback(&mut ds, 1).unwrap();
+        assert_eq!(changed, hashset!(motd.clone(), hostname.clone()));
+        assert_eq!(
+            changes,
+            vec![
+                SettingChange::new(&motd, Some("\"new\""), Some("\"old\"")),
+                SettingChange::new(&hostname, Some("\"example\""), None),
+            ]
+        );
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.motd, Some("old".try_into().unwrap()));
+        assert_eq!(get_generation(&ds).unwrap(), 3);
+
+        rollback_keys(&ds, 42).unwrap_err();
+        rollback(&mut ds, 42).unwrap_err();
+    }
+
+    #[test]
+    fn transaction_diff_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        ds.set_key(&motd, "\"old\"", &Committed::Live).unwrap();
+        ds.set_key(&motd, "\"new\"", &pending).unwrap();
+        ds.set_key(&hostname, "\"example\"", &pending).unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &motd,
+            "[\"motd\"]",
+        )
+        .unwrap();
+        // Setting a key to its current value doesn't change it, or affect its services.
+        let timezone = Key::new(KeyType::Data, "settings.timezone").unwrap();
+        ds.set_key(&timezone, "\"UTC\"", &Committed::Live).unwrap();
+        ds.set_key(&timezone, "\"UTC\"", &pending).unwrap();
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, "affected-services").unwrap(),
+            &timezone,
+            "[\"chronyd\"]",
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.motd.configuration-files").unwrap(),
+            "[\"motd\"]",
+            &Committed::Live,
+        )
+        .unwrap();
+        ds.set_key(
+            &Key::new(KeyType::Data, "services.motd.restart-commands").unwrap(),
+            "[]",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        let diff = transaction_diff(&ds, tx).unwrap();
+        assert_eq!(
+            diff,
+            TransactionDiff {
+                changes: vec![
+                    PendingChange {
+                        key: "settings.motd".to_string(),
+                        live_value: Some("old".into()),
+                        pending_value: "new".into(),
+                    },
+                    PendingChange {
+                        key: "settings.network.hostname".to_string(),
+                        live_value: None,
+                        pending_value: "example".into(),
+                    },
+                ],
+                services: vec!["motd".to_string()].into_iter().collect(),
+                configuration_files: vec!["motd".to_string()].into_iter().collect(),
+                rendered_files: None,
+            }
+        );
+
+        // Nothing is committed
+        assert_eq!(
+            ds.get_key(&motd, &Committed::Live).unwrap(),
+            Some("\"old\"".to_string())
+        );
+    }
+
+    #[test]
+    fn check_unchanged_since_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let hostname = Key::new(KeyType::Data, "settings.network.hostname").unwrap();
+        ds.set_key(&motd, "\"hi\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+        assert_eq!(get_generation(&ds).unwrap(), 1);
+
+        // Nothing has changed since the current generation
+        check_unchanged_since(&ds, 1, &hashset!(motd.clone(), hostname.clone())).unwrap();
+
+        // Another client changes motd
+        ds.set_key(&motd, "\"bye\"", &pending).unwrap();
+        commit_transaction(&mut ds, tx).unwrap();
+
+        // A client that read generation 1 can change other keys, but not motd
+        check_unchanged_since(&ds, 1, &hashset!(hostname.clone())).unwrap();
+        match check_unchanged_since(&ds, 1, &hashset!(motd.clone(), hostname)) {
+            Err(error::Error::GenerationConflict {
+                generation: 1,
+                current: 2,
+                keys,
+            }) => assert_eq!(keys, vec!["settings.motd".to_string()]),
+            other => panic!("expected conflict, got {:?}", other),
+        }
+
+        // We can't tell what changed since a generation that doesn't exist
+        match check_unchanged_since(&ds, 42, &hashset!(motd)) {
+            Err(error::Error::GenerationUnknown { .. }) => {}
+            other => panic!("expected unknown generation, got {:?}", other),
+        }
+    }
 }
@@ -55,15 +55,122 @@ pub enum Error {
     #[snafu(display("Tried to commit with no pending changes"))]
     CommitWithNoPending,
 
+    #[snafu(display("Settings to render with are not valid JSON for the model: {}", source))]
+    RenderSettingsJson { source: serde_json::Error },
+
+    #[snafu(display("Unable to build template registry: {}", source))]
+    TemplateRegistry { source: schnauzer::Error },
+
+    #[snafu(display("{}", source))]
+    TemplateRender { source: schnauzer::Error },
+
+    #[snafu(display("Invalid custom settings: {}", problems))]
+    InvalidCustomSettings { problems: String },
+
+    #[snafu(display("Invalid generation '{}', expected a number", input))]
+    InvalidGeneration { input: String },
+
+    #[snafu(display(
+        "Invalid If-Match header '{}', expected a generation number, optionally quoted, or '*'",
+        input
+    ))]
+    InvalidIfMatch { input: String },
+
     #[snafu(display("Unable to get OS release data: {}", source))]
     ReleaseData { source: bottlerocket_release::Error },
 
+    #[snafu(display("Unable to read settings history from '{}': {}", path.display(), source))]
+    HistoryRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to parse settings history from '{}': {}", path.display(), source))]
+    HistoryParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Unable to serialize settings history: {}", source))]
+    HistorySerialize { source: serde_json::Error },
+
+    #[snafu(display("Unable to write settings history to '{}': {}", path.display(), source))]
+    HistoryWrite { path: PathBuf, source: io::Error },
+
+    // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+    // Access policy errors
+    #[snafu(display("Unable to read API access policy from '{}': {}", path.display(), source))]
+    PolicyRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to parse API access policy from '{}': {}", path.display(), source))]
+    PolicyParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Invalid pattern '{}' in API access policy: {}", pattern, source))]
+    PolicyPattern {
+        pattern: String,
+        source: glob::PatternError,
+    },
+
+    #[snafu(display(
+        "Invalid route '{}' in API access policy, expected a path pattern, optionally preceded by a method",
+        route
+    ))]
+    PolicyRoute { route: String },
+
+    #[snafu(display(
+        "Invalid settings prefix '{}' in API access policy: {}",
+        prefix,
+        source
+    ))]
+    PolicyPrefix {
+        prefix: String,
+        source: datastore::Error,
+    },
+
+    #[snafu(display(
+        "Invalid settings prefix '{}' in API access policy, must start with 'settings'",
+        prefix
+    ))]
+    PolicyPrefixNotSettings { prefix: String },
+
+    #[snafu(display("Client is not permitted to use the API"))]
+    ClientNotPermitted,
+
+    #[snafu(display("Client is not permitted to {} {}", method, path))]
+    RouteNotPermitted { method: String, path: String },
+
+    #[snafu(display("Client is not permitted to change settings: {}", keys.join(", ")))]
+    SettingsNotPermitted { keys: Vec<String> },
+
     // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
     // Controller errors
     #[snafu(display("Found no '{}' in datastore", prefix))]
     MissingData { prefix: String },
 
+    #[snafu(display("Generation {} is not saved; it may be too old", generation))]
+    GenerationNotFound { generation: u64 },
+
+    #[snafu(display(
+        "Settings changed since generation {}, now generation {}: {}",
+        generation,
+        current,
+        keys.join(", ")
+    ))]
+    GenerationConflict {
+        generation: u64,
+        current: u64,
+        keys: Vec<String>,
+    },
+
+    #[snafu(display(
+        "Unable to check for changes since generation {}, now generation {}; it's not saved, and may be too old",
+        generation,
+        current
+    ))]
+    GenerationUnknown { generation: u64, current: u64 },
+
     #[snafu(display("Found no '{}' in datastore", requested))]
     ListKeys { requested: String },
 
@@ -125,6 +232,20 @@ pub enum Error {
     #[snafu(display("Unable to send input to config applier: {}", source))]
     ConfigApplierWrite { source: io::Error },
 
+    #[snafu(display("Config applier failed to render pending changes: {}", stderr))]
+    ConfigRender { stderr: String },
+
+    #[snafu(display("Config applier output was not in the expected format: {}", source))]
+    ConfigRenderOutput { source: serde_json::Error },
+
+    #[snafu(display(
+        "Unable to wait for config applier to render pending changes: {}",
+        source
+    ))]
+    ConfigRenderTask {
+        source: actix_web::error::BlockingError,
+    },
+
     #[snafu(display("Unable to start shutdown: {}", source))]
     Shutdown { source: io::Error },
 
@@ -164,6 +285,16 @@ pub enum Error {
     #[snafu(display("Failed to parse update status: {} ", source))]
     UpdateStatusParse { source: serde_json::Error },
 
+    #[snafu(display("Failed to get service status: {}", source))]
+    ServiceStatus {
+        source: thar_be_settings::error::Error,
+    },
+
+    #[snafu(display("Failed to get bootstrap container status: {}", source))]
+    BootstrapContainerStatus {
+        source: bootstrap_containers::status::Error,
+    },
+
     #[snafu(display(
         "Failed to parse update information from '{}': {} ",
         String::from_utf8_lossy(stdout),
@@ -0,0 +1,206 @@
+//! The events module lets clients watch for changes instead of polling for them.  Clients make a
+//! request to `/events` and we keep the response open, sending a server-sent event (SSE) each time
+//! a commit or rollback changes settings, and each time the update state changes.
+//!
+//! Each event has an event type, "settings" or "update-state", and its data is a JSON object
+//! describing the change.  We also send a comment regularly as a heartbeat, so we notice when a
+//! client goes away.
+
+use actix_web::rt::time::sleep;
+use actix_web::web::Bytes;
+use actix_web::{http::header, HttpResponse};
+use fs2::FileExt;
+use futures::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use std::convert::Infallible;
+use std::fs::File;
+use std::time::Duration;
+use thar_be_updates::status::{UpdateState, UPDATE_LOCKFILE};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// How many events we hold for each client before dropping the oldest.  Clients that fall this far
+/// behind are told they missed events, so they can reread whatever they're watching.
+const EVENT_BUFFER: usize = 256;
+/// How often we send a heartbeat comment to each client.  Writing to a disconnected client fails,
+/// which is how we find out it's gone.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// How often we check whether the update state has changed.  thar-be-updates runs as a separate
+/// process and records its state in a file, so we have to look.
+const UPDATE_STATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A change that clients can watch for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum Event {
+    Settings(SettingsEvent),
+    UpdateState(UpdateStateEvent),
+}
+
+/// Sent when a commit or rollback changes live settings.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SettingsEvent {
+    /// The generation of the live settings after the change.
+    pub(crate) generation: u64,
+    /// The committed transaction; None for a rollback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) transaction: Option<String>,
+    /// The saved generation whose settings were restored, for a rollback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) rollback_to: Option<u64>,
+    /// The names of the changed keys, sorted.
+    pub(crate) keys: Vec<String>,
+}
+
+/// Sent when thar-be-updates moves to a new update state.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct UpdateStateEvent {
+    /// The previous state, if we'd seen one since the server started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) previous: Option<UpdateState>,
+    pub(crate) state: UpdateState,
+}
+
+impl Event {
+    /// The SSE event type, which lets clients tell what kind of data to expect.
+    fn event_type(&self) -> &'static str {
+        match self {
+            Event::Settings(_) => "settings",
+            Event::UpdateState(_) => "update-state",
+        }
+    }
+
+    /// Formats the event as a server-sent event.  The data is a single line of JSON.
+    fn to_sse(&self) -> Bytes {
+        // Our event types only contain strings and numbers, so serialization can't fail.
+        let data = serde_json::to_string(self).unwrap_or_default();
+        Bytes::from(format!("event: {}\ndata: {}\n\n", self.event_type(), data))
+    }
+}
+
+/// Sends events to each connected client.
+pub(crate) struct Events {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Events {
+    pub(crate) fn new() -> Self {
+        let (sender, _receiver) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+
+    /// Sends the event to all connected clients.
+    pub(crate) fn send(&self, event: Event) {
+        trace!(
+            "Sending event to {} clients: {:?}",
+            self.sender.receiver_count(),
+            event
+        );
+        // This only fails if there are no clients, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Returns a streaming response that sends a client each event from now on.
+    pub(crate) fn subscribe(&self) -> HttpResponse {
+        let receiver = self.sender.subscribe();
+
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            let bytes = match receiver.recv().await {
+                Ok(event) => event.to_sse(),
+                // Let the client know it missed events, rather than silently skipping them.
+                Err(RecvError::Lagged(count)) => {
+                    Bytes::from(format!("event: lagged\ndata: {{\"missed\":{}}}\n\n", count))
+                }
+                Err(RecvError::Closed) => return None,
+            };
+            Some((bytes, receiver))
+        });
+        let heartbeats = stream::unfold((), |()| async {
+            sleep(HEARTBEAT_INTERVAL).await;
+            Some((Bytes::from_static(b": heartbeat\n\n"), ()))
+        });
+
+        // Start with a comment so the client knows it's connected before the first event.
+        let connected = stream::once(async { Bytes::from_static(b": connected\n\n") });
+        let body = connected.chain(stream::select(events, heartbeats));
+
+        // actix needs the stream to be Unpin, and our async blocks aren't.
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(Box::pin(ok_stream(body)))
+    }
+
+    /// Watches for changes to the update state recorded by thar-be-updates, sending an event for
+    /// each one.  This runs until the server stops.
+    pub(crate) async fn watch_update_state(&self) {
+        let mut previous = None;
+        loop {
+            // If the lock is held, thar-be-updates is busy; we'll catch the new state next time.
+            if let Some(state) = read_update_state() {
+                if previous.as_ref() != Some(&state) {
+                    debug!("Update state changed to {:?}", state);
+                    // The first state we see isn't a change, just the state at startup.
+                    if previous.is_some() {
+                        self.send(Event::UpdateState(UpdateStateEvent {
+                            previous: previous.clone(),
+                            state: state.clone(),
+                        }));
+                    }
+                    previous = Some(state);
+                }
+            }
+            sleep(UPDATE_STATE_INTERVAL).await;
+        }
+    }
+}
+
+/// Returns the update state recorded by thar-be-updates, or None if it can't be read right now.
+fn read_update_state() -> Option<UpdateState> {
+    let lockfile = File::create(UPDATE_LOCKFILE).ok()?;
+    FileExt::try_lock_shared(&lockfile).ok()?;
+    let status = thar_be_updates::status::get_update_status(&lockfile).ok()?;
+    Some(status.update_state().clone())
+}
+
+/// actix wants streaming bodies to yield Results; ours can't fail.
+fn ok_stream<S>(body: S) -> impl Stream<Item = Result<Bytes, Infallible>>
+where
+    S: Stream<Item = Bytes>,
+{
+    body.map(Ok)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn settings_event_format() {
+        let event = Event::Settings(SettingsEvent {
+            generation: 3,
+            transaction: Some("default".to_string()),
+            rollback_to: None,
+            keys: vec!["settings.motd".to_string()],
+        });
+        assert_eq!(
+            event.to_sse(),
+            Bytes::from_static(
+                b"event: settings\ndata: {\"generation\":3,\"transaction\":\"default\",\"keys\":[\"settings.motd\"]}\n\n"
+            )
+        );
+    }
+
+    #[test]
+    fn update_state_event_format() {
+        let event = Event::UpdateState(UpdateStateEvent {
+            previous: Some(UpdateState::Staged),
+            state: UpdateState::Ready,
+        });
+        assert_eq!(
+            event.to_sse(),
+            Bytes::from_static(
+                b"event: update-state\ndata: {\"previous\":\"Staged\",\"state\":\"Ready\"}\n\n"
+            )
+        );
+    }
+}
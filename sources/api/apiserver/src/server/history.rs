@@ -0,0 +1,215 @@
+//! The history module keeps an audit log of committed settings changes.  Each commit or rollback
+//! is recorded with the name of its transaction or the generation it rolled back to, the time, the
+//! credentials of the client that requested it, and the old and new value of each changed key.
+//!
+//! The log is stored as a JSON array in a single file outside the data store, so it survives data
+//! store migrations.  Only the most recent entries are kept, to bound its size.
+
+use crate::server::error::{self, Result};
+use crate::server::peer::PeerCredentials;
+use chrono::{DateTime, Utc};
+use datastore::Key;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::ResultExt;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+/// The maximum number of commits kept in the history; older entries are dropped first.
+pub(crate) const MAX_HISTORY_ENTRIES: usize = 1000;
+
+/// A record of a single committed transaction or rollback.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    /// The committed transaction; None for a rollback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) transaction: Option<String>,
+    /// The saved generation whose settings were restored, for a rollback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rollback_to: Option<u64>,
+    /// The generation of the live settings after the change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) generation: Option<u64>,
+    pub(crate) timestamp: DateTime<Utc>,
+    /// The client that requested the commit, if its credentials could be determined.
+    pub(crate) client: Option<PeerCredentials>,
+    pub(crate) changes: Vec<SettingChange>,
+}
+
+/// A record of the change to a single key in a committed transaction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SettingChange {
+    pub(crate) key: String,
+    /// The live value before the commit; None if the key was newly set, or if redacted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) old_value: Option<Value>,
+    /// The live value after the commit; None if the key was removed by a rollback, or if redacted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) new_value: Option<Value>,
+    /// True if the key matches one of the sensitive settings patterns, in which case its values
+    /// aren't recorded.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) redacted: bool,
+}
+
+impl SettingChange {
+    /// Creates a record of the change to `key` from the given serialized datastore values,
+    /// leaving out the values if the key is sensitive.
+    pub(crate) fn new(key: &Key, old_value: Option<&str>, new_value: Option<&str>) -> Self {
+        let name = key.name().to_string();
+        if is_sensitive(&name) {
+            return Self {
+                key: name,
+                old_value: None,
+                new_value: None,
+                redacted: true,
+            };
+        }
+
+        Self {
+            key: name,
+            old_value: old_value.map(parse_value),
+            new_value: new_value.map(parse_value),
+            redacted: false,
+        }
+    }
+}
+
+/// Datastore values are serialized scalars; we store them as JSON values so the history reads
+/// naturally.  If a value somehow isn't valid JSON, we keep it as a string rather than losing it.
+pub(crate) fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// Returns true if the given key name matches any of the patterns for sensitive settings.
+fn is_sensitive(key_name: &str) -> bool {
+    constants::SENSITIVE_SETTINGS_PATTERNS
+        .iter()
+        .any(|pattern| {
+            Pattern::new(pattern)
+                .map(|pattern| pattern.matches(key_name))
+                // The patterns are constant, so this should never happen; if it does, err on the side
+                // of hiding the value.
+                .unwrap_or(true)
+        })
+}
+
+/// Reads the recorded history, oldest entry first.  Returns an empty list if nothing has been
+/// recorded yet.
+pub(crate) fn read_history<P: AsRef<Path>>(path: P) -> Result<Vec<HistoryEntry>> {
+    let path = path.as_ref();
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(error::HistoryRead { path }),
+    };
+    serde_json::from_str(&data).context(error::HistoryParse { path })
+}
+
+/// Adds the given entry to the recorded history, dropping the oldest entries if we're over the
+/// maximum.  The history file is replaced atomically, and synced before and after the swap, so
+/// neither readers nor a crash can leave a partial history behind.
+pub(crate) fn record<P: AsRef<Path>>(path: P, entry: HistoryEntry) -> Result<()> {
+    let path = path.as_ref();
+    let mut history = read_history(path)?;
+    append(&mut history, entry, MAX_HISTORY_ENTRIES);
+
+    let data = serde_json::to_string(&history).context(error::HistorySerialize)?;
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp_file = File::create(&tmp_path).context(error::HistoryWrite { path: &tmp_path })?;
+    tmp_file
+        .write_all(data.as_bytes())
+        .and_then(|()| tmp_file.sync_all())
+        .context(error::HistoryWrite { path: &tmp_path })?;
+    fs::rename(&tmp_path, path).context(error::HistoryWrite { path })?;
+
+    // Sync the directory so the rename itself is durable.
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .context(error::HistoryWrite { path: dir })
+}
+
+/// Adds the entry to the end of the history, dropping entries from the start so there are no
+/// more than `max_entries`.
+fn append(history: &mut Vec<HistoryEntry>, entry: HistoryEntry, max_entries: usize) {
+    history.push(entry);
+    if history.len() > max_entries {
+        let excess = history.len() - max_entries;
+        history.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use datastore::KeyType;
+
+    fn entry(transaction: &str) -> HistoryEntry {
+        let key = Key::new(KeyType::Data, "settings.motd").unwrap();
+        HistoryEntry {
+            transaction: Some(transaction.to_string()),
+            rollback_to: None,
+            generation: Some(1),
+            timestamp: Utc::now(),
+            client: Some(PeerCredentials {
+                pid: Some(42),
+                uid: 0,
+                gid: 0,
+                label: None,
+            }),
+            changes: vec![SettingChange::new(&key, None, Some("\"hi\""))],
+        }
+    }
+
+    #[test]
+    fn sensitive_values_redacted() {
+        let key = Key::new(KeyType::Data, "settings.kubernetes.bootstrap-token").unwrap();
+        let change = SettingChange::new(&key, Some("\"old\""), Some("\"new\""));
+        assert!(change.redacted);
+        assert_eq!(change.old_value, None);
+        assert_eq!(change.new_value, None);
+
+        let key = Key::new(KeyType::Data, "settings.host-containers.admin.user-data").unwrap();
+        assert!(SettingChange::new(&key, None, Some("\"secret\"")).redacted);
+    }
+
+    #[test]
+    fn values_parsed() {
+        let key = Key::new(KeyType::Data, "settings.kubernetes.max-pods").unwrap();
+        let change = SettingChange::new(&key, Some("29"), Some("110"));
+        assert!(!change.redacted);
+        assert_eq!(change.old_value, Some(29.into()));
+        assert_eq!(change.new_value, Some(110.into()));
+    }
+
+    #[test]
+    fn record_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history");
+        assert_eq!(read_history(&path).unwrap(), Vec::new());
+
+        record(&path, entry("first")).unwrap();
+        record(&path, entry("second")).unwrap();
+        let history = read_history(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].transaction.as_deref(), Some("first"));
+        assert_eq!(history[1].transaction.as_deref(), Some("second"));
+    }
+
+    #[test]
+    fn oldest_dropped() {
+        let mut history = Vec::new();
+        for i in 0..5 {
+            append(&mut history, entry(&i.to_string()), 3);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].transaction.as_deref(), Some("2"));
+        assert_eq!(history[2].transaction.as_deref(), Some("4"));
+    }
+}
@@ -3,26 +3,41 @@
 
 mod controller;
 mod error;
+mod events;
 mod exec;
+mod history;
+mod peer;
+mod policy;
 
 pub use error::Error;
 
 use actix_web::{
-    body::Body, error::ResponseError, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+    body::Body,
+    dev::{Service, ServiceRequest},
+    error::ResponseError,
+    http::header,
+    web, App, HttpMessage, HttpRequest, HttpResponse, Responder,
 };
 use bottlerocket_release::BottlerocketRelease;
+use chrono::Utc;
 use datastore::{Committed, FilesystemDataStore, Key, Value};
 use error::Result;
+use events::{Event, SettingsEvent};
 use fs2::FileExt;
+use history::HistoryEntry;
 use http::StatusCode;
 use log::info;
 use model::{ConfigurationFiles, Model, Services, Settings};
 use nix::unistd::{chown, Gid};
+use policy::Policy;
+use schemars::schema::RootSchema;
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::fs::{set_permissions, File, Permissions};
+use std::fs::{self, set_permissions, File, Permissions};
+use std::io::ErrorKind;
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
 use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -54,38 +69,81 @@ fn notify_unix_socket_ready() -> Result<()> {
 
 /// This is the primary interface of the module.  It defines the server and application that actix
 /// spawns for requests.  It creates a shared datastore handle that can be used by handler methods
-/// to interface with the controller.
-pub async fn serve<P1, P2, P3>(
+/// to interface with the controller.  If a policy path is given, each request is checked against
+/// the access policy in that file; otherwise, any client that can reach the socket can do anything.
+pub async fn serve<P1, P2, P3, P4>(
     socket_path: P1,
     datastore_path: P2,
     threads: usize,
     socket_gid: Option<Gid>,
     exec_socket_path: P3,
+    history_path: P4,
+    policy_path: Option<PathBuf>,
 ) -> Result<()>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
     P3: Into<PathBuf>,
+    P4: Into<PathBuf>,
 {
+    // Fail to start if the policy is invalid, rather than starting with more or less access than
+    // intended.
+    let policy = match policy_path {
+        Some(path) => {
+            info!("Using API access policy from {}", path.display());
+            Some(Policy::load(&path)?)
+        }
+        None => None,
+    };
+
     // SharedData gives us a convenient way to make data available to handler methods when it
     // doesn't come from the request itself.  It's easier than the ownership tricks required to
     // pass parameters to the handler methods.
     let shared_data = web::Data::new(SharedData {
         ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
         exec_socket_path: exec_socket_path.into(),
+        history_path: history_path.into(),
+        events: events::Events::new(),
+        policy,
     });
 
-    let http_server = HttpServer::new(move || {
+    // Update state changes are made by thar-be-updates in another process, so we watch for them
+    // in the background to send events.
+    let watch_data = shared_data.clone();
+    actix_web::rt::spawn(async move { watch_data.events.watch_update_state().await });
+
+    // We bind the socket ourselves, and serve it through the peer module, so we can identify the
+    // client of each connection.
+    match fs::remove_file(socket_path.as_ref()) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).context(error::BindSocket {
+                path: socket_path.as_ref(),
+            })
+        }
+    }
+    let listener = UnixListener::bind(socket_path.as_ref()).context(error::BindSocket {
+        path: socket_path.as_ref(),
+    })?;
+
+    let http_server = peer::serve_uds(listener, threads, move || {
         App::new()
             // This makes the data store available to API methods merely by having a Data
             // parameter.
             .app_data(shared_data.clone())
+            // Check that the client is allowed to call the route before handling any request.
+            .wrap_fn(|req, srv| {
+                let response = check_route(&req).map(|()| srv.call(req));
+                async move { response?.await }
+            })
             // Retrieve the full API model; not all data is writable, so we only support GET.
             .route("/", web::get().to(get_model))
             .service(
                 web::scope("/settings")
                     .route("", web::get().to(get_settings))
-                    .route("", web::patch().to(patch_settings)),
+                    .route("", web::patch().to(patch_settings))
+                    .route("/history", web::get().to(get_settings_history)),
             )
             .service(
                 // Transaction support
@@ -93,24 +151,41 @@ where
                     .route("/list", web::get().to(get_transaction_list))
                     .route("", web::get().to(get_transaction))
                     .route("", web::delete().to(delete_transaction))
+                    .route("/diff", web::get().to(get_transaction_diff))
                     .route("/commit", web::post().to(commit_transaction))
                     .route("/apply", web::post().to(apply_changes))
                     .route(
                         "/commit_and_apply",
                         web::post().to(commit_transaction_and_apply),
-                    ),
+                    )
+                    .route("/generations", web::get().to(get_generations))
+                    .route("/rollback", web::post().to(rollback)),
             )
             .service(web::scope("/os").route("", web::get().to(get_os_info)))
+            .service(web::resource("/schema").route(web::get().to(get_schema)))
             .service(
                 web::scope("/metadata")
                     .route("/affected-services", web::get().to(get_affected_services))
                     .route("/setting-generators", web::get().to(get_setting_generators))
+                    .route(
+                        "/setting-generator-policies",
+                        web::get().to(get_setting_generator_policies),
+                    )
                     .route("/templates", web::get().to(get_templates)),
             )
-            .service(web::scope("/services").route("", web::get().to(get_services)))
+            .service(
+                web::scope("/services")
+                    .route("", web::get().to(get_services))
+                    .route("/status", web::get().to(get_services_status)),
+            )
+            .service(
+                web::scope("/bootstrap-containers")
+                    .route("/status", web::get().to(get_bootstrap_containers_status)),
+            )
             .service(
                 web::scope("/configuration-files")
-                    .route("", web::get().to(get_configuration_files)),
+                    .route("", web::get().to(get_configuration_files))
+                    .route("/render", web::post().to(render_configuration_file)),
             )
             .service(
                 web::scope("/actions")
@@ -122,9 +197,8 @@ where
             )
             .service(web::scope("/updates").route("/status", web::get().to(get_update_status)))
             .service(web::resource("/exec").route(web::get().to(exec::ws_exec)))
+            .service(web::resource("/events").route(web::get().to(get_events)))
     })
-    .workers(threads)
-    .bind_uds(socket_path.as_ref())
     .context(error::BindSocket {
         path: socket_path.as_ref(),
     })?;
@@ -142,7 +216,7 @@ where
     // Notify system manager the UNIX socket has been initialized, so other service units can proceed
     notify_unix_socket_ready()?;
 
-    http_server.run().await.context(error::ServerStart)
+    http_server.await.context(error::ServerStart)
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -170,12 +244,14 @@ async fn get_model(data: web::Data<SharedData>) -> Result<ModelResponse> {
 // actix-web doesn't support Query for enums, so we use a HashMap and check for the expected keys
 // ourselves.
 /// Return the live settings from the data store; if 'keys' or 'prefix' are specified in query
-/// parameters, return the subset of matching settings.
+/// parameters, return the subset of matching settings.  The generation of the live settings is
+/// returned in the ETag header, so clients can give it back in If-Match when making changes.
 async fn get_settings(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
-) -> Result<SettingsResponse> {
+) -> Result<LiveSettingsResponse> {
     let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let generation = controller::get_generation(&*datastore)?;
 
     let settings = if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
@@ -190,21 +266,39 @@ async fn get_settings(
         controller::get_settings(&*datastore, &Committed::Live)
     }?;
 
-    Ok(SettingsResponse(settings))
+    Ok(LiveSettingsResponse {
+        settings,
+        generation,
+    })
 }
 
-/// Apply the requested settings to the pending data store
+/// Apply the requested settings to the pending data store.  If the If-Match header gives a
+/// generation, the request fails unless the requested settings are unchanged in live settings
+/// since that generation.
 async fn patch_settings(
+    req: HttpRequest,
     settings: web::Json<Settings>,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<HttpResponse> {
     let transaction = transaction_name(&query);
+    let keys = controller::settings_keys(&settings)?;
+    check_settings(&req, &data, &keys)?;
+
     let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+    if let Some(generation) = if_match_generation(&req)? {
+        controller::check_unchanged_since(&*datastore, generation, &keys)?;
+    }
     controller::set_settings(&mut *datastore, &settings, transaction)?;
     Ok(HttpResponse::NoContent().finish()) // 204
 }
 
+/// Return the recorded history of committed settings changes, oldest first.
+async fn get_settings_history(data: web::Data<SharedData>) -> Result<HistoryResponse> {
+    let history = history::read_history(&data.history_path)?;
+    Ok(HistoryResponse(history))
+}
+
 async fn get_transaction_list(data: web::Data<SharedData>) -> Result<TransactionListResponse> {
     let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
     let data = controller::list_transactions(&*datastore)?;
@@ -233,21 +327,48 @@ async fn delete_transaction(
     Ok(ChangedKeysResponse(deleted))
 }
 
-/// Save settings changes from the given transaction, or the "default" transaction if unspecified,
-/// to the live data store.  Returns the list of changed keys.
-async fn commit_transaction(
+/// Describe what committing the given transaction, or the "default" transaction if unspecified,
+/// would change: the pending keys with their live and pending values, and the affected services
+/// and configuration files.  Unless the 'render' query parameter is "false", the affected
+/// configuration files are rendered with the pending settings, and the changes to each file are
+/// included.  Nothing is committed, written, or restarted.
+async fn get_transaction_diff(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
-) -> Result<ChangedKeysResponse> {
-    let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+) -> Result<TransactionDiffResponse> {
+    let transaction = transaction_name(&query).to_string();
+    let render = query.get("render").map(|r| r.as_str()) != Some("false");
 
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
+    // The config applier reads the pending settings through the API, so we can't hold the data
+    // store lock while it runs.
+    let mut diff = {
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        controller::transaction_diff(&*datastore, &transaction)?
+    };
 
-    if changes.is_empty() {
-        return error::CommitWithNoPending.fail();
+    if render && !diff.configuration_files.is_empty() {
+        let keys: Vec<String> = diff.changes.iter().map(|c| c.key.clone()).collect();
+        // Run it on the blocking thread pool so the server can answer the applier's requests.
+        let rendered = web::block(move || controller::render_pending(&transaction, &keys))
+            .await
+            .context(error::ConfigRenderTask)??;
+        diff.rendered_files = Some(rendered);
     }
 
+    Ok(TransactionDiffResponse(diff))
+}
+
+/// Save settings changes from the given transaction, or the "default" transaction if unspecified,
+/// to the live data store.  Returns the list of changed keys.  If the If-Match header gives a
+/// generation, the commit fails unless the pending keys are unchanged in live settings since that
+/// generation.
+async fn commit_transaction(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let transaction = transaction_name(&query);
+    let changes = commit_and_record(&req, &data, transaction)?;
     Ok(ChangedKeysResponse(changes))
 }
 
@@ -268,17 +389,12 @@ async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<Htt
 /// perform both a commit and an apply.  Commits the given transaction, or the "default"
 /// transaction if unspecified.
 async fn commit_transaction_and_apply(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedData>,
 ) -> Result<ChangedKeysResponse> {
     let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
-
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
-
-    if changes.is_empty() {
-        return error::CommitWithNoPending.fail();
-    }
+    let changes = commit_and_record(&req, &data, transaction)?;
 
     let key_names = changes.iter().map(|k| k.name()).collect();
     controller::apply_changes(Some(&key_names))?;
@@ -286,10 +402,88 @@ async fn commit_transaction_and_apply(
     Ok(ChangedKeysResponse(changes))
 }
 
+/// Get the generation of the live settings, and the prior generations that can be restored with a
+/// rollback.
+async fn get_generations(data: web::Data<SharedData>) -> Result<GenerationsResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let generations = controller::list_generations(&*datastore)?;
+    Ok(GenerationsResponse(generations))
+}
+
+/// Restores live settings to those of the saved generation given in the 'generation' query
+/// parameter, and applies the changes to the system.  Returns the list of changed keys, which is
+/// empty if the settings already matched.
+async fn rollback(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ChangedKeysResponse> {
+    let generation_str = query.get("generation").context(error::MissingInput {
+        input: "generation",
+    })?;
+    let generation = generation_str
+        .parse()
+        .ok()
+        .context(error::InvalidGeneration {
+            input: generation_str,
+        })?;
+
+    let changes = {
+        let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+
+        // A rollback can change any key, so we check the keys it would change against the
+        // client's policy before making it.
+        let keys = controller::rollback_keys(&*datastore, generation)?;
+        check_settings(&req, &data, &keys)?;
+
+        let new_generation = controller::get_generation(&*datastore)? + 1;
+        let (changes, setting_changes) = controller::rollback(&mut *datastore, generation)?;
+
+        if !changes.is_empty() {
+            let entry = HistoryEntry {
+                transaction: None,
+                rollback_to: Some(generation),
+                generation: Some(new_generation),
+                timestamp: Utc::now(),
+                client: peer::peer_credentials(&req),
+                changes: setting_changes,
+            };
+            record_history(&data, entry);
+
+            data.events.send(Event::Settings(SettingsEvent {
+                generation: new_generation,
+                transaction: None,
+                rollback_to: Some(generation),
+                keys: sorted_names(&changes),
+            }));
+        }
+        changes
+    };
+
+    if !changes.is_empty() {
+        let key_names = changes.iter().map(|k| k.name()).collect();
+        controller::apply_changes(Some(&key_names))?;
+    }
+
+    Ok(ChangedKeysResponse(changes))
+}
+
+/// Streams events to the client as server-sent events: one each time a commit or rollback changes
+/// settings, and one each time the update state changes.  The response stays open until the client
+/// disconnects.
+async fn get_events(data: web::Data<SharedData>) -> HttpResponse {
+    data.events.subscribe()
+}
+
 async fn get_os_info() -> Result<BottlerocketReleaseResponse> {
     Ok(BottlerocketReleaseResponse(controller::get_os_info()?))
 }
 
+/// Returns the JSON Schema describing the settings of this variant.
+async fn get_schema() -> SchemaResponse {
+    SchemaResponse(model::schema::settings_schema())
+}
+
 /// Get the affected services for a list of data keys
 async fn get_affected_services(
     query: web::Query<HashMap<String, String>>,
@@ -298,8 +492,7 @@ async fn get_affected_services(
     if let Some(keys_str) = query.get("keys") {
         let data_keys = comma_separated("keys", keys_str)?;
         let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-        let resp =
-            controller::get_metadata_for_data_keys(&*datastore, "affected-services", &data_keys)?;
+        let resp = controller::get_affected_services(&*datastore, &data_keys)?;
 
         Ok(MetadataResponse(resp))
     } else {
@@ -314,6 +507,13 @@ async fn get_setting_generators(data: web::Data<SharedData>) -> Result<MetadataR
     Ok(MetadataResponse(resp))
 }
 
+/// Get the dependencies, timeouts, and retries of setting generators
+async fn get_setting_generator_policies(data: web::Data<SharedData>) -> Result<MetadataResponse> {
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let resp = controller::get_setting_generator_policies(&*datastore)?;
+    Ok(MetadataResponse(resp))
+}
+
 /// Get the template metadata for a list of data keys
 async fn get_templates(
     query: web::Query<HashMap<String, String>>,
@@ -347,6 +547,37 @@ async fn get_services(
     Ok(ServicesResponse(resp))
 }
 
+/// Get the runtime status of all services, or if 'names' is specified, services with those names
+async fn get_services_status(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedData>,
+) -> Result<ServicesStatusResponse> {
+    let services = {
+        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+        if let Some(names_str) = query.get("names") {
+            let names = comma_separated("names", names_str)?;
+            controller::get_services_names(&*datastore, &names, &Committed::Live)
+        } else {
+            controller::get_services(&*datastore)
+        }?
+    };
+
+    let applied = thar_be_settings::status::read_service_status(
+        thar_be_settings::status::SERVICE_STATUS_FILE,
+    )
+    .context(error::ServiceStatus)?;
+    let status = controller::get_services_status(services, applied, controller::systemd_unit_state);
+    Ok(ServicesStatusResponse(status))
+}
+
+/// Get the outcome of running each bootstrap container this boot
+async fn get_bootstrap_containers_status() -> Result<BootstrapContainersStatusResponse> {
+    let status =
+        bootstrap_containers::status::read_status(bootstrap_containers::status::STATUS_DIR)
+            .context(error::BootstrapContainerStatus)?;
+    Ok(BootstrapContainersStatusResponse(status))
+}
+
 /// Get all configuration files, or if 'names' is specified, configuration files with those names
 async fn get_configuration_files(
     query: web::Query<HashMap<String, String>>,
@@ -364,13 +595,52 @@ async fn get_configuration_files(
     Ok(ConfigurationFilesResponse(resp))
 }
 
+/// Render the template of the configuration file given by the 'name' query parameter, and return
+/// the output without writing it.  The template is rendered with the live settings, with the
+/// pending settings of the transaction given by the 'tx' query parameter on top, if given, and
+/// then any settings given as JSON in the request body.
+async fn render_configuration_file(
+    query: web::Query<HashMap<String, String>>,
+    body: web::Bytes,
+    data: web::Data<SharedData>,
+) -> Result<RenderedConfigurationFileResponse> {
+    let name = query
+        .get("name")
+        .context(error::MissingInput { input: "name" })?;
+    ensure!(!name.is_empty(), error::EmptyInput { input: "name" });
+    let transaction = query.get("tx").map(|tx| tx.as_str());
+    let settings: Option<Settings> = if body.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_slice(&body).context(error::RenderSettingsJson)?)
+    };
+
+    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    let rendered =
+        controller::render_configuration_file(&*datastore, name, transaction, settings.as_ref())?;
+    Ok(RenderedConfigurationFileResponse(rendered))
+}
+
 /// Get the update status from 'thar-be-updates'
 async fn get_update_status() -> Result<UpdateStatusResponse> {
     let lockfile = File::create(UPDATE_LOCKFILE).context(error::UpdateLockOpen)?;
-    lockfile.try_lock_shared().context(error::UpdateShareLock)?;
+    if let Err(e) = FileExt::try_lock_shared(&lockfile) {
+        // An update command is running.  We still say the status is locked, so callers know to
+        // wait for the command, but we can show its progress.
+        return match thar_be_updates::status::peek_update_status() {
+            Ok(update_status) => Ok(UpdateStatusResponse {
+                status: update_status,
+                locked: true,
+            }),
+            Err(_) => Err(e).context(error::UpdateShareLock),
+        };
+    }
     let result = thar_be_updates::status::get_update_status(&lockfile);
     match result {
-        Ok(update_status) => Ok(UpdateStatusResponse(update_status)),
+        Ok(update_status) => Ok(UpdateStatusResponse {
+            status: update_status,
+            locked: false,
+        }),
         Err(e) => match e {
             thar_be_updates::error::Error::NoStatusFile { .. } => {
                 error::UninitializedUpdateStatus.fail()
@@ -380,9 +650,15 @@ async fn get_update_status() -> Result<UpdateStatusResponse> {
     }
 }
 
-/// Refreshes the list of updates and checks if an update is available matching the configured version lock
-async fn refresh_updates() -> Result<HttpResponse> {
-    controller::dispatch_update_command(&["refresh"])
+/// Refreshes the list of updates and checks if an update is available matching the configured version lock.
+/// If a repository stored on the host is given, it's used instead of the configured one.
+async fn refresh_updates(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
+    match query.get("repository") {
+        Some(repository) => {
+            controller::dispatch_update_command(&["refresh", "--repository", repository])
+        }
+        None => controller::dispatch_update_command(&["refresh"]),
+    }
 }
 
 /// Prepares update by downloading the images to the staging partition set
@@ -432,6 +708,123 @@ fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet
     Ok(input.split(',').collect())
 }
 
+/// Makes sure the access policy, if any, allows the client to make the request, based on its
+/// method and path.
+fn check_route(req: &ServiceRequest) -> Result<()> {
+    let policy = match req
+        .app_data::<web::Data<SharedData>>()
+        .and_then(|data| data.policy.as_ref())
+    {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+    let client = req.extensions().get::<peer::PeerCredentials>().cloned();
+    policy.check_route(client.as_ref(), req.method(), req.path())
+}
+
+/// Makes sure the access policy, if any, allows the client to change the given settings keys.
+fn check_settings(
+    req: &HttpRequest,
+    data: &web::Data<SharedData>,
+    keys: &HashSet<Key>,
+) -> Result<()> {
+    match &data.policy {
+        Some(policy) => policy.check_settings(
+            peer::peer_credentials(req).as_ref(),
+            req.method(),
+            req.path(),
+            keys,
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Returns the generation given in the request's If-Match header, if any.  We accept the ETag
+/// format we return, a quoted generation number, or a bare number; "*" matches any generation, so
+/// it's the same as not giving the header.
+fn if_match_generation(req: &HttpRequest) -> Result<Option<u64>> {
+    let value = match req.headers().get(header::IF_MATCH) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let input = value.to_str().ok().context(error::InvalidIfMatch {
+        input: String::from_utf8_lossy(value.as_bytes()),
+    })?;
+
+    let tag = input.trim();
+    if tag == "*" {
+        return Ok(None);
+    }
+    let tag = tag.strip_prefix('"').unwrap_or(tag);
+    let tag = tag.strip_suffix('"').unwrap_or(tag);
+    let generation = tag.parse().ok().context(error::InvalidIfMatch { input })?;
+    Ok(Some(generation))
+}
+
+/// Commits the given transaction and records the changes in the settings history, along with the
+/// credentials of the client that made the request.  Returns the changed keys.  If the request's
+/// If-Match header gives a generation, the commit fails unless the pending keys are unchanged
+/// since that generation.
+fn commit_and_record(
+    req: &HttpRequest,
+    data: &web::Data<SharedData>,
+    transaction: &str,
+) -> Result<HashSet<Key>> {
+    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
+
+    // The transaction may have been made by another client, so we check the pending keys against
+    // the committing client's policy too.
+    let keys = controller::pending_keys(&*datastore, transaction)?;
+    check_settings(req, data, &keys)?;
+
+    if let Some(generation) = if_match_generation(req)? {
+        controller::check_unchanged_since(&*datastore, generation, &keys)?;
+    }
+
+    let setting_changes = controller::pending_changes(&*datastore, transaction)?;
+    let new_generation = controller::get_generation(&*datastore)? + 1;
+    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
+
+    if changes.is_empty() {
+        return error::CommitWithNoPending.fail();
+    }
+
+    let entry = HistoryEntry {
+        transaction: Some(transaction.to_string()),
+        rollback_to: None,
+        generation: Some(new_generation),
+        timestamp: Utc::now(),
+        client: peer::peer_credentials(req),
+        changes: setting_changes,
+    };
+    record_history(data, entry);
+
+    data.events.send(Event::Settings(SettingsEvent {
+        generation: new_generation,
+        transaction: Some(transaction.to_string()),
+        rollback_to: None,
+        keys: sorted_names(&changes),
+    }));
+
+    Ok(changes)
+}
+
+/// Adds the given entry to the settings history.  This is called after the change has already
+/// happened, so failing the request here would mislead the client into thinking its changes
+/// weren't made; we make the failure visible in the log instead.
+fn record_history(data: &web::Data<SharedData>, entry: HistoryEntry) {
+    if let Err(e) = history::record(&data.history_path, entry) {
+        error!("Failed to record settings history: {}", e);
+    }
+}
+
+/// Returns the names of the given keys, sorted, for display to clients.
+fn sorted_names(keys: &HashSet<Key>) -> Vec<String> {
+    let mut names: Vec<String> = keys.iter().map(|k| k.name().to_string()).collect();
+    names.sort();
+    names
+}
+
 fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
     if let Some(name_str) = query.get("tx") {
         name_str
@@ -449,10 +842,20 @@ impl ResponseError for error::Error {
             // 400 Bad Request
             MissingInput { .. } => StatusCode::BAD_REQUEST,
             EmptyInput { .. } => StatusCode::BAD_REQUEST,
+            InvalidGeneration { .. } => StatusCode::BAD_REQUEST,
+            InvalidIfMatch { .. } => StatusCode::BAD_REQUEST,
+            RenderSettingsJson { .. } => StatusCode::BAD_REQUEST,
             NewKey { .. } => StatusCode::BAD_REQUEST,
+            InvalidCustomSettings { .. } => StatusCode::BAD_REQUEST,
+
+            // 403 Forbidden
+            ClientNotPermitted => StatusCode::FORBIDDEN,
+            RouteNotPermitted { .. } => StatusCode::FORBIDDEN,
+            SettingsNotPermitted { .. } => StatusCode::FORBIDDEN,
 
             // 404 Not Found
             MissingData { .. } => StatusCode::NOT_FOUND,
+            GenerationNotFound { .. } => StatusCode::NOT_FOUND,
             ListKeys { .. } => StatusCode::NOT_FOUND,
             UpdateDoesNotExist { .. } => StatusCode::NOT_FOUND,
             NoStagedImage { .. } => StatusCode::NOT_FOUND,
@@ -460,6 +863,7 @@ impl ResponseError for error::Error {
 
             // 422 Unprocessable Entity
             CommitWithNoPending => StatusCode::UNPROCESSABLE_ENTITY,
+            TemplateRender { .. } => StatusCode::UNPROCESSABLE_ENTITY,
 
             // 423 Locked
             UpdateShareLock { .. } => StatusCode::LOCKED,
@@ -467,6 +871,8 @@ impl ResponseError for error::Error {
 
             // 409 Conflict
             DisallowCommand { .. } => StatusCode::CONFLICT,
+            GenerationConflict { .. } => StatusCode::CONFLICT,
+            GenerationUnknown { .. } => StatusCode::CONFLICT,
 
             // 500 Internal Server Error
             DataStoreLock => StatusCode::INTERNAL_SERVER_ERROR,
@@ -484,16 +890,32 @@ impl ResponseError for error::Error {
             ConfigApplierStdin {} => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWait { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ConfigRender { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ConfigRenderOutput { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ConfigRenderTask { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            TemplateRegistry { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotify { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SystemdNotifyStatus {} => StatusCode::INTERNAL_SERVER_ERROR,
             SetPermissions { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             SetGroup { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ReleaseData { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistoryRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistoryParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistorySerialize { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistoryWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyPattern { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyRoute { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyPrefix { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            PolicyPrefixNotSettings { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Shutdown { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Reboot { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateDispatcher { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateStatusParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceStatus { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            BootstrapContainerStatus { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateInfoParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateLockOpen { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         };
@@ -507,6 +929,9 @@ impl ResponseError for error::Error {
 pub(crate) struct SharedData {
     ds: sync::RwLock<FilesystemDataStore>,
     exec_socket_path: PathBuf,
+    history_path: PathBuf,
+    events: events::Events,
+    policy: Option<Policy>,
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -538,10 +963,34 @@ impl_responder_for!(ModelResponse, self, self.0);
 struct SettingsResponse(Settings);
 impl_responder_for!(SettingsResponse, self, self.0);
 
+/// This lets us respond from our handler methods with live settings and the generation they're
+/// from, which is given in the ETag header.
+struct LiveSettingsResponse {
+    settings: Settings,
+    generation: u64,
+}
+
+impl Responder for LiveSettingsResponse {
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let body = match serde_json::to_string(&self.settings) {
+            Ok(s) => s,
+            Err(e) => return Error::ResponseSerialization { source: e }.into(),
+        };
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((header::ETAG, format!("\"{}\"", self.generation)))
+            .body(body)
+    }
+}
+
 /// This lets us respond from our handler methods with a BottlerocketRelease (or Result<BottlerocketRelease>)
 struct BottlerocketReleaseResponse(BottlerocketRelease);
 impl_responder_for!(BottlerocketReleaseResponse, self, self.0);
 
+/// This lets us respond from our handler methods with a JSON Schema
+struct SchemaResponse(RootSchema);
+impl_responder_for!(SchemaResponse, self, self.0);
+
 /// This lets us respond from our handler methods with a HashMap (or Result<HashMap>) for metadata
 struct MetadataResponse(HashMap<String, Value>);
 impl_responder_for!(MetadataResponse, self, self.0);
@@ -550,17 +999,60 @@ impl_responder_for!(MetadataResponse, self, self.0);
 struct ServicesResponse(Services);
 impl_responder_for!(ServicesResponse, self, self.0);
 
-/// This lets us respond from our handler methods with a UpdateStatus (or Result<UpdateStatus>)
-struct UpdateStatusResponse(UpdateStatus);
-impl_responder_for!(UpdateStatusResponse, self, self.0);
+/// This lets us respond from our handler methods with a UpdateStatus (or Result<UpdateStatus>).
+/// If an update command holds the update lock, the status is given with a LOCKED code.
+struct UpdateStatusResponse {
+    status: UpdateStatus,
+    locked: bool,
+}
+
+impl Responder for UpdateStatusResponse {
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let body = match serde_json::to_string(&self.status) {
+            Ok(s) => s,
+            Err(e) => return Error::ResponseSerialization { source: e }.into(),
+        };
+        let code = if self.locked {
+            StatusCode::LOCKED
+        } else {
+            StatusCode::OK
+        };
+        HttpResponse::build(code)
+            .content_type("application/json")
+            .body(body)
+    }
+}
+
+/// This lets us respond from our handler methods with the status of services
+struct ServicesStatusResponse(BTreeMap<String, controller::ServiceStatus>);
+impl_responder_for!(ServicesStatusResponse, self, self.0);
+
+struct BootstrapContainersStatusResponse(bootstrap_containers::status::BootstrapContainerStatuses);
+impl_responder_for!(BootstrapContainersStatusResponse, self, self.0);
 
 /// This lets us respond from our handler methods with a ConfigurationFiles (or
 /// Result<ConfigurationFiles>)
 struct ConfigurationFilesResponse(ConfigurationFiles);
 impl_responder_for!(ConfigurationFilesResponse, self, self.0);
 
+/// This lets us respond from our handler methods with a rendered configuration file
+struct RenderedConfigurationFileResponse(controller::RenderedConfigurationFile);
+impl_responder_for!(RenderedConfigurationFileResponse, self, self.0);
+
 struct ChangedKeysResponse(HashSet<Key>);
 impl_responder_for!(ChangedKeysResponse, self, self.0);
 
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
+
+/// This lets us respond from our handler methods with a description of a transaction's changes
+struct TransactionDiffResponse(controller::TransactionDiff);
+impl_responder_for!(TransactionDiffResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the current and saved generations
+struct GenerationsResponse(controller::Generations);
+impl_responder_for!(GenerationsResponse, self, self.0);
+
+/// This lets us respond from our handler methods with the settings history
+struct HistoryResponse(Vec<HistoryEntry>);
+impl_responder_for!(HistoryResponse, self, self.0);
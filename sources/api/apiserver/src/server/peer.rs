@@ -0,0 +1,230 @@
+//! The peer module identifies the process on the other end of a client connection.  The API is
+//! only exposed on a Unix-domain socket, so the kernel can tell us the credentials of the
+//! connecting process (SO_PEERCRED) and we don't need any form of client-supplied identity.  If
+//! an LSM like SELinux is active, the kernel can also tell us the security label of the connecting
+//! process (SO_PEERSEC).
+
+use actix_http::body::{AnyBody, MessageBody};
+use actix_http::{HttpService, Protocol, Request, Response};
+use actix_service::{IntoServiceFactory, ServiceFactoryExt};
+use actix_web::dev::{
+    fn_factory, fn_service, forward_ready, AppConfig, Server, Service, ServiceFactory,
+};
+use actix_web::rt::net::UnixStream;
+use actix_web::{HttpMessage, HttpRequest};
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::ready;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::rc::Rc;
+
+/// SELinux labels are usually well under this size; if one is larger, we ask again with the size
+/// the kernel tells us it needs.
+const LABEL_BUFFER_SIZE: usize = 256;
+
+/// The credentials of the process that opened a connection to the API socket, as reported by the
+/// kernel at connection time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PeerCredentials {
+    /// The process ID may not be available, for example if the client is in another PID
+    /// namespace and the kernel can't map it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pid: Option<i32>,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    /// The security label of the process, like an SELinux context, if an LSM that supports labels
+    /// is active.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+}
+
+impl fmt::Display for PeerCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid {} gid {}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid {}", pid)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, " label {}", label)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serves the app made by `factory` on `listener`, with the given number of worker threads.
+///
+/// actix only gives the data it collects when a client connects to the first request on the
+/// connection, so we serve connections ourselves: the credentials of each client are looked up
+/// once, when it connects, and added to every request on the connection.  Each worker shares one
+/// instance of the app among its connections, as actix's own server does.
+pub(crate) fn serve_uds<F, I, S, B>(
+    listener: UnixListener,
+    workers: usize,
+    factory: F,
+) -> io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<actix_web::Error>,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    B: MessageBody + 'static,
+    B::Error: Into<Box<dyn StdError>>,
+{
+    let server = Server::build()
+        .workers(workers)
+        .listen_uds("apiserver", listener, move || {
+            let factory = factory.clone();
+            fn_factory(move || {
+                let app = factory()
+                    .into_factory()
+                    .map_err(|e| {
+                        let e: actix_web::Error = e.into();
+                        e.error_response()
+                    })
+                    .new_service(AppConfig::default());
+                async move {
+                    let app = Rc::new(
+                        app.await
+                            .map_err(|e| error!("Unable to start API service: {:?}", e))?,
+                    );
+                    Ok::<_, ()>(fn_service(move |stream: UnixStream| {
+                        serve_connection(stream, Rc::clone(&app))
+                    }))
+                }
+            })
+        })?
+        .run();
+    Ok(server)
+}
+
+/// Serves HTTP on one client connection, adding the client's credentials to each request.
+async fn serve_connection<A, B>(stream: UnixStream, app: Rc<A>) -> Result<(), ()>
+where
+    A: Service<Request> + 'static,
+    A::Error: Into<Response<AnyBody>> + 'static,
+    A::Future: 'static,
+    A::Response: Into<Response<B>> + 'static,
+    B: MessageBody + 'static,
+    B::Error: Into<Box<dyn StdError>>,
+{
+    let connection = Connection {
+        app,
+        credentials: credentials(&stream),
+    };
+    let http = HttpService::<UnixStream, _, B>::build()
+        .finish(fn_factory(move || ready(Ok::<_, ()>(connection.clone()))))
+        .new_service(())
+        .await?;
+    http.call((stream, Protocol::Http1, None))
+        .await
+        .map_err(|e| debug!("Error serving API connection: {}", e))
+}
+
+/// The service for one client connection, which passes requests to the app along with the
+/// client's credentials.
+struct Connection<A> {
+    app: Rc<A>,
+    credentials: Option<PeerCredentials>,
+}
+
+impl<A> Clone for Connection<A> {
+    fn clone(&self) -> Self {
+        Self {
+            app: Rc::clone(&self.app),
+            credentials: self.credentials.clone(),
+        }
+    }
+}
+
+impl<A: Service<Request>> Service<Request> for Connection<A> {
+    type Response = A::Response;
+    type Error = A::Error;
+    type Future = A::Future;
+
+    forward_ready!(app);
+
+    fn call(&self, request: Request) -> Self::Future {
+        if let Some(credentials) = &self.credentials {
+            request.extensions_mut().insert(credentials.clone());
+        }
+        self.app.call(request)
+    }
+}
+
+/// Looks up the credentials of the client on the other end of the given connection.
+fn credentials(stream: &UnixStream) -> Option<PeerCredentials> {
+    match stream.peer_cred() {
+        Ok(ucred) => Some(PeerCredentials {
+            pid: ucred.pid(),
+            uid: ucred.uid(),
+            gid: ucred.gid(),
+            label: peer_label(stream.as_raw_fd()),
+        }),
+        Err(e) => {
+            warn!("Unable to get credentials of API client: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the credentials of the client that made the given request, if they could be found when
+/// the client connected.
+pub(crate) fn peer_credentials(req: &HttpRequest) -> Option<PeerCredentials> {
+    req.extensions().get::<PeerCredentials>().cloned()
+}
+
+/// Returns the security label of the process on the other end of the given socket, or None if
+/// there's no LSM that supports labels, or the label can't be read.
+fn peer_label(fd: RawFd) -> Option<String> {
+    let mut buffer = vec![0u8; LABEL_BUFFER_SIZE];
+    let mut len = buffer.len() as libc::socklen_t;
+    let mut result = get_peer_sec(fd, &mut buffer, &mut len);
+    // If the buffer was too small, the kernel tells us the size it needs.
+    if matches!(&result, Err(e) if e.raw_os_error() == Some(libc::ERANGE)) {
+        buffer.resize(len as usize, 0);
+        result = get_peer_sec(fd, &mut buffer, &mut len);
+    }
+
+    if let Err(e) = result {
+        // ENOPROTOOPT means no LSM supports labels, which is normal, for example in development.
+        if e.raw_os_error() != Some(libc::ENOPROTOOPT) {
+            debug!("Unable to get security label of API client: {}", e);
+        }
+        return None;
+    }
+
+    buffer.truncate(len as usize);
+    // The kernel may include a trailing NUL, depending on the LSM.
+    while buffer.last() == Some(&0) {
+        buffer.pop();
+    }
+    String::from_utf8(buffer).ok()
+}
+
+/// Calls getsockopt for SO_PEERSEC, filling the buffer with the label of the peer process.  `len`
+/// is updated with the length of the label, or the length needed if the buffer is too small.
+fn get_peer_sec(fd: RawFd, buffer: &mut [u8], len: &mut libc::socklen_t) -> io::Result<()> {
+    *len = buffer.len() as libc::socklen_t;
+    // This is marked unsafe because the kernel writes through the pointer; we give it the real
+    // length of the buffer, so it won't write past the end.
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERSEC,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            len,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
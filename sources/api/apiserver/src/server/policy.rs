@@ -0,0 +1,509 @@
+//! The policy module decides what each API client is allowed to do.  Without a policy, any process
+//! that can reach the API socket can do anything, including changing any setting, running commands
+//! with `exec`, and rebooting.  A policy file lets us give some clients less access, for example
+//! letting a monitoring container read settings without letting it change them.
+//!
+//! The policy is a TOML file with a list of rules.  Each rule identifies clients by the uid, gid,
+//! or security label (like an SELinux context) of the connecting process, as reported by the
+//! kernel, and says which routes they can call and which settings they can change.  A client is
+//! checked against each rule in order, and the first rule that matches decides what the client can
+//! do.  Clients that don't match any rule can't do anything.
+//!
+//! ```toml
+//! [[rule]]
+//! name = "root"
+//! uid = 0
+//! routes = ["*"]
+//! settings = ["settings"]
+//!
+//! [[rule]]
+//! name = "monitoring"
+//! label = "system_u:system_r:container_t:*"
+//! routes = ["GET /settings", "GET /os", "GET /updates/status", "GET /events"]
+//! ```
+//!
+//! Routes are given as a method and a path pattern, like "POST /actions/*", or just a path pattern,
+//! which allows any method.  Path patterns and labels are glob patterns, so "*" allows all routes.
+//! Settings are given as key prefixes; a client can change the keys at or under the prefixes in its
+//! rule, so "settings.motd" allows changing just the motd, and "settings" allows changing anything.
+//! Settings are checked when they're set in a transaction, when a transaction is committed, since a
+//! client could commit changes that another client made, and when settings are rolled back to a
+//! prior generation, since a rollback can change any key.
+
+use crate::server::error::{self, Result};
+use crate::server::peer::PeerCredentials;
+use actix_web::http::Method;
+use datastore::{Key, KeyType};
+use glob::Pattern;
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// The format of the policy file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    rule: Vec<RuleConfig>,
+}
+
+/// The format of a rule in the policy file, before we check and parse its patterns.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    name: Option<String>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    label: Option<String>,
+    #[serde(default)]
+    routes: Vec<String>,
+    #[serde(default)]
+    settings: Vec<String>,
+}
+
+/// The rules that decide what each client is allowed to do.
+#[derive(Debug)]
+pub(crate) struct Policy {
+    rules: Vec<Rule>,
+}
+
+/// A rule that identifies a set of clients and says what they're allowed to do.  Identity fields
+/// that aren't given match any client; a rule with none of them matches every client.
+#[derive(Debug)]
+pub(crate) struct Rule {
+    /// Used to say which rule applied when we log a denied request.
+    name: String,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    label: Option<Pattern>,
+    routes: Vec<Route>,
+    settings: Vec<Key>,
+}
+
+/// An allowed route: a path pattern, and the method, if it's restricted to one.
+#[derive(Debug)]
+struct Route {
+    method: Option<Method>,
+    path: Pattern,
+}
+
+impl Policy {
+    /// Reads and checks the policy file at the given path.
+    pub(crate) fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).context(error::PolicyRead { path })?;
+        let policy_file: PolicyFile =
+            toml::from_str(&contents).context(error::PolicyParse { path })?;
+        Self::from_config(policy_file)
+    }
+
+    fn from_config(policy_file: PolicyFile) -> Result<Self> {
+        let rules = policy_file
+            .rule
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| Rule::from_config(i, rule))
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Returns the rule that applies to the given client, or None if no rule matches it.  Clients
+    /// whose credentials couldn't be determined only match rules without identity fields.
+    pub(crate) fn rule_for(&self, client: Option<&PeerCredentials>) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(client))
+    }
+
+    /// Makes sure the given client is allowed to make a request with the given method and path.
+    /// Denied requests are logged.
+    pub(crate) fn check_route(
+        &self,
+        client: Option<&PeerCredentials>,
+        method: &Method,
+        path: &str,
+    ) -> Result<()> {
+        let rule = self.checked_rule_for(client, method, path)?;
+        if !rule.allows_route(method, path) {
+            warn!(
+                "Denied {} {} for client {} by rule '{}'",
+                method,
+                path,
+                describe(client),
+                rule.name
+            );
+            return error::RouteNotPermitted {
+                method: method.as_str(),
+                path,
+            }
+            .fail();
+        }
+        Ok(())
+    }
+
+    /// Makes sure the given client is allowed to change all of the given settings keys.  Denied
+    /// requests are logged.
+    pub(crate) fn check_settings(
+        &self,
+        client: Option<&PeerCredentials>,
+        method: &Method,
+        path: &str,
+        keys: &HashSet<Key>,
+    ) -> Result<()> {
+        let rule = self.checked_rule_for(client, method, path)?;
+        let mut denied: Vec<&str> = keys
+            .iter()
+            .filter(|key| !rule.allows_setting(key))
+            .map(|key| key.name().as_str())
+            .collect();
+        if !denied.is_empty() {
+            denied.sort_unstable();
+            warn!(
+                "Denied {} {} for client {} by rule '{}', not permitted to change: {}",
+                method,
+                path,
+                describe(client),
+                rule.name,
+                denied.join(", ")
+            );
+            return error::SettingsNotPermitted {
+                keys: denied.into_iter().map(str::to_string).collect::<Vec<_>>(),
+            }
+            .fail();
+        }
+        Ok(())
+    }
+
+    /// Returns the rule that applies to the given client, or logs and fails the request if there
+    /// isn't one.
+    fn checked_rule_for(
+        &self,
+        client: Option<&PeerCredentials>,
+        method: &Method,
+        path: &str,
+    ) -> Result<&Rule> {
+        match self.rule_for(client) {
+            Some(rule) => Ok(rule),
+            None => {
+                warn!(
+                    "Denied {} {} for client {}, no policy rule matches",
+                    method,
+                    path,
+                    describe(client)
+                );
+                error::ClientNotPermitted.fail()
+            }
+        }
+    }
+}
+
+impl Rule {
+    fn from_config(index: usize, config: RuleConfig) -> Result<Self> {
+        let name = config.name.unwrap_or_else(|| format!("#{}", index + 1));
+
+        let label = config
+            .label
+            .map(|label| Pattern::new(&label).context(error::PolicyPattern { pattern: label }))
+            .transpose()?;
+
+        let routes = config
+            .routes
+            .iter()
+            .map(|route| Route::parse(route))
+            .collect::<Result<_>>()?;
+
+        let settings = config
+            .settings
+            .iter()
+            .map(|prefix| {
+                let key = Key::new(KeyType::Data, prefix).context(error::PolicyPrefix {
+                    prefix: prefix.as_str(),
+                })?;
+                ensure!(
+                    key.segments()[0] == "settings",
+                    error::PolicyPrefixNotSettings { prefix }
+                );
+                Ok(key)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            name,
+            uid: config.uid,
+            gid: config.gid,
+            label,
+            routes,
+            settings,
+        })
+    }
+
+    /// Returns whether the rule applies to the given client.
+    fn matches(&self, client: Option<&PeerCredentials>) -> bool {
+        let client = match client {
+            Some(client) => client,
+            None => return self.uid.is_none() && self.gid.is_none() && self.label.is_none(),
+        };
+        if let Some(uid) = self.uid {
+            if uid != client.uid {
+                return false;
+            }
+        }
+        if let Some(gid) = self.gid {
+            if gid != client.gid {
+                return false;
+            }
+        }
+        if let Some(label) = &self.label {
+            match &client.label {
+                Some(client_label) if label.matches(client_label) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Returns whether the rule allows requests with the given method and path.
+    fn allows_route(&self, method: &Method, path: &str) -> bool {
+        self.routes.iter().any(|route| {
+            let method_allowed = match &route.method {
+                Some(route_method) => route_method == method,
+                None => true,
+            };
+            method_allowed && route.path.matches(path)
+        })
+    }
+
+    /// Returns whether the rule allows changing the given key.
+    fn allows_setting(&self, key: &Key) -> bool {
+        self.settings
+            .iter()
+            .any(|prefix| key.segments().starts_with(prefix.segments()))
+    }
+}
+
+impl Route {
+    /// Parses a route from the policy file: a method and a path pattern separated by whitespace,
+    /// or just a path pattern.
+    fn parse(input: &str) -> Result<Self> {
+        let mut parts = input.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(path), None, None) => (None, path),
+            (Some(method), Some(path), None) => {
+                let method = method
+                    .parse::<Method>()
+                    .ok()
+                    .context(error::PolicyRoute { route: input })?;
+                (Some(method), path)
+            }
+            _ => return error::PolicyRoute { route: input }.fail(),
+        };
+        let path = Pattern::new(path).context(error::PolicyPattern { pattern: path })?;
+        Ok(Self { method, path })
+    }
+}
+
+/// Describes the client for log messages.
+fn describe(client: Option<&PeerCredentials>) -> String {
+    client
+        .map(|c| c.to_string())
+        .unwrap_or_else(|| "with unknown credentials".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::controller;
+    use datastore::memory::MemoryDataStore;
+    use datastore::{Committed, DataStore};
+    use maplit::hashset;
+
+    const POLICY: &str = r#"
+        [[rule]]
+        name = "root"
+        uid = 0
+        routes = ["*"]
+        settings = ["settings"]
+
+        [[rule]]
+        name = "monitoring"
+        label = "system_u:system_r:monitor_t:*"
+        routes = ["GET /settings", "GET /events", "PATCH /settings"]
+        settings = ["settings.motd", "settings.host-containers"]
+
+        [[rule]]
+        gid = 274
+        routes = ["GET /*"]
+    "#;
+
+    fn policy() -> Policy {
+        Policy::from_config(toml::from_str(POLICY).unwrap()).unwrap()
+    }
+
+    fn client(uid: u32, gid: u32, label: Option<&str>) -> PeerCredentials {
+        PeerCredentials {
+            pid: Some(42),
+            uid,
+            gid,
+            label: label.map(str::to_string),
+        }
+    }
+
+    fn key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let policy = policy();
+        let root = client(0, 274, None);
+        assert_eq!(policy.rule_for(Some(&root)).unwrap().name, "root");
+
+        let monitor = client(1000, 274, Some("system_u:system_r:monitor_t:s0"));
+        assert_eq!(policy.rule_for(Some(&monitor)).unwrap().name, "monitoring");
+
+        // Unnamed rules are named by position.
+        let api_user = client(1000, 274, Some("system_u:system_r:container_t:s0"));
+        assert_eq!(policy.rule_for(Some(&api_user)).unwrap().name, "#3");
+
+        assert!(policy.rule_for(Some(&client(1000, 1000, None))).is_none());
+        assert!(policy.rule_for(None).is_none());
+    }
+
+    #[test]
+    fn routes_checked() {
+        let policy = policy();
+        let monitor = client(1000, 0, Some("system_u:system_r:monitor_t:s0"));
+        assert!(policy
+            .check_route(Some(&monitor), &Method::GET, "/settings")
+            .is_ok());
+        assert!(policy
+            .check_route(Some(&monitor), &Method::POST, "/actions/reboot")
+            .is_err());
+        assert!(policy
+            .check_route(Some(&monitor), &Method::POST, "/settings")
+            .is_err());
+
+        let api_user = client(1000, 274, None);
+        assert!(policy
+            .check_route(Some(&api_user), &Method::GET, "/updates/status")
+            .is_ok());
+        assert!(policy
+            .check_route(Some(&api_user), &Method::POST, "/tx/commit")
+            .is_err());
+
+        let root = client(0, 0, None);
+        assert!(policy
+            .check_route(Some(&root), &Method::POST, "/actions/reboot")
+            .is_ok());
+
+        assert!(matches!(
+            policy.check_route(None, &Method::GET, "/settings"),
+            Err(error::Error::ClientNotPermitted)
+        ));
+    }
+
+    #[test]
+    fn settings_checked() {
+        let policy = policy();
+        let monitor = client(1000, 0, Some("system_u:system_r:monitor_t:s0"));
+        let allowed = hashset!(
+            key("settings.motd"),
+            key("settings.host-containers.admin.enabled")
+        );
+        assert!(policy
+            .check_settings(Some(&monitor), &Method::PATCH, "/settings", &allowed)
+            .is_ok());
+
+        let denied = hashset!(
+            key("settings.motd"),
+            key("settings.kernel.lockdown"),
+            key("settings.motdx")
+        );
+        match policy.check_settings(Some(&monitor), &Method::PATCH, "/settings", &denied) {
+            Err(error::Error::SettingsNotPermitted { keys }) => assert_eq!(
+                keys,
+                vec![
+                    "settings.kernel.lockdown".to_string(),
+                    "settings.motdx".to_string()
+                ]
+            ),
+            other => panic!("Expected SettingsNotPermitted, got {:?}", other),
+        }
+
+        // Rules without settings can't change any.
+        let api_user = client(1000, 274, None);
+        assert!(policy
+            .check_settings(
+                Some(&api_user),
+                &Method::POST,
+                "/tx/commit",
+                &hashset!(key("settings.motd"))
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn rollback_settings_checked() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        ds.set_key(&key("settings.motd"), "\"old\"", &pending).unwrap();
+        ds.commit_transaction(tx).unwrap();
+        ds.set_key(&key("settings.motd"), "\"new\"", &pending).unwrap();
+        ds.set_key(&key("settings.kernel.lockdown"), "\"integrity\"", &pending).unwrap();
+        ds.commit_transaction(tx).unwrap();
+
+        // The monitoring rule can change the motd, but rolling back would also remove the
+        // lockdown setting, which it can't change.
+        let policy = Policy::from_config(
+            toml::from_str(
+                r#"
+                [[rule]]
+                name = "monitoring"
+                label = "system_u:system_r:monitor_t:*"
+                routes = ["POST /tx/rollback"]
+                settings = ["settings.motd"]
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let monitor = client(1000, 0, Some("system_u:system_r:monitor_t:s0"));
+        let keys = controller::rollback_keys(&ds, 1).unwrap();
+        match policy.check_settings(Some(&monitor), &Method::POST, "/tx/rollback", &keys) {
+            Err(error::Error::SettingsNotPermitted { keys }) => {
+                assert_eq!(keys, vec!["settings.kernel.lockdown".to_string()])
+            }
+            other => panic!("Expected SettingsNotPermitted, got {:?}", other),
+        }
+
+        let root = client(0, 0, None);
+        assert!(policy()
+            .check_settings(Some(&root), &Method::POST, "/tx/rollback", &keys)
+            .is_ok());
+    }
+
+    #[test]
+    fn invalid_policies_rejected() {
+        for policy in &[
+            "[[rule]]\nroutes = [\"GET /settings extra\"]",
+            "[[rule]]\nroutes = [\"\"]",
+            "[[rule]]\nroutes = [\"/actions/[\"]",
+            "[[rule]]\nsettings = [\"services.foo\"]",
+            "[[rule]]\nsettings = [\"settings..motd\"]",
+        ] {
+            let policy_file = toml::from_str(policy).unwrap();
+            assert!(
+                Policy::from_config(policy_file).is_err(),
+                "Policy should be invalid: {}",
+                policy
+            );
+        }
+
+        // Typos in field names shouldn't silently give clients more or less access.
+        assert!(toml::from_str::<PolicyFile>("[[rule]]\nuids = 0").is_err());
+    }
+}
@@ -0,0 +1,6 @@
+//! The bootstrap-containers library records the outcome of running each bootstrap container, so
+//! the API server can report it.
+
+#![deny(rust_2018_idioms)]
+
+pub mod status;
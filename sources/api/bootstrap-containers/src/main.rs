@@ -13,6 +13,13 @@ It queries the API for their settings, then configures the system by:
 instance of a systemd service
 * ensuring that the bootstap container's systemd service is enabled/disabled for the next boot
 
+The systemd service runs the container through the `run` subcommand, which runs it again if it
+fails, up to `retries` times, waiting `retry-delay` seconds (10 by default) before each retry.  If
+`timeout` is set, each run that takes longer than that many seconds is stopped and counted as a
+failure.  The outcome of each container's run, including its exit code, start and end times, and
+the number of attempts, is recorded in `/run/bootstrap-containers/status` and reported by the API
+server at `/bootstrap-containers/status`.
+
 # Examples
 Given a bootstrap container called `bear` with the following configuration:
 
@@ -21,6 +28,8 @@ Given a bootstrap container called `bear` with the following configuration:
 source="<SOURCE>"
 mode="once"
 user-data="ypXCt82h4bSlwrfKlA=="
+retries=2
+timeout=300
 ```
 
 Where `<SOURCE>`, is the url of an image with the following definition:
@@ -75,6 +84,8 @@ journalctl -u bootstrap-containers@bear.service
 #[macro_use]
 extern crate log;
 
+use bootstrap_containers::status::{self, BootstrapContainerStatus, State};
+use chrono::Utc;
 use constants;
 use datastore::{serialize_scalar, Key, KeyType};
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
@@ -88,6 +99,7 @@ use std::fs;
 use std::path::Path;
 use std::process::{self, Command};
 use std::str::FromStr;
+use std::time::Duration;
 
 use model::modeled_types::{BootstrapContainerMode, Identifier};
 
@@ -95,6 +107,8 @@ const ENV_FILE_DIR: &str = "/etc/bootstrap-containers";
 const DROPIN_FILE_DIR: &str = "/etc/systemd/system";
 const PERSISTENT_STORAGE_DIR: &str = "/local/bootstrap-containers";
 const DROP_IN_FILENAME: &str = "overrides.conf";
+const REGISTRY_CONFIG: &str = "/etc/host-containers/host-ctr.toml";
+const DEFAULT_RETRY_DELAY_SECONDS: u64 = 10;
 
 /// Stores user-supplied global arguments
 #[derive(Debug)]
@@ -117,6 +131,7 @@ impl Default for Args {
 enum Subcommand {
     CreateContainers,
     MarkBootstrap(MarkBootstrapArgs),
+    Run(RunArgs),
 }
 
 #[derive(Debug)]
@@ -125,6 +140,15 @@ struct MarkBootstrapArgs {
     mode: BootstrapContainerMode,
 }
 
+#[derive(Debug)]
+struct RunArgs {
+    container_id: String,
+    source: String,
+    retries: u32,
+    retry_delay: Duration,
+    timeout: Option<Duration>,
+}
+
 /// Print a usage message in the event a bad arg is passed
 fn usage() {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
@@ -134,6 +158,7 @@ fn usage() {
     Subcommands:
         create-containers
         mark-bootstrap
+        run
 
     Global arguments:
         [ --socket-path PATH ]
@@ -143,6 +168,13 @@ fn usage() {
         --container-id CONTAINER-ID
         --mode MODE
 
+    Run arguments:
+        --container-id CONTAINER-ID
+        --source SOURCE
+        [ --retries RETRIES ]
+        [ --retry-delay SECONDS ]
+        [ --timeout SECONDS ]
+
     Socket path defaults to {}",
         program_name,
         constants::API_SOCKET,
@@ -174,7 +206,7 @@ fn parse_args(args: env::Args) -> Result<(Args, Subcommand)> {
             }
 
             // Subcommands
-            "create-containers" | "mark-bootstrap"
+            "create-containers" | "mark-bootstrap" | "run"
                 if subcommand.is_none() && !arg.starts_with('-') =>
             {
                 subcommand = Some(arg)
@@ -188,6 +220,7 @@ fn parse_args(args: env::Args) -> Result<(Args, Subcommand)> {
     match subcommand.as_deref() {
         Some("create-containers") => Ok((global_args, Subcommand::CreateContainers {})),
         Some("mark-bootstrap") => Ok((global_args, parse_mark_bootstrap_args(subcommand_args)?)),
+        Some("run") => Ok((global_args, parse_run_args(subcommand_args)?)),
         None => {
             return error::Usage {
                 message: format!("Missing subcommand"),
@@ -248,6 +281,73 @@ fn parse_mark_bootstrap_args(args: Vec<String>) -> Result<Subcommand> {
     }))
 }
 
+/// Parses arguments for the 'run' subcommand.  The systemd unit passes the values from the
+/// environment file, so empty values mean the default.
+fn parse_run_args(args: Vec<String>) -> Result<Subcommand> {
+    let mut container_id = None;
+    let mut source = None;
+    let mut retries = 0;
+    let mut retry_delay = DEFAULT_RETRY_DELAY_SECONDS;
+    let mut timeout = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--container-id" => {
+                container_id = Some(iter.next().context(error::Usage {
+                    message: "Did not give argument to --container-id",
+                })?);
+            }
+
+            "--source" => {
+                source = Some(iter.next().context(error::Usage {
+                    message: "Did not give argument to --source",
+                })?);
+            }
+
+            "--retries" | "--retry-delay" | "--timeout" => {
+                let value = iter.next().context(error::Usage {
+                    message: format!("Did not give argument to {}", arg),
+                })?;
+                if value.is_empty() {
+                    continue;
+                }
+                let number: u64 = value.parse().ok().context(error::Usage {
+                    message: format!("Invalid {} '{}', expected a number", arg, value),
+                })?;
+                match arg.as_ref() {
+                    "--retries" => {
+                        retries = u32::try_from(number).ok().context(error::Usage {
+                            message: format!("Too many retries '{}'", value),
+                        })?
+                    }
+                    "--retry-delay" => retry_delay = number,
+                    _ => timeout = Some(number),
+                }
+            }
+
+            x => {
+                return error::Usage {
+                    message: format!("Unexpected argument '{}'", x),
+                }
+                .fail()
+            }
+        }
+    }
+
+    Ok(Subcommand::Run(RunArgs {
+        container_id: container_id.context(error::Usage {
+            message: "Did not give argument to --container-id",
+        })?,
+        source: source.context(error::Usage {
+            message: "Did not give argument to --source",
+        })?,
+        retries,
+        retry_delay: Duration::from_secs(retry_delay),
+        timeout: timeout.map(Duration::from_secs),
+    }))
+}
+
 /// Handles how the bootstrap containers' systemd units are created
 fn handle_bootstrap_container<S>(
     name: S,
@@ -273,6 +373,14 @@ where
 
     let essential = container_details.essential.unwrap_or_else(|| false);
 
+    let policy = RunPolicy {
+        retries: container_details.retries.unwrap_or(0),
+        retry_delay: container_details
+            .retry_delay
+            .unwrap_or(DEFAULT_RETRY_DELAY_SECONDS),
+        timeout: container_details.timeout,
+    };
+
     // Create the directory regardless if user data was provided for the container
     let dir = Path::new(PERSISTENT_STORAGE_DIR).join(name);
     fs::create_dir_all(&dir).context(error::Mkdir { dir: &dir })?;
@@ -295,7 +403,7 @@ where
 
     // Write the environment file needed for the systemd service to have details
     // this specific bootstrap container
-    write_config_files(name, source, &mode, essential)?;
+    write_config_files(name, source, &mode, essential, &policy)?;
 
     if mode == "off" {
         // If mode is 'off', disable the container, and clean up any left over tasks
@@ -338,8 +446,21 @@ where
     Ok(())
 }
 
+/// How the systemd unit runs a bootstrap container.
+struct RunPolicy {
+    retries: u32,
+    retry_delay: u64,
+    timeout: Option<u64>,
+}
+
 /// Write out the EnvironmentFile that systemd uses to fill in arguments to host-ctr
-fn write_config_files<S1, S2, S3>(name: S1, source: S2, mode: S3, essential: bool) -> Result<()>
+fn write_config_files<S1, S2, S3>(
+    name: S1,
+    source: S2,
+    mode: S3,
+    essential: bool,
+    policy: &RunPolicy,
+) -> Result<()>
 where
     S1: AsRef<str>,
     S2: AsRef<str>,
@@ -358,6 +479,18 @@ where
     writeln!(output, "CTR_MODE={}", mode.as_ref()).context(error::WriteConfigurationValue {
         value: mode.as_ref(),
     })?;
+    writeln!(output, "CTR_RETRIES={}", policy.retries).context(error::WriteConfigurationValue {
+        value: policy.retries.to_string(),
+    })?;
+    writeln!(output, "CTR_RETRY_DELAY={}", policy.retry_delay).context(
+        error::WriteConfigurationValue {
+            value: policy.retry_delay.to_string(),
+        },
+    )?;
+    // An empty timeout means the container can run for as long as it needs.
+    let timeout = policy.timeout.map(|t| t.to_string()).unwrap_or_default();
+    writeln!(output, "CTR_TIMEOUT={}", timeout)
+        .context(error::WriteConfigurationValue { value: &timeout })?;
 
     debug!("Writing environment file for unit '{}'", name);
     fs::write(&env_path, output).context(error::WriteConfigurationFile { path: env_path })?;
@@ -537,7 +670,7 @@ where
             .context(error::SettingsDeserialize { settings: map })?;
 
         info!("Turning off container '{}'", container_id);
-        apiclient::set::set(socket_path, &settings)
+        apiclient::set::set(socket_path, &settings, None)
             .await
             .context(error::Set)?;
     }
@@ -545,6 +678,123 @@ where
     Ok(())
 }
 
+/// Handles the `run` subcommand, which is called by the bootstrap container's systemd unit to run
+/// the container.  If it fails, it's cleaned up and run again, up to the given number of retries.
+/// The status is recorded before and after each attempt, so it can be seen while the container
+/// runs.
+async fn run_container(args: RunArgs) -> Result<()> {
+    let name = args.container_id.as_str();
+    let mut status = BootstrapContainerStatus {
+        state: State::Running,
+        attempts: 0,
+        exit_code: None,
+        error: None,
+        started_at: Utc::now(),
+        finished_at: None,
+    };
+
+    loop {
+        status.attempts += 1;
+        status.state = State::Running;
+        record_status(name, &status);
+
+        info!(
+            "Running bootstrap container '{}', attempt {} of {}",
+            name,
+            status.attempts,
+            args.retries as u64 + 1
+        );
+        let result = run_attempt(&args).await;
+        status.exit_code = match &result {
+            Ok(()) => Some(0),
+            Err(error::Error::ContainerFailure { code, .. }) => *code,
+            Err(_) => None,
+        };
+        let e = match result {
+            Ok(()) => {
+                info!("Bootstrap container '{}' succeeded", name);
+                status.state = State::Succeeded;
+                status.error = None;
+                status.finished_at = Some(Utc::now());
+                record_status(name, &status);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        error!("Bootstrap container '{}' failed: {}", name, e);
+        status.error = Some(e.to_string());
+        // Remove what's left of the failed attempt, so a retry can start fresh and a timed out
+        // container doesn't keep running.
+        if let Err(e) = command(
+            constants::HOST_CTR_BIN,
+            ["clean-up", "--container-id", &format!("boot.{}", name)],
+        ) {
+            warn!("Failed to clean up bootstrap container '{}': {}", name, e);
+        }
+
+        if status.attempts > args.retries {
+            status.state = State::Failed;
+            status.finished_at = Some(Utc::now());
+            record_status(name, &status);
+            return Err(e);
+        }
+        record_status(name, &status);
+        info!(
+            "Retrying bootstrap container '{}' in {} seconds",
+            name,
+            args.retry_delay.as_secs()
+        );
+        tokio::time::sleep(args.retry_delay).await;
+    }
+}
+
+/// Runs the bootstrap container once with host-ctr, stopping it if it doesn't finish in time.
+async fn run_attempt(args: &RunArgs) -> Result<()> {
+    let name = args.container_id.as_str();
+    let mut command = tokio::process::Command::new(constants::HOST_CTR_BIN);
+    command
+        .arg("run")
+        .arg(format!("--container-id={}", name))
+        .arg(format!("--source={}", args.source))
+        .arg("--container-type=bootstrap")
+        .arg(format!("--registry-config={}", REGISTRY_CONFIG))
+        // Dropping the command's future, like on timeout, kills host-ctr.
+        .kill_on_drop(true);
+
+    let exit_status = match args.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, command.status())
+            .await
+            .ok()
+            .context(error::ContainerTimeout {
+                name,
+                seconds: timeout.as_secs(),
+            })?,
+        None => command.status().await,
+    }
+    .context(error::ContainerSpawn { name })?;
+
+    ensure!(
+        exit_status.success(),
+        error::ContainerFailure {
+            name,
+            code: exit_status.code(),
+        }
+    );
+    Ok(())
+}
+
+/// Records the status of the bootstrap container so the API can report it.  Failing to record it
+/// doesn't change the outcome, so it's only logged.
+fn record_status(name: &str, container_status: &BootstrapContainerStatus) {
+    if let Err(e) = status::write_status(status::STATUS_DIR, name, container_status) {
+        warn!(
+            "Unable to record status of bootstrap container '{}': {}",
+            name, e
+        );
+    }
+}
+
 async fn run() -> Result<()> {
     let (args, subcommand) = parse_args(env::args())?;
 
@@ -558,6 +808,7 @@ async fn run() -> Result<()> {
         Subcommand::MarkBootstrap(mark_bootstrap_args) => {
             mark_bootstrap(mark_bootstrap_args, args.socket_path).await
         }
+        Subcommand::Run(run_args) => run_container(run_args).await,
     }
 }
 
@@ -622,6 +873,19 @@ mod error {
                         bin_path, String::from_utf8_lossy(&output.stderr)))]
         CommandFailure { bin_path: String, output: Output },
 
+        #[snafu(display(
+            "Bootstrap container '{}' failed with exit code {}",
+            name,
+            code.map(|c| c.to_string()).unwrap_or_else(|| "unknown (killed by signal)".to_string())
+        ))]
+        ContainerFailure { name: String, code: Option<i32> },
+
+        #[snafu(display("Failed to start bootstrap container '{}': {}", name, source))]
+        ContainerSpawn { name: String, source: io::Error },
+
+        #[snafu(display("Bootstrap container '{}' timed out after {} seconds", name, seconds))]
+        ContainerTimeout { name: String, seconds: u64 },
+
         #[snafu(display("Failed to execute '{:?}': {}", command, source))]
         ExecutionFailure {
             command: Command,
@@ -0,0 +1,167 @@
+//! The status module records the outcome of running each bootstrap container during this boot.
+//! Each container's status is kept in its own file, so containers can be run at the same time.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// The directory holding the status of each bootstrap container.  It's under /run, so only the
+/// runs of the current boot are known.
+pub const STATUS_DIR: &str = "/run/bootstrap-containers/status";
+
+/// The state of a bootstrap container's run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum State {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// The outcome of running a bootstrap container, so far.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BootstrapContainerStatus {
+    pub state: State,
+    /// The number of times the container has been started, including the current one, if it's
+    /// running.
+    pub attempts: u32,
+    /// The exit code of the last attempt that finished, if it exited.
+    pub exit_code: Option<i32>,
+    /// Why the last attempt failed, if it failed.
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// The status of each bootstrap container, by name.
+pub type BootstrapContainerStatuses = BTreeMap<String, BootstrapContainerStatus>;
+
+/// Reads the status of each bootstrap container from the given directory.  If it doesn't exist
+/// yet, because no bootstrap containers have run this boot, no containers have a status.
+pub fn read_status<P>(dir: P) -> Result<BootstrapContainerStatuses>
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(BootstrapContainerStatuses::new())
+        }
+        Err(e) => return Err(e).context(error::StatusRead { path: dir }),
+    };
+
+    let mut statuses = BootstrapContainerStatuses::new();
+    for entry in entries {
+        let path = entry.context(error::StatusRead { path: dir })?.path();
+        let name = match (path.file_stem(), path.extension()) {
+            (Some(name), Some(extension)) if extension == "json" => {
+                name.to_string_lossy().into_owned()
+            }
+            // Skip temporary files.
+            _ => continue,
+        };
+        let data = fs::read(&path).context(error::StatusRead { path: &path })?;
+        let status = serde_json::from_slice(&data).context(error::StatusParse { path: &path })?;
+        statuses.insert(name, status);
+    }
+    Ok(statuses)
+}
+
+/// Records the status of the named bootstrap container in the given directory.
+pub fn write_status<P>(dir: P, name: &str, status: &BootstrapContainerStatus) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).context(error::StatusWrite { path: dir })?;
+
+    // Write a new file and swap it into place, so the API server never reads a partial file.
+    let path = dir.join(format!("{}.json", name));
+    let tempfile = NamedTempFile::new_in(dir).context(error::StatusWrite { path: dir })?;
+    serde_json::to_writer_pretty(&tempfile, status).context(error::StatusSerialize)?;
+    tempfile
+        .persist(&path)
+        .context(error::StatusPersist { path: &path })?;
+    Ok(())
+}
+
+pub mod error {
+    use snafu::Snafu;
+    use std::io;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed to read status from '{}': {}", path.display(), source))]
+        StatusRead { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to parse status from '{}': {}", path.display(), source))]
+        StatusParse {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to write status to '{}': {}", path.display(), source))]
+        StatusWrite { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Failed to serialize status: {}", source))]
+        StatusSerialize { source: serde_json::Error },
+
+        #[snafu(display("Failed to move status into place at '{}': {}", path.display(), source))]
+        StatusPersist {
+            path: PathBuf,
+            source: tempfile::PersistError,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn status(state: State, attempts: u32) -> BootstrapContainerStatus {
+        BootstrapContainerStatus {
+            state,
+            attempts,
+            exit_code: None,
+            error: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let status_dir = dir.path().join("status");
+        assert!(read_status(&status_dir).unwrap().is_empty());
+
+        let running = status(State::Running, 1);
+        write_status(&status_dir, "bear", &running).unwrap();
+        let mut failed = status(State::Failed, 3);
+        failed.exit_code = Some(1);
+        failed.finished_at = Some(Utc::now());
+        write_status(&status_dir, "cat", &failed).unwrap();
+
+        // Writing again replaces the container's status.
+        let mut succeeded = running.clone();
+        succeeded.state = State::Succeeded;
+        succeeded.exit_code = Some(0);
+        write_status(&status_dir, "bear", &succeeded).unwrap();
+
+        let statuses = read_status(&status_dir).unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses["bear"], succeeded);
+        assert_eq!(statuses["cat"], failed);
+    }
+}
@@ -0,0 +1,631 @@
+//! The check module finds problems in a filesystem data store: entries that don't represent
+//! valid keys, values that don't deserialize into the model, unreadable metadata and generations,
+//! and metadata for keys that aren't part of the model.
+
+use datastore::deserialization::from_map_with_prefix;
+use datastore::{
+    deserialize_scalar, Committed, DataStore, FilesystemDataStore, Key, KeyType, ScalarError, Value,
+};
+use model::{ConfigurationFile, Service, Settings};
+use snafu::ResultExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A problem found in the data store.
+#[derive(Debug, Clone)]
+pub(crate) enum Problem {
+    /// An entry that doesn't represent a valid key or transaction.  Listing keys skips these, but
+    /// they can hide data, and a transaction directory with an invalid name stops transactions
+    /// from being listed.
+    InvalidPath { path: PathBuf, reason: String },
+    /// A key whose value can't be read, or doesn't deserialize into the model.
+    InvalidValue {
+        path: PathBuf,
+        key: Key,
+        committed: Committed,
+        reason: String,
+    },
+    /// Metadata whose value can't be read.
+    InvalidMetadata {
+        path: PathBuf,
+        data_key: Key,
+        metadata_key: Key,
+        reason: String,
+    },
+    /// Metadata for a data key that isn't part of the model.  This is harmless; it's usually left
+    /// behind when a setting is removed.
+    OrphanedMetadata {
+        path: PathBuf,
+        data_key: Key,
+        metadata_key: Key,
+    },
+    /// A commit that was interrupted after the point where it happens, so the live data doesn't
+    /// reflect it yet.  The data store finishes it before its next change.
+    InterruptedCommit { path: PathBuf },
+    /// The current generation can't be read from the "live" symlink or the generation file.
+    InvalidGeneration { path: PathBuf, reason: String },
+    /// A saved generation that can't be read, so it can't be rolled back to.
+    InvalidSavedGeneration { path: PathBuf, reason: String },
+}
+
+impl Problem {
+    /// The file or directory with the problem.
+    pub(crate) fn path(&self) -> &Path {
+        match self {
+            Problem::InvalidPath { path, .. }
+            | Problem::InvalidValue { path, .. }
+            | Problem::InvalidMetadata { path, .. }
+            | Problem::OrphanedMetadata { path, .. }
+            | Problem::InterruptedCommit { path }
+            | Problem::InvalidGeneration { path, .. }
+            | Problem::InvalidSavedGeneration { path, .. } => path,
+        }
+    }
+
+    /// Whether the problem needs repair.  Harmless problems are reported, but left alone.
+    pub(crate) fn needs_repair(&self) -> bool {
+        !matches!(self, Problem::OrphanedMetadata { .. })
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::InvalidPath { path, reason } => {
+                write!(f, "{}: invalid entry: {}", path.display(), reason)
+            }
+            Problem::InvalidValue {
+                path,
+                key,
+                committed,
+                reason,
+            } => {
+                let location = match committed {
+                    Committed::Live => "live".to_string(),
+                    Committed::Pending { tx } => format!("pending in transaction '{}'", tx),
+                };
+                write!(
+                    f,
+                    "{}: invalid value for {} key '{}': {}",
+                    path.display(),
+                    location,
+                    key,
+                    reason
+                )
+            }
+            Problem::InvalidMetadata {
+                path,
+                data_key,
+                metadata_key,
+                reason,
+            } => write!(
+                f,
+                "{}: invalid metadata '{}' for key '{}': {}",
+                path.display(),
+                metadata_key,
+                data_key,
+                reason
+            ),
+            Problem::OrphanedMetadata {
+                path,
+                data_key,
+                metadata_key,
+            } => write!(
+                f,
+                "{}: orphaned metadata '{}' for key '{}', which isn't part of the model",
+                path.display(),
+                metadata_key,
+                data_key
+            ),
+            Problem::InterruptedCommit { path } => {
+                write!(f, "{}: interrupted commit", path.display())
+            }
+            Problem::InvalidGeneration { path, reason } => {
+                write!(f, "{}: invalid generation: {}", path.display(), reason)
+            }
+            Problem::InvalidSavedGeneration { path, reason } => {
+                write!(
+                    f,
+                    "{}: invalid saved generation: {}",
+                    path.display(),
+                    reason
+                )
+            }
+        }
+    }
+}
+
+/// Checks the data store at the given path, returning the problems found.
+///
+/// If a transaction directory has an invalid name, the data store can't list transactions, so
+/// the keys of pending transactions can't be checked until it's repaired.
+pub(crate) fn check(datastore_path: &Path) -> Result<Vec<Problem>> {
+    let datastore = FilesystemDataStore::new(datastore_path);
+    let mut problems = Vec::new();
+
+    let commit_record_path = datastore_path.join("commit");
+    if commit_record_path.exists() {
+        problems.push(Problem::InterruptedCommit {
+            path: commit_record_path,
+        });
+    }
+
+    for invalid in datastore
+        .find_invalid_paths()
+        .context(error::FindInvalidPaths)?
+    {
+        problems.push(Problem::InvalidPath {
+            path: invalid.path,
+            reason: invalid.reason,
+        });
+    }
+
+    check_values(&datastore, &Committed::Live, &mut problems)?;
+    match datastore.list_transactions() {
+        Ok(transactions) => {
+            let mut transactions: Vec<String> = transactions.into_iter().collect();
+            transactions.sort();
+            for tx in transactions {
+                check_values(&datastore, &Committed::Pending { tx }, &mut problems)?;
+            }
+        }
+        Err(e) => warn!("Unable to list transactions, not checking them: {}", e),
+    }
+
+    check_metadata(&datastore, &mut problems)?;
+    check_generations(&datastore, datastore_path, &mut problems)?;
+
+    Ok(problems)
+}
+
+/// Checks that each populated key in the live data or a pending transaction can be read and
+/// deserialized into the model.  Settings are deserialized one key at a time, so a bad value
+/// doesn't hide problems with others, and we know which key is bad.  Services and configuration
+/// files have required fields, so each one is deserialized as a whole, and a problem is reported
+/// for each of its keys.
+fn check_values(
+    datastore: &FilesystemDataStore,
+    committed: &Committed,
+    problems: &mut Vec<Problem>,
+) -> Result<()> {
+    let mut keys: Vec<Key> = datastore
+        .list_populated_keys("", committed)
+        .context(error::ListKeys)?
+        .into_iter()
+        .collect();
+    keys.sort_by(|a, b| a.name().cmp(b.name()));
+
+    // Values of settings are checked as they're read; the keys of other items are grouped by
+    // item, with their values, so each item can be checked once it's complete.
+    let mut items: BTreeMap<Vec<String>, HashMap<Key, String>> = BTreeMap::new();
+    for key in keys {
+        let value = match datastore.get_key(&key, committed) {
+            Ok(Some(value)) => value,
+            // It was removed since we listed it.
+            Ok(None) => continue,
+            Err(e) => {
+                push_invalid_value(datastore, &key, committed, e.to_string(), problems)?;
+                continue;
+            }
+        };
+
+        let segments = key.segments();
+        match segments[0].as_ref() {
+            "settings" => {
+                let mut map = HashMap::new();
+                map.insert(key.clone(), value);
+                // Settings knows its own name, so it doesn't need a prefix.
+                if let Err(e) = from_map_with_prefix::<_, _, Settings, _>(None, &map) {
+                    push_invalid_value(datastore, &key, committed, e.to_string(), problems)?;
+                }
+            }
+            "services" | "configuration-files" if segments.len() > 2 => {
+                items
+                    .entry(segments[..2].to_vec())
+                    .or_default()
+                    .insert(key, value);
+            }
+            _ => push_invalid_value(
+                datastore,
+                &key,
+                committed,
+                "not part of the model".to_string(),
+                problems,
+            )?,
+        }
+    }
+
+    for (segments, map) in items {
+        // Items are deserialized on their own, so they need to be given their prefix, like the
+        // API server does.
+        let item = Key::from_segments(KeyType::Data, &segments)
+            .context(error::KeyPath {
+                key: segments.join("."),
+            })?
+            .name()
+            .to_string();
+        let result = if segments[0] == "services" {
+            from_map_with_prefix::<_, _, Service, _>(Some(item.clone()), &map).map(|_| ())
+        } else {
+            from_map_with_prefix::<_, _, ConfigurationFile, _>(Some(item.clone()), &map).map(|_| ())
+        };
+        if let Err(e) = result {
+            let mut keys: Vec<&Key> = map.keys().collect();
+            keys.sort_by(|a, b| a.name().cmp(b.name()));
+            for key in keys {
+                let reason = format!("item '{}' is invalid: {}", item, e);
+                push_invalid_value(datastore, key, committed, reason, problems)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records an invalid value for the given key.
+fn push_invalid_value(
+    datastore: &FilesystemDataStore,
+    key: &Key,
+    committed: &Committed,
+    reason: String,
+    problems: &mut Vec<Problem>,
+) -> Result<()> {
+    let path = datastore
+        .data_path(key, committed)
+        .context(error::KeyPath { key: key.name() })?;
+    problems.push(Problem::InvalidValue {
+        path,
+        key: key.clone(),
+        committed: committed.clone(),
+        reason,
+    });
+    Ok(())
+}
+
+/// Checks that each metadata value can be read and is valid, and that its data key is part of
+/// the model.
+fn check_metadata(datastore: &FilesystemDataStore, problems: &mut Vec<Problem>) -> Result<()> {
+    let schema =
+        serde_json::to_value(model::schema::settings_schema()).context(error::SchemaSerialize)?;
+
+    let metadata = datastore
+        .list_populated_metadata("", &None as &Option<&str>)
+        .context(error::ListMetadata)?;
+    let mut pairs: Vec<(Key, Key)> = metadata
+        .into_iter()
+        .flat_map(|(data_key, metadata_keys)| {
+            metadata_keys
+                .into_iter()
+                .map(move |metadata_key| (data_key.clone(), metadata_key))
+        })
+        .collect();
+    pairs.sort_by(|a, b| (a.0.name(), a.1.name()).cmp(&(b.0.name(), b.1.name())));
+
+    for (data_key, metadata_key) in pairs {
+        let path = datastore
+            .metadata_path(&metadata_key, &data_key, &Committed::Live)
+            .context(error::KeyPath {
+                key: data_key.name(),
+            })?;
+        let reason = match datastore.get_metadata_raw(&metadata_key, &data_key) {
+            Ok(Some(value)) => deserialize_scalar::<Value, ScalarError>(&value)
+                .err()
+                .map(|e| e.to_string()),
+            Ok(None) => None,
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(reason) = reason {
+            problems.push(Problem::InvalidMetadata {
+                path,
+                data_key,
+                metadata_key,
+                reason,
+            });
+        } else if !in_model(&schema, &data_key) {
+            problems.push(Problem::OrphanedMetadata {
+                path,
+                data_key,
+                metadata_key,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the given data key is a setting, or a prefix of settings, in the model
+/// described by the given settings schema.
+fn in_model(schema: &serde_json::Value, key: &Key) -> bool {
+    let segments = key.segments();
+    if segments[0] != "settings" {
+        return false;
+    }
+    in_schema(schema, schema, &segments[1..])
+}
+
+/// Returns whether the given path of property names can be found in the schema.  Any name
+/// matches a map, whose properties aren't named.
+fn in_schema(root: &serde_json::Value, schema: &serde_json::Value, path: &[String]) -> bool {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+
+    // Follow references to definitions, and alternatives like those for registry mirrors.
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        return match reference.strip_prefix('#').and_then(|p| root.pointer(p)) {
+            Some(target) => in_schema(root, target, path),
+            None => false,
+        };
+    }
+    if let Some(any_of) = schema.get("anyOf").and_then(|a| a.as_array()) {
+        return any_of.iter().any(|s| in_schema(root, s, path));
+    }
+
+    if let Some(property) = schema.get("properties").and_then(|p| p.get(first)) {
+        return in_schema(root, property, rest);
+    }
+    match schema.get("additionalProperties") {
+        Some(additional) if additional.is_object() => in_schema(root, additional, rest),
+        _ => false,
+    }
+}
+
+/// Checks that the current generation and the saved generations can be read.  The data store
+/// can't commit if it can't read the current generation, or list the saved generations.
+fn check_generations(
+    datastore: &FilesystemDataStore,
+    datastore_path: &Path,
+    problems: &mut Vec<Problem>,
+) -> Result<()> {
+    if let Err(e) = datastore.generation() {
+        // The generation comes from the name of the live directory, or from the generation file
+        // in older data stores, where the live directory isn't a symlink.
+        let live_path = datastore_path.join("live");
+        let path = match fs::symlink_metadata(&live_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => live_path,
+            _ => datastore_path.join("generation"),
+        };
+        problems.push(Problem::InvalidGeneration {
+            path,
+            reason: e.to_string(),
+        });
+    }
+
+    let generations_path = datastore_path.join("generations");
+    let entries = match fs::read_dir(&generations_path) {
+        Ok(entries) => entries,
+        // Nothing has been committed yet.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).context(error::ReadDir {
+                path: generations_path,
+            })
+        }
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry.context(error::ReadDir {
+            path: &generations_path,
+        })?;
+        paths.push(entry.path());
+    }
+    paths.sort();
+
+    for path in paths {
+        // The data store ignores other files here.
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let generation = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok());
+        let reason = match generation {
+            Some(generation) => datastore
+                .get_generation_settings(generation)
+                .err()
+                .map(|e| e.to_string()),
+            None => Some("name isn't a generation number".to_string()),
+        };
+        if let Some(reason) = reason {
+            problems.push(Problem::InvalidSavedGeneration { path, reason });
+        }
+    }
+    Ok(())
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Unable to find invalid entries in data store: {}", source))]
+        FindInvalidPaths { source: datastore::Error },
+
+        #[snafu(display("Unable to find path of key '{}': {}", key, source))]
+        KeyPath {
+            key: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Unable to list keys: {}", source))]
+        ListKeys { source: datastore::Error },
+
+        #[snafu(display("Unable to list metadata: {}", source))]
+        ListMetadata { source: datastore::Error },
+
+        #[snafu(display("Unable to read directory '{}': {}", path.display(), source))]
+        ReadDir {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Unable to serialize settings schema: {}", source))]
+        SchemaSerialize { source: serde_json::Error },
+    }
+}
+pub(crate) use error::Error;
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    fn meta(name: &str) -> Key {
+        Key::new(KeyType::Meta, name).unwrap()
+    }
+
+    #[test]
+    fn valid() {
+        let dir = TempDir::new().unwrap();
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        datastore
+            .set_key(&key("settings.motd"), "\"hi\"", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_key(
+                &key("settings.ntp.time-servers"),
+                "[\"a\"]",
+                &Committed::Pending { tx: "tx".into() },
+            )
+            .unwrap();
+        datastore
+            .set_key(&key("services.x.restart-commands"), "[]", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_key(
+                &key("services.x.configuration-files"),
+                "[]",
+                &Committed::Live,
+            )
+            .unwrap();
+        datastore
+            .set_metadata(
+                &meta("affected-services"),
+                &key("settings.motd"),
+                "[\"motd\"]",
+            )
+            .unwrap();
+
+        assert!(check(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = TempDir::new().unwrap();
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        let pending = Committed::Pending { tx: "tx".into() };
+        // Truncated, and the wrong type.
+        datastore
+            .set_key(&key("settings.motd"), "\"hi", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_key(&key("settings.ntp.time-servers"), "1", &pending)
+            .unwrap();
+        // Missing a required field.
+        datastore
+            .set_key(&key("services.x.restart-commands"), "[]", &Committed::Live)
+            .unwrap();
+        // Not in the model.
+        datastore
+            .set_key(&key("other"), "1", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_metadata(&meta("affected-services"), &key("settings.motd"), "[")
+            .unwrap();
+        datastore
+            .set_metadata(&meta("affected-services"), &key("settings.gone"), "[]")
+            .unwrap();
+        fs::write(dir.path().join("live/settings/bad%zz"), "").unwrap();
+
+        let problems = check(dir.path()).unwrap();
+        let described: Vec<String> = problems
+            .iter()
+            .map(|p| {
+                let kind = match p {
+                    Problem::InvalidPath { .. } => "path",
+                    Problem::InvalidValue { .. } => "value",
+                    Problem::InvalidMetadata { .. } => "metadata",
+                    Problem::OrphanedMetadata { .. } => "orphan",
+                    _ => "other",
+                };
+                format!(
+                    "{} {}",
+                    kind,
+                    p.path().strip_prefix(dir.path()).unwrap().display()
+                )
+            })
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                "path live/settings/bad%zz",
+                "value live/other",
+                "value live/settings/motd",
+                "value live/services/x/restart-commands",
+                "value pending/tx/settings/ntp/time-servers",
+                "orphan live/settings/gone.affected-services",
+                "metadata live/settings/motd.affected-services",
+            ]
+        );
+        assert_eq!(problems.iter().filter(|p| p.needs_repair()).count(), 6);
+    }
+
+    #[test]
+    fn generations() {
+        let dir = TempDir::new().unwrap();
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        datastore
+            .set_key(&key("settings.motd"), "\"hi\"", &Committed::Live)
+            .unwrap();
+        fs::write(dir.path().join("generation"), "x").unwrap();
+        fs::create_dir(dir.path().join("generations")).unwrap();
+        fs::write(dir.path().join("generations/0.json"), "{}").unwrap();
+        fs::write(dir.path().join("generations/1.json"), "{").unwrap();
+        fs::write(dir.path().join("generations/x.json"), "{}").unwrap();
+        // Other files are ignored by the data store.
+        fs::write(dir.path().join("generations/README"), "").unwrap();
+
+        let paths: Vec<PathBuf> = check(dir.path())
+            .unwrap()
+            .iter()
+            .map(|p| p.path().strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("generation"),
+                PathBuf::from("generations/1.json"),
+                PathBuf::from("generations/x.json"),
+            ]
+        );
+    }
+
+    #[test]
+    fn schema_paths() {
+        let schema = serde_json::to_value(model::schema::settings_schema()).unwrap();
+        assert!(in_model(&schema, &key("settings.motd")));
+        assert!(in_model(&schema, &key("settings.kernel.sysctl")));
+        // Maps take any name.
+        assert!(in_model(
+            &schema,
+            &key("settings.kernel.sysctl.\"vm.max_map_count\"")
+        ));
+        assert!(in_model(
+            &schema,
+            &key("settings.host-containers.admin.enabled")
+        ));
+        assert!(!in_model(&schema, &key("settings.gone")));
+        assert!(!in_model(&schema, &key("settings.motd.inner")));
+        assert!(!in_model(&schema, &key("services.x")));
+    }
+}
@@ -0,0 +1,267 @@
+/*!
+# Introduction
+
+datastore-fsck checks the filesystem data store for problems that could stop the API server from
+reading it, like a file that was partially written before a power loss, or a stray file.
+
+It walks the live data and each pending transaction, and reports:
+* entries with names that can't be decoded into keys, and entries that aren't regular files or
+  directories
+* values that can't be read, or that don't deserialize into the variant's model
+* metadata values that can't be read
+* metadata of keys that aren't part of the model, called orphaned metadata
+* a `live` symlink, generation file, or saved generations that can't be read
+* a commit that was interrupted, for example by a power loss, and hasn't been finished yet
+
+Orphaned metadata is harmless, and is usually left behind when a setting is removed, so it's
+reported but left alone.
+If any other problem is found, datastore-fsck exits with a non-zero status.
+
+## Repair
+
+With `--repair`, datastore-fsck first finishes any interrupted commit.
+If the `live` symlink doesn't point at the live directory of a generation, it's pointed back at
+the newest one; if there isn't one, the live data can't be repaired, and datastore-fsck exits with
+a non-zero status.
+It then moves bad entries into a `quarantine` directory in the data store,
+keeping their paths, so they can be inspected later.
+It then restores the defaults of the variant, the same defaults storewolf uses, for any
+quarantined live keys and metadata.
+
+Default settings are written to the `bottlerocket-launch` transaction rather than to live data,
+like storewolf does, so that they go through a commit, which writes the configuration files that
+use them.
+They're committed at the next boot, or you can commit them yourself:
+
+```shell
+apiclient raw -m POST -u '/tx/commit_and_apply?tx=bottlerocket-launch'
+```
+
+Keys of pending transactions are quarantined, but not restored, because their live values still
+apply.
+
+The data store isn't locked, so the API server should be stopped while repairing.
+*/
+
+#![deny(rust_2018_idioms)]
+
+#[macro_use]
+extern crate log;
+
+mod check;
+mod repair;
+
+use check::Problem;
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use snafu::{ensure, ResultExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, process};
+
+const DEFAULT_DATASTORE_PATH: &str = "/var/lib/bottlerocket/datastore/current";
+
+/// Repairing can reveal problems that couldn't be checked before, like the keys of a transaction
+/// with a bad name, so we check again after repairing, up to this many times.
+const MAX_REPAIR_ROUNDS: usize = 3;
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to check data store: {}", source))]
+        Check { source: crate::check::Error },
+
+        #[snafu(display("Failed to load default data: {}", source))]
+        Defaults { source: storewolf::error::Error },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display(
+            "Found {} problems in the data store; use --repair to quarantine them and restore defaults",
+            count
+        ))]
+        ProblemsFound { count: usize },
+
+        #[snafu(display("Failed to repair data store: {}", source))]
+        Repair { source: crate::repair::Error },
+
+        #[snafu(display(
+            "{} problems remain in the data store after {} rounds of repair",
+            count,
+            rounds
+        ))]
+        ProblemsRemain { count: usize, rounds: usize },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
+
+/// Store the args we receive on the command line
+struct Args {
+    datastore_path: PathBuf,
+    log_level: LevelFilter,
+    repair: bool,
+}
+
+/// Print a usage message in the event a bad arg is passed
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --datastore-path PATH ]
+            [ --repair ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Data store path defaults to {}",
+        program_name, DEFAULT_DATASTORE_PATH,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parse the args to the program and return an Args struct
+fn parse_args(args: env::Args) -> Args {
+    let mut datastore_path = None;
+    let mut log_level = None;
+    let mut repair = false;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--datastore-path" => {
+                datastore_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --datastore-path")),
+                )
+            }
+
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--repair" => repair = true,
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        datastore_path: datastore_path
+            .unwrap_or_else(|| DEFAULT_DATASTORE_PATH.to_string())
+            .into(),
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        repair,
+    }
+}
+
+/// Checks the data store and prints the problems found, returning the number that need repair.
+fn check_and_print(datastore_path: &Path) -> Result<(Vec<Problem>, usize)> {
+    let problems = check::check(datastore_path).context(error::Check)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let count = problems.iter().filter(|p| p.needs_repair()).count();
+    Ok((problems, count))
+}
+
+/// Repairs the data store, checking again after each round of repairs.
+fn repair_rounds(datastore_path: &Path, problems: Vec<Problem>) -> Result<()> {
+    let defaults = storewolf::defaults().context(error::Defaults)?;
+
+    // Each run quarantines into its own directory, so earlier quarantined entries are kept.
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let quarantine_path = datastore_path
+        .join("quarantine")
+        .join(timestamp.to_string());
+
+    let mut problems = problems;
+    let mut restored_settings = false;
+    for round in 1..=MAX_REPAIR_ROUNDS {
+        let repairs = repair::repair(datastore_path, &quarantine_path, &problems, &defaults)
+            .context(error::Repair)?;
+        if repairs.finished_commit {
+            println!("Finished interrupted commit");
+        }
+        for (from, to) in &repairs.quarantined {
+            println!("Quarantined {} to {}", from.display(), to.display());
+        }
+        for key in &repairs.restored_settings {
+            println!("Restored default of setting {}", key);
+        }
+        for restored in &repairs.restored {
+            println!("Restored default of {}", restored);
+        }
+        if let Some(generation) = repairs.generation {
+            println!("Restored generation {}", generation);
+        }
+        restored_settings |= !repairs.restored_settings.is_empty();
+
+        info!("Checking data store after round {} of repairs", round);
+        let (remaining, count) = check_and_print(datastore_path)?;
+        if count == 0 {
+            break;
+        }
+        ensure!(
+            round < MAX_REPAIR_ROUNDS,
+            error::ProblemsRemain {
+                count,
+                rounds: round
+            }
+        );
+        problems = remaining;
+    }
+
+    if restored_settings {
+        println!(
+            "Restored settings are in the '{}' transaction.  They'll be committed at the next boot, or you can commit them with:\n  apiclient raw -m POST -u '/tx/commit_and_apply?tx={}'",
+            constants::LAUNCH_TRANSACTION,
+            constants::LAUNCH_TRANSACTION
+        );
+    }
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    // Parse and store the args passed to the program
+    let args = parse_args(env::args());
+
+    // SimpleLogger will send errors to stderr and anything less to stdout.
+    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+
+    info!("Checking data store at {}", args.datastore_path.display());
+    let (problems, count) = check_and_print(&args.datastore_path)?;
+    if count == 0 {
+        info!("No problems need repair");
+        return Ok(());
+    }
+
+    ensure!(args.repair, error::ProblemsFound { count });
+    repair_rounds(&args.datastore_path, problems)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,452 @@
+//! The repair module fixes problems found by the check module.  Bad entries are moved into a
+//! quarantine directory, so they can be inspected later, and defaults are restored in their place.
+
+use crate::check::Problem;
+use datastore::{Committed, DataStore, FilesystemDataStore, Key};
+use snafu::{OptionExt, ResultExt};
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use storewolf::Defaults;
+
+/// What was done to repair the data store.
+#[derive(Debug, Default)]
+pub(crate) struct Repairs {
+    /// Entries that were moved into quarantine, and where they were moved.
+    pub(crate) quarantined: Vec<(PathBuf, PathBuf)>,
+    /// Settings whose defaults were written to the launch transaction.
+    pub(crate) restored_settings: Vec<Key>,
+    /// Other keys, and metadata, whose defaults were restored in live data.
+    pub(crate) restored: Vec<String>,
+    /// Whether an interrupted commit was finished.
+    pub(crate) finished_commit: bool,
+    /// The generation that was restored, if the generation file or the "live" symlink was bad.
+    pub(crate) generation: Option<u64>,
+}
+
+/// Repairs the given problems in the data store at the given path, moving bad entries into the
+/// given quarantine directory.
+///
+/// Quarantined live keys and metadata are restored from the given defaults.  Default settings
+/// are written to the launch transaction, like storewolf does, so they go through a commit that
+/// writes the configuration files that use them.  Keys of pending transactions aren't restored;
+/// they weren't committed, so their live values still apply.
+pub(crate) fn repair(
+    datastore_path: &Path,
+    quarantine_path: &Path,
+    problems: &[Problem],
+    defaults: &Defaults,
+) -> Result<Repairs> {
+    let mut datastore = FilesystemDataStore::new(datastore_path);
+    let mut repairs = Repairs::default();
+
+    // Finish an interrupted commit first, so the rest of the repairs apply to the live data it
+    // committed.  Its changes were complete when it was interrupted, so there's nothing to
+    // quarantine.
+    if problems
+        .iter()
+        .any(|p| matches!(p, Problem::InterruptedCommit { .. }))
+    {
+        datastore
+            .recover()
+            .context(error::DataStore { op: "recover" })?;
+        repairs.finished_commit = true;
+    }
+
+    // A bad "live" symlink hides all live data, so point it back at the live directory before the
+    // other repairs, which apply to live data.  The generation comes from the directory's name.
+    let live_path = datastore_path.join("live");
+    if problems
+        .iter()
+        .any(|p| matches!(p, Problem::InvalidGeneration { path, .. } if *path == live_path))
+    {
+        let generation = relink_live(datastore_path)?;
+        repairs.generation = Some(generation);
+    }
+
+    let generation_path = datastore_path.join("generation");
+    for problem in problems.iter().filter(|p| p.needs_repair()) {
+        // Only quarantine entries holding data; the "live" symlink was relinked above.
+        match problem {
+            Problem::InterruptedCommit { .. } => continue,
+            Problem::InvalidGeneration { path, .. } if *path != generation_path => continue,
+            _ => {}
+        }
+
+        if let Some(moved) = quarantine(datastore_path, quarantine_path, problem.path())? {
+            repairs
+                .quarantined
+                .push((problem.path().to_path_buf(), moved));
+        }
+
+        match problem {
+            Problem::InvalidValue {
+                key,
+                committed: Committed::Live,
+                ..
+            } => {
+                if let Some(value) = defaults.settings.get(key) {
+                    let launch = Committed::Pending {
+                        tx: constants::LAUNCH_TRANSACTION.to_string(),
+                    };
+                    // Don't replace a change that's already waiting to be committed.
+                    if !datastore
+                        .key_populated(key, &launch)
+                        .context(error::DataStore {
+                            op: "key_populated",
+                        })?
+                    {
+                        datastore
+                            .set_key(key, value, &launch)
+                            .context(error::DataStore { op: "set_key" })?;
+                    }
+                    repairs.restored_settings.push(key.clone());
+                } else if let Some(value) = defaults.other.get(key) {
+                    datastore
+                        .set_key(key, value, &Committed::Live)
+                        .context(error::DataStore { op: "set_key" })?;
+                    repairs.restored.push(key.name().clone());
+                }
+            }
+            Problem::InvalidMetadata {
+                data_key,
+                metadata_key,
+                ..
+            } => {
+                let pair = (data_key.clone(), metadata_key.clone());
+                if let Some(value) = defaults.metadata.get(&pair) {
+                    datastore
+                        .set_metadata(metadata_key, data_key, value)
+                        .context(error::DataStore { op: "set_metadata" })?;
+                    repairs
+                        .restored
+                        .push(format!("{} of {}", metadata_key, data_key));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The current generation follows the latest saved generation.  Restore it after quarantining
+    // bad saved generations, so we don't count them.
+    if problems
+        .iter()
+        .any(|p| matches!(p, Problem::InvalidGeneration { path, .. } if *path == generation_path))
+    {
+        let generation = datastore
+            .list_generations()
+            .context(error::DataStore {
+                op: "list_generations",
+            })?
+            .last()
+            .map(|latest| latest + 1)
+            .unwrap_or(0);
+        fs::write(&generation_path, generation.to_string()).context(error::Io {
+            path: &generation_path,
+        })?;
+        repairs.generation = Some(generation);
+    }
+
+    Ok(repairs)
+}
+
+/// Points the "live" symlink at the newest live directory, named for its generation, e.g. live.41,
+/// and returns its generation.  Fails if there's no live directory to point it at; the live data
+/// is gone, and can't be restored from defaults.
+fn relink_live(datastore_path: &Path) -> Result<u64> {
+    let entries = fs::read_dir(datastore_path).context(error::Io {
+        path: datastore_path,
+    })?;
+    let mut newest = None;
+    for entry in entries {
+        let entry = entry.context(error::Io {
+            path: datastore_path,
+        })?;
+        let generation = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("live."))
+            .and_then(|generation| generation.parse::<u64>().ok());
+        if let Some(generation) = generation {
+            if entry.path().is_dir() && newest.map_or(true, |newest| generation > newest) {
+                newest = Some(generation);
+            }
+        }
+    }
+    let live_path = datastore_path.join("live");
+    let generation = newest.context(error::NoLiveDirectory { path: &live_path })?;
+
+    // Renaming a new symlink over the bad one replaces it atomically, like the data store does.
+    let new_link = datastore_path.join("live.new");
+    match fs::remove_file(&new_link) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context(error::Io { path: &new_link }),
+    }
+    symlink(format!("live.{}", generation), &new_link).context(error::Io { path: &new_link })?;
+    fs::rename(&new_link, &live_path).context(error::Io { path: &live_path })?;
+    debug!("Pointed {} at live.{}", live_path.display(), generation);
+    Ok(generation)
+}
+
+/// Moves the given entry of the data store into quarantine, keeping its path relative to the data
+/// store, and removes the directories that held it if they're left empty.  Returns the new path
+/// of the entry, or None if it was already gone, for example because the directory holding it was
+/// quarantined.
+fn quarantine(
+    datastore_path: &Path,
+    quarantine_path: &Path,
+    path: &Path,
+) -> Result<Option<PathBuf>> {
+    let relative = path
+        .strip_prefix(datastore_path)
+        .ok()
+        .context(error::OutsideDataStore { path })?;
+    let destination = quarantine_path.join(relative);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).context(error::Io { path: parent })?;
+    }
+
+    match fs::rename(path, &destination) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(error::Io { path }),
+    }
+    debug!("Moved {} to {}", path.display(), destination.display());
+
+    // Remove directories left empty, stopping at the top of the live and pending trees.
+    let stops = [
+        datastore_path.to_path_buf(),
+        datastore_path.join("live"),
+        datastore_path.join("pending"),
+    ];
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if stops.iter().any(|stop| stop == dir) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        trace!("Removed empty directory {}", dir.display());
+        parent = dir.parent();
+    }
+
+    Ok(Some(destination))
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Data store error during {}: {}", op, source))]
+        DataStore {
+            op: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("I/O error on '{}': {}", path.display(), source))]
+        Io {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display(
+            "No live directory to point '{}' at; live data can't be repaired",
+            path.display()
+        ))]
+        NoLiveDirectory { path: PathBuf },
+
+        #[snafu(display("Path '{}' is outside the data store", path.display()))]
+        OutsideDataStore { path: PathBuf },
+    }
+}
+pub(crate) use error::Error;
+type Result<T> = std::result::Result<T, error::Error>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::check::check;
+    use datastore::KeyType;
+    use tempfile::TempDir;
+
+    fn key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    #[test]
+    fn quarantine_and_restore() {
+        let dir = TempDir::new().unwrap();
+        let quarantine_path = dir.path().join("quarantine/1");
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        let launch = Committed::Pending {
+            tx: constants::LAUNCH_TRANSACTION.to_string(),
+        };
+        datastore
+            .set_key(&key("settings.motd"), "\"hi", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_key(&key("settings.ntp.time-servers"), "1", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_key(&key("settings.ntp.time-servers"), "[\"a\"]", &launch)
+            .unwrap();
+        datastore
+            .set_key(&key("settings.kernel.lockdown"), "1", &launch)
+            .unwrap();
+        fs::write(dir.path().join("generation"), "x").unwrap();
+
+        let mut defaults = Defaults::default();
+        defaults
+            .settings
+            .insert(key("settings.motd"), "\"default\"".to_string());
+        defaults
+            .settings
+            .insert(key("settings.ntp.time-servers"), "[\"b\"]".to_string());
+        defaults
+            .settings
+            .insert(key("settings.kernel.lockdown"), "\"none\"".to_string());
+
+        let problems = check(dir.path()).unwrap();
+        assert_eq!(problems.len(), 4);
+        let repairs = repair(dir.path(), &quarantine_path, &problems, &defaults).unwrap();
+        assert_eq!(repairs.quarantined.len(), 4);
+        assert_eq!(repairs.generation, Some(0));
+        assert!(check(dir.path()).unwrap().is_empty());
+
+        // The bad files are kept in quarantine.
+        assert_eq!(
+            fs::read_to_string(quarantine_path.join("live/settings/motd")).unwrap(),
+            "\"hi"
+        );
+        assert!(quarantine_path
+            .join("pending/bottlerocket-launch/settings/kernel/lockdown")
+            .exists());
+        // The directory of the removed live key was removed once empty.
+        assert!(!dir.path().join("live/settings/ntp").exists());
+
+        // Defaults of live settings are restored to the launch transaction, without replacing a
+        // pending change.  Pending keys aren't restored.
+        assert_eq!(
+            datastore
+                .get_key(&key("settings.motd"), &Committed::Live)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            datastore.get_key(&key("settings.motd"), &launch).unwrap(),
+            Some("\"default\"".to_string())
+        );
+        assert_eq!(
+            datastore
+                .get_key(&key("settings.ntp.time-servers"), &launch)
+                .unwrap(),
+            Some("[\"a\"]".to_string())
+        );
+        assert_eq!(
+            datastore
+                .get_key(&key("settings.kernel.lockdown"), &launch)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn finish_interrupted_commit() {
+        let dir = TempDir::new().unwrap();
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        datastore
+            .set_key(&key("settings.motd"), "\"old\"", &Committed::Live)
+            .unwrap();
+        // A commit that was staged and recorded, but not swapped into place.
+        fs::create_dir_all(dir.path().join("live.1/settings")).unwrap();
+        fs::write(dir.path().join("live.1/settings/motd"), "\"new\"").unwrap();
+        fs::write(
+            dir.path().join("commit"),
+            r#"{"generation":1,"transaction":null}"#,
+        )
+        .unwrap();
+
+        let problems = check(dir.path()).unwrap();
+        assert_eq!(problems.len(), 1);
+        let repairs = repair(
+            dir.path(),
+            &dir.path().join("quarantine/1"),
+            &problems,
+            &Defaults::default(),
+        )
+        .unwrap();
+        assert!(repairs.finished_commit);
+        assert!(repairs.quarantined.is_empty());
+        assert!(check(dir.path()).unwrap().is_empty());
+        assert_eq!(
+            datastore
+                .get_key(&key("settings.motd"), &Committed::Live)
+                .unwrap(),
+            Some("\"new\"".to_string())
+        );
+        assert_eq!(datastore.generation().unwrap(), 1);
+    }
+
+    #[test]
+    fn relink_bad_live_link() {
+        let dir = TempDir::new().unwrap();
+        let mut datastore = FilesystemDataStore::new(dir.path());
+        datastore
+            .set_key(&key("settings.motd"), "\"hi\"", &Committed::Live)
+            .unwrap();
+        datastore
+            .set_key(
+                &key("settings.ntp.time-servers"),
+                "[\"a\"]",
+                &Committed::Pending { tx: "tx".into() },
+            )
+            .unwrap();
+        datastore.commit_transaction("tx").unwrap();
+        // Make sure the first commit converted the live directory to a symlink.
+        let generation = datastore.generation().unwrap();
+        assert!(dir.path().join(format!("live.{}", generation)).is_dir());
+
+        // A live symlink pointing at a directory that isn't a generation's live directory.
+        let live_path = dir.path().join("live");
+        fs::create_dir(dir.path().join("elsewhere")).unwrap();
+        fs::remove_file(&live_path).unwrap();
+        symlink("elsewhere", &live_path).unwrap();
+
+        let problems = check(dir.path()).unwrap();
+        assert!(problems
+            .iter()
+            .any(|p| matches!(p, Problem::InvalidGeneration { path, .. } if *path == live_path)));
+        let repairs = repair(
+            dir.path(),
+            &dir.path().join("quarantine/1"),
+            &problems,
+            &Defaults::default(),
+        )
+        .unwrap();
+        assert_eq!(repairs.generation, Some(generation));
+        assert!(check(dir.path()).unwrap().is_empty());
+        assert_eq!(
+            datastore
+                .get_key(&key("settings.motd"), &Committed::Live)
+                .unwrap(),
+            Some("\"hi\"".to_string())
+        );
+
+        // With no live directory left, the live data can't be repaired.
+        fs::remove_dir_all(dir.path().join(format!("live.{}", generation))).unwrap();
+        fs::remove_file(&live_path).unwrap();
+        symlink("elsewhere", &live_path).unwrap();
+        let problems = check(dir.path()).unwrap();
+        let err = repair(
+            dir.path(),
+            &dir.path().join("quarantine/2"),
+            &problems,
+            &Defaults::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::NoLiveDirectory { .. }));
+    }
+}
@@ -59,6 +59,30 @@ pub enum Error {
 
     #[snafu(display("Key name beyond maximum length {}: {}", name, max))]
     KeyTooLong { name: String, max: usize },
+
+    #[snafu(display("Generation {} is not saved in the data store", generation))]
+    GenerationNotFound { generation: u64 },
+
+    #[snafu(display("Error serializing settings of generation {}: {}", generation, source))]
+    GenerationSerialize {
+        generation: u64,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error parsing saved generation at {}: {}", path.display(), source))]
+    GenerationParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error serializing commit record: {}", source))]
+    CommitRecordSerialize { source: serde_json::Error },
+
+    #[snafu(display("Error parsing commit record at {}: {}", path.display(), source))]
+    CommitRecordParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
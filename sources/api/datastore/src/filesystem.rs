@@ -3,21 +3,53 @@
 //!
 //! Data is kept in files with paths resembling the keys, e.g. a/b/c for a.b.c, and metadata is
 //! kept in a suffixed file next to the data, e.g. a/b/c.meta for metadata "meta" about a.b.c
+//!
+//! Live data is kept in a directory named for its generation, e.g. live.41, and the "live" symlink
+//! points to it.  A commit hard-links the live data into a directory for the next generation,
+//! applies the pending changes there, and then replaces the symlink, so readers see all of a commit
+//! or none of it.  Files are only ever replaced, never written in place, so changing a file in the
+//! new directory doesn't change the previous generation, and unchanged files aren't copied.  Before
+//! the symlink is replaced, a record of the commit is written to the "commit" file; that's the
+//! point at which the commit happens.  If we're interrupted after it, the commit is finished by the
+//! next change to the data store, or by `recover`.  Older data stores have a "live" directory, with
+//! the generation kept in the "generation" file; their first commit converts them.
+//!
+//! The settings of saved prior generations are kept as JSON maps of key name to value in the
+//! "generations" directory, e.g. generations/41.json.
+//!
+//! Each file is written to a temporary file that's renamed into place, so it has either its old or
+//! its new contents, even after a crash.  By default, files and directories are also synced to
+//! disk, so changes survive a power loss; see `SyncPolicy`.  A commit only syncs the files it
+//! writes and the directories of the new generation; linked files were synced when written.
+//!
+//! Files that don't represent valid keys are skipped when listing keys; `find_invalid_paths`
+//! finds them, for tools that check the integrity of a data store.
 
-use log::{debug, error, trace};
+use log::{debug, error, info, trace};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::fs;
-use std::io;
+use std::io::{self, Write};
+use std::os::unix::fs::symlink;
 use std::path::{self, Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
 use super::key::{Key, KeyType};
-use super::{error, Committed, DataStore, Result};
+use super::{error, rollback_changes, Committed, DataStore, Result, GENERATIONS_KEPT};
 
 const METADATA_KEY_PREFIX: &str = ".";
 
+// The directory holding the live data of a generation is named with this prefix and the generation
+// number.  Other entries with the prefix are temporary, and are removed after each commit.
+const LIVE_DIR_PREFIX: &str = "live.";
+// A live directory from an older data store is moved here while it's replaced with a symlink.
+const OLD_LIVE_DIR: &str = "live.old";
+// A new symlink is made here and then renamed over the "live" symlink.
+const NEW_LIVE_LINK: &str = "live.new";
+
 // This describes the set of characters we encode when making the filesystem path for a given key.
 // Any non-ASCII characters, plus these ones, will be encoded.
 // We start off very strict (anything not alphanumeric) and remove characters we'll allow.
@@ -25,20 +57,53 @@ const METADATA_KEY_PREFIX: &str = ".";
 // allowed in a Key.
 const ENCODE_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'_').remove(b'-');
 
+/// Whether the data store syncs files and directories to disk as it writes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync each change before it's considered done, so that it survives a power loss.  This is
+    /// the default, and it's meant for data stores in use, like the one the API server serves.
+    Always,
+    /// Leave syncing to the OS.  This is faster, but recent changes can be lost on a power loss,
+    /// so it's only suitable for data stores that are thrown away or rebuilt if we're interrupted,
+    /// like the intermediate data stores of a migration, or data stores in tests.  Call `sync`
+    /// once the data store is complete, if it's kept.  Each write still replaces a file all at
+    /// once, and commits are still atomic.  A data store that's only partly written to disk can
+    /// have empty or truncated files, so one that's kept as-is after an interruption, like the
+    /// one storewolf populates, needs `Always`.
+    Never,
+}
+
 #[derive(Debug)]
 pub struct FilesystemDataStore {
+    datastore_path: PathBuf,
     live_path: PathBuf,
     pending_base_path: PathBuf,
+    generation_path: PathBuf,
+    generations_base_path: PathBuf,
+    commit_record_path: PathBuf,
+    sync_policy: SyncPolicy,
 }
 
 impl FilesystemDataStore {
     pub fn new<P: AsRef<Path>>(base_path: P) -> FilesystemDataStore {
         FilesystemDataStore {
+            datastore_path: base_path.as_ref().to_path_buf(),
             live_path: base_path.as_ref().join("live"),
             pending_base_path: base_path.as_ref().join("pending"),
+            generation_path: base_path.as_ref().join("generation"),
+            generations_base_path: base_path.as_ref().join("generations"),
+            commit_record_path: base_path.as_ref().join("commit"),
+            sync_policy: SyncPolicy::Always,
         }
     }
 
+    /// Sets whether the data store syncs its changes to disk.  The default is
+    /// `SyncPolicy::Always`.
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.sync_policy = sync_policy;
+        self
+    }
+
     /// Returns the appropriate filesystem path for pending or live data.
     fn base_path(&self, committed: &Committed) -> PathBuf {
         match committed {
@@ -51,31 +116,12 @@ impl FilesystemDataStore {
     }
 
     /// Returns the appropriate path on the filesystem for the given data key.
-    fn data_path(&self, key: &Key, committed: &Committed) -> Result<PathBuf> {
-        let base_path = self.base_path(committed);
-
-        // Encode key segments so they're filesystem-safe
-        let encoded: Vec<_> = key.segments().iter().map(encode_path_component).collect();
-        // Join segments with filesystem separator to get path underneath data store
-        let path_suffix = encoded.join(&path::MAIN_SEPARATOR.to_string());
-
-        // Make path from base + prefix
-        // FIXME: canonicalize requires that the full path exists.  We know our Key is checked
-        // for acceptable characters, so join should be safe enough, but come back to this.
-        // let path = fs::canonicalize(self.base_path.join(path_suffix))?;
-        let path = base_path.join(path_suffix);
-
-        // Confirm no path traversal outside of base
-        ensure!(
-            path != *base_path && path.starts_with(base_path),
-            error::PathTraversal { name: key.name() }
-        );
-
-        Ok(path)
+    pub fn data_path(&self, key: &Key, committed: &Committed) -> Result<PathBuf> {
+        key_path(&self.base_path(committed), key)
     }
 
     /// Returns the appropriate path on the filesystem for the given metadata key.
-    fn metadata_path(
+    pub fn metadata_path(
         &self,
         metadata_key: &Key,
         data_key: &Key,
@@ -117,60 +163,394 @@ impl FilesystemDataStore {
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
+        delete_key_path(path.as_ref(), &self.base_path(committed))
+    }
+}
+
+/// The record of a commit, written once the live directory of its generation is ready.  Once it's
+/// written, the commit has happened, and finishing it only makes it visible and cleans up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CommitRecord {
+    /// The generation whose live directory is being made live.
+    generation: u64,
+    /// The transaction being committed, which is removed once it's live; None for rollbacks.
+    transaction: Option<String>,
+}
 
-        // Remove the file.  If it doesn't exist, we're still OK.
-        match fs::remove_file(path) {
-            Ok(()) => {}
+impl FilesystemDataStore {
+    /// Returns the path of the saved settings of the given generation.
+    fn saved_generation_path(&self, generation: u64) -> PathBuf {
+        self.generations_base_path
+            .join(format!("{}.json", generation))
+    }
+
+    /// Returns the path of the directory holding the live data of the given generation.
+    fn live_dir(&self, generation: u64) -> PathBuf {
+        self.datastore_path
+            .join(format!("{}{}", LIVE_DIR_PREFIX, generation))
+    }
+
+    /// Returns the generation of the live directory that the "live" symlink points to, or None if
+    /// there's no symlink, i.e. there's no live data yet or it's an older data store.
+    fn live_generation(&self) -> Result<Option<u64>> {
+        match fs::symlink_metadata(&self.live_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {}
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
             Err(e) => {
-                if e.kind() != io::ErrorKind::NotFound {
-                    return Err(e).context(error::DeleteKey { path });
-                }
+                return Err(e).context(error::Io {
+                    path: &self.live_path,
+                })
             }
         }
 
-        // Remove the directory if it's empty, i.e. if the setting we removed was the last setting
-        // in that prefix.  Continue up the tree until the base, in case it was the only thing in
-        // that subtree.
-        let base = self.base_path(committed);
-        if let Some(parent) = path.parent() {
-            // Note: ancestors() includes 'parent' itself
-            for parent in parent.ancestors() {
-                // Stop at the base directory; we don't expect anything here or above to be empty,
-                // but stop as a safeguard.
-                if parent == base {
-                    break;
+        let target = fs::read_link(&self.live_path).context(error::Io {
+            path: &self.live_path,
+        })?;
+        let generation = target
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(LIVE_DIR_PREFIX))
+            .and_then(|generation| generation.parse().ok())
+            .context(error::Corruption {
+                msg: format!("live link points to '{}'", target.display()),
+                path: &self.live_path,
+            })?;
+        Ok(Some(generation))
+    }
+
+    /// Saves the live settings as the given generation, removing the oldest saved generations so
+    /// we keep at most GENERATIONS_KEPT.
+    fn save_generation(&mut self, generation: u64) -> Result<()> {
+        let live = self.get_prefix("settings.", &Committed::Live)?;
+        let saved: HashMap<&String, &String> = live
+            .iter()
+            .map(|(key, value)| (key.name(), value))
+            .collect();
+        let data =
+            serde_json::to_string(&saved).context(error::GenerationSerialize { generation })?;
+
+        debug!("Saving live settings as generation {}", generation);
+        write_file_mkdir(
+            self.saved_generation_path(generation),
+            data,
+            self.sync_policy,
+        )?;
+
+        let generations = self.list_generations()?;
+        if generations.len() > GENERATIONS_KEPT {
+            let excess = generations.len() - GENERATIONS_KEPT;
+            for old in &generations[..excess] {
+                debug!("Removing old generation {}", old);
+                let path = self.saved_generation_path(*old);
+                fs::remove_file(&path).context(error::Io { path })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares a commit that makes the given changes to live data.  The current live settings
+    /// are saved so the commit can be rolled back, and the live directory of the next generation
+    /// is made from the current live data and the changes.  Nothing is visible to readers until
+    /// the commit is completed with `complete_commit`; if we're interrupted before then, the
+    /// prepared directory is replaced by the next attempt.
+    fn prepare_commit(
+        &mut self,
+        to_set: &HashMap<Key, String>,
+        to_unset: &HashSet<Key>,
+        transaction: Option<String>,
+    ) -> Result<CommitRecord> {
+        let generation = self.generation()?;
+        self.save_generation(generation)?;
+
+        let record = CommitRecord {
+            generation: generation + 1,
+            transaction,
+        };
+        let dir = self.live_dir(record.generation);
+        debug!("Preparing live directory {}", dir.display());
+        remove_path(&dir)?;
+        if self.live_path.exists() {
+            link_dir(&self.live_path, &dir)?;
+        } else {
+            fs::create_dir_all(&dir).context(error::Io { path: &dir })?;
+        }
+
+        // The directories are all new, so they're synced at once below; we only sync the files we
+        // write, since linked files were synced when they were written.
+        for (key, value) in to_set {
+            let path = key_path(&dir, key)?;
+            write_file_mkdir(path.clone(), value, SyncPolicy::Never)?;
+            sync_path(&path, self.sync_policy)?;
+        }
+        for key in to_unset {
+            delete_key_path(&key_path(&dir, key)?, &dir)?;
+        }
+        sync_dirs(&dir, self.sync_policy)?;
+        sync_path(&self.datastore_path, self.sync_policy)?;
+
+        Ok(record)
+    }
+
+    /// Completes a prepared commit by writing its record, the point at which the commit happens,
+    /// and then finishing it.
+    fn complete_commit(&self, record: &CommitRecord) -> Result<()> {
+        let data = serde_json::to_string(record).context(error::CommitRecordSerialize)?;
+        write_file_mkdir(self.commit_record_path.clone(), data, self.sync_policy)?;
+        self.finish_commit(record)
+    }
+
+    /// Makes a recorded commit visible by pointing the "live" symlink at the live directory of
+    /// its generation, then cleans up the committed transaction, old live directories, and the
+    /// record.  Each step can be repeated, so this can finish a commit that was interrupted.
+    fn finish_commit(&self, record: &CommitRecord) -> Result<()> {
+        let dir = self.live_dir(record.generation);
+        ensure!(
+            dir.is_dir(),
+            error::Corruption {
+                msg: format!("live directory of generation {} missing", record.generation),
+                path: &self.commit_record_path,
+            }
+        );
+
+        if self.live_generation()? != Some(record.generation) {
+            // An older data store has a live directory rather than a symlink, and it has to be
+            // moved aside before the symlink can take its place.  If we're interrupted before the
+            // symlink is in place, there's no live data, but the commit record is still there,
+            // so the commit will be finished before anything else is done.
+            let is_dir = match fs::symlink_metadata(&self.live_path) {
+                Ok(metadata) => metadata.is_dir(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+                Err(e) => {
+                    return Err(e).context(error::Io {
+                        path: &self.live_path,
+                    })
                 }
-                if let Err(e) = fs::remove_dir(parent) {
-                    // If the directory doesn't exist, continue up the tree.  Modulo timing issues,
-                    // this means the key didn't exist either, which means a previous attempt to remove
-                    // the directory failed or we got an unset request for a bogus key.  Either way, we
-                    // can clean up and make things consistent.
-                    if e.kind() == io::ErrorKind::NotFound {
-                        continue;
-
-                    // "Directory not empty" doesn't have its own ErrorKind, so we have to check a
-                    // platform-specific error number or the error description, neither of which is
-                    // ideal.  Still, we can at least log an error in the case we know.  Don't
-                    // fail, though, because we've still accomplished our main purpose.
-                    } else if e.raw_os_error() != Some(39) {
-                        error!(
-                            "Failed to delete directory '{}' we believe is empty: {}",
-                            parent.display(),
-                            e
-                        );
-                    }
-                    // We won't be able to delete parent directories if this one still exists.
-                    break;
+            };
+            if is_dir {
+                let old = self.datastore_path.join(OLD_LIVE_DIR);
+                remove_path(&old)?;
+                fs::rename(&self.live_path, &old).context(error::Io {
+                    path: &self.live_path,
+                })?;
+            }
+
+            // Renaming a new symlink over the old one replaces it atomically.
+            let new_link = self.datastore_path.join(NEW_LIVE_LINK);
+            remove_path(&new_link)?;
+            let target = format!("{}{}", LIVE_DIR_PREFIX, record.generation);
+            symlink(&target, &new_link).context(error::Io { path: &new_link })?;
+            fs::rename(&new_link, &self.live_path).context(error::Io {
+                path: &self.live_path,
+            })?;
+            sync_path(&self.datastore_path, self.sync_policy)?;
+            info!("Live data is now generation {}", record.generation);
+        }
+
+        if let Some(transaction) = &record.transaction {
+            let pending = self.base_path(&Committed::Pending {
+                tx: transaction.clone(),
+            });
+            debug!("Removing committed transaction {}", pending.display());
+            remove_path(&pending)?;
+            sync_path(&self.pending_base_path, self.sync_policy)?;
+        }
+
+        // Remove the live directories of older generations, and anything left over from
+        // interrupted commits.  Older data stores kept the generation in a file, which the
+        // symlink replaces.
+        let entries = fs::read_dir(&self.datastore_path).context(error::Io {
+            path: &self.datastore_path,
+        })?;
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.datastore_path,
+            })?;
+            let path = entry.path();
+            let name = entry.file_name();
+            let is_live_entry = name
+                .to_str()
+                .map(|name| name.starts_with(LIVE_DIR_PREFIX))
+                .unwrap_or(false);
+            if is_live_entry && path != dir {
+                debug!("Removing old live data {}", path.display());
+                remove_path(&path)?;
+            }
+        }
+        remove_path(&self.generation_path)?;
+        sync_path(&self.datastore_path, self.sync_policy)?;
+
+        remove_path(&self.commit_record_path)?;
+        sync_path(&self.datastore_path, self.sync_policy)
+    }
+
+    /// Syncs the whole data store to disk: live data, pending transactions, and saved generations.
+    /// This is for data stores made with `SyncPolicy::Never` that are kept once they're complete.
+    pub fn sync(&self) -> Result<()> {
+        debug!("Syncing data store {}", self.datastore_path.display());
+        sync_tree(&self.datastore_path)
+    }
+
+    /// Finishes a commit or rollback that was interrupted after the point at which it happened,
+    /// if there is one.  Changes to the data store do this first, so they're made on top of the
+    /// finished commit; call it after opening a data store if reads should see it, too.
+    pub fn recover(&self) -> Result<()> {
+        let data = match fs::read_to_string(&self.commit_record_path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(e).context(error::Io {
+                    path: &self.commit_record_path,
+                })
+            }
+        };
+        let record: CommitRecord =
+            serde_json::from_str(&data).context(error::CommitRecordParse {
+                path: &self.commit_record_path,
+            })?;
+
+        info!(
+            "Finishing interrupted commit of generation {}",
+            record.generation
+        );
+        self.finish_commit(&record)
+    }
+}
+
+/// An entry in the data store that doesn't represent a valid key or transaction, as found by
+/// `find_invalid_paths`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidPath {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl FilesystemDataStore {
+    /// Walks the live data and every pending transaction, returning any entries that don't
+    /// represent a valid key, like files with names that can't be decoded, or entries that
+    /// aren't regular files or directories.  Listing keys skips such entries, so this is the way
+    /// to find them.
+    pub fn find_invalid_paths(&self) -> Result<Vec<InvalidPath>> {
+        let mut invalid = Vec::new();
+        if self.live_path.exists() {
+            find_invalid_key_paths(&self.live_path, true, &mut invalid)?;
+        }
+
+        let entries = match fs::read_dir(&self.pending_base_path) {
+            Ok(entries) => entries,
+            // No pending transactions.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(invalid),
+            Err(e) => {
+                return Err(e).context(error::Io {
+                    path: &self.pending_base_path,
+                })
+            }
+        };
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.pending_base_path,
+            })?;
+            let path = entry.path();
+            let file_type = entry.file_type().context(error::Io { path: &path })?;
+            if !file_type.is_dir() {
+                invalid.push(InvalidPath {
+                    path,
+                    reason: "not a transaction directory".to_string(),
+                });
+                continue;
+            }
+            let name_check = entry
+                .file_name()
+                .to_str()
+                .map(|name| decode_path_component(name, &path).map(|_| ()));
+            match name_check {
+                Some(Ok(())) => find_invalid_key_paths(&path, false, &mut invalid)?,
+                Some(Err(e)) => invalid.push(InvalidPath {
+                    path,
+                    reason: e.to_string(),
+                }),
+                None => invalid.push(InvalidPath {
+                    path,
+                    reason: "transaction name is not UTF-8".to_string(),
+                }),
+            }
+        }
+
+        Ok(invalid)
+    }
+}
+
+/// Helper for `find_invalid_paths` that checks the entries under the base path of the live data
+/// or of a pending transaction.  Metadata is only stored in live data.
+fn find_invalid_key_paths(
+    base: &Path,
+    allow_metadata: bool,
+    invalid: &mut Vec<InvalidPath>,
+) -> Result<()> {
+    let walker = WalkDir::new(base)
+        .min_depth(1)
+        .follow_links(false)
+        .same_file_system(true);
+
+    for entry in walker {
+        let entry = entry.context(error::ListKeys)?;
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        }
+        let path = entry.path().to_path_buf();
+        if !file_type.is_file() {
+            invalid.push(InvalidPath {
+                path,
+                reason: "not a regular file or directory".to_string(),
+            });
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(base).context(error::Path)?;
+        match KeyPath::from_path(relative) {
+            Ok(key_path) => {
+                if key_path.metadata_key.is_some() && !allow_metadata {
+                    invalid.push(InvalidPath {
+                        path,
+                        reason: "metadata in a pending transaction".to_string(),
+                    });
                 }
             }
+            Err(e) => invalid.push(InvalidPath {
+                path,
+                reason: e.to_string(),
+            }),
         }
-        Ok(())
     }
+    Ok(())
 }
 
 // Filesystem helpers
 
+/// Returns the path of the given data key under the given base path, i.e. the live data, a live
+/// directory being prepared, or a pending transaction.
+fn key_path(base_path: &Path, key: &Key) -> Result<PathBuf> {
+    // Encode key segments so they're filesystem-safe
+    let encoded: Vec<_> = key.segments().iter().map(encode_path_component).collect();
+    // Join segments with filesystem separator to get path underneath data store
+    let path_suffix = encoded.join(&path::MAIN_SEPARATOR.to_string());
+
+    // Make path from base + prefix
+    // FIXME: canonicalize requires that the full path exists.  We know our Key is checked
+    // for acceptable characters, so join should be safe enough, but come back to this.
+    // let path = fs::canonicalize(self.base_path.join(path_suffix))?;
+    let path = base_path.join(path_suffix);
+
+    // Confirm no path traversal outside of base
+    ensure!(
+        path != *base_path && path.starts_with(base_path),
+        error::PathTraversal { name: key.name() }
+    );
+
+    Ok(path)
+}
+
 /// Encodes a string so that it's safe to use as a filesystem path component.
 fn encode_path_component<S: AsRef<str>>(segment: S) -> String {
     let encoded = utf8_percent_encode(segment.as_ref(), ENCODE_CHARACTERS);
@@ -198,6 +578,57 @@ where
         })
 }
 
+/// Helper for `delete_key_path` that stops removing empty directories at the given base path,
+/// so it can also be used on a live directory that's being prepared for a commit.
+fn delete_key_path(path: &Path, base: &Path) -> Result<()> {
+    // Remove the file.  If it doesn't exist, we're still OK.
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) => {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e).context(error::DeleteKey { path });
+            }
+        }
+    }
+
+    // Remove the directory if it's empty, i.e. if the setting we removed was the last setting
+    // in that prefix.  Continue up the tree until the base, in case it was the only thing in
+    // that subtree.
+    if let Some(parent) = path.parent() {
+        // Note: ancestors() includes 'parent' itself
+        for parent in parent.ancestors() {
+            // Stop at the base directory; we don't expect anything here or above to be empty,
+            // but stop as a safeguard.
+            if parent == base {
+                break;
+            }
+            if let Err(e) = fs::remove_dir(parent) {
+                // If the directory doesn't exist, continue up the tree.  Modulo timing issues,
+                // this means the key didn't exist either, which means a previous attempt to remove
+                // the directory failed or we got an unset request for a bogus key.  Either way, we
+                // can clean up and make things consistent.
+                if e.kind() == io::ErrorKind::NotFound {
+                    continue;
+
+                // "Directory not empty" doesn't have its own ErrorKind, so we have to check a
+                // platform-specific error number or the error description, neither of which is
+                // ideal.  Still, we can at least log an error in the case we know.  Don't
+                // fail, though, because we've still accomplished our main purpose.
+                } else if e.raw_os_error() != Some(39) {
+                    error!(
+                        "Failed to delete directory '{}' we believe is empty: {}",
+                        parent.display(),
+                        e
+                    );
+                }
+                // We won't be able to delete parent directories if this one still exists.
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Helper for reading a key from the filesystem.  Returns Ok(None) if the file doesn't exist
 /// rather than erroring.
 fn read_file_for_key(key: &Key, path: &Path) -> Result<Option<String>> {
@@ -215,7 +646,11 @@ fn read_file_for_key(key: &Key, path: &Path) -> Result<Option<String>> {
 
 /// Helper for writing a file that makes the directory tree beforehand, so we can handle
 /// arbitrarily dotted keys without needing to create fixed structure first.
-fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
+///
+/// The data is written to a temporary file that's renamed into place, so readers see either the
+/// old or the new contents, even if we're interrupted.  The temporary file's name starts with a
+/// dot, so it's never mistaken for a key.
+fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S, sync_policy: SyncPolicy) -> Result<()> {
     // create key prefix directory if necessary
     let dirname = path.parent().with_context(|| error::Internal {
         msg: format!(
@@ -223,9 +658,121 @@ fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
             path.display()
         ),
     })?;
-    fs::create_dir_all(dirname).context(error::Io { path: dirname })?;
+    create_dir_all(dirname, sync_policy)?;
+
+    let file_name = path.file_name().with_context(|| error::Internal {
+        msg: format!("Given path to write without file name: {}", path.display()),
+    })?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".tmp");
+    let tmp_path = dirname.join(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path).context(error::Io { path: &tmp_path })?;
+    file.write_all(data.as_ref().as_bytes())
+        .context(error::Io { path: &tmp_path })?;
+    if sync_policy == SyncPolicy::Always {
+        file.sync_all().context(error::Io { path: &tmp_path })?;
+    }
+    fs::rename(&tmp_path, &path).context(error::Io { path: &path })?;
+    sync_path(dirname, sync_policy)
+}
+
+/// Helper for creating a directory and any missing parents.  Each new directory is synced into its
+/// parent, according to the sync policy.
+fn create_dir_all(path: &Path, sync_policy: SyncPolicy) -> Result<()> {
+    if path.as_os_str().is_empty() || path.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent, sync_policy)?;
+    }
+    match fs::create_dir(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(()),
+        Err(e) => return Err(e).context(error::Io { path }),
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => sync_path(parent, sync_policy),
+        _ => Ok(()),
+    }
+}
+
+/// Helper for syncing a file or directory to disk, if the sync policy calls for it.  Syncing a
+/// directory makes changes to its entries durable, like new, renamed, or removed files.
+fn sync_path(path: &Path, sync_policy: SyncPolicy) -> Result<()> {
+    if sync_policy == SyncPolicy::Always {
+        fs::File::open(path)
+            .and_then(|file| file.sync_all())
+            .context(error::Io { path })?;
+    }
+    Ok(())
+}
 
-    fs::write(&path, data.as_ref().as_bytes()).context(error::Io { path: &path })
+/// Helper for syncing the directories of a directory tree to disk, if the sync policy calls for
+/// it, so their entries are durable.  Subdirectories are synced before the directories that hold
+/// them.  Files aren't synced; see `sync_tree` for that.
+fn sync_dirs(path: &Path, sync_policy: SyncPolicy) -> Result<()> {
+    if sync_policy == SyncPolicy::Always {
+        for entry in WalkDir::new(path).contents_first(true) {
+            let entry = entry.context(error::ListKeys)?;
+            if entry.file_type().is_dir() {
+                sync_path(entry.path(), sync_policy)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Helper for syncing every file and directory of a directory tree to disk.  Entries are synced
+/// before the directories that hold them.  Symlinks aren't followed; they're synced as entries of
+/// their directories.
+fn sync_tree(path: &Path) -> Result<()> {
+    for entry in WalkDir::new(path).contents_first(true).follow_links(false) {
+        let entry = entry.context(error::ListKeys)?;
+        if entry.file_type().is_file() || entry.file_type().is_dir() {
+            sync_path(entry.path(), SyncPolicy::Always)?;
+        }
+    }
+    Ok(())
+}
+
+/// Helper for linking a directory tree of keys into a new directory.  Directories are created and
+/// files are hard-linked, so nothing is copied; this is safe because files are only ever replaced,
+/// never written in place.  Only directories and regular files are linked; nothing else represents
+/// a key.  The new directories aren't synced; see `sync_dirs`.
+fn link_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir(to).context(error::Io { path: to })?;
+
+    // The source may be the live symlink, which is followed, but nothing under it is.
+    let walker = WalkDir::new(from).min_depth(1).follow_links(false);
+    for entry in walker {
+        let entry = entry.context(error::ListKeys)?;
+        let relative = entry.path().strip_prefix(from).context(error::Path)?;
+        let target = to.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir(&target).context(error::Io { path: &target })?;
+        } else if entry.file_type().is_file() {
+            fs::hard_link(entry.path(), &target).context(error::Io { path: &target })?;
+        } else {
+            trace!("Not linking non-file entry: {}", entry.path().display());
+        }
+    }
+    Ok(())
+}
+
+/// Helper for removing a file, symlink, or directory tree.  Returns Ok if it doesn't exist.
+fn remove_path(path: &Path) -> Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context(error::Io { path }),
+    }
 }
 
 /// KeyPath represents the filesystem path to a data or metadata key, relative to the base path of
@@ -448,11 +995,13 @@ impl DataStore for FilesystemDataStore {
     }
 
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        self.recover()?;
         let path = self.data_path(key, committed)?;
-        write_file_mkdir(path, value)
+        write_file_mkdir(path, value, self.sync_policy)
     }
 
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
+        self.recover()?;
         let path = self.data_path(key, committed)?;
         self.delete_key_path(path, committed)
     }
@@ -468,23 +1017,28 @@ impl DataStore for FilesystemDataStore {
         data_key: &Key,
         value: S,
     ) -> Result<()> {
+        self.recover()?;
         let path = self.metadata_path(metadata_key, data_key, &Committed::Live)?;
-        write_file_mkdir(path, value)
+        write_file_mkdir(path, value, self.sync_policy)
     }
 
     fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()> {
+        self.recover()?;
         let path = self.metadata_path(metadata_key, data_key, &Committed::Live)?;
         self.delete_key_path(path, &Committed::Live)
     }
 
-    /// We commit by copying pending keys to live, then removing pending.  Something smarter (lock,
-    /// atomic flip, etc.) will be required to make the server concurrent.
+    /// We commit by preparing a live directory for the next generation, with the pending keys
+    /// applied, and then pointing the live symlink at it; see the module docs.  The user still
+    /// needs to handle locking.
     fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
+        self.recover()?;
+        let transaction = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
         // Get data for changed keys
         let pending_data = self.get_prefix("settings.", &pending)?;
@@ -497,14 +1051,10 @@ impl DataStore for FilesystemDataStore {
         // Save Keys for return value
         let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
 
-        // Apply changes to live
+        // Apply changes to a new live directory, and make it live
         debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, &Committed::Live)?;
-
-        // Remove pending
-        debug!("Removing old pending keys");
-        let path = self.base_path(&pending);
-        fs::remove_dir_all(&path).context(error::Io { path })?;
+        let record = self.prepare_commit(&pending_data, &HashSet::new(), Some(transaction))?;
+        self.complete_commit(&record)?;
 
         Ok(pending_keys)
     }
@@ -513,6 +1063,7 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        self.recover()?;
         let pending = Committed::Pending {
             tx: transaction.into(),
         };
@@ -578,11 +1129,105 @@ impl DataStore for FilesystemDataStore {
 
         Ok(transactions)
     }
+
+    /// The generation is the one whose live directory the live symlink points to.  Older data
+    /// stores keep it in the generation file.
+    fn generation(&self) -> Result<u64> {
+        if let Some(generation) = self.live_generation()? {
+            return Ok(generation);
+        }
+
+        let path = &self.generation_path;
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            // Nothing has been committed yet.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e).context(error::Io { path }),
+        };
+        data.trim().parse().ok().context(error::Corruption {
+            msg: format!("invalid generation '{}'", data.trim()),
+            path,
+        })
+    }
+
+    /// Saved generations are files named by generation number in the generations directory.
+    fn list_generations(&self) -> Result<Vec<u64>> {
+        let path = &self.generations_base_path;
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            // Nothing has been committed yet.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(error::Io { path }),
+        };
+
+        let mut generations = Vec::new();
+        for entry in entries {
+            let entry = entry.context(error::Io { path })?;
+            let entry_path = entry.path();
+            if entry_path.extension() != Some("json".as_ref()) {
+                trace!("Skipping non-generation file {}", entry_path.display());
+                continue;
+            }
+            let generation = entry_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+                .context(error::Corruption {
+                    msg: "invalid saved generation name",
+                    path: &entry_path,
+                })?;
+            generations.push(generation);
+        }
+        generations.sort_unstable();
+
+        Ok(generations)
+    }
+
+    /// Saved generations are JSON maps of setting name to serialized value.
+    fn get_generation_settings(&self, generation: u64) -> Result<HashMap<Key, String>> {
+        let path = self.saved_generation_path(generation);
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return error::GenerationNotFound { generation }.fail()
+            }
+            Err(e) => return Err(e).context(error::Io { path }),
+        };
+        let saved: HashMap<String, String> =
+            serde_json::from_str(&data).context(error::GenerationParse { path: &path })?;
+
+        saved
+            .into_iter()
+            .map(|(name, value)| Ok((Key::new(KeyType::Data, name)?, value)))
+            .collect()
+    }
+
+    fn rollback_to_generation(&mut self, generation: u64) -> Result<HashSet<Key>> {
+        self.recover()?;
+        let saved = self.get_generation_settings(generation)?;
+        let live = self.get_prefix("settings.", &Committed::Live)?;
+        let (to_set, to_unset) = rollback_changes(&live, saved);
+
+        let mut changed: HashSet<Key> = to_set.keys().cloned().collect();
+        changed.extend(to_unset.iter().cloned());
+        if changed.is_empty() {
+            debug!("Live settings already match generation {}", generation);
+            return Ok(changed);
+        }
+
+        // Like a commit, this saves the current live settings, so the rollback can be undone
+        debug!("Restoring live settings from generation {}", generation);
+        let record = self.prepare_commit(&to_set, &to_unset, None)?;
+        self.complete_commit(&record)?;
+
+        Ok(changed)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::os::unix::fs::MetadataExt;
 
     #[test]
     fn data_path() {
@@ -644,4 +1289,272 @@ mod test {
         // Invalid UTF-8
         decode_path_component("%C3%28", "").unwrap_err();
     }
+
+    #[test]
+    fn rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(dir.path());
+        fs::create_dir(dir.path().join("live")).unwrap();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b.\"c.d\"").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        assert_eq!(f.generation().unwrap(), 0);
+        assert!(f.list_generations().unwrap().is_empty());
+
+        f.set_key(&k1, "1", &pending).unwrap();
+        f.commit_transaction(tx).unwrap();
+        f.set_key(&k1, "2", &pending).unwrap();
+        f.set_key(&k2, "3", &pending).unwrap();
+        f.commit_transaction(tx).unwrap();
+        assert_eq!(f.generation().unwrap(), 2);
+        assert_eq!(f.list_generations().unwrap(), vec![0, 1]);
+
+        let changed = f.rollback_to_generation(1).unwrap();
+        assert_eq!(changed, vec![k1.clone(), k2.clone()].into_iter().collect());
+        assert_eq!(f.get_key(&k1, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(f.get_key(&k2, &Committed::Live).unwrap(), None);
+        assert_eq!(f.generation().unwrap(), 3);
+        assert_eq!(f.list_generations().unwrap(), vec![0, 1, 2]);
+
+        f.rollback_to_generation(2).unwrap();
+        assert_eq!(f.get_key(&k2, &Committed::Live).unwrap(), Some("3".into()));
+
+        f.rollback_to_generation(42).unwrap_err();
+    }
+
+    /// Makes a data store on a tempdir, like storewolf does, with a live directory rather than a
+    /// live symlink, and the given live setting and pending setting in the given transaction.
+    fn store_with(
+        dir: &Path,
+        key: &Key,
+        live: &str,
+        pending: &str,
+        tx: &str,
+    ) -> FilesystemDataStore {
+        let mut f = FilesystemDataStore::new(dir);
+        f.set_key(key, live, &Committed::Live).unwrap();
+        f.set_key(key, pending, &Committed::Pending { tx: tx.into() })
+            .unwrap();
+        f
+    }
+
+    /// Lists the entries of the data store directory, other than the ones that are always there.
+    fn live_entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("live") || name == "generation" || name == "commit")
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn commit_replaces_live() {
+        let dir = tempfile::tempdir().unwrap();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = "test transaction";
+        let mut f = store_with(dir.path(), &k, "1", "2", tx);
+        fs::write(dir.path().join("generation"), "5").unwrap();
+        assert_eq!(f.generation().unwrap(), 5);
+
+        // The first commit converts the live directory to a symlink.
+        f.commit_transaction(tx).unwrap();
+        assert_eq!(
+            fs::read_link(dir.path().join("live")).unwrap(),
+            PathBuf::from("live.6")
+        );
+        assert_eq!(live_entries(dir.path()), vec!["live", "live.6"]);
+        assert_eq!(f.generation().unwrap(), 6);
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("2".into()));
+        assert!(f.list_transactions().unwrap().is_empty());
+        assert_eq!(f.list_generations().unwrap(), vec![5]);
+
+        // Later commits replace the symlink and remove the old live directory.
+        f.set_key(&k, "3", &Committed::Pending { tx: tx.into() })
+            .unwrap();
+        f.commit_transaction(tx).unwrap();
+        assert_eq!(live_entries(dir.path()), vec!["live", "live.7"]);
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("3".into()));
+        assert_eq!(
+            f.get_generation_settings(6).unwrap(),
+            vec![(k.clone(), "2".to_string())].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn commit_links_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let b = Key::new(KeyType::Data, "settings.b").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let mut f = store_with(dir.path(), &a, "1", "2", tx);
+        f.set_key(&b, "3", &Committed::Live).unwrap();
+        let inode = |path: PathBuf| fs::metadata(path).unwrap().ino();
+        let live_b = inode(f.data_path(&b, &Committed::Live).unwrap());
+
+        // The prepared directory links the unchanged file, and the changed file is replaced
+        // without changing the current live data.
+        let data = f.get_prefix("", &pending).unwrap();
+        f.prepare_commit(&data, &HashSet::new(), Some(tx.into()))
+            .unwrap();
+        let prepared = dir.path().join("live.1/settings");
+        assert_eq!(inode(prepared.join("b")), live_b);
+        assert_eq!(fs::read_to_string(prepared.join("a")).unwrap(), "2");
+        assert_eq!(f.get_key(&a, &Committed::Live).unwrap(), Some("1".into()));
+
+        let f = FilesystemDataStore::new(dir.path());
+        f.complete_commit(&CommitRecord {
+            generation: 1,
+            transaction: Some(tx.into()),
+        })
+        .unwrap();
+        assert_eq!(f.get_key(&a, &Committed::Live).unwrap(), Some("2".into()));
+        assert_eq!(inode(f.data_path(&b, &Committed::Live).unwrap()), live_b);
+    }
+
+    #[test]
+    fn interrupted_before_commit_point() {
+        let dir = tempfile::tempdir().unwrap();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let mut f = store_with(dir.path(), &k, "1", "2", tx);
+
+        // Prepare a commit, and "crash" before its record is written.
+        let data = f.get_prefix("", &pending).unwrap();
+        f.prepare_commit(&data, &HashSet::new(), Some(tx.into()))
+            .unwrap();
+        assert!(dir.path().join("live.1").is_dir());
+
+        // Nothing changed for a new user of the data store.
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.recover().unwrap();
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(f.get_key(&k, &pending).unwrap(), Some("2".into()));
+        assert_eq!(f.generation().unwrap(), 0);
+
+        // Committing again replaces the prepared directory.
+        f.commit_transaction(tx).unwrap();
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("2".into()));
+        assert_eq!(f.generation().unwrap(), 1);
+        assert_eq!(live_entries(dir.path()), vec!["live", "live.1"]);
+    }
+
+    #[test]
+    fn interrupted_after_commit_point() {
+        let dir = tempfile::tempdir().unwrap();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let mut f = store_with(dir.path(), &k, "1", "2", tx);
+
+        // Prepare a commit and write its record, then "crash" before finishing it.
+        let data = f.get_prefix("", &pending).unwrap();
+        let record = f
+            .prepare_commit(&data, &HashSet::new(), Some(tx.into()))
+            .unwrap();
+        fs::write(
+            dir.path().join("commit"),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+
+        // The next change finishes the commit before it's made, so the committed transaction
+        // doesn't linger.
+        let mut f = FilesystemDataStore::new(dir.path());
+        f.set_key(&k2, "3", &pending).unwrap();
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("2".into()));
+        assert_eq!(f.get_key(&k, &pending).unwrap(), None);
+        assert_eq!(f.get_key(&k2, &pending).unwrap(), Some("3".into()));
+        assert_eq!(f.generation().unwrap(), 1);
+        assert_eq!(live_entries(dir.path()), vec!["live", "live.1"]);
+    }
+
+    #[test]
+    fn interrupted_conversion() {
+        let dir = tempfile::tempdir().unwrap();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let mut f = store_with(dir.path(), &k, "1", "2", tx);
+
+        // "Crash" after moving the old live directory aside, before the symlink replaces it.
+        let data = f.get_prefix("", &pending).unwrap();
+        let record = f
+            .prepare_commit(&data, &HashSet::new(), Some(tx.into()))
+            .unwrap();
+        fs::write(
+            dir.path().join("commit"),
+            serde_json::to_string(&record).unwrap(),
+        )
+        .unwrap();
+        fs::rename(dir.path().join("live"), dir.path().join("live.old")).unwrap();
+
+        let f = FilesystemDataStore::new(dir.path());
+        f.recover().unwrap();
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("2".into()));
+        assert_eq!(f.generation().unwrap(), 1);
+        assert_eq!(live_entries(dir.path()), vec!["live", "live.1"]);
+    }
+
+    #[test]
+    fn atomic_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let mut f = FilesystemDataStore::new(dir.path()).with_sync_policy(SyncPolicy::Never);
+        f.set_key(&k, "1", &Committed::Live).unwrap();
+        f.set_key(&k, "2", &Committed::Live).unwrap();
+        let settings = dir.path().join("live/settings");
+        assert_eq!(fs::read_dir(&settings).unwrap().count(), 1);
+
+        // A temporary file left by an interrupted write isn't mistaken for a key.
+        fs::write(settings.join(".a.tmp"), "3").unwrap();
+        assert_eq!(
+            f.list_populated_keys("", &Committed::Live).unwrap(),
+            vec![k.clone()].into_iter().collect()
+        );
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("2".into()));
+    }
+
+    #[test]
+    fn invalid_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = FilesystemDataStore::new(dir.path());
+        let live = dir.path().join("live");
+        fs::create_dir_all(live.join("settings")).unwrap();
+        fs::write(live.join("settings/motd"), "\"hi\"").unwrap();
+        fs::write(live.join("settings/motd.affected-services"), "[]").unwrap();
+        fs::write(live.join("settings/%C3%28"), "1").unwrap();
+        std::os::unix::fs::symlink("motd", live.join("settings/link")).unwrap();
+
+        let tx = dir.path().join("pending/tx");
+        fs::create_dir_all(tx.join("settings")).unwrap();
+        fs::write(tx.join("settings/motd"), "\"hi\"").unwrap();
+        fs::write(tx.join("settings/motd.affected-services"), "[]").unwrap();
+        fs::create_dir_all(dir.path().join("pending/%C3%28")).unwrap();
+        fs::write(dir.path().join("pending/stray"), "").unwrap();
+
+        let mut paths: Vec<PathBuf> = f
+            .find_invalid_paths()
+            .unwrap()
+            .into_iter()
+            .map(|invalid| invalid.path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                live.join("settings/%C3%28"),
+                live.join("settings/link"),
+                dir.path().join("pending/%C3%28"),
+                dir.path().join("pending/stray"),
+                tx.join("settings/motd.affected-services"),
+            ]
+        );
+    }
 }
@@ -20,10 +20,26 @@ The `serialization` module provides code to serialize Rust types into a mapping
 
 The `deserialization` module provides code to deserialize datastore-acceptable keys (a.b.c) and values into Rust types.
 
+# Generations
+
+Each commit that changes live settings moves the data store to a new generation.
+Before the change, the live settings of the current generation are saved, and the most recent saved generations (see `GENERATIONS_KEPT`) can be restored with `rollback_to_generation`.
+A rollback is itself a change to live settings, so it also creates a new generation, and can be undone in the same way.
+
+Only settings are saved, not metadata or other data like services and configuration files, since those come from the variant's defaults rather than from users.
+Saved generations are not carried across data store migrations, because they use the data format of the old version.
+
+# Atomic commits
+
+A commit applies all of a transaction's changes or none of them, even if it's interrupted by a crash or power loss.
+The filesystem data store prepares the next generation's live data in a new directory, hard-linking the files that don't change, records the commit, and then points the `live` symlink at the new directory; an interrupted commit that was recorded is finished the next time the data store is changed, or by `FilesystemDataStore::recover`.
+Files and directories are synced to disk as they're written, unless the data store is made with `SyncPolicy::Never`.
+`SyncPolicy::Never` is meant for data stores that are thrown away or rebuilt if we're interrupted, like the intermediate data stores of migrations; call `FilesystemDataStore::sync` if the data store is kept.
+A data store that's kept as-is after an interruption, like the one storewolf populates, needs `SyncPolicy::Always`, because an interrupted write can leave an empty or truncated file that would look like a value that's already set.
+
 # Current limitations
 
 * The user (e.g. apiserver) needs to handle locking.
-* There's no support for rolling back transactions.
 * The `serialization` module can't handle complex types under lists; it assumes lists can be serialized as scalars.
 */
 
@@ -35,7 +51,7 @@ pub mod memory;
 pub mod serialization;
 
 pub use error::{Error, Result};
-pub use filesystem::FilesystemDataStore;
+pub use filesystem::{FilesystemDataStore, SyncPolicy};
 pub use key::{Key, KeyType, KEY_SEPARATOR, KEY_SEPARATOR_STR};
 
 use log::trace;
@@ -43,6 +59,9 @@ use serde::{Deserialize, Serialize};
 use snafu::OptionExt;
 use std::collections::{HashMap, HashSet};
 
+/// The number of prior generations of live settings kept for rollback.
+pub const GENERATIONS_KEPT: usize = 10;
+
 /// Committed represents whether we want to look at pending (uncommitted) or live (committed) data
 /// in the datastore.
 #[derive(Debug, Clone)]
@@ -138,6 +157,23 @@ pub trait DataStore {
     /// Returns a list of the names of any pending transactions in the data store.
     fn list_transactions(&self) -> Result<HashSet<String>>;
 
+    /// Returns the generation of the live data store.  It starts at 0 and increases each time a
+    /// commit or rollback changes live settings.
+    fn generation(&self) -> Result<u64>;
+
+    /// Returns the prior generations whose settings are saved and can be restored with
+    /// `rollback_to_generation`, oldest first.
+    fn list_generations(&self) -> Result<Vec<u64>>;
+
+    /// Returns the live settings saved for the given prior generation, as they were when it was
+    /// the live generation.
+    fn get_generation_settings(&self, generation: u64) -> Result<HashMap<Key, String>>;
+
+    /// Restores live settings to those saved for the given prior generation.  The current live
+    /// settings are saved as a generation first, like a commit, so the rollback can be undone.
+    /// Returns the list of changed keys, which is empty if the settings were already the same.
+    fn rollback_to_generation(&mut self, generation: u64) -> Result<HashSet<Key>>;
+
     /// Set multiple data keys at once in the data store.
     ///
     /// Implementers can replace the default implementation if there's a faster way than setting
@@ -244,6 +280,25 @@ pub trait DataStore {
     }
 }
 
+/// Compares live settings to the settings saved for a prior generation.  Returns the keys and
+/// values that need to be set, and the keys that need to be removed, to restore the saved
+/// generation.
+fn rollback_changes(
+    live: &HashMap<Key, String>,
+    saved: HashMap<Key, String>,
+) -> (HashMap<Key, String>, HashSet<Key>) {
+    let to_unset = live
+        .keys()
+        .filter(|key| !saved.contains_key(key))
+        .cloned()
+        .collect();
+    let to_set = saved
+        .into_iter()
+        .filter(|(key, value)| live.get(key) != Some(value))
+        .collect();
+    (to_set, to_unset)
+}
+
 /////
 
 // This section ties together serialization and deserialization of scalar values, so it's in the
@@ -1,11 +1,12 @@
 //! In-memory datastore for use in testing other modules.
 //!
 //! Mimics some of the decisions made for FilesystemDataStore, e.g. metadata being committed
-//! immediately.
+//! immediately, and commits being prepared in full before they replace live data.
 
-use std::collections::{HashMap, HashSet};
+use snafu::OptionExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use super::{Committed, DataStore, Key, Result};
+use super::{error, rollback_changes, Committed, DataStore, Key, Result, GENERATIONS_KEPT};
 
 #[derive(Debug)]
 pub struct MemoryDataStore {
@@ -16,6 +17,10 @@ pub struct MemoryDataStore {
     // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
     // arbitrary (string/serialized) values.
     metadata: HashMap<Key, HashMap<Key, String>>,
+    // The generation of the live data.
+    generation: u64,
+    // Generation -> saved live settings of that generation.
+    generations: BTreeMap<u64, HashMap<Key, String>>,
 }
 
 impl MemoryDataStore {
@@ -24,6 +29,8 @@ impl MemoryDataStore {
             pending: HashMap::new(),
             live: HashMap::new(),
             metadata: HashMap::new(),
+            generation: 0,
+            generations: BTreeMap::new(),
         }
     }
 
@@ -40,6 +47,60 @@ impl MemoryDataStore {
             Committed::Pending { tx } => self.pending.entry(tx.clone()).or_default(),
         }
     }
+
+    fn live_settings(&self) -> HashMap<Key, String> {
+        self.live
+            .iter()
+            .filter(|(key, _value)| key.name().starts_with("settings."))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Prepares a commit that makes the given changes to live data, without changing anything,
+    /// like FilesystemDataStore prepares a live directory.  If we're interrupted before it's
+    /// completed, nothing has changed.
+    fn prepare_commit(
+        &self,
+        to_set: &HashMap<Key, String>,
+        to_unset: &HashSet<Key>,
+        transaction: Option<String>,
+    ) -> PreparedCommit {
+        let mut live = self.live.clone();
+        for (key, value) in to_set {
+            live.insert(key.clone(), value.clone());
+        }
+        for key in to_unset {
+            live.remove(key);
+        }
+        PreparedCommit { live, transaction }
+    }
+
+    /// Completes a prepared commit: saves the live settings as the current generation, replaces
+    /// live data and moves to the next generation, and removes the committed transaction.  This
+    /// can't fail, so a commit happens completely or not at all.  The oldest saved generations are
+    /// dropped so we keep at most GENERATIONS_KEPT.
+    fn complete_commit(&mut self, prepared: PreparedCommit) {
+        self.generations
+            .insert(self.generation, self.live_settings());
+        self.generation += 1;
+        self.live = prepared.live;
+        if let Some(transaction) = prepared.transaction {
+            self.pending.remove(&transaction);
+        }
+
+        while self.generations.len() > GENERATIONS_KEPT {
+            if let Some(&oldest) = self.generations.keys().next() {
+                self.generations.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// The live data that will result from a commit, and the transaction it commits, if any.
+#[derive(Debug)]
+struct PreparedCommit {
+    live: HashMap<Key, String>,
+    transaction: Option<String>,
 }
 
 impl DataStore for MemoryDataStore {
@@ -154,15 +215,22 @@ impl DataStore for MemoryDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
-        // Remove anything pending for this transaction
-        if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Apply pending changes to live
-            self.set_keys(&pending, &Committed::Live)?;
-            // Return keys that were committed
-            Ok(pending.keys().cloned().collect())
+        let transaction = transaction.into();
+        let pending = match self.pending.get(&transaction) {
+            Some(pending) => pending,
+            None => return Ok(HashSet::new()),
+        };
+        // Return keys that were committed
+        let keys = pending.keys().cloned().collect();
+
+        if pending.is_empty() {
+            self.pending.remove(&transaction);
         } else {
-            Ok(HashSet::new())
+            // Apply pending changes to live
+            let prepared = self.prepare_commit(pending, &HashSet::new(), Some(transaction));
+            self.complete_commit(prepared);
         }
+        Ok(keys)
     }
 
     fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
@@ -181,13 +249,44 @@ impl DataStore for MemoryDataStore {
     fn list_transactions(&self) -> Result<HashSet<String>> {
         Ok(self.pending.keys().cloned().collect())
     }
+
+    fn generation(&self) -> Result<u64> {
+        Ok(self.generation)
+    }
+
+    fn list_generations(&self) -> Result<Vec<u64>> {
+        Ok(self.generations.keys().copied().collect())
+    }
+
+    fn get_generation_settings(&self, generation: u64) -> Result<HashMap<Key, String>> {
+        self.generations
+            .get(&generation)
+            .cloned()
+            .context(error::GenerationNotFound { generation })
+    }
+
+    fn rollback_to_generation(&mut self, generation: u64) -> Result<HashSet<Key>> {
+        let saved = self.get_generation_settings(generation)?;
+        let (to_set, to_unset) = rollback_changes(&self.live_settings(), saved);
+
+        let mut changed: HashSet<Key> = to_set.keys().cloned().collect();
+        changed.extend(to_unset.iter().cloned());
+        if changed.is_empty() {
+            return Ok(changed);
+        }
+
+        let prepared = self.prepare_commit(&to_set, &to_unset, None);
+        self.complete_commit(prepared);
+        Ok(changed)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::super::{Committed, DataStore, Key, KeyType};
+    use super::super::{Committed, DataStore, Key, KeyType, GENERATIONS_KEPT};
     use super::MemoryDataStore;
-    use maplit::hashset;
+    use maplit::{hashmap, hashset};
+    use std::collections::HashSet;
 
     #[test]
     fn get_set_unset() {
@@ -251,6 +350,32 @@ mod test {
         assert!(m.key_populated(&k, &Committed::Live).unwrap());
     }
 
+    #[test]
+    fn interrupted_commit() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        m.set_key(&k, "1", &Committed::Live).unwrap();
+        m.set_key(&k, "2", &pending).unwrap();
+
+        // A commit that's prepared but never completed changes nothing.
+        let data = m.get_prefix("", &pending).unwrap();
+        let prepared = m.prepare_commit(&data, &HashSet::new(), Some(tx.to_string()));
+        assert_eq!(prepared.live.get(&k), Some(&"2".to_string()));
+        drop(prepared);
+        assert_eq!(m.get_key(&k, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(m.get_key(&k, &pending).unwrap(), Some("2".into()));
+        assert_eq!(m.generation().unwrap(), 0);
+        assert!(m.list_generations().unwrap().is_empty());
+
+        // The transaction can still be committed.
+        m.commit_transaction(tx).unwrap();
+        assert_eq!(m.get_key(&k, &Committed::Live).unwrap(), Some("2".into()));
+        assert!(m.list_transactions().unwrap().is_empty());
+        assert_eq!(m.generation().unwrap(), 1);
+    }
+
     #[test]
     fn delete_transaction() {
         let mut m = MemoryDataStore::new();
@@ -276,4 +401,60 @@ mod test {
         // Assure other transactions were not deleted
         assert!(m.key_populated(&k2, &pending2).unwrap());
     }
+
+    #[test]
+    fn rollback() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        m.set_key(&k1, "1", &pending).unwrap();
+        m.commit_transaction(tx).unwrap();
+        m.set_key(&k1, "2", &pending).unwrap();
+        m.set_key(&k2, "3", &pending).unwrap();
+        m.commit_transaction(tx).unwrap();
+        assert_eq!(m.generation().unwrap(), 2);
+        assert_eq!(m.list_generations().unwrap(), vec![0, 1]);
+        assert_eq!(
+            m.get_generation_settings(1).unwrap(),
+            hashmap!(k1.clone() => "1".to_string())
+        );
+
+        // Rolling back to generation 1 restores k1 and removes k2
+        let changed = m.rollback_to_generation(1).unwrap();
+        assert_eq!(changed, hashset!(k1.clone(), k2.clone()));
+        assert_eq!(m.get_key(&k1, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(m.get_key(&k2, &Committed::Live).unwrap(), None);
+        assert_eq!(m.generation().unwrap(), 3);
+
+        // The rollback itself can be undone
+        m.rollback_to_generation(2).unwrap();
+        assert_eq!(m.get_key(&k2, &Committed::Live).unwrap(), Some("3".into()));
+
+        assert_eq!(m.generation().unwrap(), 4);
+
+        // Rolling back to identical settings changes nothing
+        assert!(m.rollback_to_generation(2).unwrap().is_empty());
+        assert_eq!(m.generation().unwrap(), 4);
+
+        m.rollback_to_generation(42).unwrap_err();
+    }
+
+    #[test]
+    fn old_generations_dropped() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        for i in 0..GENERATIONS_KEPT + 2 {
+            m.set_key(&k, i.to_string(), &pending).unwrap();
+            m.commit_transaction(tx).unwrap();
+        }
+        let generations = m.list_generations().unwrap();
+        assert_eq!(generations.len(), GENERATIONS_KEPT);
+        assert_eq!(generations[0], 2);
+    }
 }
@@ -21,7 +21,7 @@ use std::collections::HashMap;
 use std::env;
 use std::fmt;
 
-use datastore::{Committed, Value};
+use datastore::{Committed, SyncPolicy, Value};
 pub use datastore::{DataStore, FilesystemDataStore};
 
 use args::{parse_args, Args};
@@ -88,7 +88,10 @@ fn validate_migrated_data(_migrated: &MigrationData) -> Result<()> {
 /// normally be parsed from the migration binary's command line.
 pub fn run_migration(mut migration: impl Migration, args: &Args) -> Result<()> {
     let source = DataStoreImplementation::new(&args.source_datastore);
-    let mut target = DataStoreImplementation::new(&args.target_datastore);
+    // The migrator throws the target away if we're interrupted, and syncs it once all migrations
+    // are done, so there's no need to sync each write.
+    let mut target =
+        DataStoreImplementation::new(&args.target_datastore).with_sync_policy(SyncPolicy::Never);
 
     // Run for live data and for each pending transaction
     let mut committeds = vec![Committed::Live];
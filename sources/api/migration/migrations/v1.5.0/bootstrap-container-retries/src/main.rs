@@ -0,0 +1,50 @@
+#![deny(rust_2018_idioms)]
+
+use migration_helpers::{migrate, Migration, MigrationData, Result};
+use std::process;
+
+/// The bootstrap container settings added in this version.
+const NEW_SETTINGS: &[&str] = &[".retries", ".retry-delay", ".timeout"];
+
+/// This migration removes the retry and timeout settings of bootstrap containers when downgrading
+/// to versions that don't understand them.
+pub struct BootstrapContainerRetriesMigration;
+
+impl Migration for BootstrapContainerRetriesMigration {
+    /// The settings are left empty on upgrade, so containers run once with no timeout, as before.
+    fn forward(&mut self, input: MigrationData) -> Result<MigrationData> {
+        println!("BootstrapContainerRetriesMigration has no work to do on upgrade.");
+        Ok(input)
+    }
+
+    /// Older versions don't know about the retry and timeout settings; we remove them so that old
+    /// versions don't see them and fail deserialization.
+    fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+        for setting in input.data.clone().keys() {
+            // We don't currently have structured data available to migrations, and we don't want
+            // to re-parse keys.  We know no other keys could match these basic patterns.
+            if setting.starts_with("settings.bootstrap-containers.")
+                && NEW_SETTINGS.iter().any(|suffix| setting.ends_with(suffix))
+            {
+                if let Some(data) = input.data.remove(setting) {
+                    println!("Removed {}, which was set to '{}'", setting, data);
+                }
+            }
+        }
+        Ok(input)
+    }
+}
+
+fn run() -> Result<()> {
+    migrate(BootstrapContainerRetriesMigration)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
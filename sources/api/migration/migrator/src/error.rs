@@ -19,6 +19,9 @@ pub(crate) enum Error {
     #[snafu(display("Unable to open data store directory '{}': {}", path.display(), source))]
     DataStoreDirOpen { path: PathBuf, source: nix::Error },
 
+    #[snafu(display("Unable to sync data store '{}' to disk: {}", path.display(), source))]
+    DataStoreSync { path: PathBuf, source: io::Error },
+
     #[snafu(display("Data store link '{}' points to /", path.display()))]
     DataStoreLinkToRoot { path: PathBuf },
 
@@ -307,6 +307,14 @@ where
         source_datastore = &target_datastore;
     }
 
+    // Migrations don't sync their output, since it's thrown away if we're interrupted.  The final
+    // data store is about to become live, so it has to be on disk first.
+    info!(
+        "Syncing migrated data store at {}",
+        target_datastore.display()
+    );
+    sync_datastore(&target_datastore)?;
+
     // Remove the intermediate data stores
     intermediate_datastores.remove(&target_datastore);
     for intermediate_datastore in intermediate_datastores {
@@ -329,6 +337,24 @@ where
     Ok(target_datastore)
 }
 
+/// Syncs every file and directory of the given data store to disk.  Entries are synced before the
+/// directories that hold them.  Symlinks aren't followed; they're synced as entries of their
+/// directories.
+fn sync_datastore(path: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).context(error::DataStoreSync { path })?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path).context(error::DataStoreSync { path })? {
+            let entry = entry.context(error::DataStoreSync { path })?;
+            sync_datastore(&entry.path())?;
+        }
+    } else if !metadata.is_file() {
+        return Ok(());
+    }
+    File::open(path)
+        .and_then(|file| file.sync_all())
+        .context(error::DataStoreSync { path })
+}
+
 /// Atomically flips version symlinks to point to the given "to" datastore so that it becomes live.
 ///
 /// This includes:
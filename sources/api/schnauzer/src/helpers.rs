@@ -3,7 +3,11 @@
 // text at render time.
 
 use dns_lookup::lookup_host;
-use handlebars::{Context, Handlebars, Helper, Output, RenderContext, RenderError};
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+    ScopedJson,
+};
+use ipnet::IpNet;
 use lazy_static::lazy_static;
 use num_cpus;
 use serde_json::value::Value;
@@ -12,6 +16,7 @@ use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
 use url::Url;
 
 lazy_static! {
@@ -231,6 +236,38 @@ mod error {
             number: usize,
             source: std::num::TryFromIntError,
         },
+
+        #[snafu(display("Invalid CIDR block '{}' in template '{}': {}", cidr, template, source))]
+        InvalidCidr {
+            cidr: String,
+            template: String,
+            source: ipnet::AddrParseError,
+        },
+
+        #[snafu(display("Invalid IP address '{}' in template '{}': {}", ip, template, source))]
+        InvalidIpAddress {
+            ip: String,
+            template: String,
+            source: std::net::AddrParseError,
+        },
+
+        #[snafu(display("Unable to serialize JSON in template '{}': {}", template, source))]
+        SerializeJson {
+            template: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to serialize TOML in template '{}': {}", template, source))]
+        SerializeToml {
+            template: String,
+            source: toml::ser::Error,
+        },
+
+        #[snafu(display("Unable to serialize YAML in template '{}': {}", template, source))]
+        SerializeYaml {
+            template: String,
+            source: serde_yaml::Error,
+        },
     }
 
     // Handlebars helpers are required to return a RenderError.
@@ -839,6 +876,34 @@ pub fn kube_reserve_cpu(
     _: &Context,
     renderctx: &mut RenderContext<'_, '_>,
     out: &mut dyn Output,
+) -> Result<(), RenderError> {
+    render_kube_reserve_cpu(helper, renderctx, out, num_cpus::get())
+}
+
+/// The kube_reserve_cpu helper for a host with the given number of CPUs, rather than the current
+/// host, so templates can be rendered offline.
+pub struct KubeReserveCpuStub {
+    pub cpus: usize,
+}
+
+impl HelperDef for KubeReserveCpuStub {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        renderctx: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        render_kube_reserve_cpu(helper, renderctx, out, self.cpus)
+    }
+}
+
+fn render_kube_reserve_cpu(
+    helper: &Helper<'_, '_>,
+    renderctx: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+    num_cores: usize,
 ) -> Result<(), RenderError> {
     trace!("Starting kube_reserve_cpu helper");
     let template_name = template_name(renderctx);
@@ -848,7 +913,6 @@ pub fn kube_reserve_cpu(
     check_param_count(helper, template_name, 1)?;
 
     // Calculates the amount of CPU to reserve
-    let num_cores = num_cpus::get();
     let cpu_to_reserve_value = get_param(helper, 0)?;
     let cpu_to_reserve = match cpu_to_reserve_value {
         Value::Number(n) => n.to_string(),
@@ -885,6 +949,35 @@ pub fn add_unresolvable_hostname(
     renderctx: &mut RenderContext<'_, '_>,
     out: &mut dyn Output,
 ) -> Result<(), RenderError> {
+    render_unresolvable_hostname(helper, renderctx, out, hostname_resolvable)
+}
+
+/// The add_unresolvable_hostname helper without DNS, so templates can be rendered offline.  Every
+/// hostname is treated as unresolvable, so the `/etc/hosts` entries are always written.
+pub struct AddUnresolvableHostnameStub;
+
+impl HelperDef for AddUnresolvableHostnameStub {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        renderctx: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        render_unresolvable_hostname(helper, renderctx, out, |_| false)
+    }
+}
+
+fn render_unresolvable_hostname<F>(
+    helper: &Helper<'_, '_>,
+    renderctx: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+    resolvable: F,
+) -> Result<(), RenderError>
+where
+    F: Fn(&str) -> bool,
+{
     // To give context to our errors, get the template name, if available.
     trace!("Starting base64_decode helper");
     let template_name = template_name(renderctx);
@@ -914,8 +1007,25 @@ pub fn add_unresolvable_hostname(
         })?;
     trace!("Hostname string from template: {}", hostname_str);
 
-    // Attempt to resolve the hostname
-    let hostname_resolveable = match lookup_host(hostname_str) {
+    let hostname_resolveable = resolvable(hostname_str);
+
+    // Only write an entry to the template if the hostname is unresolvable
+    if !hostname_resolveable {
+        let ipv4_entry = format!("{} {}", IPV4_LOCALHOST, hostname_str);
+        let ipv6_entry = format!("{} {}", IPV6_LOCALHOST, hostname_str);
+        let entries = format!("{}\n{}", ipv4_entry, ipv6_entry);
+
+        out.write(&entries).context(error::TemplateWrite {
+            template: template_name.to_owned(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Attempts to resolve the given hostname in DNS.  Hostnames that don't resolve to any address, or
+/// that resolve to localhost, are considered unresolvable.
+fn hostname_resolvable(hostname: &str) -> bool {
+    match lookup_host(hostname) {
         Ok(ip_list) => {
             // If the list of IPs is empty or resolves to localhost, consider the hostname
             // unresolvable
@@ -932,19 +1042,353 @@ pub fn add_unresolvable_hostname(
             trace!("DNS hostname lookup failed: {},", e);
             false
         }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+// general-purpose helpers
+
+/// A helper that computes a value from its params.  Unlike helpers that only write to the
+/// template, it can be used in a subexpression, like `{{#if (eq settings.a "b")}}`, and its value
+/// keeps its type there.  Used on its own, its value is written to the template as-is, like the
+/// output of our other helpers.
+pub struct ValueHelper(pub fn(&Helper<'_, '_>, &str) -> Result<Value, RenderError>);
+
+impl HelperDef for ValueHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        renderctx: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let template_name = template_name(renderctx);
+        trace!(
+            "Starting {} helper in template {}",
+            helper.name(),
+            template_name
+        );
+        (self.0)(helper, template_name).map(ScopedJson::Derived)
+    }
+
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        registry: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        renderctx: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let value = self.call_inner(helper, registry, ctx, renderctx)?.render();
+        out.write(&value).with_context(|| error::TemplateWrite {
+            template: template_name(renderctx).to_owned(),
+        })?;
+        Ok(())
+    }
+}
+
+/// `replace` replaces every occurrence of a string with another string.
+///
+/// Example:
+///    {{ replace settings.kubernetes.cluster-name "_" "-" }}
+///    ...where `cluster-name` is "my_cluster"
+///    ...will produce: "my-cluster"
+pub fn replace(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 3)?;
+    let text = scalar_param(helper, 0, template_name)?;
+    let from = scalar_param(helper, 1, template_name)?;
+    let to = scalar_param(helper, 2, template_name)?;
+    Ok(Value::String(text.replace(&from, &to)))
+}
+
+/// `upper` converts a string to upper case.
+///
+/// Example:
+///    {{ upper settings.motd }}
+///    ...where `motd` is "hi"
+///    ...will produce: "HI"
+pub fn upper(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let text = scalar_param(helper, 0, template_name)?;
+    Ok(Value::String(text.to_uppercase()))
+}
+
+/// `lower` converts a string to lower case.
+///
+/// Example:
+///    {{ lower settings.motd }}
+///    ...where `motd` is "HI"
+///    ...will produce: "hi"
+pub fn lower(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let text = scalar_param(helper, 0, template_name)?;
+    Ok(Value::String(text.to_lowercase()))
+}
+
+/// `to_json` serializes a value, which can be a whole map or list of settings, as JSON on a
+/// single line.
+///
+/// Example:
+///    {{ to_json settings.kubernetes.node-labels }}
+///    ...where `node-labels` is {"a": "b"}
+///    ...will produce: {"a":"b"}
+pub fn to_json(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let value = get_param(helper, 0)?;
+    let json = serde_json::to_string(value).context(error::SerializeJson {
+        template: template_name,
+    })?;
+    Ok(Value::String(json))
+}
+
+/// `to_toml` serializes a map of settings as a TOML document.  TOML has no null, so the map can't
+/// contain any.
+///
+/// Example:
+///    {{ to_toml settings.kubernetes.node-labels }}
+///    ...where `node-labels` is {"a": "b"}
+///    ...will produce: a = "b"
+pub fn to_toml(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let value = get_param(helper, 0)?;
+    if !value.is_object() {
+        return Err(RenderError::from(
+            error::TemplateHelperError::InvalidTemplateValue {
+                expected: "map",
+                value: value.to_owned(),
+                template: template_name.to_owned(),
+            },
+        ));
+    }
+    let toml = toml::to_string(value).context(error::SerializeToml {
+        template: template_name,
+    })?;
+    Ok(Value::String(toml))
+}
+
+/// `to_yaml` serializes a value, which can be a whole map or list of settings, as YAML, without
+/// the leading document marker or the trailing newline.  Use `indent` to nest it in a YAML
+/// template.
+///
+/// Example:
+///    {{ to_yaml settings.kubernetes.node-labels }}
+///    ...where `node-labels` is {"a": "b"}
+///    ...will produce: a: b
+pub fn to_yaml(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let value = get_param(helper, 0)?;
+    let yaml = serde_yaml::to_string(value).context(error::SerializeYaml {
+        template: template_name,
+    })?;
+    let yaml = yaml.strip_prefix("---\n").unwrap_or(&yaml);
+    Ok(Value::String(yaml.trim_end_matches('\n').to_string()))
+}
+
+/// `eq` checks whether two values are equal.  Numbers are equal if they have the same value, even
+/// if one is an integer and the other isn't.  A missing setting is only equal to another.
+///
+/// Example:
+///    {{#if (eq settings.kubernetes.authentication-mode "tls")}}...{{/if}}
+pub fn eq(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 2)?;
+    let left = get_param(helper, 0)?;
+    let right = get_param(helper, 1)?;
+    Ok(Value::Bool(values_equal(left, right)))
+}
+
+/// `ne` checks whether two values are not equal, the opposite of `eq`.
+///
+/// Example:
+///    {{#if (ne settings.kubernetes.authentication-mode "tls")}}...{{/if}}
+pub fn ne(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 2)?;
+    let left = get_param(helper, 0)?;
+    let right = get_param(helper, 1)?;
+    Ok(Value::Bool(!values_equal(left, right)))
+}
+
+/// `lt` checks whether the first value is less than the second.  Both must be numbers, or both
+/// must be strings, which are compared character by character.
+///
+/// Example:
+///    {{#if (lt settings.kubernetes.max-pods 110)}}...{{/if}}
+pub fn lt(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 2)?;
+    let left = get_param(helper, 0)?;
+    let right = get_param(helper, 1)?;
+    let less = match (left, right) {
+        (Value::Number(l), Value::Number(r)) => match (l.as_i64(), r.as_i64()) {
+            (Some(l), Some(r)) => l < r,
+            _ => number_as_f64(l) < number_as_f64(r),
+        },
+        (Value::String(l), Value::String(r)) => l < r,
+        _ => {
+            return Err(RenderError::from(
+                error::TemplateHelperError::InvalidTemplateValue {
+                    expected: "two numbers or two strings",
+                    value: Value::Array(vec![left.to_owned(), right.to_owned()]),
+                    template: template_name.to_owned(),
+                },
+            ))
+        }
     };
+    Ok(Value::Bool(less))
+}
 
-    // Only write an entry to the template if the hostname is unresolvable
-    if !hostname_resolveable {
-        let ipv4_entry = format!("{} {}", IPV4_LOCALHOST, hostname_str);
-        let ipv6_entry = format!("{} {}", IPV6_LOCALHOST, hostname_str);
-        let entries = format!("{}\n{}", ipv4_entry, ipv6_entry);
+/// `any_enabled` checks whether any of the entries in a map, such as the host containers, has its
+/// `enabled` field set to true.  A missing map has no enabled entries.
+///
+/// Example:
+///    {{#if (any_enabled settings.host-containers)}}...{{/if}}
+pub fn any_enabled(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let map_value = get_param(helper, 0)?;
+    let map = match map_value {
+        Value::Null => return Ok(Value::Bool(false)),
+        Value::Object(map) => map,
+        _ => {
+            return Err(RenderError::from(
+                error::TemplateHelperError::InvalidTemplateValue {
+                    expected: "map",
+                    value: map_value.to_owned(),
+                    template: template_name.to_owned(),
+                },
+            ))
+        }
+    };
 
-        out.write(&entries).context(error::TemplateWrite {
+    for entry in map.values() {
+        let entry = entry
+            .as_object()
+            .with_context(|| error::InvalidTemplateValue {
+                expected: "map of maps",
+                value: map_value.to_owned(),
+                template: template_name.to_owned(),
+            })?;
+        if entry.get("enabled") == Some(&Value::Bool(true)) {
+            return Ok(Value::Bool(true));
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+/// `sorted_keys` returns the keys of a map in sorted order, for use with `each`.  A missing map
+/// has no keys.
+///
+/// Example:
+///    {{#each (sorted_keys settings.kubernetes.node-labels)}}{{this}} {{/each}}
+///    ...where `node-labels` is {"b": "x", "a": "y"}
+///    ...will produce: "a b "
+pub fn sorted_keys(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let map_value = get_param(helper, 0)?;
+    let mut keys: Vec<&String> = match map_value {
+        Value::Null => Vec::new(),
+        Value::Object(map) => map.keys().collect(),
+        _ => {
+            return Err(RenderError::from(
+                error::TemplateHelperError::InvalidTemplateValue {
+                    expected: "map",
+                    value: map_value.to_owned(),
+                    template: template_name.to_owned(),
+                },
+            ))
+        }
+    };
+    keys.sort();
+    Ok(Value::Array(
+        keys.into_iter().map(|k| Value::String(k.clone())).collect(),
+    ))
+}
+
+/// `indent` adds the given number of spaces to the start of each line of a string, except empty
+/// lines.  It's useful for nesting a value in a YAML template.
+///
+/// Example:
+///    labels:
+///    {{ indent 2 (to_yaml settings.kubernetes.node-labels) }}
+///    ...where `node-labels` is {"a": "b", "c": "d"}
+///    ...will produce:
+///    labels:
+///      a: b
+///      c: d
+pub fn indent(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 2)?;
+    let spaces_value = get_param(helper, 0)?;
+    let spaces = spaces_value
+        .as_u64()
+        .with_context(|| error::InvalidTemplateValue {
+            expected: "non-negative integer",
+            value: spaces_value.to_owned(),
             template: template_name.to_owned(),
         })?;
-    }
-    Ok(())
+    let spaces = usize::try_from(spaces).ok().context(error::ConvertNumber {
+        what: "indent",
+        number: spaces.to_string(),
+        target: "usize",
+    })?;
+    let text = scalar_param(helper, 1, template_name)?;
+
+    let prefix = " ".repeat(spaces);
+    let indented: Vec<String> = text
+        .split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect();
+    Ok(Value::String(indented.join("\n")))
+}
+
+/// `cidr_contains` checks whether an IP address is in a CIDR block.  Either can be IPv4 or IPv6;
+/// an address is never in a block of the other family.
+///
+/// Example:
+///    {{#if (cidr_contains "10.0.0.0/8" settings.kubernetes.node-ip)}}...{{/if}}
+pub fn cidr_contains(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 2)?;
+    let cidr_value = get_param(helper, 0)?;
+    let cidr_str = cidr_value
+        .as_str()
+        .with_context(|| error::InvalidTemplateValue {
+            expected: "string",
+            value: cidr_value.to_owned(),
+            template: template_name.to_owned(),
+        })?;
+    let cidr = IpNet::from_str(cidr_str).context(error::InvalidCidr {
+        cidr: cidr_str,
+        template: template_name,
+    })?;
+    let ip = ip_param(helper, 1, template_name)?;
+    Ok(Value::Bool(cidr.contains(&ip)))
+}
+
+/// `ip_family` returns "ipv4" or "ipv6" for an IP address, or for a CIDR block.
+///
+/// Example:
+///    {{#if (eq (ip_family settings.kubernetes.node-ip) "ipv6")}}...{{/if}}
+pub fn ip_family(helper: &Helper<'_, '_>, template_name: &str) -> Result<Value, RenderError> {
+    check_param_count(helper, template_name, 1)?;
+    let value = get_param(helper, 0)?;
+    let addr_str = value
+        .as_str()
+        .with_context(|| error::InvalidTemplateValue {
+            expected: "string",
+            value: value.to_owned(),
+            template: template_name.to_owned(),
+        })?;
+    let ip = match IpNet::from_str(addr_str) {
+        Ok(cidr) => cidr.addr(),
+        Err(_) => ip_param(helper, 0, template_name)?,
+    };
+    let family = match ip {
+        IpAddr::V4(_) => "ipv4",
+        IpAddr::V6(_) => "ipv6",
+    };
+    Ok(Value::String(family.to_string()))
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -990,6 +1434,68 @@ fn check_param_count<S: AsRef<str>>(
     Ok(())
 }
 
+/// Gets the value at `idx` as a string.  Numbers and booleans are converted to strings, so they
+/// can be used like strings in templates; other types are an error.
+fn scalar_param(
+    helper: &Helper<'_, '_>,
+    idx: usize,
+    template_name: &str,
+) -> Result<String, RenderError> {
+    let value = get_param(helper, idx)?;
+    match value {
+        Value::String(s) => Ok(s.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => Err(RenderError::from(
+            error::TemplateHelperError::InvalidTemplateValue {
+                expected: "string",
+                value: value.to_owned(),
+                template: template_name.to_owned(),
+            },
+        )),
+    }
+}
+
+/// Gets the value at `idx` as an IP address.
+fn ip_param(
+    helper: &Helper<'_, '_>,
+    idx: usize,
+    template_name: &str,
+) -> Result<IpAddr, RenderError> {
+    let value = get_param(helper, idx)?;
+    let ip_str = value
+        .as_str()
+        .with_context(|| error::InvalidTemplateValue {
+            expected: "string",
+            value: value.to_owned(),
+            template: template_name.to_owned(),
+        })?;
+    Ok(IpAddr::from_str(ip_str).context(error::InvalidIpAddress {
+        ip: ip_str,
+        template: template_name,
+    })?)
+}
+
+/// Checks whether two values are equal, treating numbers as equal if they have the same value.
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => match (l.as_i64(), r.as_i64()) {
+            (Some(l), Some(r)) => l == r,
+            _ => number_as_f64(l) == number_as_f64(r),
+        },
+        _ => left == right,
+    }
+}
+
+/// Converts a JSON number to a float for comparisons.  Every JSON number can be represented,
+/// though large integers lose precision.
+fn number_as_f64(number: &serde_json::Number) -> f64 {
+    number
+        .as_f64()
+        .or_else(|| number.as_u64().map(|n| n as f64))
+        .unwrap_or_default()
+}
+
 /// Constructs the fully qualified domain name for the ECR registry for the
 /// given region. Returns a default ECR registry if the region is not mapped.
 fn ecr_registry<S: AsRef<str>>(region: S) -> String {
@@ -1666,6 +2172,16 @@ mod test_kube_reserve_cpu {
         .unwrap();
         assert_eq!(result, "30m");
     }
+
+    #[test]
+    fn kube_reserve_cpu_stub() {
+        let mut registry = Handlebars::new();
+        registry.register_helper("kube_reserve_cpu", Box::new(KubeReserveCpuStub { cpus: 4 }));
+        let result = registry
+            .render_template(TEMPLATE, &json!({"not-the-setting": "hi"}))
+            .unwrap();
+        assert_eq!(result, "80m");
+    }
 }
 #[cfg(test)]
 mod test_kube_cpu_helper {
@@ -1736,4 +2252,644 @@ mod test_add_unresolvable_hostname {
         .unwrap();
         assert_eq!(result, "")
     }
+
+    #[test]
+    fn stub_renders_entries() {
+        let mut registry = Handlebars::new();
+        registry.register_helper(
+            "add_unresolvable_hostname",
+            Box::new(AddUnresolvableHostnameStub),
+        );
+        let result = registry
+            .render_template(
+                "{{add_unresolvable_hostname name}}",
+                &json!({"name": "amazon.com"}),
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            "127.0.0.1 amazon.com
+::1 amazon.com"
+        )
+    }
+}
+
+#[cfg(test)]
+mod test_replace {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("replace", Box::new(ValueHelper(replace)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn replaces_all() {
+        let result = setup_and_render_template(
+            r#"{{replace name "_" "-"}}"#,
+            &json!({"name": "my_cluster_name"}),
+        )
+        .unwrap();
+        assert_eq!(result, "my-cluster-name");
+    }
+
+    #[test]
+    fn replaces_in_numbers() {
+        let result =
+            setup_and_render_template(r#"{{replace port "0" "1"}}"#, &json!({"port": 8080}))
+                .unwrap();
+        assert_eq!(result, "8181");
+    }
+
+    #[test]
+    fn missing_value_fails() {
+        setup_and_render_template(r#"{{replace name "_" "-"}}"#, &json!({})).unwrap_err();
+    }
+
+    #[test]
+    fn wrong_param_count_fails() {
+        setup_and_render_template(r#"{{replace name "_"}}"#, &json!({"name": "a_b"})).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_upper {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("upper", Box::new(ValueHelper(upper)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn uppercases() {
+        let result = setup_and_render_template("{{upper mode}}", &json!({"mode": "Tls"})).unwrap();
+        assert_eq!(result, "TLS");
+    }
+
+    #[test]
+    fn map_fails() {
+        setup_and_render_template("{{upper mode}}", &json!({"mode": {"a": "b"}})).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_lower {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("lower", Box::new(ValueHelper(lower)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn lowercases() {
+        let result = setup_and_render_template("{{lower mode}}", &json!({"mode": "Tls"})).unwrap();
+        assert_eq!(result, "tls");
+    }
+
+    #[test]
+    fn bool_is_a_string() {
+        let result = setup_and_render_template("{{lower flag}}", &json!({"flag": true})).unwrap();
+        assert_eq!(result, "true");
+    }
+}
+
+#[cfg(test)]
+mod test_to_json {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("to_json", Box::new(ValueHelper(to_json)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn serializes_map_unescaped() {
+        let result = setup_and_render_template(
+            "{{to_json labels}}",
+            &json!({"labels": {"a": "b", "c": [1, null]}}),
+        )
+        .unwrap();
+        assert_eq!(result, r#"{"a":"b","c":[1,null]}"#);
+    }
+
+    #[test]
+    fn serializes_missing_as_null() {
+        let result = setup_and_render_template("{{to_json labels}}", &json!({})).unwrap();
+        assert_eq!(result, "null");
+    }
+}
+
+#[cfg(test)]
+mod test_to_toml {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("to_toml", Box::new(ValueHelper(to_toml)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn serializes_map() {
+        let result = setup_and_render_template(
+            "{{to_toml settings}}",
+            &json!({"settings": {"motd": "hi", "ntp": {"time-servers": ["a", "b"]}}}),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            r#"motd = "hi"
+
+[ntp]
+time-servers = ["a", "b"]
+"#
+        );
+    }
+
+    #[test]
+    fn non_map_fails() {
+        setup_and_render_template("{{to_toml servers}}", &json!({"servers": ["a"]})).unwrap_err();
+    }
+
+    #[test]
+    fn null_fails() {
+        setup_and_render_template("{{to_toml settings}}", &json!({"settings": {"a": null}}))
+            .unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_to_yaml {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("to_yaml", Box::new(ValueHelper(to_yaml)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn serializes_map() {
+        let result = setup_and_render_template(
+            "{{to_yaml labels}}",
+            &json!({"labels": {"a": "b", "c": ["d"]}}),
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            "a: b
+c:
+  - d"
+        );
+    }
+
+    #[test]
+    fn serializes_scalar() {
+        let result = setup_and_render_template("{{to_yaml pods}}", &json!({"pods": 110})).unwrap();
+        assert_eq!(result, "110");
+    }
+}
+
+#[cfg(test)]
+mod test_eq {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("eq", Box::new(ValueHelper(eq)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    const TEMPLATE: &str = "{{#if (eq a b)}}yes{{else}}no{{/if}}";
+
+    #[test]
+    fn equal_strings() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": "tls", "b": "tls"})).unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn different_types() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": "1", "b": 1})).unwrap();
+        assert_eq!(result, "no");
+    }
+
+    #[test]
+    fn equal_numbers() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": 1, "b": 1.0})).unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn missing_value() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"b": "tls"})).unwrap();
+        assert_eq!(result, "no");
+    }
+
+    #[test]
+    fn inline() {
+        let result = setup_and_render_template("{{eq a b}}", &json!({"a": [1], "b": [1]})).unwrap();
+        assert_eq!(result, "true");
+    }
+}
+
+#[cfg(test)]
+mod test_ne {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("ne", Box::new(ValueHelper(ne)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    const TEMPLATE: &str = "{{#if (ne a b)}}yes{{else}}no{{/if}}";
+
+    #[test]
+    fn different_strings() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": "aws", "b": "tls"})).unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn equal_numbers() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": 2.0, "b": 2})).unwrap();
+        assert_eq!(result, "no");
+    }
+}
+
+#[cfg(test)]
+mod test_lt {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("lt", Box::new(ValueHelper(lt)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    const TEMPLATE: &str = "{{#if (lt a b)}}yes{{else}}no{{/if}}";
+
+    #[test]
+    fn numbers() {
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": 29, "b": 110})).unwrap();
+        assert_eq!(result, "yes");
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": 1.5, "b": 1})).unwrap();
+        assert_eq!(result, "no");
+        let result = setup_and_render_template(TEMPLATE, &json!({"a": -1, "b": 0.5})).unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn strings() {
+        let result =
+            setup_and_render_template(TEMPLATE, &json!({"a": "1.20", "b": "1.21"})).unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn mixed_types_fail() {
+        setup_and_render_template(TEMPLATE, &json!({"a": "1", "b": 2})).unwrap_err();
+        setup_and_render_template(TEMPLATE, &json!({"b": 2})).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_any_enabled {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("any_enabled", Box::new(ValueHelper(any_enabled)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    const TEMPLATE: &str = "{{#if (any_enabled containers)}}yes{{else}}no{{/if}}";
+
+    #[test]
+    fn one_enabled() {
+        let result = setup_and_render_template(
+            TEMPLATE,
+            &json!({"containers": {"admin": {"enabled": false}, "control": {"enabled": true}}}),
+        )
+        .unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn none_enabled() {
+        let result = setup_and_render_template(
+            TEMPLATE,
+            &json!({"containers": {"admin": {"enabled": false}, "control": {"source": "x"}}}),
+        )
+        .unwrap();
+        assert_eq!(result, "no");
+    }
+
+    #[test]
+    fn missing_map() {
+        let result = setup_and_render_template(TEMPLATE, &json!({})).unwrap();
+        assert_eq!(result, "no");
+    }
+
+    #[test]
+    fn not_map_of_maps_fails() {
+        setup_and_render_template(TEMPLATE, &json!({"containers": {"admin": true}})).unwrap_err();
+        setup_and_render_template(TEMPLATE, &json!({"containers": [{"enabled": true}]}))
+            .unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_sorted_keys {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("sorted_keys", Box::new(ValueHelper(sorted_keys)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    const TEMPLATE: &str = "{{#each (sorted_keys labels)}}{{this}},{{/each}}";
+
+    #[test]
+    fn sorted() {
+        let result =
+            setup_and_render_template(TEMPLATE, &json!({"labels": {"b": "1", "c": "2", "a": "3"}}))
+                .unwrap();
+        assert_eq!(result, "a,b,c,");
+    }
+
+    #[test]
+    fn missing_map() {
+        let result = setup_and_render_template(TEMPLATE, &json!({})).unwrap();
+        assert_eq!(result, "");
+    }
+
+    #[test]
+    fn non_map_fails() {
+        setup_and_render_template(TEMPLATE, &json!({"labels": "a"})).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_indent {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("indent", Box::new(ValueHelper(indent)));
+        registry.register_helper("to_yaml", Box::new(ValueHelper(to_yaml)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn indents_lines() {
+        let result =
+            setup_and_render_template("{{indent 2 text}}", &json!({"text": "a\n\nb\n"})).unwrap();
+        assert_eq!(result, "  a\n\n  b\n");
+    }
+
+    #[test]
+    fn indents_subexpression() {
+        let result = setup_and_render_template(
+            "labels:\n{{indent 2 (to_yaml labels)}}",
+            &json!({"labels": {"a": "b", "c": "d"}}),
+        )
+        .unwrap();
+        assert_eq!(result, "labels:\n  a: b\n  c: d");
+    }
+
+    #[test]
+    fn bad_count_fails() {
+        setup_and_render_template("{{indent -1 text}}", &json!({"text": "a"})).unwrap_err();
+        setup_and_render_template(r#"{{indent "2" text}}"#, &json!({"text": "a"})).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_cidr_contains {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("cidr_contains", Box::new(ValueHelper(cidr_contains)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    const TEMPLATE: &str = "{{#if (cidr_contains cidr ip)}}yes{{else}}no{{/if}}";
+
+    #[test]
+    fn ipv4() {
+        let result =
+            setup_and_render_template(TEMPLATE, &json!({"cidr": "10.0.0.0/8", "ip": "10.1.2.3"}))
+                .unwrap();
+        assert_eq!(result, "yes");
+        let result =
+            setup_and_render_template(TEMPLATE, &json!({"cidr": "10.0.0.0/8", "ip": "11.1.2.3"}))
+                .unwrap();
+        assert_eq!(result, "no");
+    }
+
+    #[test]
+    fn ipv6() {
+        let result =
+            setup_and_render_template(TEMPLATE, &json!({"cidr": "fd00::/8", "ip": "fd12::1"}))
+                .unwrap();
+        assert_eq!(result, "yes");
+    }
+
+    #[test]
+    fn other_family() {
+        let result =
+            setup_and_render_template(TEMPLATE, &json!({"cidr": "::/0", "ip": "10.1.2.3"}))
+                .unwrap();
+        assert_eq!(result, "no");
+    }
+
+    #[test]
+    fn invalid_fails() {
+        setup_and_render_template(TEMPLATE, &json!({"cidr": "10.0.0.0", "ip": "10.0.0.1"}))
+            .unwrap_err();
+        setup_and_render_template(TEMPLATE, &json!({"cidr": "10.0.0.0/8", "ip": "10.0.0"}))
+            .unwrap_err();
+        setup_and_render_template(TEMPLATE, &json!({"cidr": "10.0.0.0/8"})).unwrap_err();
+    }
+}
+
+#[cfg(test)]
+mod test_ip_family {
+    use super::*;
+    use handlebars::RenderError;
+    use serde::Serialize;
+    use serde_json::json;
+
+    // A thin wrapper around the handlebars render_template method that includes
+    // setup and registration of helpers
+    fn setup_and_render_template<T>(tmpl: &str, data: &T) -> Result<String, RenderError>
+    where
+        T: Serialize,
+    {
+        let mut registry = Handlebars::new();
+        registry.register_helper("ip_family", Box::new(ValueHelper(ip_family)));
+        registry.register_helper("eq", Box::new(ValueHelper(eq)));
+
+        registry.render_template(tmpl, data)
+    }
+
+    #[test]
+    fn addresses() {
+        let result =
+            setup_and_render_template("{{ip_family ip}}", &json!({"ip": "192.0.2.1"})).unwrap();
+        assert_eq!(result, "ipv4");
+        let result =
+            setup_and_render_template("{{ip_family ip}}", &json!({"ip": "2001:db8::1"})).unwrap();
+        assert_eq!(result, "ipv6");
+    }
+
+    #[test]
+    fn cidr() {
+        let result =
+            setup_and_render_template("{{ip_family ip}}", &json!({"ip": "2001:db8::/32"})).unwrap();
+        assert_eq!(result, "ipv6");
+    }
+
+    #[test]
+    fn subexpression() {
+        let result = setup_and_render_template(
+            r#"{{#if (eq (ip_family ip) "ipv4")}}v4{{/if}}"#,
+            &json!({"ip": "10.0.0.1"}),
+        )
+        .unwrap();
+        assert_eq!(result, "v4");
+    }
+
+    #[test]
+    fn invalid_fails() {
+        setup_and_render_template("{{ip_family ip}}", &json!({"ip": "host"})).unwrap_err();
+        setup_and_render_template("{{ip_family ip}}", &json!({})).unwrap_err();
+    }
 }
@@ -6,8 +6,10 @@
 extern crate log;
 
 mod helpers;
+pub mod offline;
 
 use handlebars::Handlebars;
+use helpers::ValueHelper;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde::de::DeserializeOwned;
 use snafu::ResultExt;
@@ -49,6 +51,73 @@ pub mod error {
             uri: String,
             source: serde_json::Error,
         },
+
+        #[snafu(display("Settings with pending changes don't fit the model: {}", source))]
+        PendingModel { source: serde_json::Error },
+
+        // handlebars includes the line and column of the problem, when it knows them.
+        #[snafu(display("Failed to parse template '{}': {}", name, source))]
+        TemplateRegister {
+            name: String,
+            source: handlebars::TemplateError,
+        },
+
+        #[snafu(display("Failed to render template '{}': {}", name, source))]
+        TemplateRender {
+            name: String,
+            source: handlebars::RenderError,
+        },
+
+        #[snafu(display("Configuration file '{}' has no template or template path", name))]
+        MissingTemplate { name: String },
+
+        #[snafu(display("Template of '{}' is not valid base64: {}", name, source))]
+        TemplateBase64 {
+            name: String,
+            source: base64::DecodeError,
+        },
+
+        #[snafu(display("Template of '{}' is not valid UTF-8: {}", name, source))]
+        TemplateUtf8 {
+            name: String,
+            source: std::string::FromUtf8Error,
+        },
+
+        #[snafu(display("Failed to read '{}': {}", path.display(), source))]
+        ReadDefaults {
+            path: std::path::PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Defaults file '{}' is not valid TOML: {}", path.display(), source))]
+        DefaultsToml {
+            path: std::path::PathBuf,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Defaults file '{}' can't be represented as JSON: {}", path.display(), source))]
+        DefaultsJson {
+            path: std::path::PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("{} must be a JSON object, got: {}", what, value))]
+        NotObject {
+            what: &'static str,
+            value: serde_json::Value,
+        },
+
+        #[snafu(display(
+            "Failed to generate setting '{}' from template '{}': {}",
+            setting,
+            template,
+            source
+        ))]
+        GenerateSetting {
+            setting: String,
+            template: String,
+            source: handlebars::RenderError,
+        },
     }
 }
 pub use error::Error;
@@ -111,8 +180,119 @@ where
     Ok(settings)
 }
 
+/// Requests all settings from the API, like `get_settings`, with the pending settings from the
+/// given transaction applied on top.  This lets templates be rendered as they would be after the
+/// transaction is committed.
+pub async fn get_settings_with_pending<P, S>(socket_path: P, transaction: S) -> Result<model::Model>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    debug!("Querying API for settings data");
+    let mut model: serde_json::Value =
+        get_json(&socket_path, "/", None as Option<(String, String)>).await?;
+
+    debug!(
+        "Querying API for pending settings in transaction '{}'",
+        transaction.as_ref()
+    );
+    let pending: serde_json::Value =
+        get_json(&socket_path, "/tx", Some(("tx", transaction.as_ref()))).await?;
+
+    if let Some(settings) = model.get_mut("settings") {
+        merge_json(settings, pending);
+    }
+    let model = serde_json::from_value(model).context(error::PendingModel)?;
+    trace!("Model values with pending settings: {:?}", model);
+
+    Ok(model)
+}
+
+/// Recursively merges the JSON object `from` into `into`.  Objects are merged key by key; other
+/// values from `from` replace those in `into`.
+fn merge_json(into: &mut serde_json::Value, from: serde_json::Value) {
+    match (into, from) {
+        (serde_json::Value::Object(into_map), serde_json::Value::Object(from_map)) => {
+            for (key, from_value) in from_map {
+                match into_map.get_mut(&key) {
+                    Some(into_value) => merge_json(into_value, from_value),
+                    None => {
+                        into_map.insert(key, from_value);
+                    }
+                }
+            }
+        }
+        (into, from) => *into = from,
+    }
+}
+
+/// Registers the template file at the given path under the given name, and renders it with the
+/// given data.  Errors say where in the template the problem is, when handlebars knows.
+pub fn render_template_file<P, T>(
+    registry: &mut Handlebars<'_>,
+    name: &str,
+    path: P,
+    data: &T,
+) -> Result<String>
+where
+    P: AsRef<Path>,
+    T: serde::Serialize,
+{
+    registry
+        .register_template_file(name, path.as_ref())
+        .context(error::TemplateRegister { name })?;
+    render_template(registry, name, data)
+}
+
+/// Registers the template of the given configuration file under the given name: its inline
+/// template, if it has one, or else the template file at its template path.
+pub fn register_configuration_template(
+    registry: &mut Handlebars<'_>,
+    name: &str,
+    configuration_file: &model::ConfigurationFile,
+) -> Result<()> {
+    if let Some(template) = &configuration_file.template {
+        let decoded =
+            base64::decode(template.as_bytes()).context(error::TemplateBase64 { name })?;
+        let template = String::from_utf8(decoded).context(error::TemplateUtf8 { name })?;
+        registry
+            .register_template_string(name, template)
+            .context(error::TemplateRegister { name })
+    } else if let Some(template_path) = &configuration_file.template_path {
+        registry
+            .register_template_file(name, template_path.as_ref())
+            .context(error::TemplateRegister { name })
+    } else {
+        error::MissingTemplate { name }.fail()
+    }
+}
+
+/// Renders the registered template with the given name with the given data.
+pub fn render_template<T>(registry: &Handlebars<'_>, name: &str, data: &T) -> Result<String>
+where
+    T: serde::Serialize,
+{
+    registry
+        .render(name, data)
+        .context(error::TemplateRender { name })
+}
+
 /// Build a handlebars template registry with our common helper functions.
 pub fn build_template_registry() -> Result<handlebars::Handlebars<'static>> {
+    let mut template_registry = build_base_template_registry();
+
+    // These helpers look at the host they're running on.
+    template_registry.register_helper("kube_reserve_cpu", Box::new(helpers::kube_reserve_cpu));
+    template_registry.register_helper(
+        "add_unresolvable_hostname",
+        Box::new(helpers::add_unresolvable_hostname),
+    );
+
+    Ok(template_registry)
+}
+
+/// Build a handlebars template registry with the helper functions that don't depend on the host.
+fn build_base_template_registry() -> Handlebars<'static> {
     let mut template_registry = Handlebars::new();
     // Strict mode will panic if a key exists in the template
     // but isn't provided in the data given to the renderer
@@ -127,23 +307,45 @@ pub fn build_template_registry() -> Result<handlebars::Handlebars<'static>> {
     template_registry.register_helper("host", Box::new(helpers::host));
     template_registry.register_helper("goarch", Box::new(helpers::goarch));
     template_registry.register_helper("join_array", Box::new(helpers::join_array));
-    template_registry.register_helper("kube_reserve_cpu", Box::new(helpers::kube_reserve_cpu));
     template_registry.register_helper(
         "kube_reserve_memory",
         Box::new(helpers::kube_reserve_memory),
     );
+
+    // General-purpose helpers, which can also be used in subexpressions.  These replace the
+    // handlebars built-in comparisons, which only compare integers.
+    template_registry.register_helper("replace", Box::new(ValueHelper(helpers::replace)));
+    template_registry.register_helper("upper", Box::new(ValueHelper(helpers::upper)));
+    template_registry.register_helper("lower", Box::new(ValueHelper(helpers::lower)));
+    template_registry.register_helper("to_json", Box::new(ValueHelper(helpers::to_json)));
+    template_registry.register_helper("to_toml", Box::new(ValueHelper(helpers::to_toml)));
+    template_registry.register_helper("to_yaml", Box::new(ValueHelper(helpers::to_yaml)));
+    template_registry.register_helper("eq", Box::new(ValueHelper(helpers::eq)));
+    template_registry.register_helper("ne", Box::new(ValueHelper(helpers::ne)));
+    template_registry.register_helper("lt", Box::new(ValueHelper(helpers::lt)));
+    template_registry.register_helper("any_enabled", Box::new(ValueHelper(helpers::any_enabled)));
+    template_registry.register_helper("sorted_keys", Box::new(ValueHelper(helpers::sorted_keys)));
+    template_registry.register_helper("indent", Box::new(ValueHelper(helpers::indent)));
     template_registry.register_helper(
-        "add_unresolvable_hostname",
-        Box::new(helpers::add_unresolvable_hostname),
+        "cidr_contains",
+        Box::new(ValueHelper(helpers::cidr_contains)),
     );
+    template_registry.register_helper("ip_family", Box::new(ValueHelper(helpers::ip_family)));
 
-    Ok(template_registry)
+    template_registry
 }
 
 #[cfg(test)]
 mod test {
+    use super::{
+        build_template_registry, merge_json, register_configuration_template, render_template,
+        render_template_file,
+    };
     use handlebars::Handlebars;
+    use model::ConfigurationFile;
     use serde_json::json;
+    use std::convert::TryInto;
+    use std::fs;
 
     #[test]
     fn render_whitespace() {
@@ -182,4 +384,80 @@ y";
         let result = registry.render_template(&tmpl, &data).unwrap();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn merge_pending() {
+        let mut live = json!({"motd": "hi", "ntp": {"time-servers": ["a"]}, "x": {"y": 1}});
+        let pending = json!({"ntp": {"time-servers": ["b", "c"]}, "x": {"z": 2}});
+        merge_json(&mut live, pending);
+        assert_eq!(
+            live,
+            json!({"motd": "hi", "ntp": {"time-servers": ["b", "c"]}, "x": {"y": 1, "z": 2}})
+        );
+    }
+
+    #[test]
+    fn render_file_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("template");
+        let data = json!({"settings": {"motd": "hi"}});
+
+        fs::write(&path, "motd={{settings.motd}}\n").unwrap();
+        let mut registry = build_template_registry().unwrap();
+        assert_eq!(
+            render_template_file(&mut registry, "good", &path, &data).unwrap(),
+            "motd=hi\n"
+        );
+
+        // Parse errors give the position in the template.
+        fs::write(&path, "a\n{{#if settings.motd}}\nb\n").unwrap();
+        let err = render_template_file(&mut registry, "unclosed", &path, &data).unwrap_err();
+        assert!(matches!(err, super::Error::TemplateRegister { .. }));
+        assert!(err.to_string().contains("\"unclosed\":"), "{}", err);
+
+        // So do render errors, like missing settings in strict mode.
+        fs::write(&path, "a\nb={{settings.missing}}\n").unwrap();
+        let err = render_template_file(&mut registry, "missing", &path, &data).unwrap_err();
+        assert!(matches!(err, super::Error::TemplateRender { .. }));
+        assert!(err.to_string().contains("line 2, col 3"), "{}", err);
+    }
+
+    #[test]
+    fn register_configuration_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("template");
+        fs::write(&path, "file={{settings.motd}}\n").unwrap();
+        let data = json!({"settings": {"motd": "hi"}});
+        let mut registry = build_template_registry().unwrap();
+
+        let mut file = ConfigurationFile {
+            path: "/etc/custom/a".try_into().unwrap(),
+            template_path: Some(path.to_str().unwrap().try_into().unwrap()),
+            template: None,
+            mode: None,
+            owner: None,
+            group: None,
+            render_if: None,
+        };
+        register_configuration_template(&mut registry, "a", &file).unwrap();
+        assert_eq!(render_template(&registry, "a", &data).unwrap(), "file=hi\n");
+
+        // An inline template is used instead of the template path.
+        file.template = Some(
+            base64::encode("inline={{settings.motd}}")
+                .try_into()
+                .unwrap(),
+        );
+        register_configuration_template(&mut registry, "a", &file).unwrap();
+        assert_eq!(render_template(&registry, "a", &data).unwrap(), "inline=hi");
+
+        file.template = Some(base64::encode([0xff, 0xfe]).try_into().unwrap());
+        let err = register_configuration_template(&mut registry, "a", &file).unwrap_err();
+        assert!(matches!(err, super::Error::TemplateUtf8 { .. }));
+
+        file.template = None;
+        file.template_path = None;
+        let err = register_configuration_template(&mut registry, "a", &file).unwrap_err();
+        assert!(matches!(err, super::Error::MissingTemplate { .. }));
+    }
 }
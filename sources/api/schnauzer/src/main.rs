@@ -10,14 +10,36 @@ The setting we're generating is expected to have a metadata key already set: "te
 For example, if we're generating "settings.x" and we have template "foo-{{ settings.bar }}", we look up the value of "settings.bar" in the API.
 If the returned value is "baz", our generated value will be "foo-baz".
 
+# Rendering offline
+
+`schnauzer render` renders a template file without a running Bottlerocket host, so that configuration file templates can be checked, for example with snapshot tests in CI.
+
+```sh
+schnauzer render --variant aws-k8s-1.21 --template packages/kubernetes-1.21/kubelet-config \
+    --defaults-dir sources/models/src/aws-k8s-1.21/defaults.d --settings-json settings.json
+```
+
+Settings come from the TOML files in the given `defaults.d` directory, merged in order like storewolf does, with any settings in the given JSON file applied on top.
+The JSON can be a map of settings, or have them under a "settings" key, like the output of `apiclient get settings`.
+
+Settings that are generated at boot are filled in unless they're given.
+Those generated by schnauzer are rendered from their templates in the defaults, and others, like the region and the node IP, get fixed stand-in values.
+Helpers that look at the host use stand-in values too: `kube_reserve_cpu` assumes 2 CPUs, and `add_unresolvable_hostname` doesn't use DNS, so it always adds the hostname.
+The architecture and region can be given with `--arch` and `--region`; they default to x86_64 and us-west-2.
+
+The rendered template is printed to stdout.
+
 (The name "schnauzer" comes from the fact that Schnauzers are search and rescue dogs (similar to this search and replace task) and because they have mustaches.)
 */
 
 #![deny(rust_2018_idioms)]
 
 use constants;
+use schnauzer::offline::{self, Host};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 use std::string::String;
 use std::{env, process};
 
@@ -27,6 +49,7 @@ const API_METADATA_URI_BASE: &str = "/metadata/";
 mod error {
     use http::StatusCode;
     use snafu::Snafu;
+    use std::path::PathBuf;
 
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub(super)")]
@@ -71,6 +94,27 @@ mod error {
         #[snafu(display("Failed to get settings from API: {}", source))]
         GetSettings { source: schnauzer::Error },
 
+        #[snafu(display("Failed to read defaults: {}", source))]
+        ReadDefaults { source: schnauzer::Error },
+
+        #[snafu(display("Failed to read settings from '{}': {}", path.display(), source))]
+        ReadSettings {
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Settings in '{}' are not valid JSON: {}", path.display(), source))]
+        ParseSettings {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Failed to build template data: {}", source))]
+        TemplateData { source: schnauzer::Error },
+
+        #[snafu(display("{}", source))]
+        RenderFile { source: schnauzer::Error },
+
         #[snafu(display(
             "Failed to render setting '{}' from template '{}': {}",
             setting_name,
@@ -126,24 +170,166 @@ async fn get_metadata(key: &str, meta: &str) -> Result<String> {
     Ok(response_str.to_string())
 }
 
+/// What we were asked to do.
+enum Mode {
+    /// Generate the given setting from its template, using the API.
+    Generate { setting_name: String },
+    /// Render a template file offline.
+    Render(RenderArgs),
+}
+
+/// Stores user-supplied arguments for offline rendering.
+struct RenderArgs {
+    variant: String,
+    template: PathBuf,
+    defaults_dir: Option<PathBuf>,
+    settings_json: Option<PathBuf>,
+    arch: Option<String>,
+    region: Option<String>,
+}
+
 /// Print usage message.
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
-    eprintln!("Usage: {} SETTING_KEY", program_name);
+    eprintln!(
+        r"Usage: {0} SETTING_KEY
+       {0} render --variant VARIANT --template PATH
+            [ --defaults-dir PATH ]   defaults.d directory of the variant
+            [ --settings-json PATH ]  settings to apply on top of the defaults
+            [ --arch ARCH ]           defaults to x86_64
+            [ --region REGION ]       defaults to us-west-2",
+        program_name
+    );
     process::exit(2);
 }
 
-/// Parses args for the setting key name.
-fn parse_args(mut args: env::Args) -> String {
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses args for the setting key name, or for offline rendering.
+fn parse_args(mut args: env::Args) -> Mode {
     let arg = args.nth(1).unwrap_or_else(|| usage());
     if arg == "--help" || arg == "-h" {
         usage()
     }
-    arg
+    if arg == "render" {
+        return Mode::Render(parse_render_args(args));
+    }
+    Mode::Generate { setting_name: arg }
+}
+
+/// Parses args for offline rendering.
+fn parse_render_args(mut args: env::Args) -> RenderArgs {
+    let mut variant = None;
+    let mut template = None;
+    let mut defaults_dir = None;
+    let mut settings_json = None;
+    let mut arch = None;
+    let mut region = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--variant" => {
+                variant = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --variant")),
+                )
+            }
+
+            "--template" => {
+                template =
+                    Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --template")
+                    })))
+            }
+
+            "--defaults-dir" => {
+                defaults_dir =
+                    Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --defaults-dir")
+                    })))
+            }
+
+            "--settings-json" => {
+                settings_json =
+                    Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --settings-json")
+                    })))
+            }
+
+            "--arch" => {
+                arch = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --arch")),
+                )
+            }
+
+            "--region" => {
+                region = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --region")),
+                )
+            }
+
+            "-h" | "--help" => usage(),
+
+            x => usage_msg(format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    RenderArgs {
+        variant: variant.unwrap_or_else(|| usage_msg("--variant is required")),
+        template: template.unwrap_or_else(|| usage_msg("--template is required")),
+        defaults_dir,
+        settings_json,
+        arch,
+        region,
+    }
+}
+
+/// Renders a template file offline, and prints it.
+fn render(args: RenderArgs) -> Result<()> {
+    let mut host = Host::new(args.variant);
+    if let Some(arch) = args.arch {
+        host.arch = arch;
+    }
+    if let Some(region) = args.region {
+        host.region = region;
+    }
+
+    let defaults = match args.defaults_dir {
+        Some(dir) => Some(offline::read_defaults_dir(dir).context(error::ReadDefaults)?),
+        None => None,
+    };
+    let settings: Option<serde_json::Value> = match args.settings_json {
+        Some(path) => {
+            let data = fs::read_to_string(&path).context(error::ReadSettings { path: &path })?;
+            Some(serde_json::from_str(&data).context(error::ParseSettings { path: &path })?)
+        }
+        None => None,
+    };
+    let data = offline::template_data(&host, defaults.as_ref(), settings.as_ref())
+        .context(error::TemplateData)?;
+
+    let mut registry =
+        offline::build_template_registry(&host).context(error::BuildTemplateRegistry)?;
+    let name = args.template.display().to_string();
+    let rendered = schnauzer::render_template_file(&mut registry, &name, &args.template, &data)
+        .context(error::RenderFile)?;
+
+    // Print exactly what would be written to the file.
+    print!("{}", rendered);
+    Ok(())
 }
 
 async fn run() -> Result<()> {
-    let setting_name = parse_args(env::args());
+    let setting_name = match parse_args(env::args()) {
+        Mode::Generate { setting_name } => setting_name,
+        Mode::Render(render_args) => return render(render_args),
+    };
 
     let registry = schnauzer::build_template_registry().context(error::BuildTemplateRegistry)?;
     let template = get_metadata(&setting_name, "templates").await?;
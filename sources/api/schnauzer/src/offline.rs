@@ -0,0 +1,388 @@
+//! The offline module renders templates without a running Bottlerocket host, for example to check
+//! a variant's configuration files in CI.  Settings come from a variant's `defaults.d` directory
+//! and from JSON given by the user, rather than from the API.
+//!
+//! On a host, some settings are generated at boot from information about the host, like the
+//! region from instance metadata, and some helpers look at the host itself.  Offline, those use
+//! fixed stand-in values from `Host`, so the output is the same wherever it's rendered.
+
+use crate::{error, helpers, merge_json, Result};
+use bottlerocket_release::BottlerocketRelease;
+use handlebars::Handlebars;
+use semver::Version;
+use serde_json::{json, Map, Value};
+use snafu::{ensure, ResultExt};
+use std::fs;
+use std::path::Path;
+
+/// Base64-encoded user-data for the admin container, as shibaken generates it for an instance
+/// without SSH keys.
+const STUB_ADMIN_USER_DATA: &str = "eyJzc2giOnsiYXV0aG9yaXplZC1rZXlzIjpbXX19";
+
+/// The host that templates are rendered for.
+#[derive(Debug, Clone)]
+pub struct Host {
+    /// The variant, e.g. "aws-k8s-1.21".
+    pub variant: String,
+    /// The architecture, e.g. "x86_64".
+    pub arch: String,
+    /// The OS version.
+    pub version: Version,
+    /// The region, used for `settings.aws.region` unless it's given.
+    pub region: String,
+    /// The number of CPUs, used by helpers that size things for the host.
+    pub cpus: usize,
+}
+
+impl Host {
+    /// Describes a host of the given variant, with stand-in values for everything else.
+    pub fn new<S: Into<String>>(variant: S) -> Self {
+        Self {
+            variant: variant.into(),
+            arch: "x86_64".to_string(),
+            version: Version::new(0, 0, 0),
+            region: "us-west-2".to_string(),
+            cpus: 2,
+        }
+    }
+
+    /// The OS release information the API gives on this host.
+    fn os(&self) -> BottlerocketRelease {
+        BottlerocketRelease {
+            pretty_name: format!("Bottlerocket OS {}", self.version),
+            variant_id: self.variant.clone(),
+            version_id: self.version.clone(),
+            build_id: "0000000".to_string(),
+            arch: self.arch.clone(),
+        }
+    }
+
+    /// Returns a stand-in value for a setting that's generated from information about the host,
+    /// other than by schnauzer, if we know the setting.
+    fn generated_setting(&self, name: &str) -> Option<Value> {
+        let value = match name {
+            "settings.kubernetes.max-pods" => json!(110),
+            "settings.kubernetes.cluster-dns-ip" => json!("10.100.0.10"),
+            "settings.kubernetes.node-ip" => json!("192.0.2.10"),
+            "settings.network.hostname" => {
+                json!(format!("ip-192-0-2-10.{}.compute.internal", self.region))
+            }
+            "settings.host-containers.admin.user-data" => json!(STUB_ADMIN_USER_DATA),
+            "settings.updates.seed" => json!(0),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Reads the TOML files in a variant's `defaults.d` directory and merges them in order of their
+/// names, like storewolf does when it's built.  Returns the merged defaults as JSON.
+pub fn read_defaults_dir<P: AsRef<Path>>(dir: P) -> Result<Value> {
+    let dir = dir.as_ref();
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).context(error::ReadDefaults { path: dir })? {
+        let entry = entry.context(error::ReadDefaults { path: dir })?;
+        if entry.file_name().to_string_lossy().ends_with(".toml") {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    let mut defaults = Value::Object(Map::new());
+    for path in paths {
+        debug!("Reading defaults from {}", path.display());
+        let data = fs::read_to_string(&path).context(error::ReadDefaults { path: &path })?;
+        let value: toml::Value =
+            toml::from_str(&data).context(error::DefaultsToml { path: &path })?;
+        let value = serde_json::to_value(value).context(error::DefaultsJson { path: &path })?;
+        merge_json(&mut defaults, value);
+    }
+    Ok(defaults)
+}
+
+/// Builds the data for rendering templates, like the API gives on the given host.  Settings start
+/// from the given defaults, as returned by `read_defaults_dir`, with the given settings applied on
+/// top.  The given settings can be a map of settings, or have them under a "settings" key, like
+/// the API's output.
+///
+/// Settings that would be generated at boot are filled in unless they're given.  Those generated
+/// by schnauzer are rendered from their templates in the defaults; others use stand-in values
+/// from the `Host`.  The region is filled in the same way, since it comes from instance metadata.
+pub fn template_data(
+    host: &Host,
+    defaults: Option<&Value>,
+    settings: Option<&Value>,
+) -> Result<Value> {
+    let mut data = match defaults {
+        Some(Value::Object(defaults)) => defaults.clone(),
+        Some(defaults) => {
+            return error::NotObject {
+                what: "Defaults",
+                value: defaults.clone(),
+            }
+            .fail()
+        }
+        None => Map::new(),
+    };
+    // Metadata isn't part of the API's model; we only need it to find generated settings.
+    let metadata = data.remove("metadata").unwrap_or(Value::Null);
+    data.insert(
+        "os".to_string(),
+        serde_json::to_value(host.os()).expect("OS release serializes to JSON"),
+    );
+
+    let data_settings = data.entry("settings").or_insert_with(|| json!({}));
+    if let Some(settings) = settings {
+        let settings = match settings.as_object() {
+            Some(map) if map.len() == 1 && map.contains_key("settings") => &map["settings"],
+            _ => settings,
+        };
+        ensure!(
+            settings.is_object(),
+            error::NotObject {
+                what: "Settings",
+                value: settings.clone()
+            }
+        );
+        merge_json(data_settings, settings.clone());
+    }
+
+    let mut data = Value::Object(data);
+    set_if_missing(
+        &mut data,
+        &["settings", "aws", "region"],
+        json!(host.region),
+    );
+
+    // Fill in generated settings that weren't given, saving the templated ones for later, since
+    // their templates can refer to the others.
+    let mut templated = Vec::new();
+    for generated in generated_settings(&metadata) {
+        let name = generated.path.join(".");
+        if get_path(&data, &generated.path).is_some() {
+            continue;
+        }
+        let is_schnauzer = generated.generator.split_whitespace().next() == Some("schnauzer");
+        match (is_schnauzer, generated.template) {
+            (true, Some(template)) => templated.push((generated.path, name, template)),
+            _ => match host.generated_setting(&name) {
+                Some(value) => set_path(&mut data, &generated.path, value),
+                None => debug!("No stand-in for generated setting '{}'", name),
+            },
+        }
+    }
+
+    // Templates can refer to other templated settings, so render whichever we can until they're
+    // all done, or until none of the rest can be rendered.
+    let registry = build_template_registry(host)?;
+    while !templated.is_empty() {
+        let count = templated.len();
+        let mut remaining = Vec::new();
+        let mut first_error = None;
+        for (path, name, template) in templated {
+            match registry.render_template(&template, &data) {
+                Ok(value) => set_path(&mut data, &path, Value::String(value)),
+                Err(e) => {
+                    if first_error.is_none() {
+                        first_error = Some(Err(e).context(error::GenerateSetting {
+                            setting: &name,
+                            template: &template,
+                        }));
+                    }
+                    remaining.push((path, name, template));
+                }
+            }
+        }
+        if remaining.len() == count {
+            if let Some(err) = first_error {
+                return err;
+            }
+        }
+        templated = remaining;
+    }
+
+    Ok(data)
+}
+
+/// Build a handlebars template registry with our common helper functions, like
+/// `schnauzer::build_template_registry`, except that helpers that look at the host use the
+/// stand-in values of the given `Host`.
+pub fn build_template_registry(host: &Host) -> Result<Handlebars<'static>> {
+    let mut template_registry = crate::build_base_template_registry();
+
+    template_registry.register_helper(
+        "kube_reserve_cpu",
+        Box::new(helpers::KubeReserveCpuStub { cpus: host.cpus }),
+    );
+    template_registry.register_helper(
+        "add_unresolvable_hostname",
+        Box::new(helpers::AddUnresolvableHostnameStub),
+    );
+
+    Ok(template_registry)
+}
+
+/// A setting that's generated at boot, as described by metadata in the defaults.
+struct GeneratedSetting {
+    path: Vec<String>,
+    generator: String,
+    template: Option<String>,
+}
+
+/// Finds the settings that have a generator in the given metadata from the defaults.
+fn generated_settings(metadata: &Value) -> Vec<GeneratedSetting> {
+    fn walk(value: &Value, path: &mut Vec<String>, found: &mut Vec<GeneratedSetting>) {
+        if let Value::Object(map) = value {
+            if let Some(Value::String(generator)) = map.get("setting-generator") {
+                found.push(GeneratedSetting {
+                    path: path.clone(),
+                    generator: generator.clone(),
+                    template: map
+                        .get("template")
+                        .and_then(Value::as_str)
+                        .map(String::from),
+                });
+            }
+            for (name, child) in map {
+                path.push(name.clone());
+                walk(child, path, found);
+                path.pop();
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    walk(metadata, &mut Vec::new(), &mut found);
+    found
+}
+
+/// Returns the value at the given path of object keys, if there is one.
+fn get_path<'a, S: AsRef<str>>(value: &'a Value, path: &[S]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(value, |value, name| value.get(name.as_ref()))
+}
+
+/// Sets the value at the given path of object keys, adding objects along the way as needed.
+fn set_path<S: AsRef<str>>(value: &mut Value, path: &[S], new: Value) {
+    let mut current = value;
+    for name in path {
+        if !current.is_object() {
+            *current = json!({});
+        }
+        current = current
+            .as_object_mut()
+            .expect("just made an object")
+            .entry(name.as_ref())
+            .or_insert(Value::Null);
+    }
+    *current = new;
+}
+
+/// Sets the value at the given path of object keys, unless there's already one there.
+fn set_if_missing<S: AsRef<str>>(value: &mut Value, path: &[S], new: Value) {
+    if get_path(value, path).is_none() {
+        set_path(value, path, new);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    const DEFAULTS: &str = r#"
+[settings.kubernetes]
+cluster-domain = "cluster.local"
+
+[metadata.settings.kubernetes]
+max-pods.setting-generator = "pluto max-pods"
+node-ip.setting-generator = "pluto node-ip"
+unknown.setting-generator = "pluto unknown"
+
+[metadata.settings.host-containers.admin.source]
+setting-generator = "schnauzer settings.host-containers.admin.source"
+template = "{{ ecr-prefix settings.aws.region }}/bottlerocket-admin:{{ settings.tag }}"
+
+[metadata.settings.tag]
+setting-generator = "schnauzer settings.tag"
+template = "{{ os.variant_id }}"
+"#;
+
+    #[test]
+    fn defaults_dir_merged_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("20-b.toml"), "[settings]\nmotd = \"b\"\n").unwrap();
+        fs::write(
+            dir.path().join("10-a.toml"),
+            "[settings]\nmotd = \"a\"\nx = 1\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("30-c.txt"), "not TOML").unwrap();
+
+        let defaults = read_defaults_dir(dir.path()).unwrap();
+        assert_eq!(defaults, json!({"settings": {"motd": "b", "x": 1}}));
+    }
+
+    #[test]
+    fn generated_settings_filled_in() {
+        let defaults =
+            serde_json::to_value(toml::from_str::<toml::Value>(DEFAULTS).unwrap()).unwrap();
+        let settings = json!({"settings": {"kubernetes": {"node-ip": "10.0.0.1"}}});
+        let mut host = Host::new("aws-k8s-1.21");
+        host.region = "eu-south-1".to_string();
+
+        let data = template_data(&host, Some(&defaults), Some(&settings)).unwrap();
+        assert_eq!(
+            data["settings"],
+            json!({
+                "aws": {"region": "eu-south-1"},
+                "host-containers": {"admin": {"source":
+                    "586180183710.dkr.ecr.eu-south-1.amazonaws.com/bottlerocket-admin:aws-k8s-1.21"
+                }},
+                "kubernetes": {
+                    "cluster-domain": "cluster.local",
+                    "max-pods": 110,
+                    "node-ip": "10.0.0.1",
+                },
+                "tag": "aws-k8s-1.21",
+            })
+        );
+        assert_eq!(data["os"]["variant_id"], "aws-k8s-1.21");
+        assert_eq!(data["os"]["arch"], "x86_64");
+        assert!(data.get("metadata").is_none());
+    }
+
+    #[test]
+    fn bad_settings() {
+        let host = Host::new("aws-k8s-1.21");
+        assert!(template_data(&host, None, Some(&json!(["motd"]))).is_err());
+        assert!(template_data(&host, None, Some(&json!({"settings": 1}))).is_err());
+    }
+
+    #[test]
+    fn variant_defaults() {
+        // The real defaults of a variant should render without a host.
+        let defaults_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../models/src/aws-k8s-1.21/defaults.d");
+        let defaults = read_defaults_dir(defaults_dir).unwrap();
+        let data = template_data(&Host::new("aws-k8s-1.21"), Some(&defaults), None).unwrap();
+        assert_eq!(
+            data["settings"]["host-containers"]["admin"]["source"],
+            "328549459982.dkr.ecr.us-west-2.amazonaws.com/bottlerocket-admin:v0.7.2"
+        );
+        assert_eq!(
+            data["settings"]["updates"]["metadata-base-url"],
+            "https://updates.bottlerocket.aws/2020-07-07/aws-k8s-1.21/x86_64/"
+        );
+
+        let registry = build_template_registry(&Host::new("aws-k8s-1.21")).unwrap();
+        let rendered = registry
+            .render_template(
+                "{{kube_reserve_cpu settings.kubernetes.kube-reserved.cpu}} \
+                 {{kube_reserve_memory settings.kubernetes.max-pods settings.kubernetes.kube-reserved.memory}}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(rendered, "70m 1465Mi");
+    }
+}
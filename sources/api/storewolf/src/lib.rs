@@ -1,10 +1,17 @@
 #![deny(rust_2018_idioms)]
 
 use bottlerocket_release::BottlerocketRelease;
+use datastore::key::{Key, KeyType};
+use datastore::serialization::{to_pairs, to_pairs_with_prefix};
+use datastore::ScalarError;
+use log::{debug, trace};
+use model::modeled_types::SingleLineString;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use semver::Version;
-use snafu::ResultExt;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs;
 use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
@@ -13,6 +20,9 @@ pub mod error {
     use std::io;
     use std::path::PathBuf;
 
+    use datastore::key::KeyType;
+    use datastore::{self, serialization, ScalarError};
+    use model::modeled_types::error::Error as ModeledTypesError;
     use snafu::Snafu;
 
     /// Public error type for `libstorewolf`
@@ -22,11 +32,48 @@ pub mod error {
         #[snafu(display("Unable to create directory at '{}': {}", path.display(), source))]
         DirectoryCreation { path: PathBuf, source: io::Error },
 
+        #[snafu(display("Merged defaults file '{}' is not valid TOML: {}", path.display(), source))]
+        DefaultsFormatting {
+            path: PathBuf,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Default settings are not a TOML table"))]
+        DefaultsNotTable {},
+
+        #[snafu(display("'settings' key in defaults is not a TOML table"))]
+        DefaultSettingsNotTable {},
+
+        #[snafu(display("Default settings' metadata has unexpected types"))]
+        DefaultsMetadataUnexpectedFormat {},
+
+        #[snafu(display("Internal error: {}", msg))]
+        Internal { msg: String },
+
+        #[snafu(display("Unable to create {:?} key '{}': {}", key_type, key, source))]
+        InvalidKey {
+            key_type: KeyType,
+            key: String,
+            source: datastore::Error,
+        },
+
         #[snafu(display("Failed to create symlink at '{}': {}", path.display(), source))]
         LinkCreate { path: PathBuf, source: io::Error },
 
         #[snafu(display("Unable to get OS version: {}", source))]
         ReleaseVersion { source: bottlerocket_release::Error },
+
+        #[snafu(display("Error serializing {}: {} ", given, source))]
+        Serialization {
+            given: String,
+            source: serialization::Error,
+        },
+
+        #[snafu(display("Error serializing scalar {}: {} ", given, source))]
+        SerializeScalar { given: String, source: ScalarError },
+
+        #[snafu(display("Keys can't contain newlines: {}", source))]
+        SingleLineString { source: ModeledTypesError },
     }
 }
 
@@ -112,3 +159,181 @@ pub fn create_new_datastore<P: AsRef<Path>>(
     })?;
     Ok(data_store_path)
 }
+
+/// Convert the generic toml::Value representing metadata into a
+/// Vec<Metadata> that can be used to write the metadata to the datastore.
+// The input to this function is a toml::Value that represents the metadata
+// read from the TOML default settings files. This table is structured like so:
+//
+// Table({"settings": Table({"foo": Table({"affected-services": Array([ ... ])})})})
+//
+// This function will convert the above table to a Vec<model::Metadata>,
+// validating the types and structure. The resulting Vec looks like:
+//
+// [
+//   Metadata {key: "settings.motd", md: "affected-services", val: Array([ ... ])},
+//   Metadata { ... },
+// ]
+fn parse_metadata_toml(md_toml_val: toml::Value) -> Result<Vec<model::Metadata>> {
+    debug!("Parsing metadata toml");
+    let mut def_metadatas: Vec<model::Metadata> = Vec::new();
+
+    // Do a breadth-first search of the toml::Value table.
+    // Create a Vec of tuples to keep track of where we have visited in the
+    // toml::Value data structure. The first value in the tuple is the key
+    // (represented as a Vec of key segments), the second is the toml::Value
+    // associated with that key. It ends up looking like:
+    // [
+    //   (
+    //     ["settings", "motd"],
+    //     toml::Value
+    //   ),
+    //   ...
+    // ]
+    // For each key/value of the table we visit, match on the value of the
+    // table. If it's another table, we add it to the list to process its
+    // contents. If it is an array or string, we can construct a
+    // model::Metadata, and add it to the Vec of model::Metadata to be
+    // returned from the function.
+
+    // Start at the root of the tree.
+    let mut to_process = vec![(Vec::new(), md_toml_val)];
+
+    while !to_process.is_empty() {
+        let (mut path, toml_value) = to_process.pop().unwrap();
+        trace!("Current metadata table path: {:#?}", &path);
+
+        match toml_value {
+            // A table means there is more processing to do. Add the current
+            // key and value to the Vec to be processed further.
+            toml::Value::Table(table) => {
+                for (key, val) in table {
+                    trace!("Found table for key '{}'", &key);
+                    let mut path = path.clone();
+                    path.push(key.to_string());
+                    to_process.push((path, val));
+                }
+            }
+
+            // An array or string means we're ready to create a model::Metadata
+            val @ toml::Value::Array(_) | val @ toml::Value::String(_) => {
+                // Get the metadata key from the end of the path
+                let md_key = path.pop().context(error::Internal {
+                    msg: "parse_metadata_toml found empty 'path' in the to_process vec - is 'metadata' not a Table?",
+                })?;
+
+                // Make sure that the path contains more than 1 item, i.e. ["settings", "motd"]
+                ensure!(
+                    path.len() >= 1,
+                    error::Internal {
+                        msg: format!(
+                            "Cannot create empty metadata data key - is root not a Table?"
+                        )
+                    }
+                );
+                let data_key = path.join(".");
+
+                trace!(
+                    "Found metadata key '{}' for data key '{}'",
+                    &md_key,
+                    &data_key
+                );
+
+                // Ensure the metadata/data keys don't contain newline chars
+                let md = SingleLineString::try_from(md_key).context(error::SingleLineString)?;
+                let key = SingleLineString::try_from(data_key).context(error::SingleLineString)?;
+
+                // Create the Metadata struct
+                def_metadatas.push(model::Metadata { key, md, val })
+            }
+
+            // We don't recognize any other values yet, something is awry
+            _ => return error::DefaultsMetadataUnexpectedFormat {}.fail(),
+        };
+    }
+    Ok(def_metadatas)
+}
+
+/// The default data of the current variant, from the TOML files in its `defaults.d` directory,
+/// serialized for the data store.
+#[derive(Debug, Default)]
+pub struct Defaults {
+    /// Default settings.  storewolf writes these to the launch transaction rather than to live
+    /// data, so they go through a commit, which writes the configuration files that use them.
+    pub settings: HashMap<Key, String>,
+    /// Default metadata values, keyed by data key and metadata key.
+    pub metadata: HashMap<(Key, Key), String>,
+    /// Any other defaults, like services and configuration files, which are written to live data.
+    pub other: HashMap<Key, String>,
+}
+
+/// Returns the default data of the current variant.  The defaults are merged from the variant's
+/// `defaults.d` directory when storewolf is built.
+pub fn defaults() -> Result<Defaults> {
+    // Here we read in the merged settings file built by build.rs.
+    let defaults_str = include_str!(concat!(env!("OUT_DIR"), "/defaults.toml"));
+    let mut defaults_val: toml::Value =
+        toml::from_str(defaults_str).context(error::DefaultsFormatting {
+            path: concat!(env!("OUT_DIR"), "/defaults.toml"),
+        })?;
+
+    // Check if we have metadata and settings. If so, pull them out
+    // of `shared_defaults_val`
+    let table = defaults_val
+        .as_table_mut()
+        .context(error::DefaultsNotTable)?;
+    let maybe_metadata_val = table.remove("metadata");
+    let maybe_settings_val = table.remove("settings");
+
+    let mut defaults = Defaults::default();
+
+    if let Some(def_settings_val) = maybe_settings_val {
+        debug!("Serializing default settings");
+        let def_settings_table = def_settings_val
+            .as_table()
+            .context(error::DefaultSettingsNotTable)?;
+
+        // The default settings were removed from the "settings" key of the
+        // defaults table above. We still need them under a "settings" key
+        // before serializing so we have full dotted keys like
+        // "settings.foo.bar" and not just "foo.bar". We use a HashMap
+        // to rebuild the nested structure.
+        defaults.settings = to_pairs_with_prefix("settings", &def_settings_table).context(
+            error::Serialization {
+                given: "default settings",
+            },
+        )?;
+    }
+
+    if let Some(def_metadata_val) = maybe_metadata_val {
+        debug!("Serializing metadata");
+        // Create a Vec<Metadata> from the metadata toml::Value
+        let def_metadatas = parse_metadata_toml(def_metadata_val)?;
+
+        for def_metadata in def_metadatas {
+            let model::Metadata { key, md, val } = def_metadata;
+            let data_key = Key::new(KeyType::Data, &key).context(error::InvalidKey {
+                key_type: KeyType::Data,
+                key,
+            })?;
+            let md_key = Key::new(KeyType::Meta, &md).context(error::InvalidKey {
+                key_type: KeyType::Meta,
+                key: md,
+            })?;
+            let value = datastore::serialize_scalar::<_, ScalarError>(&val).with_context(|| {
+                error::SerializeScalar {
+                    given: format!("metadata value '{}'", val),
+                }
+            })?;
+            defaults.metadata.insert((data_key, md_key), value);
+        }
+    }
+
+    // Any other defaults remain (configuration files, services, etc)
+    debug!("Serializing other defaults");
+    defaults.other = to_pairs(&defaults_val).context(error::Serialization {
+        given: "other defaults",
+    })?;
+
+    Ok(defaults)
+}
@@ -13,28 +13,21 @@ extern crate log;
 
 use semver::Version;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use snafu::{ensure, OptionExt, ResultExt};
+use snafu::ResultExt;
 use std::collections::{HashMap, HashSet};
-use std::convert::TryFrom;
 use std::io;
 use std::path::Path;
 use std::str::FromStr;
 use std::{env, fs, process};
 
-use datastore::key::{Key, KeyType};
-use datastore::serialization::{to_pairs, to_pairs_with_prefix};
-use datastore::{self, DataStore, FilesystemDataStore, ScalarError};
-use model::modeled_types::SingleLineString;
+use datastore::key::Key;
+use datastore::{self, DataStore, FilesystemDataStore};
 
 use constants;
 
 mod error {
     use std::io;
-    use std::path::PathBuf;
 
-    use datastore::key::KeyType;
-    use datastore::{self, serialization, ScalarError};
-    use model::modeled_types::error::Error as ModeledTypesError;
     use snafu::Snafu;
 
     /// Potential errors during execution
@@ -47,20 +40,11 @@ mod error {
         #[snafu(display("Unable to create datastore: {}", source))]
         DatastoreCreation { source: storewolf::error::Error },
 
-        #[snafu(display("Merged defaults file '{}' is not valid TOML: {}", path.display(), source))]
-        DefaultsFormatting {
-            path: PathBuf,
-            source: toml::de::Error,
-        },
+        #[snafu(display("Unable to read defaults: {}", source))]
+        Defaults { source: storewolf::error::Error },
 
-        #[snafu(display("Default settings are not a TOML table"))]
-        DefaultsNotTable {},
-
-        #[snafu(display("'settings' key in defaults is not a TOML table"))]
-        DefaultSettingsNotTable {},
-
-        #[snafu(display("Default settings' metadata has unexpected types"))]
-        DefaultsMetadataUnexpectedFormat {},
+        #[snafu(display("Unable to finish interrupted datastore commit: {}", source))]
+        Recover { source: datastore::Error },
 
         #[snafu(display("Error querying datstore for populated keys: {}", source))]
         QueryData { source: datastore::Error },
@@ -68,36 +52,14 @@ mod error {
         #[snafu(display("Error querying datstore for populated metadata: {}", source))]
         QueryMetadata { source: datastore::Error },
 
-        #[snafu(display("Error serializing {}: {} ", given, source))]
-        Serialization {
-            given: String,
-            source: serialization::Error,
-        },
-
-        #[snafu(display("Error serializing scalar {}: {} ", given, source))]
-        SerializeScalar { given: String, source: ScalarError },
-
         #[snafu(display("Unable to write keys to the datastore: {}", source))]
         WriteKeys { source: datastore::Error },
 
-        #[snafu(display("Unable to create {:?} key '{}': {}", key_type, key, source))]
-        InvalidKey {
-            key_type: KeyType,
-            key: String,
-            source: datastore::Error,
-        },
-
         #[snafu(display("Unable to write metadata to the datastore: {}", source))]
         WriteMetadata { source: datastore::Error },
 
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
-
-        #[snafu(display("Internal error: {}", msg))]
-        Internal { msg: String },
-
-        #[snafu(display("Keys can't contain newlines: {}", source))]
-        SingleLineString { source: ModeledTypesError },
     }
 }
 
@@ -106,100 +68,6 @@ use storewolf::create_new_datastore;
 
 type Result<T> = std::result::Result<T, StorewolfError>;
 
-/// Convert the generic toml::Value representing metadata into a
-/// Vec<Metadata> that can be used to write the metadata to the datastore.
-// The input to this function is a toml::Value that represents the metadata
-// read from the TOML default settings files. This table is structured like so:
-//
-// Table({"settings": Table({"foo": Table({"affected-services": Array([ ... ])})})})
-//
-// This function will convert the above table to a Vec<model::Metadata>,
-// validating the types and structure. The resulting Vec looks like:
-//
-// [
-//   Metadata {key: "settings.motd", md: "affected-services", val: Array([ ... ])},
-//   Metadata { ... },
-// ]
-fn parse_metadata_toml(md_toml_val: toml::Value) -> Result<Vec<model::Metadata>> {
-    debug!("Parsing metadata toml");
-    let mut def_metadatas: Vec<model::Metadata> = Vec::new();
-
-    // Do a breadth-first search of the toml::Value table.
-    // Create a Vec of tuples to keep track of where we have visited in the
-    // toml::Value data structure. The first value in the tuple is the key
-    // (represented as a Vec of key segments), the second is the toml::Value
-    // associated with that key. It ends up looking like:
-    // [
-    //   (
-    //     ["settings", "motd"],
-    //     toml::Value
-    //   ),
-    //   ...
-    // ]
-    // For each key/value of the table we visit, match on the value of the
-    // table. If it's another table, we add it to the list to process its
-    // contents. If it is an array or string, we can construct a
-    // model::Metadata, and add it to the Vec of model::Metadata to be
-    // returned from the function.
-
-    // Start at the root of the tree.
-    let mut to_process = vec![(Vec::new(), md_toml_val)];
-
-    while !to_process.is_empty() {
-        let (mut path, toml_value) = to_process.pop().unwrap();
-        trace!("Current metadata table path: {:#?}", &path);
-
-        match toml_value {
-            // A table means there is more processing to do. Add the current
-            // key and value to the Vec to be processed further.
-            toml::Value::Table(table) => {
-                for (key, val) in table {
-                    trace!("Found table for key '{}'", &key);
-                    let mut path = path.clone();
-                    path.push(key.to_string());
-                    to_process.push((path, val));
-                }
-            }
-
-            // An array or string means we're ready to create a model::Metadata
-            val @ toml::Value::Array(_) | val @ toml::Value::String(_) => {
-                // Get the metadata key from the end of the path
-                let md_key = path.pop().context(error::Internal {
-                    msg: "parse_metadata_toml found empty 'path' in the to_process vec - is 'metadata' not a Table?",
-                })?;
-
-                // Make sure that the path contains more than 1 item, i.e. ["settings", "motd"]
-                ensure!(
-                    path.len() >= 1,
-                    error::Internal {
-                        msg: format!(
-                            "Cannot create empty metadata data key - is root not a Table?"
-                        )
-                    }
-                );
-                let data_key = path.join(".");
-
-                trace!(
-                    "Found metadata key '{}' for data key '{}'",
-                    &md_key,
-                    &data_key
-                );
-
-                // Ensure the metadata/data keys don't contain newline chars
-                let md = SingleLineString::try_from(md_key).context(error::SingleLineString)?;
-                let key = SingleLineString::try_from(data_key).context(error::SingleLineString)?;
-
-                // Create the Metadata struct
-                def_metadatas.push(model::Metadata { key, md, val })
-            }
-
-            // We don't recognize any other values yet, something is awry
-            _ => return error::DefaultsMetadataUnexpectedFormat {}.fail(),
-        };
-    }
-    Ok(def_metadatas)
-}
-
 /// Creates a new FilesystemDataStore at the given path, with data and metadata coming from
 /// the variant's TOML default settings files at compile time.
 fn populate_default_datastore<P: AsRef<Path>>(
@@ -215,10 +83,16 @@ fn populate_default_datastore<P: AsRef<Path>>(
     // actually lives. This is the start of the chain, whose name never
     // changes, so it can be used consistently by the rest of the OS.
     let datastore_path = base_path.as_ref().join("current");
+    // We skip defaults whose keys already exist, so each write has to be synced; a write cut short
+    // by a power loss could otherwise leave an empty or truncated key that we'd never rewrite.
     let mut datastore = FilesystemDataStore::new(&datastore_path);
     let mut existing_data = HashSet::new();
     let mut existing_metadata = HashMap::new();
 
+    // A commit interrupted by a reboot can leave the "live" symlink briefly missing, so finish
+    // it before checking whether the datastore exists.
+    datastore.recover().context(error::Recover)?;
+
     // If the "live" path of the datastore exists, query it for populated
     // meta/data.  Otherwise, create the datastore path.
     let live_path = &datastore_path.join("live");
@@ -235,50 +109,21 @@ fn populate_default_datastore<P: AsRef<Path>>(
         create_new_datastore(&base_path, version).context(error::DatastoreCreation)?;
     }
 
-    // Here we read in the merged settings file built by build.rs.
-    let defaults_str = include_str!(concat!(env!("OUT_DIR"), "/defaults.toml"));
-    let mut defaults_val: toml::Value =
-        toml::from_str(defaults_str).context(error::DefaultsFormatting {
-            path: concat!(env!("OUT_DIR"), "/defaults.toml"),
-        })?;
-
-    // Check if we have metadata and settings. If so, pull them out
-    // of `shared_defaults_val`
-    let table = defaults_val
-        .as_table_mut()
-        .context(error::DefaultsNotTable)?;
-    let maybe_metadata_val = table.remove("metadata");
-    let maybe_settings_val = table.remove("settings");
+    let def = storewolf::defaults().context(error::Defaults)?;
 
     // If there are default settings, write them to the datastore in the shared pending
     // transaction. This ensures the settings will go through a commit cycle when first-boot
     // services run, which will create config files for default keys that require them.
-    if let Some(def_settings_val) = maybe_settings_val {
-        debug!("Serializing default settings and writing new ones to datastore");
-        let def_settings_table = def_settings_val
-            .as_table()
-            .context(error::DefaultSettingsNotTable)?;
-
-        // The default settings were removed from the "settings" key of the
-        // defaults table above. We still need them under a "settings" key
-        // before serializing so we have full dotted keys like
-        // "settings.foo.bar" and not just "foo.bar". We use a HashMap
-        // to rebuild the nested structure.
-        let def_settings = to_pairs_with_prefix("settings", &def_settings_table).context(
-            error::Serialization {
-                given: "default settings",
-            },
-        )?;
-
-        // For each of the default settings, check if it exists in the
-        // datastore. If not, add it to the map of settings to write
-        let mut settings_to_write = HashMap::new();
-        for (key, val) in def_settings {
-            if !existing_data.contains(&key) {
-                settings_to_write.insert(key, val);
-            }
+    //
+    // For each of the default settings, check if it exists in the
+    // datastore. If not, add it to the map of settings to write
+    let mut settings_to_write = HashMap::new();
+    for (key, val) in def.settings {
+        if !existing_data.contains(&key) {
+            settings_to_write.insert(key, val);
         }
-
+    }
+    if !settings_to_write.is_empty() {
         trace!(
             "Writing default settings to datastore: {:#?}",
             &settings_to_write
@@ -292,78 +137,48 @@ fn populate_default_datastore<P: AsRef<Path>>(
     }
 
     // If we have metadata, write it out to the datastore in Live state
-    if let Some(def_metadata_val) = maybe_metadata_val {
-        debug!("Serializing metadata and writing new keys to datastore");
-        // Create a Vec<Metadata> from the metadata toml::Value
-        let def_metadatas = parse_metadata_toml(def_metadata_val)?;
-
-        // Before this transformation, `existing_metadata` is a
-        // map of data key to set of metadata keys:
-        // HashMap(dataKey => HashSet(metaKey)).
-        //
-        // To make comparison easier, we
-        // flatten the map to a HashSet of tuples:
-        // HashSet((dataKey, metaKey)).
-        let existing_metadata: HashSet<(&Key, &Key)> = existing_metadata
-            .iter()
-            .flat_map(|data| data.1.iter().map(move |md_key| (data.0, md_key)))
-            .collect();
-
-        // For each of the default metadatas, check if it exists in the
-        // datastore. If not, add it to the set of metadatas to write
-        let mut metadata_to_write = HashSet::new();
-        for def_metadata in def_metadatas {
-            let model::Metadata { key, md, val } = def_metadata;
-            let data_key = Key::new(KeyType::Data, &key).context(error::InvalidKey {
-                key_type: KeyType::Data,
-                key,
-            })?;
-            let md_key = Key::new(KeyType::Meta, &md).context(error::InvalidKey {
-                key_type: KeyType::Meta,
-                key: md,
-            })?;
-
-            // Put the `data_key` and `md_key` tuple into a variable so we
-            // can more easily read the subsequent `contains()` call
-            let def_metadata_keypair = (&data_key, &md_key);
-            if !existing_metadata.contains(&def_metadata_keypair) {
-                let value =
-                    datastore::serialize_scalar::<_, ScalarError>(&val).with_context(|| {
-                        error::SerializeScalar {
-                            given: format!("metadata value '{}'", val),
-                        }
-                    })?;
-                metadata_to_write.insert((md_key, data_key, value));
-            }
+    //
+    // Before this transformation, `existing_metadata` is a
+    // map of data key to set of metadata keys:
+    // HashMap(dataKey => HashSet(metaKey)).
+    //
+    // To make comparison easier, we
+    // flatten the map to a HashSet of tuples:
+    // HashSet((dataKey, metaKey)).
+    let existing_metadata: HashSet<(&Key, &Key)> = existing_metadata
+        .iter()
+        .flat_map(|data| data.1.iter().map(move |md_key| (data.0, md_key)))
+        .collect();
+
+    // For each of the default metadatas, check if it exists in the
+    // datastore. If not, add it to the set of metadatas to write
+    let mut metadata_to_write = HashSet::new();
+    for ((data_key, md_key), value) in &def.metadata {
+        if !existing_metadata.contains(&(data_key, md_key)) {
+            metadata_to_write.insert((md_key, data_key, value));
         }
+    }
 
-        trace!(
-            "Writing default metadata to datastore: {:#?}",
-            metadata_to_write
-        );
-        for metadata in metadata_to_write {
-            let (md, key, val) = metadata;
-            datastore
-                .set_metadata(&md, &key, val)
-                .context(error::WriteMetadata)?;
-        }
+    trace!(
+        "Writing default metadata to datastore: {:#?}",
+        metadata_to_write
+    );
+    for metadata in metadata_to_write {
+        let (md, key, val) = metadata;
+        datastore
+            .set_metadata(md, key, val)
+            .context(error::WriteMetadata)?;
     }
 
     // If any other defaults remain (configuration files, services, etc),
     // write them to the datastore in Live state
-    debug!("Serializing other defaults and writing new ones to datastore");
-    let defaults = to_pairs(&defaults_val).context(error::Serialization {
-        given: "other defaults",
-    })?;
-
     let mut other_defaults_to_write = HashMap::new();
-    if !defaults.is_empty() {
-        for (key, val) in defaults {
-            if !existing_data.contains(&key) {
-                other_defaults_to_write.insert(key, val);
-            }
+    for (key, val) in def.other {
+        if !existing_data.contains(&key) {
+            other_defaults_to_write.insert(key, val);
         }
-
+    }
+    if !other_defaults_to_write.is_empty() {
         trace!(
             "Writing other default data to datastore: {:#?}",
             &other_defaults_to_write
@@ -0,0 +1,148 @@
+//! The graph module orders setting generators by the settings they depend on, so that
+//! generators that don't depend on each other can run at the same time.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Tracks which generators are waiting for which generated settings.  Generators are named by
+/// the setting they generate.
+#[derive(Debug, Default)]
+pub(crate) struct GeneratorGraph {
+    /// Generators that haven't been started, with the generated settings they're waiting for.
+    waiting: HashMap<String, HashSet<String>>,
+    /// For each generated setting, the generators that depend on it.
+    dependents: HashMap<String, Vec<String>>,
+}
+
+impl GeneratorGraph {
+    /// Builds the graph from each generator's list of dependencies.  A dependency on a setting
+    /// that isn't being generated is already met, because the setting was set some other way.
+    pub(crate) fn new(depends_on: &HashMap<String, Vec<String>>) -> Self {
+        let mut graph = Self::default();
+        for (setting, dependencies) in depends_on {
+            let mut waiting_for = HashSet::new();
+            for dependency in dependencies {
+                if depends_on.contains_key(dependency) && waiting_for.insert(dependency.clone()) {
+                    graph
+                        .dependents
+                        .entry(dependency.clone())
+                        .or_default()
+                        .push(setting.clone());
+                }
+            }
+            graph.waiting.insert(setting.clone(), waiting_for);
+        }
+        graph
+    }
+
+    /// Returns the generators whose dependencies have all been met, in order, and stops tracking
+    /// them.
+    pub(crate) fn take_ready(&mut self) -> Vec<String> {
+        let ready: BTreeSet<String> = self
+            .waiting
+            .iter()
+            .filter(|(_, waiting_for)| waiting_for.is_empty())
+            .map(|(setting, _)| setting.clone())
+            .collect();
+        for setting in &ready {
+            self.waiting.remove(setting);
+        }
+        ready.into_iter().collect()
+    }
+
+    /// Whether any generator that hasn't started yet depends on the given setting.
+    pub(crate) fn has_dependents(&self, setting: &str) -> bool {
+        self.dependents
+            .get(setting)
+            .map(|dependents| dependents.iter().any(|d| self.waiting.contains_key(d)))
+            .unwrap_or(false)
+    }
+
+    /// Records that the given setting was generated, so generators no longer wait for it.
+    pub(crate) fn generated(&mut self, setting: &str) {
+        for dependent in self.dependents.get(setting).into_iter().flatten() {
+            if let Some(waiting_for) = self.waiting.get_mut(dependent) {
+                waiting_for.remove(setting);
+            }
+        }
+    }
+
+    /// Records that the given setting wasn't generated.  Returns the generators that can't run
+    /// because they depend on it, directly or indirectly, in order, and stops tracking them.
+    pub(crate) fn not_generated(&mut self, setting: &str) -> Vec<String> {
+        let mut skipped = BTreeSet::new();
+        let mut to_visit = vec![setting.to_string()];
+        while let Some(current) = to_visit.pop() {
+            for dependent in self.dependents.get(&current).into_iter().flatten() {
+                if self.waiting.remove(dependent).is_some() {
+                    skipped.insert(dependent.clone());
+                    to_visit.push(dependent.clone());
+                }
+            }
+        }
+        skipped.into_iter().collect()
+    }
+
+    /// Returns the generators that are still waiting, in order, and stops tracking them.  Once
+    /// nothing is running, these are waiting on a cycle of dependencies, so they can never run.
+    pub(crate) fn take_waiting(&mut self) -> Vec<String> {
+        let waiting: BTreeSet<String> = self.waiting.drain().map(|(setting, _)| setting).collect();
+        waiting.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> GeneratorGraph {
+        let depends_on = edges
+            .iter()
+            .map(|(setting, dependencies)| {
+                (
+                    setting.to_string(),
+                    dependencies.iter().map(|d| d.to_string()).collect(),
+                )
+            })
+            .collect();
+        GeneratorGraph::new(&depends_on)
+    }
+
+    #[test]
+    fn runs_in_dependency_order() {
+        // "a" depends on a setting that isn't generated, so it's ready.
+        let mut g = graph(&[
+            ("a", &["settings.user"]),
+            ("b", &[]),
+            ("c", &["a", "b"]),
+            ("d", &["c"]),
+        ]);
+        assert_eq!(g.take_ready(), vec!["a", "b"]);
+        assert!(g.has_dependents("a"));
+        g.generated("a");
+        assert!(g.take_ready().is_empty());
+        g.generated("b");
+        assert_eq!(g.take_ready(), vec!["c"]);
+        g.generated("c");
+        assert_eq!(g.take_ready(), vec!["d"]);
+        assert!(!g.has_dependents("d"));
+        assert!(g.take_waiting().is_empty());
+    }
+
+    #[test]
+    fn skips_dependents_of_failures() {
+        let mut g = graph(&[("a", &[]), ("b", &["a"]), ("c", &["b"]), ("d", &[])]);
+        assert_eq!(g.take_ready(), vec!["a", "d"]);
+        assert_eq!(g.not_generated("a"), vec!["b", "c"]);
+        assert!(g.take_ready().is_empty());
+        assert!(g.take_waiting().is_empty());
+    }
+
+    #[test]
+    fn cycles_never_run() {
+        let mut g = graph(&[("a", &["b"]), ("b", &["a"]), ("c", &["b"]), ("d", &[])]);
+        assert_eq!(g.take_ready(), vec!["d"]);
+        g.generated("d");
+        assert!(g.take_ready().is_empty());
+        assert_eq!(g.take_waiting(), vec!["a", "b", "c"]);
+    }
+}
@@ -5,6 +5,31 @@ sundog is a small program to handle settings that must be generated at OS runtim
 
 It requests settings generators from the API and runs them.
 The output is collected and sent to a known Bottlerocket API server endpoint.
+
+# Generator policies
+
+Generators run at the same time, unless one depends on the output of another.
+A generator's dependencies, and how it's run, are read from metadata of the setting it generates:
+* `setting-generator-depends-on`: a list of settings whose generators must finish first.
+  The output of those generators is committed before this one starts, so it can read them from the API.
+  It's committed in its own `sundog-dependencies` transaction, so settings still pending in the `bottlerocket-launch` transaction aren't committed early.
+  Dependencies on settings that aren't being generated are already met.
+* `setting-generator-timeout-seconds`: how long the generator may run before it's stopped; the default is 300.
+* `setting-generator-retries`: how many times the generator is run again if it fails or times out; the default is 0.
+
+For example:
+
+```toml
+[metadata.settings.kubernetes.max-pods]
+setting-generator = "pluto max-pods"
+setting-generator-depends-on = ["settings.kubernetes.node-ip"]
+setting-generator-timeout-seconds = 60
+setting-generator-retries = 2
+```
+
+If a generator fails, or exits with status 2 to say it has nothing to set, the generators that depend on it are skipped.
+The settings that were generated are still sent to the API.
+sundog prints a JSON summary of each generator's outcome, and exits with an error if any generator failed.
 */
 
 #![deny(rust_2018_idioms)]
@@ -12,14 +37,20 @@ The output is collected and sent to a known Bottlerocket API server endpoint.
 #[macro_use]
 extern crate log;
 
+mod graph;
+
 use constants;
+use graph::GeneratorGraph;
+use serde::{Deserialize, Serialize};
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::path::Path;
 use std::process;
 use std::str::{self, FromStr};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 use datastore::serialization::to_pairs_with_prefix;
 use datastore::{self, deserialization, Key, KeyType};
@@ -41,6 +72,12 @@ mod error {
             source: std::io::Error,
         },
 
+        #[snafu(display("Setting generator '{}' timed out after {} seconds", program, seconds))]
+        GeneratorTimeout { program: String, seconds: u64 },
+
+        #[snafu(display("{} setting generators failed", count))]
+        GeneratorsFailed { count: usize },
+
         #[snafu(display("Generator command is invalid (empty, etc.) - '{}'", command))]
         InvalidCommand { command: String },
 
@@ -125,6 +162,9 @@ mod error {
         #[snafu(display("Error serializing Settings to JSON: {}", source))]
         SerializeRequest { source: serde_json::error::Error },
 
+        #[snafu(display("Error serializing generator summary: {}", source))]
+        SerializeSummary { source: serde_json::error::Error },
+
         #[snafu(display("Error serializing Settings: {} ", source))]
         SerializeSettings { source: serialization::Error },
 
@@ -150,6 +190,54 @@ use error::SundogError;
 
 type Result<T> = std::result::Result<T, SundogError>;
 
+const API_COMMIT_URI: &str = "/tx/commit";
+
+/// The transaction for generated settings that other generators depend on.  They're committed
+/// while we run, and keeping them out of the launch transaction means nothing else is committed
+/// with them.
+const DEPENDENCY_TRANSACTION: &str = "sundog-dependencies";
+
+/// How long a generator may run, if its metadata doesn't say.
+const DEFAULT_TIMEOUT_SECONDS: u64 = 300;
+
+/// How long to wait before running a failed generator again.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How a setting generator is run, from the metadata of the setting.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GeneratorPolicy {
+    #[serde(default)]
+    depends_on: Vec<String>,
+    #[serde(default = "default_timeout_seconds")]
+    timeout_seconds: u64,
+    #[serde(default)]
+    retries: u32,
+}
+
+fn default_timeout_seconds() -> u64 {
+    DEFAULT_TIMEOUT_SECONDS
+}
+
+impl Default for GeneratorPolicy {
+    fn default() -> Self {
+        Self {
+            depends_on: Vec::new(),
+            timeout_seconds: DEFAULT_TIMEOUT_SECONDS,
+            retries: 0,
+        }
+    }
+}
+
+/// What happened to a setting generator, for the summary printed when sundog finishes.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+enum Outcome {
+    Generated,
+    Skipped { reason: String },
+    Failed { attempts: u32, error: String },
+}
+
 /// Request the setting generators from the API.
 async fn get_setting_generators<S>(socket_path: S) -> Result<HashMap<String, String>>
 where
@@ -178,6 +266,34 @@ where
     Ok(generators)
 }
 
+/// Request the policies of setting generators from the API.
+async fn get_generator_policies<S>(socket_path: S) -> Result<HashMap<String, GeneratorPolicy>>
+where
+    S: AsRef<str>,
+{
+    let uri = constants::API_SETTING_GENERATOR_POLICIES_URI;
+
+    debug!("Requesting setting generator policies from API");
+    let (code, response_body) = apiclient::raw_request(socket_path.as_ref(), uri, "GET", None)
+        .await
+        .context(error::APIRequest { method: "GET", uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method: "GET",
+            uri,
+            code,
+            response_body,
+        }
+    );
+
+    let policies: HashMap<String, GeneratorPolicy> =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method: "GET", uri })?;
+    trace!("Generator policies: {:?}", &policies);
+
+    Ok(policies)
+}
+
 /// Given a list of settings, query the API for any that are currently set.
 async fn get_populated_settings<P>(socket_path: P, to_query: Vec<&str>) -> Result<HashSet<Key>>
 where
@@ -222,24 +338,159 @@ where
     Ok(populated_settings)
 }
 
-/// Run the setting generators and collect the output
-async fn get_dynamic_settings<P>(
-    socket_path: P,
+/// Runs a setting generator and returns its output, serialized for the data store, or None if
+/// the generator has nothing to set.  The generator is stopped if it runs longer than the given
+/// timeout.
+async fn run_generator(
+    setting: &Key,
+    generator: &str,
+    timeout: Duration,
+) -> Result<Option<String>> {
+    debug!("Running generator: '{}'", generator);
+
+    // Split on space, assume the first item is the command
+    // and the rest are args.
+    let mut command_strings = generator.split_whitespace();
+    let command = command_strings
+        .next()
+        .context(error::InvalidCommand { command: generator })?;
+
+    // Dropping the command's future, like on timeout, kills the process.
+    let output = tokio::process::Command::new(command)
+        .args(command_strings)
+        .kill_on_drop(true)
+        .output();
+    let result = tokio::time::timeout(timeout, output)
+        .await
+        .ok()
+        .context(error::GeneratorTimeout {
+            program: generator,
+            seconds: timeout.as_secs(),
+        })?
+        .context(error::CommandFailure { program: generator })?;
+
+    // Match on the generator's exit code. This code lays the foundation
+    // for handling alternative exit codes from generators.
+    match result.status.code() {
+        Some(0) => {}
+        Some(1) => {
+            return error::FailedSettingGenerator {
+                program: generator,
+                code: 1.to_string(),
+                stderr: String::from_utf8_lossy(&result.stderr),
+            }
+            .fail()
+        }
+        Some(2) => {
+            warn!(
+                "'{}' returned 2, not setting '{}', continuing with other generators",
+                command, generator
+            );
+            return Ok(None);
+        }
+        Some(x) => {
+            return error::UnexpectedReturnCode {
+                program: generator,
+                code: x.to_string(),
+                stderr: String::from_utf8_lossy(&result.stderr),
+            }
+            .fail()
+        }
+        // A process will return None if terminated by a signal, regard this as
+        // a failure since we could have incomplete data
+        None => {
+            return error::FailedSettingGenerator {
+                program: generator,
+                code: "signal",
+                stderr: String::from_utf8_lossy(&result.stderr),
+            }
+            .fail()
+        }
+    }
+
+    // Sundog programs are expected to output JSON, which allows them to represent types other
+    // than strings, which in turn allows our API model to use types more accurate than strings
+    // for generated settings.
+    //
+    // First, we pull the raw string from the process output.
+    let output_raw = str::from_utf8(&result.stdout)
+        .context(error::GeneratorOutput { program: generator })?
+        .trim()
+        .to_string();
+    trace!("Generator '{}' output: {}", generator, &output_raw);
+
+    // Next, we deserialize the text into a Value that can represent any JSON type.
+    let output_value: serde_json::Value =
+        serde_json::from_str(&output_raw).context(error::CommandJson {
+            generator,
+            input: &output_raw,
+        })?;
+
+    // Finally, we re-serialize the command output; we intend to call the datastore-level
+    // construct `from_map` on it, which expects serialized values.
+    //
+    // We have to go through the round-trip of serialization because the data store
+    // serialization format may not be the same as the format we choose for sundog.
+    let serialized_output =
+        datastore::serialize_scalar(&output_value).context(error::SerializeScalar {
+            value: output_value,
+        })?;
+    trace!("Serialized output: {}", &serialized_output);
+
+    // Make sure the output fits the model now, so a bad value fails only its own generator.
+    let mut setting_map = HashMap::new();
+    setting_map.insert(setting.clone(), serialized_output);
+    let _: model::Settings = deserialization::from_map(&setting_map).context(error::Deserialize)?;
+
+    Ok(setting_map.remove(setting))
+}
+
+/// Runs a setting generator according to its policy, running it again after a failure if it
+/// has retries left.  Returns the result of the last run, and the number of runs.
+async fn run_generator_with_retries(
+    setting: &Key,
+    generator: &str,
+    policy: &GeneratorPolicy,
+) -> (Result<Option<String>>, u32) {
+    let timeout = Duration::from_secs(policy.timeout_seconds);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match run_generator(setting, generator, timeout).await {
+            Err(e) if attempts <= policy.retries => {
+                warn!(
+                    "Generator for '{}' failed on attempt {}, retrying: {}",
+                    setting, attempts, e
+                );
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            result => return (result, attempts),
+        }
+    }
+}
+
+/// Run the setting generators and send their output to the API.  Each generator starts as soon
+/// as the generators it depends on have finished; their output is committed first, in its own
+/// transaction, so it can read it from the API.  Returns the outcome of each generator.
+async fn generate_settings<S>(
+    socket_path: S,
     generators: HashMap<String, String>,
-) -> Result<model::Settings>
+    mut policies: HashMap<String, GeneratorPolicy>,
+) -> Result<BTreeMap<String, Outcome>>
 where
-    P: AsRef<Path>,
+    S: AsRef<str>,
 {
-    let mut settings = HashMap::new();
+    let mut summary = BTreeMap::new();
 
     // Build the list of settings to query from the datastore to see if they
     // are currently populated.
     // `generators` keys are setting names in the proper dotted
     // format, i.e. "settings.kubernetes.node-ip"
     let settings_to_query: Vec<&str> = generators.keys().map(|s| s.as_ref()).collect();
-    let populated_settings = get_populated_settings(&socket_path, settings_to_query).await?;
+    let populated_settings =
+        get_populated_settings(socket_path.as_ref(), settings_to_query).await?;
 
-    // For each generator, run it and capture the output
+    let mut to_run = HashMap::new();
     for (setting_str, generator) in generators {
         let setting = Key::new(KeyType::Data, &setting_str).context(error::InvalidKey {
             key_type: KeyType::Data,
@@ -248,119 +499,129 @@ where
         // Don't clobber settings that are already populated
         if populated_settings.contains(&setting) {
             debug!("Setting '{}' is already populated, skipping", setting);
+            summary.insert(
+                setting_str,
+                Outcome::Skipped {
+                    reason: "already set".to_string(),
+                },
+            );
             continue;
         }
+        let policy = policies.remove(&setting_str).unwrap_or_default();
+        to_run.insert(setting_str, (setting, generator, policy));
+    }
 
-        debug!("Running generator: '{}'", &generator);
+    let depends_on = to_run
+        .iter()
+        .map(|(setting_str, (_, _, policy))| (setting_str.clone(), policy.depends_on.clone()))
+        .collect();
+    let mut graph = GeneratorGraph::new(&depends_on);
 
-        // Split on space, assume the first item is the command
-        // and the rest are args.
-        let mut command_strings = generator.split_whitespace();
-        let command = command_strings.next().context(error::InvalidCommand {
-            command: generator.as_str(),
-        })?;
+    // Generators run in their own tasks, and send their results back here.
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut running = 0;
+    let mut settings = HashMap::new();
+    loop {
+        for setting_str in graph.take_ready() {
+            let (setting, generator, policy) = match to_run.remove(&setting_str) {
+                Some(generator) => generator,
+                None => continue,
+            };
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let (result, attempts) =
+                    run_generator_with_retries(&setting, &generator, &policy).await;
+                // The receiver lives until every generator has reported.
+                let _ = sender.send((setting_str, setting, result, attempts));
+            });
+            running += 1;
+        }
 
-        let result = process::Command::new(command)
-            .args(command_strings)
-            .output()
-            .context(error::CommandFailure {
-                program: generator.as_str(),
-            })?;
-
-        // Match on the generator's exit code. This code lays the foundation
-        // for handling alternative exit codes from generators.
-        match result.status.code() {
-            Some(0) => {}
-            Some(1) => {
-                return error::FailedSettingGenerator {
-                    program: generator.as_str(),
-                    code: 1.to_string(),
-                    stderr: String::from_utf8_lossy(&result.stderr),
+        if running == 0 {
+            break;
+        }
+        let (setting_str, setting, result, attempts) = match receiver.recv().await {
+            Some(message) => message,
+            None => break,
+        };
+        running -= 1;
+
+        match result {
+            Ok(Some(value)) => {
+                info!("Generated '{}'", setting_str);
+                if graph.has_dependents(&setting_str) {
+                    // Generators read settings from the API, so their dependencies have to be
+                    // committed before they start.
+                    let mut to_commit = HashMap::new();
+                    to_commit.insert(setting, value);
+                    set_settings(socket_path.as_ref(), &to_commit, DEPENDENCY_TRANSACTION).await?;
+                    commit_settings(socket_path.as_ref(), DEPENDENCY_TRANSACTION).await?;
+                } else {
+                    settings.insert(setting, value);
                 }
-                .fail()
+                graph.generated(&setting_str);
+                summary.insert(setting_str, Outcome::Generated);
             }
-            Some(2) => {
-                warn!(
-                    "'{}' returned 2, not setting '{}', continuing with other generators",
-                    command, generator
-                );
-                continue;
-            }
-            Some(x) => {
-                return error::UnexpectedReturnCode {
-                    program: generator.as_str(),
-                    code: x.to_string(),
-                    stderr: String::from_utf8_lossy(&result.stderr),
+            Ok(None) => {
+                for skipped in graph.not_generated(&setting_str) {
+                    warn!("Skipping '{}', which depends on '{}'", skipped, setting_str);
+                    let reason = format!("depends on '{}', which was not generated", setting_str);
+                    summary.insert(skipped, Outcome::Skipped { reason });
                 }
-                .fail()
+                let reason = "generator had nothing to set".to_string();
+                summary.insert(setting_str, Outcome::Skipped { reason });
             }
-            // A process will return None if terminated by a signal, regard this as
-            // a failure since we could have incomplete data
-            None => {
-                return error::FailedSettingGenerator {
-                    program: generator.as_str(),
-                    code: "signal",
-                    stderr: String::from_utf8_lossy(&result.stderr),
+            Err(e) => {
+                error!("Generator for '{}' failed: {}", setting_str, e);
+                for skipped in graph.not_generated(&setting_str) {
+                    warn!("Skipping '{}', which depends on '{}'", skipped, setting_str);
+                    let reason = format!("depends on '{}', whose generator failed", setting_str);
+                    summary.insert(skipped, Outcome::Skipped { reason });
                 }
-                .fail()
+                let error = e.to_string();
+                summary.insert(setting_str, Outcome::Failed { attempts, error });
             }
         }
+    }
 
-        // Sundog programs are expected to output JSON, which allows them to represent types other
-        // than strings, which in turn allows our API model to use types more accurate than strings
-        // for generated settings.
-        //
-        // First, we pull the raw string from the process output.
-        let output_raw = str::from_utf8(&result.stdout)
-            .context(error::GeneratorOutput {
-                program: generator.as_str(),
-            })?
-            .trim()
-            .to_string();
-        trace!("Generator '{}' output: {}", &generator, &output_raw);
-
-        // Next, we deserialize the text into a Value that can represent any JSON type.
-        let output_value: serde_json::Value =
-            serde_json::from_str(&output_raw).context(error::CommandJson {
-                generator: &generator,
-                input: &output_raw,
-            })?;
-
-        // Finally, we re-serialize the command output; we intend to call the datastore-level
-        // construct `from_map` on it, which expects serialized values.
-        //
-        // We have to go through the round-trip of serialization because the data store
-        // serialization format may not be the same as the format we choose for sundog.
-        let serialized_output =
-            datastore::serialize_scalar(&output_value).context(error::SerializeScalar {
-                value: output_value,
-            })?;
-        trace!("Serialized output: {}", &serialized_output);
-
-        settings.insert(setting, serialized_output);
+    // Anything left is waiting on a generator that can't start until it finishes itself.
+    for skipped in graph.take_waiting() {
+        warn!("Skipping '{}', which is in a dependency cycle", skipped);
+        let reason = "in or depends on a cycle of dependencies".to_string();
+        summary.insert(skipped, Outcome::Skipped { reason });
     }
 
-    // The API takes a properly nested Settings struct, so deserialize our map to a Settings
-    // and ensure it is correct
-    let settings_struct: model::Settings =
-        deserialization::from_map(&settings).context(error::Deserialize)?;
+    if !settings.is_empty() {
+        info!("Sending settings values to the API");
+        set_settings(
+            socket_path.as_ref(),
+            &settings,
+            constants::LAUNCH_TRANSACTION,
+        )
+        .await?;
+    }
 
-    Ok(settings_struct)
+    Ok(summary)
 }
 
-/// Send the settings to the datastore through the API
-async fn set_settings<S>(socket_path: S, settings: model::Settings) -> Result<()>
+/// Send the settings to the given transaction through the API
+async fn set_settings<S>(
+    socket_path: S,
+    settings: &HashMap<Key, String>,
+    transaction: &str,
+) -> Result<()>
 where
     S: AsRef<str>,
 {
+    // The API takes a properly nested Settings struct, so deserialize our map to a Settings
+    // and ensure it is correct
+    let settings: model::Settings =
+        deserialization::from_map(settings).context(error::Deserialize)?;
+
     // Serialize our Settings struct to the JSON wire format
     let request_body = serde_json::to_string(&settings).context(error::SerializeRequest)?;
 
-    let uri = &format!(
-        "{}?tx={}",
-        constants::API_SETTINGS_URI,
-        constants::LAUNCH_TRANSACTION
-    );
+    let uri = &format!("{}?tx={}", constants::API_SETTINGS_URI, transaction);
     let method = "PATCH";
     trace!("Settings to {} to {}: {}", method, uri, &request_body);
     let (code, response_body) =
@@ -380,6 +641,31 @@ where
     Ok(())
 }
 
+/// Commit the settings sent to the given transaction, so later generators can read them from the
+/// API
+async fn commit_settings<S>(socket_path: S, transaction: &str) -> Result<()>
+where
+    S: AsRef<str>,
+{
+    let uri = &format!("{}?tx={}", API_COMMIT_URI, transaction);
+    let method = "POST";
+    debug!("Committing generated settings");
+    let (code, response_body) = apiclient::raw_request(socket_path.as_ref(), uri, method, None)
+        .await
+        .context(error::APIRequest { method, uri })?;
+    ensure!(
+        code.is_success(),
+        error::APIResponse {
+            method,
+            uri,
+            code,
+            response_body,
+        }
+    );
+
+    Ok(())
+}
+
 /// Store the args we receive on the command line
 struct Args {
     log_level: LevelFilter,
@@ -457,11 +743,20 @@ async fn run() -> Result<()> {
         process::exit(0)
     }
 
+    let policies = get_generator_policies(&args.socket_path).await?;
+
     info!("Retrieving settings values");
-    let settings = get_dynamic_settings(&args.socket_path, generators).await?;
+    let summary = generate_settings(&args.socket_path, generators, policies).await?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&summary).context(error::SerializeSummary)?
+    );
 
-    info!("Sending settings values to the API");
-    set_settings(&args.socket_path, settings).await?;
+    let failed = summary
+        .values()
+        .filter(|outcome| matches!(outcome, Outcome::Failed { .. }))
+        .count();
+    ensure!(failed == 0, error::GeneratorsFailed { count: failed });
 
     Ok(())
 }
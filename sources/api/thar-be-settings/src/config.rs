@@ -1,9 +1,18 @@
 use crate::service::Services;
 use crate::{error, Result};
 use itertools::join;
-use snafu::ResultExt;
+use model::modeled_types::CUSTOM_FILE_DIR;
+use nix::fcntl::OFlag;
+use nix::unistd::{fchown, Gid, Group, Uid, User};
+use serde::Serialize;
+use serde_json::Value;
+use similar::TextDiff;
+use snafu::{OptionExt, ResultExt};
 use std::collections::HashSet;
-use std::fs;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
 /// Query the API for ConfigurationFile data
@@ -46,26 +55,41 @@ pub fn get_config_file_names(services: &Services) -> HashSet<String> {
 // If strict is True, return an error if we fail to render any template.
 // If strict is False, ignore failures, always returning an Ok value
 // containing any successfully rendered templates.
+// Files whose `render-if` setting isn't truthy aren't rendered; they're returned with no contents,
+// so they're removed from disk.
 pub fn render_config_files(
     registry: &handlebars::Handlebars<'_>,
     config_files: model::ConfigurationFiles,
     settings: model::Model,
     strict: bool,
 ) -> Result<Vec<RenderedConfigFile>> {
+    // Conditions are checked against the same data the templates see.
+    let settings_value = if config_files.values().any(|f| f.render_if.is_some()) {
+        Some(serde_json::to_value(&settings).context(error::SerializeSettings)?)
+    } else {
+        None
+    };
+
     // Go write all the configuration files from template
     let mut rendered_configs = Vec::new();
     for (name, metadata) in config_files {
-        debug!("Rendering {}", &name);
+        if let (Some(key), Some(value)) = (&metadata.render_if, &settings_value) {
+            if !setting_is_truthy(value, key) {
+                debug!("Not rendering {}, '{}' isn't set", &name, key);
+                rendered_configs.push(RenderedConfigFile::from_metadata(&metadata, None));
+                continue;
+            }
+        }
 
+        debug!("Rendering {}", &name);
         let try_rendered = registry.render(&name, &settings);
         if strict {
             let rendered = try_rendered.context(error::TemplateRender { template: name })?;
-            rendered_configs.push(RenderedConfigFile::new(&metadata.path, rendered));
+            rendered_configs.push(RenderedConfigFile::from_metadata(&metadata, Some(rendered)));
         } else {
             match try_rendered {
-                Ok(rendered) => {
-                    rendered_configs.push(RenderedConfigFile::new(&metadata.path, rendered))
-                }
+                Ok(rendered) => rendered_configs
+                    .push(RenderedConfigFile::from_metadata(&metadata, Some(rendered))),
                 Err(err) => warn!("Unable to render template '{}': {}", &name, err),
             }
         }
@@ -83,38 +107,250 @@ pub fn write_config_files(rendered_config: Vec<RenderedConfigFile>) -> Result<()
     Ok(())
 }
 
+/// Compare rendered configuration files to the files currently on disk, without writing anything.
+/// Returns a diff for each file that would change, sorted by path.
+pub fn diff_config_files(rendered_config: &[RenderedConfigFile]) -> Result<Vec<ConfigFileDiff>> {
+    let mut diffs = Vec::new();
+    for cfg in rendered_config {
+        debug!("Comparing {:?}", &cfg.path);
+        if let Some(diff) = cfg.diff_from_disk()? {
+            diffs.push(ConfigFileDiff {
+                path: cfg.path.clone(),
+                diff,
+            });
+        }
+    }
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(diffs)
+}
+
+/// Returns whether the setting with the given dotted name, like "settings.ecs.enabled", is set in
+/// the given data and is truthy the way a template's `{{#if}}` sees it: false, zero, null, and
+/// empty strings, lists, and maps aren't truthy.
+fn setting_is_truthy(data: &Value, key: &str) -> bool {
+    let mut current = data;
+    for segment in key.split('.') {
+        match current.get(segment) {
+            Some(value) => current = value,
+            None => return false,
+        }
+    }
+    match current {
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map(|f| f != 0.0).unwrap_or(true),
+        Value::Null => false,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+/// ConfigFileDiff describes the change that writing a rendered config file would make to the file
+/// on disk, as a unified diff.
+#[derive(Debug, Serialize)]
+pub struct ConfigFileDiff {
+    pub path: PathBuf,
+    pub diff: String,
+}
+
 /// RenderedConfigFile contains both the path to the config file
-/// and the rendered data to write.
+/// and the rendered data to write, along with the file's mode and ownership, if
+/// given.  If there's no rendered data, the file shouldn't exist.
 #[derive(Debug)]
 pub struct RenderedConfigFile {
     path: PathBuf,
-    rendered: String,
+    rendered: Option<String>,
+    mode: Option<u32>,
+    owner: Option<String>,
+    group: Option<String>,
 }
 
 impl RenderedConfigFile {
-    fn new(path: &str, rendered: String) -> RenderedConfigFile {
+    fn new(path: &str, rendered: Option<String>) -> RenderedConfigFile {
         RenderedConfigFile {
             path: PathBuf::from(&path),
             rendered,
+            mode: None,
+            owner: None,
+            group: None,
         }
     }
 
-    /// Writes the rendered template at the proper location
+    fn from_metadata(
+        metadata: &model::ConfigurationFile,
+        rendered: Option<String>,
+    ) -> RenderedConfigFile {
+        RenderedConfigFile {
+            mode: metadata.mode.as_ref().map(|m| m.bits()),
+            owner: metadata.owner.as_ref().map(|o| o.to_string()),
+            group: metadata.group.as_ref().map(|g| g.to_string()),
+            ..Self::new(&metadata.path, rendered)
+        }
+    }
+
+    /// Writes the rendered template at the proper location, or removes the file if there's
+    /// nothing to write.
+    ///
+    /// The file is written to a temporary file in the same directory, which is given the
+    /// requested mode and ownership and then renamed into place, so readers never see a partial
+    /// file or one with the wrong permissions.
     fn write_to_disk(&self) -> Result<()> {
-        if let Some(dirname) = self.path.parent() {
+        let rendered = match &self.rendered {
+            Some(rendered) => rendered,
+            None => return self.remove_from_disk(),
+        };
+
+        let dirname = self.path.parent().unwrap_or_else(|| Path::new("/"));
+        if self.path.starts_with(CUSTOM_FILE_DIR) {
+            create_dir_under(Path::new(CUSTOM_FILE_DIR), dirname)?;
+        } else {
             fs::create_dir_all(dirname).context(error::TemplateWrite {
                 path: dirname,
                 pathtype: "directory",
             })?;
-        };
+        }
 
-        fs::write(&self.path, self.rendered.as_bytes()).context(error::TemplateWrite {
-            path: &self.path,
+        let filename = self
+            .path
+            .file_name()
+            .context(error::InvalidPath { path: &self.path })?;
+        let tmp_path = dirname.join(format!(".{}.tmp", filename.to_string_lossy()));
+        let result = self.write_temp_file(&tmp_path, rendered).and_then(|_| {
+            fs::rename(&tmp_path, &self.path).context(error::TemplateWrite {
+                path: &self.path,
+                pathtype: "file",
+            })
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    /// Writes the given contents to the given temporary path with this file's mode and
+    /// ownership.  The mode and ownership are set before anything is written, so the contents
+    /// are never readable by anyone else.
+    fn write_temp_file(&self, tmp_path: &Path, rendered: &str) -> Result<()> {
+        let write_context = error::TemplateWrite {
+            path: tmp_path,
             pathtype: "file",
-        })
+        };
+        // A temporary file left behind by an earlier failure would keep its old mode and owner,
+        // so remove it, and only write to a file we created.
+        match fs::remove_file(tmp_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(write_context),
+        }
+        // Without a requested mode, new files get the usual default, subject to the umask.
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(self.mode.unwrap_or(0o666))
+            .custom_flags(OFlag::O_NOFOLLOW.bits())
+            .open(tmp_path)
+            .context(write_context)?;
+
+        // The umask may have masked the requested mode, so set it explicitly.
+        if let Some(mode) = self.mode {
+            file.set_permissions(Permissions::from_mode(mode))
+                .context(write_context)?;
+        }
+        if self.owner.is_some() || self.group.is_some() {
+            let uid = self.owner.as_deref().map(lookup_user).transpose()?;
+            let gid = self.group.as_deref().map(lookup_group).transpose()?;
+            fchown(file.as_raw_fd(), uid, gid).context(error::SetOwner { path: tmp_path })?;
+        }
+
+        file.write_all(rendered.as_bytes()).context(write_context)?;
+        file.sync_all().context(write_context)
+    }
+
+    /// Removes the file, if it exists.
+    fn remove_from_disk(&self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).context(error::TemplateRemove { path: &self.path }),
+        }
+    }
+
+    /// Returns a unified diff from the current file on disk to the rendered template, or None if
+    /// they're the same.  A file that doesn't exist yet, or that would be removed, is treated as
+    /// empty.
+    fn diff_from_disk(&self) -> Result<Option<String>> {
+        let current = match fs::read_to_string(&self.path) {
+            Ok(current) => current,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(error::TemplateRead { path: &self.path }),
+        };
+        let rendered = self.rendered.as_deref().unwrap_or("");
+        if current == rendered {
+            return Ok(None);
+        }
+
+        let path = self.path.display().to_string();
+        let diff = TextDiff::from_lines(current.as_str(), rendered)
+            .unified_diff()
+            .header(&path, &path)
+            .to_string();
+        Ok(Some(diff))
     }
 }
 
+/// Creates the given directory, which must be under the given base directory, and any missing
+/// directories between them.  Unlike `create_dir_all`, symlinks aren't followed, so files written
+/// to the directory can't end up outside of the base directory.
+fn create_dir_under(base: &Path, dir: &Path) -> Result<()> {
+    // Check the base directory itself, too.
+    let parent = base.parent().unwrap_or(base);
+    let relative = dir
+        .strip_prefix(parent)
+        .ok()
+        .filter(|_| dir.starts_with(base))
+        .context(error::UnsafeDirectory { path: dir })?;
+
+    let mut current = parent.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return error::UnsafeDirectory { path: current }.fail(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir(&current).context(error::TemplateWrite {
+                    path: &current,
+                    pathtype: "directory",
+                })?
+            }
+            Err(e) => {
+                return Err(e).context(error::TemplateWrite {
+                    path: current,
+                    pathtype: "directory",
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Finds the ID of the given user, which can be a name or a numeric ID.
+fn lookup_user(owner: &str) -> Result<Uid> {
+    if let Ok(id) = owner.parse() {
+        return Ok(Uid::from_raw(id));
+    }
+    let user = User::from_name(owner).context(error::LookupUser { name: owner })?;
+    Ok(user.context(error::UnknownUser { name: owner })?.uid)
+}
+
+/// Finds the ID of the given group, which can be a name or a numeric ID.
+fn lookup_group(group: &str) -> Result<Gid> {
+    if let Ok(id) = group.parse() {
+        return Ok(Gid::from_raw(id));
+    }
+    let found = Group::from_name(group).context(error::LookupGroup { name: group })?;
+    Ok(found.context(error::UnknownGroup { name: group })?.gid)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -127,11 +363,13 @@ mod test {
         let input_map = hashmap!(
             "foo".to_string() => model::Service {
                 configuration_files: vec!["file1".try_into().unwrap()],
-                restart_commands: vec!["echo hi".to_string()]
+                restart_commands: vec!["echo hi".to_string()],
+                restart_actions: vec![],
             },
             "bar".to_string() => model::Service {
                 configuration_files: vec!["file1".try_into().unwrap(), "file2".try_into().unwrap()],
-                restart_commands: vec!["echo hi".to_string()]
+                restart_commands: vec!["echo hi".to_string()],
+                restart_actions: vec![],
             },
         );
         let services = Services::from_model_services(input_map, None);
@@ -140,4 +378,135 @@ mod test {
 
         assert_eq!(get_config_file_names(&services), expected_output)
     }
+
+    #[test]
+    fn test_diff_new_file() {
+        let rendered = vec![RenderedConfigFile::new(
+            "/nonexistent/thar-be-settings/test.conf",
+            Some("a = 1\nb = 2\n".to_string()),
+        )];
+        let diffs = diff_config_files(&rendered).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(
+            diffs[0].diff,
+            "--- /nonexistent/thar-be-settings/test.conf\n\
+             +++ /nonexistent/thar-be-settings/test.conf\n\
+             @@ -0,0 +1,2 @@\n\
+             +a = 1\n\
+             +b = 2\n"
+        );
+    }
+
+    #[test]
+    fn test_setting_is_truthy() {
+        let data = serde_json::json!({"settings": {
+            "on": true, "off": false, "zero": 0, "one": 1, "empty": "", "text": "x",
+            "list": [], "map": {"key": "v"}, "null": null,
+        }});
+        for key in &[
+            "settings.on",
+            "settings.one",
+            "settings.text",
+            "settings.map",
+        ] {
+            assert!(setting_is_truthy(&data, key), "{}", key);
+        }
+        for key in &[
+            "settings.off",
+            "settings.zero",
+            "settings.empty",
+            "settings.list",
+            "settings.null",
+            "settings.missing",
+            "settings.on.deeper",
+        ] {
+            assert!(!setting_is_truthy(&data, key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_write_with_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub/creds");
+        let mut cfg = RenderedConfigFile::new(path.to_str().unwrap(), Some("old".to_string()));
+        cfg.write_to_disk().unwrap();
+
+        // Rewriting replaces the file and applies the mode, leaving no temporary file behind.
+        cfg.rendered = Some("secret".to_string());
+        cfg.mode = Some(0o600);
+        cfg.write_to_disk().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(fs::read_dir(dir.path().join("sub")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "a\n").unwrap();
+        let cfg = RenderedConfigFile::new(path.to_str().unwrap(), None);
+
+        let diffs = diff_config_files(std::slice::from_ref(&cfg)).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert!(diffs[0].diff.ends_with("@@ -1 +0,0 @@\n-a\n"));
+
+        cfg.write_to_disk().unwrap();
+        assert!(!path.exists());
+        // Removing a file that's already gone is fine.
+        cfg.write_to_disk().unwrap();
+        assert!(diff_config_files(&[cfg]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_create_dir_under() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("custom");
+        create_dir_under(&base, &base.join("a/b")).unwrap();
+        assert!(base.join("a/b").is_dir());
+        // Existing directories are fine.
+        create_dir_under(&base, &base.join("a")).unwrap();
+
+        // Directories outside the base, or reached through a symlink, are refused.
+        create_dir_under(&base, dir.path()).unwrap_err();
+        create_dir_under(&base, &dir.path().join("customs")).unwrap_err();
+        std::os::unix::fs::symlink(dir.path(), base.join("link")).unwrap();
+        create_dir_under(&base, &base.join("link/c")).unwrap_err();
+        assert!(!dir.path().join("c").exists());
+        fs::write(base.join("file"), "").unwrap();
+        create_dir_under(&base, &base.join("file/d")).unwrap_err();
+    }
+
+    #[test]
+    fn test_stale_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::write(&target, "keep").unwrap();
+        std::os::unix::fs::symlink(&target, dir.path().join(".file.tmp")).unwrap();
+
+        // A leftover temporary file is replaced rather than written through.
+        let path = dir.path().join("file");
+        let mut cfg = RenderedConfigFile::new(path.to_str().unwrap(), Some("new".to_string()));
+        cfg.write_to_disk().unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "keep");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        // A leftover temporary file doesn't keep its mode.
+        let tmp_path = dir.path().join(".file.tmp");
+        fs::write(&tmp_path, "old").unwrap();
+        fs::set_permissions(&tmp_path, Permissions::from_mode(0o666)).unwrap();
+        cfg.mode = Some(0o600);
+        cfg.write_to_disk().unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+        assert!(!tmp_path.exists());
+    }
+
+    #[test]
+    fn test_numeric_owner() {
+        assert_eq!(lookup_user("0").unwrap(), Uid::from_raw(0));
+        assert_eq!(lookup_group("1234").unwrap(), Gid::from_raw(1234));
+        assert!(lookup_user("no-such-user-here").is_err());
+    }
 }
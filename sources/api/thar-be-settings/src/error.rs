@@ -2,6 +2,7 @@ use http::StatusCode;
 use snafu::Snafu;
 use std::io;
 use std::path::PathBuf;
+use std::time::Duration;
 
 /// Potential errors during configuration application
 #[derive(Debug, Snafu)]
@@ -27,15 +28,78 @@ pub enum Error {
         source: io::Error,
     },
 
+    #[snafu(display("Failed to read current configuration file at {}: {}", path.display(), source))]
+    TemplateRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to remove configuration file at {}: {}", path.display(), source))]
+    TemplateRemove { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Configuration file path '{}' has no file name", path.display()))]
+    InvalidPath { path: PathBuf },
+
+    #[snafu(display(
+        "Refusing to write to '{}', which is a symlink or not under {}",
+        path.display(),
+        model::modeled_types::CUSTOM_FILE_DIR
+    ))]
+    UnsafeDirectory { path: PathBuf },
+
+    #[snafu(display("Failed to set owner of {}: {}", path.display(), source))]
+    SetOwner { path: PathBuf, source: nix::Error },
+
+    #[snafu(display("Failed to look up user '{}': {}", name, source))]
+    LookupUser { name: String, source: nix::Error },
+
+    #[snafu(display("Unknown user '{}'", name))]
+    UnknownUser { name: String },
+
+    #[snafu(display("Failed to look up group '{}': {}", name, source))]
+    LookupGroup { name: String, source: nix::Error },
+
+    #[snafu(display("Unknown group '{}'", name))]
+    UnknownGroup { name: String },
+
+    #[snafu(display("Failed to serialize settings to check render conditions: {}", source))]
+    SerializeSettings { source: serde_json::Error },
+
     #[snafu(display("Failed to run restart command - '{}': {}", command, source))]
     CommandExecutionFailure { command: String, source: io::Error },
 
     #[snafu(display("Restart command failed - '{}': {}", command, stderr))]
-    FailedRestartCommand { command: String, stderr: String },
+    FailedRestartCommand {
+        command: String,
+        exit_code: Option<i32>,
+        stderr: String,
+    },
+
+    #[snafu(display("Restart command '{}' didn't finish within {:?}", command, timeout))]
+    RestartTimeout { command: String, timeout: Duration },
+
+    #[snafu(display("Failed to restart services: {}", services))]
+    RestartFailed { services: String },
 
     #[snafu(display("Restart command is invalid (empty, space prefix, etc.) - {}", command))]
     InvalidRestartCommand { command: String },
 
+    #[snafu(display("Unknown signal '{}': {}", signal, source))]
+    InvalidSignal { signal: String, source: nix::Error },
+
+    #[snafu(display("Failed to connect to systemd: {}", source))]
+    SystemdConnect { source: zbus::Error },
+
+    #[snafu(display("Failed to call systemd {} for '{}': {}", method, unit, source))]
+    SystemdCall {
+        method: String,
+        unit: String,
+        source: zbus::Error,
+    },
+
+    #[snafu(display("Failed to read systemd job status for '{}': {}", unit, source))]
+    SystemdJobStatus { unit: String, source: zbus::Error },
+
+    #[snafu(display("systemd job for '{}' finished with result '{}'", unit, result))]
+    SystemdJobFailed { unit: String, result: String },
+
     #[snafu(display("Configuration file '{}' failed to render: {}", template, source))]
     TemplateRender {
         template: String,
@@ -69,6 +133,30 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[snafu(display("Failed to lock service status at {}: {}", path.display(), source))]
+    StatusLock { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to read service status from {}: {}", path.display(), source))]
+    StatusRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to parse service status from {}: {}", path.display(), source))]
+    StatusParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to write service status in {}: {}", path.display(), source))]
+    StatusWrite { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to serialize service status: {}", source))]
+    StatusSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to move service status into place at {}: {}", path.display(), source))]
+    StatusPersist {
+        path: PathBuf,
+        source: tempfile::PersistError,
+    },
+
     #[snafu(display("Error GETing JSON from '{}': {}", uri, source))]
     GetJson {
         uri: String,
@@ -7,11 +7,41 @@ Its job is to update configuration files and restart services, as necessary, to
 In the normal ("specific keys") mode, it's intended to be called by the Bottlerocket API server after a settings commit.
 It's told the keys that changed, and then queries metadata APIs to determine which services and configuration files are affected by changes to those keys.
 Detailed data is then fetched for the relevant services and configuration files.
-Configuration file data from the API includes paths to template files for each configuration file, along with the final path to write.
+Configuration file data from the API includes paths to template files for each configuration file, or the base64-encoded template itself, along with the final path to write.
 It then renders the templates and rewrites the affected configuration files.
 Service data from the API includes any commands needed to restart services affected by configuration file changes, which are run here.
 
+Files are written to a temporary file and renamed into place, so services never see a partial file.
+Configuration file data can also give the file's `mode` (in octal, like "0600"), `owner`, and `group`, which are set on the temporary file before anything is written to it, so files holding credentials are never readable by others.
+If it has `render-if`, the name of a setting like "settings.ecs.enabled", the file is only written if that setting is truthy, as in a template's `{{#if}}`; otherwise, the file is removed.
+
+Services can list free-form `restart-commands`, which are split on spaces and run, and typed `restart-actions`, which are run in order after them:
+* `restart-unit <unit>` restarts a systemd unit, and `reload-unit <unit>` reloads it, or restarts it if it can't be reloaded
+* `signal-unit <unit> <signal>` sends a signal like SIGHUP to a systemd unit
+* `run <path> [args]` runs a program
+
+The unit actions only affect units that are already running, so applying settings at boot doesn't start units early.
+They're run through systemd's D-Bus API rather than systemctl: `restart-unit` and `reload-unit` wait for the job they start to finish, and `signal-unit` checks whether the unit is running and signals it in a single call, so a unit that's stopping at the same time isn't an error.
+Each action can have a `timeout-seconds`, after which it's counted as a failure; a `run` program that's still running is killed, and a unit's job is left to systemd.
+A service's commands and actions stop at its first failure, but other services are still restarted; the outcome of each one is logged, and thar-be-settings fails if any service failed.
+The outcome is also recorded, with the time and the changed settings that caused it, in `/run/cache/thar-be-settings/service-status.json`, which the API server reports at `/services/status`.
+
+```toml
+[services.example]
+configuration-files = ["example-config"]
+restart-actions = [
+  { action = "run /usr/bin/example-setup --quiet" },
+  { action = "reload-unit example.service", timeout-seconds = 30 },
+]
+```
+
+Custom files given in `settings.custom-files` are treated the same as built-in ones; the API server lists each one as a configuration file and a service named `custom-<name>`.
+Custom files can only be written under `/etc/custom`, and thar-be-settings won't follow symlinks when creating the directories there.
+
 In the standalone ("all keys") mode, it queries the API for all services and configuration files, then renders and rewrites all configuration files and restarts all services.
+
+In either mode, `--dry-run` renders the configuration files without writing them or restarting anything, and prints a JSON list of unified diffs against the files on disk.
+With `--tx`, the files are rendered as if the pending settings in the given transaction were committed; the API server uses this to preview the effects of a transaction.
 */
 
 #![deny(rust_2018_idioms)]
@@ -26,6 +56,8 @@ use std::io::{self, Read};
 pub mod config;
 pub mod error;
 pub mod service;
+pub mod status;
+mod systemd;
 
 pub use error::Error;
 type Result<T> = std::result::Result<T, Error>;
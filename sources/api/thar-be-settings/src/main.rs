@@ -1,21 +1,24 @@
 #[macro_use]
 extern crate log;
 
+use chrono::Utc;
 use constants;
 use nix::unistd::{fork, ForkResult};
-use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger, WriteLogger};
 use snafu::ResultExt;
 use std::collections::HashSet;
 use std::env;
+use std::io;
 use std::process;
 use std::str::FromStr;
 use tokio::runtime::Runtime;
 
-use thar_be_settings::{config, get_changed_settings, service};
+use thar_be_settings::config::RenderedConfigFile;
+use thar_be_settings::service::{RestartResults, Services};
+use thar_be_settings::{config, get_changed_settings, service, status};
 
 mod error {
     use snafu::Snafu;
-    use std::path::PathBuf;
 
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub(super)")]
@@ -23,12 +26,11 @@ mod error {
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
 
-        #[snafu(display("Failure to read template '{}' from '{}': {}", name, path.display(), source))]
-        TemplateRegister {
-            name: String,
-            path: PathBuf,
-            source: handlebars::TemplateError,
-        },
+        #[snafu(display("Failed to serialize configuration file changes: {}", source))]
+        SerializeDiffs { source: serde_json::Error },
+
+        #[snafu(display("{}", source))]
+        TemplateRegister { source: schnauzer::Error },
     }
 }
 
@@ -44,9 +46,11 @@ enum RunMode {
 /// Store the args we receive on the command line
 struct Args {
     daemon: bool,
+    dry_run: bool,
     log_level: LevelFilter,
     mode: RunMode,
     socket_path: String,
+    transaction: Option<String>,
 }
 
 /// Print a usage message in the event a bad arg is passed
@@ -56,6 +60,7 @@ fn usage() -> ! {
         r"Usage: {}
             [ --all ]
             [ --daemon ]
+            [ --dry-run [ --tx TRANSACTION ] ]
             [ --socket-path PATH ]
             [ --log-level trace|debug|info|warn|error ]
 
@@ -67,6 +72,12 @@ fn usage() -> ! {
     If --daemon is given, thar-be-settings will fork and do its work in a new
     process; this is useful to prevent blocking an API call.
 
+    If --dry-run is given, nothing is written and no services are restarted;
+    instead, a JSON list of the changes that would be made to configuration
+    files is printed, with a unified diff for each changed file.  If --tx is
+    also given, files are rendered as if the pending settings in that
+    transaction were committed.
+
     Socket path defaults to {}",
         program_name,
         constants::API_SOCKET,
@@ -83,9 +94,11 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 /// Parse the args to the program and return an Args struct
 fn parse_args(args: env::Args) -> Args {
     let mut daemon = false;
+    let mut dry_run = false;
     let mut log_level = None;
     let mut mode = RunMode::SpecificKeys;
     let mut socket_path = None;
+    let mut transaction = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -94,6 +107,8 @@ fn parse_args(args: env::Args) -> Args {
 
             "--daemon" => daemon = true,
 
+            "--dry-run" => dry_run = true,
+
             "--log-level" => {
                 let log_level_str = iter
                     .next()
@@ -110,24 +125,40 @@ fn parse_args(args: env::Args) -> Args {
                 )
             }
 
+            "--tx" => {
+                transaction = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --tx")),
+                )
+            }
+
             _ => usage(),
         }
     }
 
+    if dry_run && daemon {
+        usage_msg("Cannot use --daemon with --dry-run; the caller needs our output");
+    }
+    if transaction.is_some() && !dry_run {
+        usage_msg("--tx can only be used with --dry-run; only committed settings are applied");
+    }
+
     Args {
         daemon,
+        dry_run,
         mode,
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| constants::API_SOCKET.to_string()),
+        transaction,
     }
 }
 
-/// Render and write config files to disk.  If `files_limit` is Some, only
-/// write those files, otherwise write all known files.
-async fn write_config_files(
+/// Render config files.  If `files_limit` is Some, only render those files,
+/// otherwise render all known files.
+async fn render_config_files(
     args: &Args,
     files_limit: Option<HashSet<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<Vec<RenderedConfigFile>, Box<dyn std::error::Error>> {
     // Create a vec of ConfigFile structs from the list of changed services
     info!("Requesting configuration file data for affected services");
     let config_files = config::get_affected_config_files(&args.socket_path, files_limit).await?;
@@ -137,21 +168,19 @@ async fn write_config_files(
     debug!("Building template registry");
     let mut template_registry = schnauzer::build_template_registry()?;
     for (name, metadata) in &config_files {
-        debug!(
-            "Registering {} at path '{}'",
-            &name, &metadata.template_path
-        );
-        template_registry
-            .register_template_file(&name, metadata.template_path.as_ref())
-            .context(error::TemplateRegister {
-                name: name.as_str(),
-                path: metadata.template_path.as_ref(),
-            })?;
+        debug!("Registering template for {}", &name);
+        schnauzer::register_configuration_template(&mut template_registry, name, metadata)
+            .context(error::TemplateRegister)?;
     }
 
     // Get all settings values for config file templates
     debug!("Requesting settings values");
-    let settings = schnauzer::get_settings(&args.socket_path).await?;
+    let settings = match &args.transaction {
+        Some(transaction) => {
+            schnauzer::get_settings_with_pending(&args.socket_path, transaction).await?
+        }
+        None => schnauzer::get_settings(&args.socket_path).await?,
+    };
 
     // Ensure all files render properly
     info!("Rendering config files...");
@@ -161,6 +190,17 @@ async fn write_config_files(
     };
     let rendered = config::render_config_files(&template_registry, config_files, settings, strict)?;
 
+    Ok(rendered)
+}
+
+/// Render and write config files to disk.  If `files_limit` is Some, only
+/// write those files, otherwise write all known files.
+async fn write_config_files(
+    args: &Args,
+    files_limit: Option<HashSet<String>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rendered = render_config_files(args, files_limit).await?;
+
     // If all the config renders properly, write it to disk
     info!("Writing config files to disk...");
     config::write_config_files(rendered)?;
@@ -168,9 +208,37 @@ async fn write_config_files(
     Ok(())
 }
 
+/// Record the outcome of restarting services so the API can report it.  Failing to record it
+/// doesn't change the outcome, so it's only logged.
+fn record_service_status(services: &Services, results: &RestartResults) {
+    if let Err(e) =
+        status::record_service_status(status::SERVICE_STATUS_FILE, services, results, Utc::now())
+    {
+        warn!("Unable to record service status: {}", e);
+    }
+}
+
+/// Print the changes that writing the given rendered config files would make, as JSON, without
+/// writing them.
+fn print_config_file_diffs(
+    rendered: &[RenderedConfigFile],
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Comparing config files to disk...");
+    let diffs = config::diff_config_files(rendered)?;
+    let output = serde_json::to_string(&diffs).context(error::SerializeDiffs)?;
+    println!("{}", output);
+    Ok(())
+}
+
 async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
-    // SimpleLogger will send errors to stderr and anything less to stdout.
-    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+    if args.dry_run {
+        // Our output goes to stdout, so keep all logging on stderr.
+        WriteLogger::init(args.log_level, LogConfig::default(), io::stderr())
+            .context(error::Logger)?;
+    } else {
+        // SimpleLogger will send errors to stderr and anything less to stdout.
+        SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+    }
 
     info!("thar-be-settings started");
 
@@ -190,19 +258,38 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
             trace!("Found services: {:?}", services);
             if services.0.is_empty() {
                 info!("No services are affected, exiting...");
+                if args.dry_run {
+                    print_config_file_diffs(&[])?;
+                }
                 process::exit(0)
             }
 
             // Create a HashSet of configuration file names
             let config_file_names = config::get_config_file_names(&services);
 
+            if args.dry_run {
+                let rendered = if config_file_names.is_empty() {
+                    Vec::new()
+                } else {
+                    render_config_files(&args, Some(config_file_names)).await?
+                };
+                print_config_file_diffs(&rendered)?;
+                return Ok(());
+            }
+
             if !config_file_names.is_empty() {
                 write_config_files(&args, Some(config_file_names)).await?;
             }
 
             // Now go bounce the affected services
             info!("Restarting affected services...");
-            service::restart_services(services)?;
+            let results = service::restart_services(&services);
+            record_service_status(&services, &results);
+            service::check_restart_results(&results)?;
+        }
+        RunMode::All if args.dry_run => {
+            let rendered = render_config_files(&args, None).await?;
+            print_config_file_diffs(&rendered)?;
         }
         RunMode::All => {
             write_config_files(&args, None).await?;
@@ -210,7 +297,9 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
             info!("Restarting all services...");
             let services = service::get_affected_services(&args.socket_path, None).await?;
             trace!("Found services: {:?}", services);
-            service::restart_services(services)?;
+            let results = service::restart_services(&services);
+            record_service_status(&services, &results);
+            service::check_restart_results(&results)?;
         }
     }
 
@@ -1,9 +1,16 @@
-use crate::{error, Result};
+use crate::{error, systemd, Result};
 use itertools::join;
+use model::modeled_types::ServiceActionKind;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 // TODO: thar-be-settings isn't used as a library; declare its modules in main rather than lib so
 // we don't have to expose helper types like this just so we can call related functions in main.
@@ -133,70 +140,342 @@ where
     Ok(service_map)
 }
 
-/// Call the `restart()` method on each Service in a Services object
-pub fn restart_services(services: Services) -> Result<()> {
-    for (name, service) in services.0 {
+/// The outcome of one restart command or action of a service.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ActionResult {
+    /// The restart command or action that was run.
+    pub action: String,
+    /// The exit code of the last command run for the action, if it ran and exited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Why the action failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ActionResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The outcomes of the restart commands and actions of each service, by service name.
+pub type RestartResults = BTreeMap<String, Vec<ActionResult>>;
+
+/// Call the `restart()` method on each Service in a Services object.  A failure doesn't stop
+/// other services from being restarted; check the results with `check_restart_results`.
+pub fn restart_services(services: &Services) -> RestartResults {
+    let mut results = BTreeMap::new();
+    for (name, service) in &services.0 {
         debug!("Checking for restart-commands for {}", name);
-        service.restart()?;
+        results.insert(name.clone(), service.restart());
     }
+    results
+}
+
+/// Returns an error naming each service with a failed restart command or action, if any.
+pub fn check_restart_results(results: &RestartResults) -> Result<()> {
+    let failed: Vec<&String> = results
+        .iter()
+        .filter(|(_, actions)| actions.iter().any(|a| !a.succeeded()))
+        .map(|(name, _)| name)
+        .collect();
+    ensure!(
+        failed.is_empty(),
+        error::RestartFailed {
+            services: join(failed, ", ")
+        }
+    );
     Ok(())
 }
 
 /// This trait is primarily meant to extend the Service model.  It uses the metadata
 /// inside the Service struct to restart the service.
 trait ServiceRestart {
-    /// Restart the service
-    fn restart(&self) -> Result<()>;
+    /// Restart the service, running its restart commands and then its restart actions in order,
+    /// and stopping at the first failure.  Returns the outcome of each one that ran.
+    fn restart(&self) -> Vec<ActionResult>;
 }
 
 impl ServiceRestart for Service {
-    fn restart(&self) -> Result<()> {
+    fn restart(&self) -> Vec<ActionResult> {
         let restart_commands = &self.model.restart_commands;
+        let restart_actions = &self.model.restart_actions;
         info!("restart commands {:?}", restart_commands);
+        info!("restart actions {:?}", restart_actions);
+
+        let mut results = Vec::new();
         for restart_command in restart_commands {
-            // Split on space, assume the first item is the command
-            // and the rest are args.
-            debug!("Restart command: {:?}", &restart_command);
-            let mut command_strings = restart_command.split(' ');
-            let command = command_strings
-                .next()
-                .context(error::InvalidRestartCommand {
-                    command: restart_command.as_str(),
-                })?;
-            trace!("Command: {}", &command);
-            trace!("Args: {:?}", &command_strings);
-
-            // Go execute the restart command
-            let mut process_command = Command::new(command);
-            process_command.args(command_strings);
-            if let Some(ref changed_settings) = self.changed_settings {
-                if !changed_settings.is_empty() {
-                    process_command.env("CHANGED_SETTINGS", join(changed_settings, " "));
-                }
+            let result = self.run_restart_command(restart_command);
+            results.push(action_result(restart_command.clone(), result));
+            if results.last().map(|r| !r.succeeded()).unwrap_or(false) {
+                return results;
+            }
+        }
+        for restart_action in restart_actions {
+            let result = self.run_restart_action(restart_action);
+            results.push(action_result(restart_action.action.to_string(), result));
+            if results.last().map(|r| !r.succeeded()).unwrap_or(false) {
+                return results;
+            }
+        }
+        results
+    }
+}
+
+impl Service {
+    /// Runs a free-form restart command, returning its exit code.
+    fn run_restart_command(&self, restart_command: &str) -> Result<Option<i32>> {
+        // Split on space, assume the first item is the command
+        // and the rest are args.
+        debug!("Restart command: {:?}", &restart_command);
+        let mut command_strings = restart_command.split(' ');
+        let command = command_strings.next().filter(|c| !c.is_empty()).context(
+            error::InvalidRestartCommand {
+                command: restart_command,
+            },
+        )?;
+        trace!("Command: {}", &command);
+        trace!("Args: {:?}", &command_strings);
+
+        // Go execute the restart command
+        let mut process_command = Command::new(command);
+        process_command.args(command_strings);
+        self.set_changed_settings(&mut process_command);
+        run(process_command, restart_command, None)
+    }
+
+    /// Runs a restart action, returning the exit code of the command it ran, if any.
+    fn run_restart_action(&self, restart_action: &model::RestartAction) -> Result<Option<i32>> {
+        debug!("Restart action: {:?}", &restart_action);
+        let description = restart_action.action.as_ref();
+        let timeout = restart_action.timeout_seconds.map(Duration::from_secs);
+
+        // Like the restart commands of our built-in services, the unit actions don't start units
+        // that aren't running yet, so applying settings at boot doesn't start them early.
+        match restart_action.action.kind() {
+            ServiceActionKind::RestartUnit { unit } => {
+                let unit = unit.to_string();
+                run_unit_action(
+                    move || systemd::try_restart_unit(&unit),
+                    description,
+                    timeout,
+                )
+            }
+            ServiceActionKind::ReloadUnit { unit } => {
+                let unit = unit.to_string();
+                run_unit_action(
+                    move || systemd::reload_or_try_restart_unit(&unit),
+                    description,
+                    timeout,
+                )
+            }
+            ServiceActionKind::SignalUnit { unit, signal } => {
+                let signal: Signal = signal.parse().context(error::InvalidSignal { signal })?;
+                let unit = unit.to_string();
+                run_unit_action(
+                    move || systemd::kill_unit(&unit, signal),
+                    description,
+                    timeout,
+                )
+            }
+            ServiceActionKind::Run { path, args } => {
+                let mut command = Command::new(path);
+                command.args(args);
+                self.set_changed_settings(&mut command);
+                run(command, description, timeout)
+            }
+        }
+    }
+
+    /// Tells the command which of the service's settings changed, if we know.
+    fn set_changed_settings(&self, command: &mut Command) {
+        if let Some(ref changed_settings) = self.changed_settings {
+            if !changed_settings.is_empty() {
+                command.env("CHANGED_SETTINGS", join(changed_settings, " "));
+            }
+        }
+    }
+}
+
+/// Builds the ActionResult for a restart command or action from the result of running it.
+fn action_result(action: String, result: Result<Option<i32>>) -> ActionResult {
+    match result {
+        Ok(exit_code) => ActionResult {
+            action,
+            exit_code,
+            error: None,
+        },
+        Err(e) => {
+            error!("{}", e);
+            let exit_code = match e {
+                error::Error::FailedRestartCommand { exit_code, .. } => exit_code,
+                _ => None,
+            };
+            ActionResult {
+                action,
+                exit_code,
+                error: Some(e.to_string()),
             }
-            let result = process_command
-                .output()
-                .context(error::CommandExecutionFailure {
-                    command: restart_command.as_str(),
-                })?;
-
-            // If the restart command exited nonzero, call it a failure
-            ensure!(
-                result.status.success(),
-                error::FailedRestartCommand {
-                    command: restart_command.as_str(),
-                    stderr: String::from_utf8_lossy(&result.stderr),
+        }
+    }
+}
+
+/// Runs the given unit action, waiting for it up to the given timeout, if any.  Unit actions don't
+/// run a command, so there's no exit code to return.
+fn run_unit_action<F>(
+    action: F,
+    description: &str,
+    timeout: Option<Duration>,
+) -> Result<Option<i32>>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return action().map(|()| None),
+    };
+
+    // Run the action on another thread so we can stop waiting when the timeout expires.  We
+    // can't take back a job we've queued, so systemd finishes it on its own.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(action());
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result.map(|()| None),
+        Err(_) => error::RestartTimeout {
+            command: description,
+            timeout,
+        }
+        .fail(),
+    }
+}
+
+/// Runs the given command, waiting for it up to the given timeout, if any, and returns its exit
+/// code.  It's an error if the command exits nonzero or doesn't finish in time.
+fn run(mut command: Command, description: &str, timeout: Option<Duration>) -> Result<Option<i32>> {
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context(error::CommandExecutionFailure {
+            command: description,
+        })?;
+    let pid = Pid::from_raw(child.id() as i32);
+
+    // Collect the output on another thread so we can stop waiting when the timeout expires.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(child.wait_with_output());
+    });
+    let output = match timeout {
+        Some(timeout) => match receiver.recv_timeout(timeout) {
+            Ok(output) => output,
+            Err(_) => {
+                // The thread waiting for the command will reap it once it's killed.
+                let _ = kill(pid, Signal::SIGKILL);
+                return error::RestartTimeout {
+                    command: description,
+                    timeout,
                 }
-            );
-            trace!(
-                "Command stdout: {}",
-                String::from_utf8_lossy(&result.stdout)
-            );
-            trace!(
-                "Command stderr: {}",
-                String::from_utf8_lossy(&result.stderr)
-            );
+                .fail();
+            }
+        },
+        None => receiver
+            .recv()
+            .expect("thread waiting for command exited early"),
+    }
+    .context(error::CommandExecutionFailure {
+        command: description,
+    })?;
+
+    trace!(
+        "Command stdout: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    trace!(
+        "Command stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // If the restart command exited nonzero, call it a failure
+    ensure!(
+        output.status.success(),
+        error::FailedRestartCommand {
+            command: description,
+            exit_code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+    Ok(output.status.code())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn service(restart_commands: &[&str], restart_actions: &[(&str, Option<u64>)]) -> Service {
+        Service {
+            changed_settings: None,
+            model: model::Service {
+                configuration_files: vec![],
+                restart_commands: restart_commands.iter().map(|c| c.to_string()).collect(),
+                restart_actions: restart_actions
+                    .iter()
+                    .map(|(action, timeout_seconds)| model::RestartAction {
+                        action: (*action).try_into().unwrap(),
+                        timeout_seconds: *timeout_seconds,
+                    })
+                    .collect(),
+            },
         }
-        Ok(())
+    }
+
+    #[test]
+    fn commands_then_actions() {
+        let results = service(&["/bin/true"], &[("run /bin/sh -c true", None)]).restart();
+        assert_eq!(
+            results,
+            vec![
+                ActionResult {
+                    action: "/bin/true".to_string(),
+                    exit_code: Some(0),
+                    error: None,
+                },
+                ActionResult {
+                    action: "run /bin/sh -c true".to_string(),
+                    exit_code: Some(0),
+                    error: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_first_failure() {
+        let results = service(&[], &[("run /bin/false", None), ("run /bin/true", None)]).restart();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].exit_code, Some(1));
+        assert!(!results[0].succeeded());
+
+        let mut all = RestartResults::new();
+        all.insert("a".to_string(), results);
+        all.insert("b".to_string(), vec![]);
+        assert_eq!(
+            check_restart_results(&all).unwrap_err().to_string(),
+            "Failed to restart services: a"
+        );
+    }
+
+    #[test]
+    fn action_timeout() {
+        let results = service(&[], &[("run /bin/sleep 10", Some(0))]).restart();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].exit_code, None);
+        assert!(results[0].error.as_ref().unwrap().contains("didn't finish"));
     }
 }
@@ -0,0 +1,174 @@
+//! The status module records the outcome of applying settings to each service, so the API server
+//! can report it.  The status of each service is kept until the next time settings are applied
+//! to that service.
+
+use crate::service::{ActionResult, RestartResults, Services};
+use crate::{error, Result};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+pub const SERVICE_STATUS_FILE: &str = "/run/cache/thar-be-settings/service-status.json";
+
+/// The outcome of the last time settings were applied to a service.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServiceStatus {
+    pub last_applied: DateTime<Utc>,
+    /// The changed settings that caused the service to be restarted, or None if all services
+    /// were restarted, like at boot.
+    pub changed_settings: Option<Vec<String>>,
+    /// The outcome of each restart command and action that ran, in order.
+    pub restart_results: Vec<ActionResult>,
+}
+
+/// The status of each service, by service name.
+pub type ServiceStatuses = BTreeMap<String, ServiceStatus>;
+
+/// Reads the status of each service from the given file.  If it doesn't exist yet, because
+/// settings haven't been applied since boot, no services have a status.
+pub fn read_service_status<P>(path: P) -> Result<ServiceStatuses>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ServiceStatuses::new()),
+        Err(e) => return Err(e).context(error::StatusRead { path }),
+    };
+    serde_json::from_slice(&data).context(error::StatusParse { path })
+}
+
+/// Records the given results of restarting the given services in the status file at the given
+/// path, keeping the status of other services.
+pub fn record_service_status<P>(
+    path: P,
+    services: &Services,
+    results: &RestartResults,
+    now: DateTime<Utc>,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    fs::create_dir_all(dir).context(error::StatusWrite { path: dir })?;
+
+    // Settings can be applied by more than one thar-be-settings at a time, so hold a lock while
+    // we update the file.
+    let lock_path = lock_path(path);
+    let lockfile = File::create(&lock_path).context(error::StatusLock { path: &lock_path })?;
+    lockfile
+        .lock_exclusive()
+        .context(error::StatusLock { path: &lock_path })?;
+
+    let mut statuses = read_service_status(path)?;
+    for (name, restart_results) in results {
+        let changed_settings = services
+            .0
+            .get(name)
+            .and_then(|service| service.changed_settings.as_ref())
+            .map(|changed| {
+                let mut changed: Vec<String> = changed.iter().cloned().collect();
+                changed.sort();
+                changed
+            });
+        statuses.insert(
+            name.clone(),
+            ServiceStatus {
+                last_applied: now,
+                changed_settings,
+                restart_results: restart_results.clone(),
+            },
+        );
+    }
+
+    // Write a new file and swap it into place, so the API server never reads a partial file.
+    let tempfile = NamedTempFile::new_in(dir).context(error::StatusWrite { path: dir })?;
+    serde_json::to_writer_pretty(&tempfile, &statuses).context(error::StatusSerialize)?;
+    tempfile
+        .persist(path)
+        .context(error::StatusPersist { path })?;
+    Ok(())
+}
+
+/// Returns the path of the lock file that guards the status file at the given path.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    PathBuf::from(lock_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::{hashmap, hashset};
+
+    fn result(action: &str, exit_code: i32) -> ActionResult {
+        ActionResult {
+            action: action.to_string(),
+            exit_code: Some(exit_code),
+            error: None,
+        }
+    }
+
+    fn service() -> model::Service {
+        model::Service {
+            configuration_files: vec![],
+            restart_commands: vec![],
+            restart_actions: vec![],
+        }
+    }
+
+    #[test]
+    fn record_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status/service-status.json");
+        assert!(read_service_status(&path).unwrap().is_empty());
+
+        let services = Services::from_model_services(
+            hashmap!("a".to_string() => service(), "b".to_string() => service()),
+            Some(
+                hashmap!("a".to_string() => hashset!("settings.y".to_string(), "settings.x".to_string())),
+            ),
+        );
+        let first = Utc::now();
+        let mut results = RestartResults::new();
+        results.insert("a".to_string(), vec![result("run /bin/a", 0)]);
+        results.insert("b".to_string(), vec![]);
+        record_service_status(&path, &services, &results, first).unwrap();
+
+        // Applying settings again only replaces the status of the services it restarted.
+        let services = Services::from_model_services(hashmap!("b".to_string() => service()), None);
+        let second = Utc::now();
+        let mut results = RestartResults::new();
+        results.insert("b".to_string(), vec![result("run /bin/b", 0)]);
+        record_service_status(&path, &services, &results, second).unwrap();
+
+        let statuses = read_service_status(&path).unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(
+            statuses["a"],
+            ServiceStatus {
+                last_applied: first,
+                changed_settings: Some(vec!["settings.x".to_string(), "settings.y".to_string()]),
+                restart_results: vec![result("run /bin/a", 0)],
+            }
+        );
+        assert_eq!(
+            statuses["b"],
+            ServiceStatus {
+                last_applied: second,
+                changed_settings: None,
+                restart_results: vec![result("run /bin/b", 0)],
+            }
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! The restart actions that act on systemd units talk to systemd over D-Bus rather than running
+//! systemctl, so we can wait for the jobs they start and signal a unit in a single call.
+
+use crate::{error, Result};
+use nix::sys::signal::Signal;
+use snafu::{ensure, ResultExt};
+use zbus::{Connection, Message, MessageType, Proxy};
+use zvariant::OwnedObjectPath;
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const SYSTEMD_MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+const DBUS_DESTINATION: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+const DBUS_INTERFACE: &str = "org.freedesktop.DBus";
+
+/// The bus only delivers signals that match a rule we've added.
+const JOB_REMOVED_MATCH: &str = "type='signal',sender='org.freedesktop.systemd1',\
+    interface='org.freedesktop.systemd1.Manager',member='JobRemoved'";
+
+/// The results of a finished job that mean it did what we asked.  A "try" job on a unit that
+/// isn't running has nothing to do, and is reported as done.
+const JOB_SUCCEEDED: &[&str] = &["done", "skipped"];
+
+/// The errors from KillUnit that mean the unit isn't running, so there's nothing to signal.
+const NOT_RUNNING_ERRORS: &[&str] = &[
+    "org.freedesktop.systemd1.NoSuchUnit",
+    "org.freedesktop.systemd1.NoSuchProcess",
+];
+
+/// Restarts the unit if it's running, waiting for the restart to finish.
+pub(crate) fn try_restart_unit(unit: &str) -> Result<()> {
+    run_job("TryRestartUnit", unit)
+}
+
+/// Reloads the unit if it's running, or restarts it if it can't be reloaded, waiting for that to
+/// finish.
+pub(crate) fn reload_or_try_restart_unit(unit: &str) -> Result<()> {
+    run_job("ReloadOrTryRestartUnit", unit)
+}
+
+/// Sends the signal to all of the unit's processes.  systemd checks whether the unit is running
+/// as part of the same call, so a unit that isn't running, or stops while we're asking, isn't an
+/// error; there's just nothing to signal.
+pub(crate) fn kill_unit(unit: &str, signal: Signal) -> Result<()> {
+    let connection = Connection::new_system().context(error::SystemdConnect)?;
+    let manager = manager(&connection)?;
+    match manager.call::<_, ()>("KillUnit", &(unit, "all", signal as i32)) {
+        Ok(()) => Ok(()),
+        Err(zbus::Error::MethodError(ref name, _, _))
+            if NOT_RUNNING_ERRORS.contains(&name.as_str()) =>
+        {
+            debug!("Not signaling {}, it isn't running", unit);
+            Ok(())
+        }
+        Err(e) => Err(e).context(error::SystemdCall {
+            method: "KillUnit",
+            unit,
+        }),
+    }
+}
+
+/// Calls a Manager method that queues a job for the unit, like systemctl does, and waits for
+/// systemd to report that the job finished.
+fn run_job(method: &'static str, unit: &str) -> Result<()> {
+    let connection = Connection::new_system().context(error::SystemdConnect)?;
+    let manager = manager(&connection)?;
+
+    // systemd only sends job signals once a client subscribes.  We subscribe and add our match
+    // before queuing the job, so we can't miss it finishing; the connection queues any signals
+    // that arrive while we're waiting for the reply.
+    manager
+        .call::<_, ()>("Subscribe", &())
+        .context(error::SystemdCall {
+            method: "Subscribe",
+            unit,
+        })?;
+    Proxy::new(&connection, DBUS_DESTINATION, DBUS_PATH, DBUS_INTERFACE)
+        .and_then(|bus| bus.call::<_, ()>("AddMatch", &JOB_REMOVED_MATCH))
+        .context(error::SystemdCall {
+            method: "AddMatch",
+            unit,
+        })?;
+
+    let job: OwnedObjectPath = manager
+        .call(method, &(unit, "replace"))
+        .context(error::SystemdCall { method, unit })?;
+    debug!("Waiting for systemd job {} for {}", job.as_str(), unit);
+
+    loop {
+        let message = connection
+            .receive_message()
+            .context(error::SystemdJobStatus { unit })?;
+        if let Some((removed, result)) =
+            job_removed(&message).context(error::SystemdJobStatus { unit })?
+        {
+            if removed == job {
+                ensure!(
+                    JOB_SUCCEEDED.contains(&result.as_str()),
+                    error::SystemdJobFailed { unit, result }
+                );
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Returns the job and its result if the message is a JobRemoved signal.
+fn job_removed(message: &Message) -> zbus::Result<Option<(OwnedObjectPath, String)>> {
+    let header = message.header()?;
+    if header.message_type()? != MessageType::Signal || header.member()? != Some("JobRemoved") {
+        return Ok(None);
+    }
+    let (_id, job, _unit, result): (u32, OwnedObjectPath, String, String) = message.body()?;
+    Ok(Some((job, result)))
+}
+
+fn manager(connection: &Connection) -> Result<Proxy<'_>> {
+    Proxy::new(
+        connection,
+        SYSTEMD_DESTINATION,
+        SYSTEMD_PATH,
+        SYSTEMD_MANAGER_INTERFACE,
+    )
+    .context(error::SystemdConnect)
+}
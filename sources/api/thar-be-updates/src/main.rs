@@ -13,6 +13,9 @@ The output and status of the command will be written to the update status file.
 This allows the caller to synchronously call thar-be-updates without having to wait for a result to come back.
 
 thar-be-updates uses a lockfile to control read/write access to the disks and the update status file.
+While an update is being prepared, updog writes its download progress to a separate file, which the API includes in the status it gives while the lock is held.
+
+If `refresh` is given a repository stored on the host with `--repository`, for hosts that can't reach the configured repository, it's saved in the update status so the chosen update is prepared from the same repository.
 
 */
 
@@ -24,7 +27,7 @@ use num_traits::cast::ToPrimitive;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
 use snafu::ensure;
 use snafu::{OptionExt, ResultExt};
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
 use std::process::{exit, Command};
 use std::str::FromStr;
@@ -33,8 +36,8 @@ use tempfile::NamedTempFile;
 use thar_be_updates::error;
 use thar_be_updates::error::{Error, Result, TbuErrorStatus};
 use thar_be_updates::status::{
-    get_update_status, UpdateCommand, UpdateState, UpdateStatus, UPDATE_LOCKFILE,
-    UPDATE_STATUS_FILE,
+    get_update_status, UpdateCommand, UpdateState, UpdateStatus, DOWNLOAD_PROGRESS_FILE,
+    UPDATE_LOCKFILE, UPDATE_STATUS_FILE,
 };
 
 const UPDATE_STATUS_DIR: &str = "/run/cache/thar-be-updates";
@@ -44,6 +47,7 @@ struct Args {
     subcommand: UpdateCommand,
     log_level: LevelFilter,
     socket_path: String,
+    repository: Option<String>,
 }
 
 /// Prints an usage message
@@ -54,6 +58,9 @@ fn usage() -> ! {
             Subcommands:
                 refresh     Query update repository, store the list of available updates,
                             and check if chosen version is available
+                    [ --repository LOCATION ]  Query the repository stored on the host at this
+                                               file:// URL or path instead, and prepare the
+                                               chosen update from it
                 prepare     Download the chosen update and write the update image to the
                             inactive partition
                 activate    Marks the inactive partition for boot
@@ -79,6 +86,7 @@ fn parse_args(args: std::env::Args) -> Args {
     let mut subcommand = None;
     let mut log_level = None;
     let mut socket_path = None;
+    let mut repository = None;
 
     let mut iter = args.skip(1).peekable();
     while let Some(arg) = iter.next() {
@@ -98,6 +106,12 @@ fn parse_args(args: std::env::Args) -> Args {
                         .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
                 )
             }
+            "--repository" => {
+                repository = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --repository")),
+                )
+            }
             // Assume any arguments not prefixed with '-' is a subcommand
             s if !s.starts_with('-') => {
                 if subcommand.is_some() {
@@ -114,6 +128,7 @@ fn parse_args(args: std::env::Args) -> Args {
         subcommand: subcommand.unwrap_or_else(|| usage()),
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| constants::API_SOCKET.to_string()),
+        repository,
     }
 }
 
@@ -122,11 +137,9 @@ fn parse_args(args: std::env::Args) -> Args {
 // that refer to the same lock. Once the parent returns and closes its copy of the lockfile fd,
 // the child will still hold the lock. The lock is only released when all copies of the file descriptor are closed.
 fn lock_exclusive(lockfile: &File) -> Result<()> {
-    lockfile
-        .try_lock_exclusive()
-        .context(error::UpdateLockHeld {
-            path: UPDATE_LOCKFILE,
-        })?;
+    FileExt::try_lock_exclusive(lockfile).context(error::UpdateLockHeld {
+        path: UPDATE_LOCKFILE,
+    })?;
     debug!("Obtained exclusive lock");
     Ok(())
 }
@@ -184,10 +197,12 @@ macro_rules! fork_and_return {
 fn refresh(status: &mut UpdateStatus, socket_path: &str) -> Result<bool> {
     fork_and_return!({
         debug!("Spawning 'updog whats'");
-        let output = Command::new("updog")
-            .args(&["whats", "--all", "--json"])
-            .output()
-            .context(error::Updog)?;
+        let mut command = Command::new("updog");
+        command.args(["whats", "--all", "--json"]);
+        if let Some(repository) = status.repository() {
+            command.arg("--repository").arg(repository);
+        }
+        let output = command.output().context(error::Updog)?;
         status.set_recent_command_info(UpdateCommand::Refresh, &output);
         if !output.status.success() {
             warn!("Failed to check for updates with updog");
@@ -207,10 +222,19 @@ fn prepare(status: &mut UpdateStatus) -> Result<()> {
             .chosen_update()
             .context(error::UpdateDoesNotExist)?
             .clone();
-        let output = Command::new("updog")
+        let mut command = Command::new("updog");
+        command
             .arg("update-image")
-            .output()
-            .context(error::Updog)?;
+            .arg("--progress-file")
+            .arg(DOWNLOAD_PROGRESS_FILE);
+        if let Some(repository) = status.repository() {
+            command.arg("--repository").arg(repository);
+        }
+        let output = command.output().context(error::Updog)?;
+        // The progress is only shown while we're preparing.
+        if let Err(e) = fs::remove_file(DOWNLOAD_PROGRESS_FILE) {
+            debug!("Unable to remove download progress file: {}", e);
+        }
         status.set_recent_command_info(UpdateCommand::Prepare, &output);
         if !output.status.success() {
             warn!("Failed to prepare the update with updog");
@@ -260,7 +284,13 @@ fn drive_state_machine(
     update_status: &mut UpdateStatus,
     operation: &UpdateCommand,
     socket_path: &str,
+    repository: Option<String>,
 ) -> Result<()> {
+    // Each refresh picks the repository that later commands use, going back to the configured
+    // repository unless another is given.
+    if *operation == UpdateCommand::Refresh {
+        update_status.set_repository(repository);
+    }
     let new_state = match (operation, update_status.update_state()) {
         (UpdateCommand::Refresh, UpdateState::Idle)
         | (UpdateCommand::Refresh, UpdateState::Available) => {
@@ -349,7 +379,12 @@ fn run() -> Result<()> {
     // The commands inside drive_state_machine update the update_status object (hence &mut) to
     // reflect success or failure, and we want to reflect that in our status file regardless of
     // success, so we store the result rather than returning early here.
-    let result = drive_state_machine(&mut update_status, &args.subcommand, &args.socket_path);
+    let result = drive_state_machine(
+        &mut update_status,
+        &args.subcommand,
+        &args.socket_path,
+        args.repository,
+    );
     write_update_status(&update_status)?;
     result
 }
@@ -14,8 +14,10 @@ use tokio::runtime::Runtime;
 
 pub const UPDATE_LOCKFILE: &str = "/run/lock/thar-be-updates.lock";
 pub const UPDATE_STATUS_FILE: &str = "/run/cache/thar-be-updates/status.json";
+/// updog writes the progress of the update it's downloading here while preparing an update.
+pub const DOWNLOAD_PROGRESS_FILE: &str = "/run/cache/thar-be-updates/progress.json";
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum UpdateState {
     Idle,
     Available,
@@ -77,6 +79,13 @@ struct CommandResult {
     stderr: Option<String>,
 }
 
+/// DownloadProgress represents how much of an update has been downloaded while it's being prepared
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DownloadProgress {
+    bytes_done: u64,
+    bytes_total: u64,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateStatus {
     update_state: UpdateState,
@@ -85,6 +94,13 @@ pub struct UpdateStatus {
     active_partition: Option<StagedImage>,
     staging_partition: Option<StagedImage>,
     most_recent_command: Option<CommandResult>,
+    /// Only set while an update is being prepared; see `peek_update_status`.
+    #[serde(default)]
+    download_progress: Option<DownloadProgress>,
+    /// The repository on the host that updates were last refreshed from, instead of the configured
+    /// one.  The chosen update is prepared from the same repository.
+    #[serde(default)]
+    repository: Option<String>,
 }
 
 impl Default for UpdateStatus {
@@ -107,6 +123,24 @@ pub fn get_update_status(_lockfile: &File) -> Result<UpdateStatus> {
     )
 }
 
+/// Loads and returns the update status from disk while an update command holds the update lock,
+/// including the download progress of an update being prepared.  The status file is replaced
+/// atomically, so it's always complete, but it won't reflect the running command until it's done.
+pub fn peek_update_status() -> Result<UpdateStatus> {
+    let status_file = File::open(UPDATE_STATUS_FILE).context(error::NoStatusFile {
+        path: UPDATE_STATUS_FILE,
+    })?;
+    let mut status: UpdateStatus =
+        serde_json::from_reader(status_file).context(error::StatusParse {
+            path: UPDATE_STATUS_FILE,
+        })?;
+    // Progress is informational, and there's none unless an update is being prepared.
+    status.download_progress = File::open(DOWNLOAD_PROGRESS_FILE)
+        .ok()
+        .and_then(|progress_file| serde_json::from_reader(progress_file).ok());
+    Ok(status)
+}
+
 /// Retrieves settings from the API.
 ///
 /// NOTE: this function creates its own tokio runtime to make the async apiclient call.  It should
@@ -136,6 +170,8 @@ impl UpdateStatus {
             active_partition: None,
             staging_partition: None,
             most_recent_command: None,
+            download_progress: None,
+            repository: None,
         }
     }
 
@@ -154,6 +190,14 @@ impl UpdateStatus {
         }
     }
 
+    pub fn repository(&self) -> Option<&str> {
+        self.repository.as_deref()
+    }
+
+    pub fn set_repository(&mut self, repository: Option<String>) {
+        self.repository = repository;
+    }
+
     pub fn staging_partition(&self) -> Option<&StagedImage> {
         match &self.staging_partition {
             Some(partition_info) => Some(&partition_info),
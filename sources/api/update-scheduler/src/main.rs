@@ -0,0 +1,225 @@
+/*!
+# Introduction
+
+update-scheduler updates the host on its own schedule, for hosts that aren't updated by an
+orchestrator or operator through the API.
+
+It only does anything if `settings.updates.policy` is `automatic`.
+In that case, if the current time falls in one of the maintenance windows in
+`settings.updates.maintenance-windows`, it checks for an update, prepares and activates it, and
+reboots into it, using the same update API actions as `apiclient update`.
+If there are no maintenance windows, the host may update at any time.
+
+Updates are checked for with updog, so the waves of an update are respected unless
+`settings.updates.ignore-waves` is set, and `settings.updates.version-lock` chooses the version.
+Preparing an update can take a while, so update-scheduler only reboots if the window is still open
+afterward; otherwise the update stays activated, and the host reboots into it in its next window.
+
+update-scheduler makes one pass each time it runs, and is meant to be run periodically by a
+systemd timer.
+*/
+
+#![deny(rust_2018_idioms)]
+
+mod window;
+
+use chrono::Utc;
+use log::{debug, info, trace};
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use snafu::ResultExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::{env, process};
+use thar_be_updates::status::{UpdateState, UpdateStatus};
+
+/// Store the args we receive on the command line.
+struct Args {
+    log_level: LevelFilter,
+    socket_path: String,
+}
+
+/// Main entry point.
+async fn run() -> Result<()> {
+    let args = parse_args(env::args());
+
+    // SimpleLogger will send errors to stderr and anything less to stdout.
+    SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
+
+    let updates = match get_updates_settings(&args.socket_path).await? {
+        Some(updates) => updates,
+        None => {
+            info!("No update settings, nothing to do");
+            return Ok(());
+        }
+    };
+    let automatic = matches!(&updates.policy, Some(policy) if policy.is_automatic());
+    if !automatic {
+        info!("Update policy isn't 'automatic', nothing to do");
+        return Ok(());
+    }
+
+    let windows = updates.maintenance_windows.unwrap_or_default();
+    if !window::in_maintenance_window(windows.values(), Utc::now()) {
+        info!("Not in a maintenance window, nothing to do");
+        return Ok(());
+    }
+
+    let status = apiclient::update::check(&args.socket_path, None)
+        .await
+        .context(error::Check)?;
+    let status: UpdateStatus = serde_json::from_str(&status).context(error::UpdateStatusJson)?;
+    match status.update_state() {
+        UpdateState::Idle => {
+            info!("No update available");
+            return Ok(());
+        }
+        UpdateState::Available | UpdateState::Staged => {
+            apiclient::update::apply(&args.socket_path)
+                .await
+                .context(error::Apply)?;
+        }
+        UpdateState::Ready => {
+            info!("Update already activated");
+        }
+    }
+
+    if !window::in_maintenance_window(windows.values(), Utc::now()) {
+        info!("Maintenance window closed while preparing the update; will reboot in the next one");
+        return Ok(());
+    }
+    apiclient::reboot::reboot(&args.socket_path)
+        .await
+        .context(error::Reboot)
+}
+
+/// Retrieves the update settings from the API.
+async fn get_updates_settings<P>(socket_path: P) -> Result<Option<model::UpdatesSettings>>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/settings?keys=settings.updates";
+    let method = "GET";
+    trace!("{}ing from {}", method, uri);
+    let (_code, response_body) = apiclient::raw_request(socket_path, uri, method, None)
+        .await
+        .context(error::APIRequest { method, uri })?;
+    trace!("JSON response: {}", response_body);
+
+    let settings: model::Settings =
+        serde_json::from_str(&response_body).context(error::ResponseJson { method, uri })?;
+    debug!("Update settings: {:?}", settings.updates);
+    Ok(settings.updates)
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Print a usage message in the event a bad argument is given.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Socket path defaults to {}",
+        program_name,
+        constants::API_SOCKET,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the arguments to the program and return a representative `Args`.
+fn parse_args(args: env::Args) -> Args {
+    let mut log_level = None;
+    let mut socket_path = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        socket_path: socket_path.unwrap_or_else(|| constants::API_SOCKET.to_string()),
+    }
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Error {}ing to {}: {}", method, uri, source))]
+        APIRequest {
+            method: String,
+            uri: String,
+            source: apiclient::Error,
+        },
+
+        #[snafu(display("Failed to apply update: {}", source))]
+        Apply { source: apiclient::update::Error },
+
+        #[snafu(display("Failed to check for updates: {}", source))]
+        Check { source: apiclient::update::Error },
+
+        #[snafu(display("Logger setup error: {}", source))]
+        Logger { source: log::SetLoggerError },
+
+        #[snafu(display("Failed to reboot into update: {}", source))]
+        Reboot { source: apiclient::reboot::Error },
+
+        #[snafu(display(
+            "Error deserializing response as JSON from {} to '{}': {}",
+            method,
+            uri,
+            source
+        ))]
+        ResponseJson {
+            method: &'static str,
+            uri: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Update status was not in the expected format: {}", source))]
+        UpdateStatusJson { source: serde_json::Error },
+    }
+}
+type Result<T> = std::result::Result<T, error::Error>;
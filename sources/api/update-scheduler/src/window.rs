@@ -0,0 +1,131 @@
+//! The window module decides whether a time falls in one of the host's maintenance windows.
+
+use chrono::{DateTime, Datelike, Duration, Utc};
+use model::MaintenanceWindow;
+
+/// Returns whether the given time is in any of the given maintenance windows.  If there are no
+/// windows, the host may update at any time.
+pub(crate) fn in_maintenance_window<'a, I>(windows: I, now: DateTime<Utc>) -> bool
+where
+    I: IntoIterator<Item = &'a MaintenanceWindow>,
+{
+    let mut windows = windows.into_iter().peekable();
+    if windows.peek().is_none() {
+        return true;
+    }
+    windows.any(|window| window_open(window, now))
+}
+
+/// Returns whether the given time is in the given maintenance window.  A window without a start
+/// or end is never open.  A window without days is open every day, and a window without a
+/// timezone uses UTC.
+pub(crate) fn window_open(window: &MaintenanceWindow, now: DateTime<Utc>) -> bool {
+    let (start, end) = match (&window.start, &window.end) {
+        (Some(start), Some(end)) => (start.time(), end.time()),
+        _ => return false,
+    };
+    let local = match &window.timezone {
+        Some(timezone) => now.with_timezone(&timezone.tz()).naive_local(),
+        None => now.naive_utc(),
+    };
+
+    // A window that opened yesterday can still be open if it ends the next day, so check the
+    // windows that opened on both days.
+    for days_ago in 0..2 {
+        let date = local.date() - Duration::days(days_ago);
+        if let Some(days) = &window.days {
+            if !days.contains(date.weekday()) {
+                continue;
+            }
+        }
+        let opens = date.and_time(start);
+        let closes = if end > start {
+            date.and_time(end)
+        } else {
+            (date + Duration::days(1)).and_time(end)
+        };
+        if opens <= local && local < closes {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+    use std::convert::TryInto;
+
+    fn window(days: &str, start: &str, end: &str, timezone: &str) -> MaintenanceWindow {
+        MaintenanceWindow {
+            days: Some(days.try_into().unwrap()),
+            start: Some(start.try_into().unwrap()),
+            end: Some(end.try_into().unwrap()),
+            timezone: Some(timezone.try_into().unwrap()),
+        }
+    }
+
+    // 2021-11-06 is a Saturday.
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 11, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn same_day() {
+        let weekend = window("sat,sun", "02:00", "04:00", "UTC");
+        assert!(!window_open(&weekend, utc(6, 1, 59)));
+        assert!(window_open(&weekend, utc(6, 2, 0)));
+        assert!(window_open(&weekend, utc(7, 3, 59)));
+        assert!(!window_open(&weekend, utc(7, 4, 0)));
+        // Monday
+        assert!(!window_open(&weekend, utc(8, 3, 0)));
+    }
+
+    #[test]
+    fn overnight() {
+        // Opens Friday night and closes Saturday morning.
+        let friday_night = window("fri", "22:00", "02:00", "UTC");
+        assert!(!window_open(&friday_night, utc(5, 21, 59)));
+        assert!(window_open(&friday_night, utc(5, 23, 0)));
+        assert!(window_open(&friday_night, utc(6, 1, 59)));
+        assert!(!window_open(&friday_night, utc(6, 2, 0)));
+        // It doesn't open on Saturday night.
+        assert!(!window_open(&friday_night, utc(6, 23, 0)));
+    }
+
+    #[test]
+    fn timezone() {
+        // New York is UTC-4 until November 7, 2021, when it changes to UTC-5.
+        let nightly = window("*", "01:00", "03:00", "America/New_York");
+        assert!(window_open(&nightly, utc(6, 5, 30)));
+        assert!(!window_open(&nightly, utc(6, 1, 30)));
+        assert!(window_open(&nightly, utc(9, 6, 30)));
+        assert!(!window_open(&nightly, utc(9, 5, 30)));
+    }
+
+    #[test]
+    fn defaults() {
+        let mut anyday = window("mon", "02:00", "04:00", "UTC");
+        anyday.days = None;
+        anyday.timezone = None;
+        assert!(window_open(&anyday, utc(6, 3, 0)));
+
+        let mut no_end = anyday;
+        no_end.end = None;
+        assert!(!window_open(&no_end, utc(6, 3, 0)));
+    }
+
+    #[test]
+    fn any_window() {
+        assert!(in_maintenance_window(&[], utc(6, 12, 0)));
+
+        let windows = vec![
+            window("sat", "02:00", "04:00", "UTC"),
+            window("sun", "12:00", "13:00", "UTC"),
+        ];
+        assert!(in_maintenance_window(&windows, utc(6, 3, 0)));
+        assert!(in_maintenance_window(&windows, utc(7, 12, 30)));
+        assert!(!in_maintenance_window(&windows, utc(7, 3, 0)));
+    }
+}
@@ -6,6 +6,28 @@
 pub const API_SOCKET: &str = "/run/api.sock";
 pub const API_SETTINGS_URI: &str = "/settings";
 pub const API_SETTINGS_GENERATORS_URI: &str = "/metadata/setting-generators";
+pub const API_SETTING_GENERATOR_POLICIES_URI: &str = "/metadata/setting-generator-policies";
+
+// Metadata that describes how setting generators are run, with the name under which each is
+// returned by the setting generator policies endpoint.
+pub const SETTING_GENERATOR_POLICY_METADATA: &[(&str, &str)] = &[
+    // A list of settings whose generators must finish before this one starts.
+    ("setting-generator-depends-on", "depends-on"),
+    // The number of seconds the generator may run before it's stopped.
+    ("setting-generator-timeout-seconds", "timeout-seconds"),
+    // The number of times the generator is run again if it fails.
+    ("setting-generator-retries", "retries"),
+];
+
+// Patterns for settings whose values shouldn't be shown in logs or records of changes.  These
+// follow the Unix shell style pattern outlined here:
+// https://docs.rs/glob/0.3.0/glob/struct.Pattern.html.
+pub const SENSITIVE_SETTINGS_PATTERNS: &[&str] = &[
+    "*.user-data",
+    "settings.kubernetes.bootstrap-token",
+    // Can contain a username:password component
+    "settings.network.https-proxy",
+];
 
 // Shared transaction used by boot time services
 pub const LAUNCH_TRANSACTION: &str = "bottlerocket-launch";
@@ -27,15 +27,6 @@ const COMMON_REQUESTS: &str = include_str!("../conf/logdog.common.conf");
 /// The `logdog` log requests that are specific to the current variant.
 const VARIANT_REQUESTS: &str = include_str!("../conf/current/logdog.conf");
 
-/// Patterns to filter from settings output.  These follow the Unix shell style pattern outlined
-/// here: https://docs.rs/glob/0.3.0/glob/struct.Pattern.html.
-const SENSITIVE_SETTINGS_PATTERNS: &[&str] = &[
-    "*.user-data",
-    "settings.kubernetes.bootstrap-token",
-    // Can contain a username:password component
-    "settings.network.https-proxy",
-];
-
 /// Returns the list of log requests to run by combining `VARIANT_REQUESTS` and `COMMON_REQUESTS`.
 /// These are read at compile time from files named `logdog.conf` and `logdog.common.conf`
 /// respectively.
@@ -157,7 +148,7 @@ where
     let mut settings_map = to_pairs(&settings).context(error::SerializeSettings)?;
 
     // Filter all settings that match any of the "sensitive" patterns
-    for pattern in SENSITIVE_SETTINGS_PATTERNS {
+    for pattern in constants::SENSITIVE_SETTINGS_PATTERNS {
         let pattern =
             Pattern::new(pattern).context(error::ParseGlobPattern { pattern: *pattern })?;
         settings_map.retain(|k, _| !pattern.matches(k.name().as_str()))
@@ -22,6 +22,15 @@ All structs must serde-`Serializable` and -`Deserializable`, and comparable via
 `Debug` is added for convenience.
 `Default` can also be added by specifying the argument `impl_default = true`.
 
+`JsonSchema` is added so the model can be described with a JSON Schema; see the `schema` module of the models crate.
+Field types must implement `schemars::JsonSchema`, which modeled types do.
+
+## Schema
+
+The JSON Schema for a `HashMap` only describes its values, so `HashMap` fields have a `#[schemars(...)]` attribute added that also describes the keys, using `crate::schema::MapSchema`.
+For example, this lets the schema check that Kubernetes label keys are valid.
+This is skipped for fields that already have a `schemars` attribute.
+
 ## Serde
 
 Structs have a `#[serde(...)]` attribute added to deny unknown fields and rename fields to kebab-case.
@@ -44,7 +53,8 @@ use proc_macro::TokenStream;
 use quote::ToTokens;
 use syn::visit_mut::{self, VisitMut};
 use syn::{
-    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, ItemStruct, Visibility,
+    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, GenericArgument, ItemStruct,
+    PathArguments, Type, Visibility,
 };
 
 /// Define a `#[model]` attribute that can be placed on structs to be used in an API model.
@@ -126,9 +136,9 @@ impl VisitMut for ModelHelper {
         if !is_attr_set("derive", &node.attrs) {
             // Derive Default, if the user requested
             let attr = if self.impl_default {
-                parse_quote!(#[derive(Debug, Default, PartialEq, Serialize, Deserialize)])
+                parse_quote!(#[derive(Debug, Default, PartialEq, Serialize, Deserialize, schemars::JsonSchema)])
             } else {
-                parse_quote!(#[derive(Debug, PartialEq, Serialize, Deserialize)])
+                parse_quote!(#[derive(Debug, PartialEq, Serialize, Deserialize, schemars::JsonSchema)])
             };
             // Rust 1.52 added a legacy_derive_helpers warning (soon to be an error) that yells if
             // you use an attribute macro before the derive macro that introduces it.  We should
@@ -149,6 +159,19 @@ impl VisitMut for ModelHelper {
             _ => {}
         }
 
+        // Describe map keys in the schema, if the user hasn't set a schema attribute
+        if !is_attr_set("schemars", &node.attrs) {
+            if let Some((key, value)) = map_types(&node.ty) {
+                let schema_type = if self.add_option {
+                    format!("Option<crate::schema::MapSchema<{}, {}>>", key, value)
+                } else {
+                    format!("crate::schema::MapSchema<{}, {}>", key, value)
+                };
+                node.attrs
+                    .push(parse_quote!(#[schemars(with = #schema_type)]));
+            }
+        }
+
         // Add our serde attribute, if the user hasn't set one
         if self.add_option {
             if !is_attr_set("serde", &node.attrs) {
@@ -167,6 +190,27 @@ impl VisitMut for ModelHelper {
     }
 }
 
+/// If the given type is a HashMap, returns its key and value types, as strings.
+fn map_types(ty: &Type) -> Option<(String, String)> {
+    let path = match ty {
+        Type::Path(type_path) => &type_path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != "HashMap" {
+        return None;
+    }
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => &args.args,
+        _ => return None,
+    };
+    let mut types = args.iter().filter_map(|arg| match arg {
+        GenericArgument::Type(ty) => Some(ty.to_token_stream().to_string()),
+        _ => None,
+    });
+    Some((types.next()?, types.next()?))
+}
+
 /// Checks whether an attribute named `attr_name` (e.g. "serde") is set in the given list of
 /// `syn::Attribute`s.
 fn is_attr_set(attr_name: &'static str, attrs: &[Attribute]) -> bool {
@@ -19,6 +19,12 @@ Entries are sorted by filename, and later entries take precedence.
 
 The `#[model]` attribute on Settings and its sub-structs reduces duplication and adds some required metadata; see [its docs](model-derive/) for details.
 
+## JSON Schema
+
+The `schema` module generates a [JSON Schema](https://json-schema.org/) for the variant's `Settings`, so the schema always matches the model.
+The checks made by modeled types are included as patterns, lengths, and allowed values, so most invalid settings can be found using the schema alone.
+The API server serves the schema at `/schema`, and `apiclient set --validate` uses it to check settings without making changes, for example in CI.
+
 ## aws-k8s-1.18: Kubernetes 1.18
 
 * [Model](src/aws-k8s-1.21/mod.rs)
@@ -99,6 +105,9 @@ pub use variant::*;
 // Types used to communicate between client and server for 'apiclient exec'.
 pub mod exec;
 
+// The JSON Schema for Settings, and a validator that checks settings against it.
+pub mod schema;
+
 // Below, we define common structures used in the API surface; specific variants build a Settings
 // structure based on these, and that's what gets exposed via the API.  (Specific variants' models
 // are in subdirectories and linked into place by build.rs at variant/current.)
@@ -110,13 +119,15 @@ use std::net::IpAddr;
 
 use crate::de::deserialize_mirrors;
 use crate::modeled_types::{
-    BootstrapContainerMode, CpuManagerPolicy, DNSDomain, ECSAgentLogLevel, ECSAttributeKey,
-    ECSAttributeValue, FriendlyVersion, Identifier, KubernetesAuthenticationMode,
-    KubernetesBootstrapToken, KubernetesCloudProvider, KubernetesClusterName,
-    KubernetesDurationValue, KubernetesEvictionHardKey, KubernetesLabelKey, KubernetesLabelValue,
-    KubernetesQuantityValue, KubernetesReservedResourceKey, KubernetesTaintValue,
-    KubernetesThresholdValue, Lockdown, PemCertificateString, SingleLineString, SysctlKey,
-    TopologyManagerPolicy, TopologyManagerScope, Url, ValidBase64, ValidLinuxHostname,
+    BootstrapContainerMode, CpuManagerPolicy, CustomFileAction, CustomFileMode, CustomFilePath,
+    DNSDomain, ECSAgentLogLevel, ECSAttributeKey, ECSAttributeValue, FileMode, FriendlyVersion,
+    Identifier, KubernetesAuthenticationMode, KubernetesBootstrapToken, KubernetesCloudProvider,
+    KubernetesClusterName, KubernetesDurationValue, KubernetesEvictionHardKey, KubernetesLabelKey,
+    KubernetesLabelValue, KubernetesQuantityValue, KubernetesReservedResourceKey,
+    KubernetesTaintValue, KubernetesThresholdValue, Lockdown, MaintenanceDays,
+    PemCertificateString, SemanticVersion, ServiceAction, SingleLineString, SysctlKey, TimeOfDay,
+    Timezone, TopologyManagerPolicy, TopologyManagerScope, UpdatePolicy, Url, ValidBase64,
+    ValidLinuxHostname, VersionRequirement,
 };
 
 // Kubernetes static pod manifest settings
@@ -191,6 +202,7 @@ struct RegistryMirror {
 #[model]
 struct RegistrySettings {
     #[serde(deserialize_with = "deserialize_mirrors")]
+    #[schemars(with = "Option<crate::schema::MirrorsSchema>")]
     mirrors: Vec<RegistryMirror>,
 }
 
@@ -204,6 +216,26 @@ struct UpdatesSettings {
     // Version to update to when updating via the API.
     version_lock: FriendlyVersion,
     ignore_waves: bool,
+    // Versions that are never updated to, and the range of versions that may be updated to.
+    denied_versions: Vec<SemanticVersion>,
+    allowed_versions: VersionRequirement,
+    // Whether the host is updated through the API, updates itself during its maintenance
+    // windows, or isn't updated at all.
+    policy: UpdatePolicy,
+    maintenance_windows: HashMap<Identifier, MaintenanceWindow>,
+    // Maximum rate at which update images are downloaded, in KiB per second.
+    download_rate_limit: u32,
+}
+
+// A time range on some days of the week during which the host may update itself and reboot, if
+// the update policy is "automatic".  If the end isn't after the start, the window ends on the
+// following day.
+#[model]
+struct MaintenanceWindow {
+    days: MaintenanceDays,
+    start: TimeOfDay,
+    end: TimeOfDay,
+    timezone: Timezone,
 }
 
 #[model]
@@ -214,6 +246,23 @@ struct HostContainer {
     user_data: ValidBase64,
 }
 
+// Custom configuration files are rendered and written like the built-in ones, and their restart
+// actions are run when the file or any of the settings it's affected by changes.  Their actions
+// can only act on the units custom files are allowed to, and their mode can't make them setuid
+// programs.
+#[model]
+struct CustomFile {
+    path: CustomFilePath,
+    template: ValidBase64,
+    mode: CustomFileMode,
+    owner: SingleLineString,
+    group: SingleLineString,
+    restart_actions: Vec<CustomFileRestartAction>,
+    // Names of settings, like "settings.custom.fleet"; changes to them or anything below them
+    // affect the file.
+    affected_by: Vec<SingleLineString>,
+}
+
 // Network settings. These settings will affect host service components' network behavior
 #[model]
 struct NetworkSettings {
@@ -265,7 +314,27 @@ pub type Services = HashMap<String, Service>;
 #[model(add_option = false, rename = "")]
 struct Service {
     configuration_files: Vec<SingleLineString>,
+    // Each command is split on spaces and run directly; restart-actions are preferred.
+    #[serde(default)]
     restart_commands: Vec<String>,
+    // Run in order, after any restart-commands.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    restart_actions: Vec<RestartAction>,
+}
+
+#[model(add_option = false, rename = "")]
+struct RestartAction {
+    action: ServiceAction,
+    // If the action doesn't finish in time, it's stopped and counted as a failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_seconds: Option<u64>,
+}
+
+#[model(add_option = false, rename = "")]
+struct CustomFileRestartAction {
+    action: CustomFileAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_seconds: Option<u64>,
 }
 
 pub type ConfigurationFiles = HashMap<String, ConfigurationFile>;
@@ -273,12 +342,31 @@ pub type ConfigurationFiles = HashMap<String, ConfigurationFile>;
 #[model(add_option = false, rename = "")]
 struct ConfigurationFile {
     path: SingleLineString,
-    template_path: SingleLineString,
+    // Built-in files have a template on disk; custom files have their template inline, in
+    // base64.  One or the other is required.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template_path: Option<SingleLineString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template: Option<ValidBase64>,
+    // If not given, the file gets the default mode and is owned by the user and group of
+    // thar-be-settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<FileMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<SingleLineString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<SingleLineString>,
+    // The name of a setting, e.g. "settings.ecs.enabled".  The file is only written if the
+    // setting is truthy, like in a template's `{{#if}}`; otherwise, it's removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    render_if: Option<SingleLineString>,
 }
 
 ///// Metadata
 
+// Metadata values can be any TOML value, which has no schema, and metadata isn't part of Settings.
 #[model(add_option = false, rename = "metadata")]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct Metadata {
     key: SingleLineString,
     md: SingleLineString,
@@ -293,6 +381,11 @@ struct BootstrapContainer {
     mode: BootstrapContainerMode,
     user_data: ValidBase64,
     essential: bool,
+    // The number of times the container is run again if it fails, the number of seconds to wait
+    // before each retry, and the number of seconds each run may take before it's stopped.
+    retries: u32,
+    retry_delay: u64,
+    timeout: u64,
 }
 
 ///// PEM Certificates
@@ -49,6 +49,12 @@ impl TryFrom<&str> for ECSAttributeKey {
 
 string_impls_for!(ECSAttributeKey, "ECSAttributeKey");
 
+json_schema_for!(
+    ECSAttributeKey,
+    description: "An ECS attribute key",
+    pattern: Some(r"^[a-zA-Z0-9._/-]{1,128}$"),
+);
+
 #[cfg(test)]
 mod test_ecs_attribute_key {
     use super::ECSAttributeKey;
@@ -134,6 +140,12 @@ impl TryFrom<&str> for ECSAttributeValue {
 
 string_impls_for!(ECSAttributeValue, "ECSAttributeValue");
 
+json_schema_for!(
+    ECSAttributeValue,
+    description: "An ECS attribute value, without leading or trailing spaces",
+    pattern: Some(r"^[a-zA-Z0-9.@:_/\\-]([a-zA-Z0-9.@: _/\\-]{0,126}[a-zA-Z0-9.@:_/\\-])?$"),
+);
+
 #[cfg(test)]
 mod test_ecs_attribute_value {
     use super::ECSAttributeValue;
@@ -196,6 +208,12 @@ enum ECSLogLevel {
 
 string_impls_for!(ECSAgentLogLevel, "ECSAgentLogLevel");
 
+json_schema_for!(
+    ECSAgentLogLevel,
+    description: "An ECS agent log level",
+    values: &["debug", "info", "warn", "error", "crit"],
+);
+
 impl TryFrom<&str> for ECSAgentLogLevel {
     type Error = error::Error;
 
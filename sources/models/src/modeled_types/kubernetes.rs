@@ -42,6 +42,12 @@ impl TryFrom<&str> for KubernetesName {
 
 string_impls_for!(KubernetesName, "KubernetesName");
 
+json_schema_for!(
+    KubernetesName,
+    description: "A Kubernetes resource name",
+    pattern: Some(KUBERNETES_NAME.as_str()),
+);
+
 #[cfg(test)]
 mod test_kubernetes_name {
     use super::KubernetesName;
@@ -107,6 +113,14 @@ impl TryFrom<&str> for KubernetesLabelKey {
 
 string_impls_for!(KubernetesLabelKey, "KubernetesLabelKey");
 
+json_schema_for!(
+    KubernetesLabelKey,
+    description: "A Kubernetes label key, with an optional DNS prefix",
+    pattern: Some(
+        r"^([A-Za-z0-9.-]{1,253}/)?[A-Za-z0-9]([A-Za-z0-9._-]{0,61}[A-Za-z0-9])?$"
+    ),
+);
+
 #[cfg(test)]
 mod test_kubernetes_label_key {
     use super::KubernetesLabelKey;
@@ -183,6 +197,12 @@ impl TryFrom<&str> for KubernetesLabelValue {
 
 string_impls_for!(KubernetesLabelValue, "KubernetesLabelValue");
 
+json_schema_for!(
+    KubernetesLabelValue,
+    description: "A Kubernetes label value",
+    pattern: Some(r"^([A-Za-z0-9]([A-Za-z0-9._-]{0,61}[A-Za-z0-9])?)?$"),
+);
+
 #[cfg(test)]
 mod test_kubernetes_label_value {
     use super::KubernetesLabelValue;
@@ -256,6 +276,14 @@ impl TryFrom<&str> for KubernetesTaintValue {
 
 string_impls_for!(KubernetesTaintValue, "KubernetesTaintValue");
 
+json_schema_for!(
+    KubernetesTaintValue,
+    description: "A Kubernetes taint value and effect, like 'value:NoSchedule'",
+    pattern: Some(
+        r"^([A-Za-z0-9]([A-Za-z0-9._-]{0,61}[A-Za-z0-9])?)?:[A-Za-z0-9]{1,253}$"
+    ),
+);
+
 #[cfg(test)]
 mod test_kubernetes_taint_value {
     use super::KubernetesTaintValue;
@@ -331,6 +359,12 @@ impl TryFrom<&str> for KubernetesClusterName {
 
 string_impls_for!(KubernetesClusterName, "KubernetesClusterName");
 
+json_schema_for!(
+    KubernetesClusterName,
+    description: "A Kubernetes cluster name, which must be a valid label value",
+    pattern: Some(r"^[A-Za-z0-9]([A-Za-z0-9._-]{0,61}[A-Za-z0-9])?$"),
+);
+
 #[cfg(test)]
 mod test_kubernetes_cluster_name {
     use super::KubernetesClusterName;
@@ -376,6 +410,12 @@ impl TryFrom<&str> for KubernetesAuthenticationMode {
 
 string_impls_for!(KubernetesAuthenticationMode, "KubernetesAuthenticationMode");
 
+json_schema_for!(
+    KubernetesAuthenticationMode,
+    description: "A kubelet authentication mode",
+    values: &["aws", "tls"],
+);
+
 #[cfg(test)]
 mod test_kubernetes_authentication_mode {
     use super::KubernetesAuthenticationMode;
@@ -431,6 +471,12 @@ impl TryFrom<&str> for KubernetesBootstrapToken {
 
 string_impls_for!(KubernetesBootstrapToken, "KubernetesBootstrapToken");
 
+json_schema_for!(
+    KubernetesBootstrapToken,
+    description: "A Kubernetes bootstrap token",
+    pattern: Some(KUBERNETES_BOOTSTRAP_TOKEN.as_str()),
+);
+
 #[cfg(test)]
 mod test_kubernetes_bootstrap_token {
     use super::KubernetesBootstrapToken;
@@ -492,6 +538,19 @@ impl TryFrom<&str> for KubernetesEvictionHardKey {
 }
 string_impls_for!(KubernetesEvictionHardKey, "KubernetesEvictionHardKey");
 
+json_schema_for!(
+    KubernetesEvictionHardKey,
+    description: "A Kubernetes eviction signal",
+    values: &[
+        "memory.available",
+        "nodefs.available",
+        "nodefs.inodesFree",
+        "imagefs.available",
+        "imagefs.inodesFree",
+        "pid.available",
+    ],
+);
+
 #[cfg(test)]
 mod test_kubernetes_eviction_hard_key {
     use super::KubernetesEvictionHardKey;
@@ -544,6 +603,12 @@ lazy_static! {
     .unwrap();
 }
 
+// The quantity formats above combined into one pattern for the JSON Schema, which can't use the
+// extended syntax.
+const QUANTITY_PATTERN: &str = r"^[+-]?[0-9.]+(e?[0-9]*|[EPTGMK]i?|[numk])$";
+const THRESHOLD_PATTERN: &str =
+    r"^([+-]?[0-9.]+(e?[0-9]*|[EPTGMK]i?|[numk])|[+-]?[0-9.]+([eE][+-]?[0-9]+)?%)$";
+
 impl TryFrom<&str> for KubernetesThresholdValue {
     type Error = error::Error;
 
@@ -574,6 +639,12 @@ impl TryFrom<&str> for KubernetesThresholdValue {
 }
 string_impls_for!(KubernetesThresholdValue, "KubernetesThresholdValue");
 
+json_schema_for!(
+    KubernetesThresholdValue,
+    description: "A Kubernetes quantity, or a percentage less than 100",
+    pattern: Some(THRESHOLD_PATTERN),
+);
+
 #[cfg(test)]
 mod test_kubernetes_threshold_value {
     use super::KubernetesThresholdValue;
@@ -642,6 +713,12 @@ string_impls_for!(
     "KubernetesReservedResourceKey"
 );
 
+json_schema_for!(
+    KubernetesReservedResourceKey,
+    description: "A Kubernetes resource that can be reserved",
+    values: &["cpu", "memory", "ephemeral-storage"],
+);
+
 #[cfg(test)]
 mod test_reserved_resources_key {
     use super::KubernetesReservedResourceKey;
@@ -692,6 +769,12 @@ impl TryFrom<&str> for KubernetesQuantityValue {
 }
 string_impls_for!(KubernetesQuantityValue, "KubernetesQuantityValue");
 
+json_schema_for!(
+    KubernetesQuantityValue,
+    description: "A Kubernetes quantity",
+    pattern: Some(QUANTITY_PATTERN),
+);
+
 #[cfg(test)]
 mod test_kubernetes_quantity_value {
     use super::KubernetesQuantityValue;
@@ -749,6 +832,12 @@ impl TryFrom<&str> for KubernetesCloudProvider {
 
 string_impls_for!(KubernetesCloudProvider, "KubernetesCloudProvider");
 
+json_schema_for!(
+    KubernetesCloudProvider,
+    description: "A kubelet cloud provider",
+    values: &["aws", "external"],
+);
+
 #[cfg(test)]
 mod test_kubernetes_cloud_provider {
     use super::KubernetesCloudProvider;
@@ -798,6 +887,12 @@ impl TryFrom<&str> for CpuManagerPolicy {
 }
 string_impls_for!(CpuManagerPolicy, "CpuManagerPolicy");
 
+json_schema_for!(
+    CpuManagerPolicy,
+    description: "A kubelet CPU manager policy",
+    values: &["static", "none"],
+);
+
 #[cfg(test)]
 mod test_cpu_manager_policy {
     use super::CpuManagerPolicy;
@@ -853,6 +948,13 @@ impl TryFrom<&str> for KubernetesDurationValue {
 
 string_impls_for!(KubernetesDurationValue, "KubernetesDurationValue");
 
+json_schema_for!(
+    KubernetesDurationValue,
+    description: "A Kubernetes duration, like '1h30m'",
+    pattern: Some(KUBERNETES_DURATION_VALUE.as_str()),
+    min_length: Some(1),
+);
+
 #[cfg(test)]
 mod test_kubernetes_duration_value {
     use super::KubernetesDurationValue;
@@ -920,6 +1022,12 @@ impl TryFrom<&str> for TopologyManagerScope {
 }
 string_impls_for!(TopologyManagerScope, "TopologyManagerScope");
 
+json_schema_for!(
+    TopologyManagerScope,
+    description: "A kubelet topology manager scope",
+    values: &["container", "pod"],
+);
+
 #[cfg(test)]
 mod test_topology_manager_scope {
     use super::TopologyManagerScope;
@@ -973,6 +1081,12 @@ impl TryFrom<&str> for TopologyManagerPolicy {
 }
 string_impls_for!(TopologyManagerPolicy, "TopologyManagerPolicy");
 
+json_schema_for!(
+    TopologyManagerPolicy,
+    description: "A kubelet topology manager policy",
+    values: &["none", "restricted", "best-effort", "single-numa-node"],
+);
+
 #[cfg(test)]
 mod test_topology_manager_policy {
     use super::TopologyManagerPolicy;
@@ -4,7 +4,10 @@
 
 // The pattern in this module is to make a struct and implement TryFrom<&str> with code that does
 // necessary checks and returns the struct.  Other traits that treat the struct like a string can
-// be implemented for you with the string_impls_for macro.
+// be implemented for you with the string_impls_for macro.  The checks should also be described
+// for the JSON Schema of the model with the json_schema_for macro.
+
+use schemars::schema::{InstanceType, Metadata, Schema, SchemaObject, StringValidation};
 
 pub mod error {
     use regex::Regex;
@@ -35,6 +38,12 @@ pub mod error {
         #[snafu(display("Invalid version string '{}'", input))]
         InvalidVersion { input: String },
 
+        #[snafu(display("Invalid version requirement '{}': {}", input, source))]
+        InvalidVersionRequirement {
+            input: String,
+            source: semver::Error,
+        },
+
         #[snafu(display("{} must match '{}', given: {}", thing, pattern, input))]
         Pattern {
             thing: String,
@@ -113,6 +122,38 @@ pub mod error {
             input: String,
             source: serde_plain::Error,
         },
+
+        #[snafu(display("Invalid file mode '{}', expected 3 or 4 octal digits", input))]
+        InvalidFileMode { input: String },
+
+        #[snafu(display("Invalid restart action '{}': {}", input, msg))]
+        InvalidRestartAction { input: String, msg: String },
+
+        #[snafu(display("Invalid custom file path '{}', expected a file under {}", input, dir))]
+        InvalidCustomFilePath { input: String, dir: String },
+
+        #[snafu(display(
+            "Invalid custom file mode '{}', expected permission bits in octal, like \"0640\", \
+             without setuid, setgid, or sticky bits",
+            input
+        ))]
+        InvalidCustomFileMode { input: String },
+
+        #[snafu(display("Invalid custom file restart action '{}': {}", input, msg))]
+        InvalidCustomFileAction { input: String, msg: String },
+
+        #[snafu(display(
+            "Invalid days '{}', expected '*' or a comma-separated list of days like 'mon' and \
+             ranges of days like 'mon-fri'",
+            input
+        ))]
+        InvalidMaintenanceDays { input: String },
+
+        #[snafu(display(
+            "Invalid timezone '{}', expected a name like 'America/New_York'",
+            input
+        ))]
+        InvalidTimezone { input: String },
     }
 }
 
@@ -209,6 +250,66 @@ macro_rules! string_impls_for {
     };
 }
 
+/// Describes the strings a modeled type accepts, for the JSON Schema of the model.  Fields that
+/// aren't set aren't checked.  A schema can't express every check a modeled type makes, so the
+/// schema should accept everything the type accepts, and the description should mention the
+/// checks the schema doesn't make.
+#[derive(Debug, Default)]
+pub(crate) struct StringSchema {
+    pub(crate) description: &'static str,
+    /// A regular expression the string must match.  It's used by JSON Schema validators in other
+    /// languages, so it should only use syntax that's common to ECMA 262 and the regex crate.
+    pub(crate) pattern: Option<&'static str>,
+    /// If not empty, the only values allowed.
+    pub(crate) values: &'static [&'static str],
+    pub(crate) min_length: Option<u32>,
+    pub(crate) max_length: Option<u32>,
+}
+
+impl From<StringSchema> for Schema {
+    fn from(string_schema: StringSchema) -> Self {
+        let enum_values = if string_schema.values.is_empty() {
+            None
+        } else {
+            Some(string_schema.values.iter().map(|v| (*v).into()).collect())
+        };
+        Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            metadata: Some(Box::new(Metadata {
+                description: Some(string_schema.description.to_string()),
+                ..Default::default()
+            })),
+            string: Some(Box::new(StringValidation {
+                pattern: string_schema.pattern.map(str::to_string),
+                min_length: string_schema.min_length,
+                max_length: string_schema.max_length,
+            })),
+            enum_values,
+            ..Default::default()
+        })
+    }
+}
+
+/// Helper macro for implementing JsonSchema for a modeled type.  Pass the name of the type, and the
+/// StringSchema fields describing the strings it accepts.
+macro_rules! json_schema_for {
+    ($for:ident, $($field:ident: $value:expr),* $(,)?) => {
+        impl schemars::JsonSchema for $for {
+            fn schema_name() -> String {
+                stringify!($for).to_string()
+            }
+
+            fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+                $crate::modeled_types::StringSchema {
+                    $($field: $value,)*
+                    ..Default::default()
+                }
+                .into()
+            }
+        }
+    };
+}
+
 // Must be after macro definition
 mod ecs;
 mod kubernetes;
@@ -3,9 +3,11 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 // Just need serde's Error in scope to get its trait methods
 use super::error;
-use semver::Version;
+use chrono::{NaiveTime, Weekday};
+use chrono_tz::Tz;
+use semver::{Version, VersionReq};
 use serde::de::Error as _;
-use snafu::{ensure, ResultExt};
+use snafu::{ensure, OptionExt, ResultExt};
 use std::borrow::Borrow;
 use std::convert::TryFrom;
 use std::fmt;
@@ -38,6 +40,12 @@ impl TryFrom<&str> for ValidBase64 {
 
 string_impls_for!(ValidBase64, "ValidBase64");
 
+json_schema_for!(
+    ValidBase64,
+    description: "Base64-encoded data",
+    pattern: Some(r"^[A-Za-z0-9+/]*={0,2}$"),
+);
+
 #[cfg(test)]
 mod test_valid_base64 {
     use super::ValidBase64;
@@ -98,6 +106,12 @@ impl TryFrom<&str> for SingleLineString {
 
 string_impls_for!(SingleLineString, "SingleLineString");
 
+json_schema_for!(
+    SingleLineString,
+    description: "A string without line terminators",
+    pattern: Some(r"^[^\n\r\u000B\u000C\u0085\u2028\u2029]*$"),
+);
+
 #[cfg(test)]
 mod test_single_line_string {
     use super::SingleLineString;
@@ -187,6 +201,14 @@ impl TryFrom<&str> for ValidLinuxHostname {
 
 string_impls_for!(ValidLinuxHostname, "ValidLinuxHostname");
 
+json_schema_for!(
+    ValidLinuxHostname,
+    description: "A Linux hostname: dot-separated segments of [0-9a-z-], each 1-63 characters \
+                  and not starting with '-'",
+    pattern: Some(r"^[0-9a-z][0-9a-z-]{0,62}(\.[0-9a-z][0-9a-z-]{0,62})*$"),
+    max_length: Some(253),
+);
+
 #[cfg(test)]
 mod test_valid_linux_hostname {
     use super::ValidLinuxHostname;
@@ -259,6 +281,13 @@ impl TryFrom<&str> for Identifier {
 
 string_impls_for!(Identifier, "Identifier");
 
+json_schema_for!(
+    Identifier,
+    description: "An identifier of ASCII alphanumerics and hyphens",
+    pattern: Some(r"^[A-Za-z0-9-]*$"),
+    max_length: Some(CONTAINERD_ID_LENGTH as u32),
+);
+
 #[cfg(test)]
 mod test_valid_identifier {
     use super::{Identifier, CONTAINERD_ID_LENGTH};
@@ -323,6 +352,11 @@ impl TryFrom<&str> for Url {
 
 string_impls_for!(Url, "Url");
 
+json_schema_for!(
+    Url,
+    description: "A URL; the scheme may be omitted, in which case http is assumed",
+);
+
 #[cfg(test)]
 mod test_url {
     use super::Url;
@@ -410,6 +444,14 @@ impl TryFrom<FriendlyVersion> for semver::Version {
 
 string_impls_for!(FriendlyVersion, "FriendlyVersion");
 
+json_schema_for!(
+    FriendlyVersion,
+    description: "A semantic version, optionally prefixed with 'v', or 'latest'",
+    pattern: Some(
+        r"^(latest|v?[0-9]+\.[0-9]+\.[0-9]+(-[0-9A-Za-z.-]+)?(\+[0-9A-Za-z.-]+)?)$"
+    ),
+);
+
 #[cfg(test)]
 mod test_version {
     use super::FriendlyVersion;
@@ -466,6 +508,142 @@ mod test_version {
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
+/// SemanticVersion represents a string that contains a specific semantic version, optionally
+/// prefixed with 'v', like "1.4.2" or "v1.4.2".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct SemanticVersion {
+    inner: String,
+    version: Version,
+}
+
+impl TryFrom<&str> for SemanticVersion {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        // If the string begins with a 'v', skip it before checking if it is valid semver.
+        let version = input
+            .strip_prefix('v')
+            .unwrap_or(input)
+            .parse()
+            .ok()
+            .context(error::InvalidVersion { input })?;
+        Ok(SemanticVersion {
+            inner: input.to_string(),
+            version,
+        })
+    }
+}
+
+impl SemanticVersion {
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+}
+
+string_impls_for!(SemanticVersion, "SemanticVersion");
+
+json_schema_for!(
+    SemanticVersion,
+    description: "A semantic version, optionally prefixed with 'v'",
+    pattern: Some(r"^v?[0-9]+\.[0-9]+\.[0-9]+(-[0-9A-Za-z.-]+)?(\+[0-9A-Za-z.-]+)?$"),
+);
+
+#[cfg(test)]
+mod test_semantic_version {
+    use super::SemanticVersion;
+    use semver::Version;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_vals() {
+        for (val, version) in &[
+            ("1.4.2", "1.4.2"),
+            ("v1.4.2", "1.4.2"),
+            ("v1.5.0-rc.1", "1.5.0-rc.1"),
+        ] {
+            assert_eq!(
+                SemanticVersion::try_from(*val).unwrap().version(),
+                &Version::parse(version).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &["", "latest", "1.4", "v", "vv1.4.2", ">=1.4.2", " 1.4.2"] {
+            SemanticVersion::try_from(*val).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// VersionRequirement represents a string that contains a semantic version requirement, like
+/// ">=1.4, <1.6", as understood by Cargo.  A version without an operator, like "1.4", allows
+/// compatible versions, like "^1.4" would.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct VersionRequirement {
+    inner: String,
+    requirement: VersionReq,
+}
+
+impl TryFrom<&str> for VersionRequirement {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let requirement = input
+            .parse()
+            .context(error::InvalidVersionRequirement { input })?;
+        Ok(VersionRequirement {
+            inner: input.to_string(),
+            requirement,
+        })
+    }
+}
+
+impl VersionRequirement {
+    /// Returns whether the given version meets the requirement.
+    pub fn matches(&self, version: &Version) -> bool {
+        self.requirement.matches(version)
+    }
+}
+
+string_impls_for!(VersionRequirement, "VersionRequirement");
+
+json_schema_for!(
+    VersionRequirement,
+    description: "A semantic version requirement, like '>=1.4, <1.6'",
+);
+
+#[cfg(test)]
+mod test_version_requirement {
+    use super::VersionRequirement;
+    use semver::Version;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_vals() {
+        let range = VersionRequirement::try_from(">=1.4, <1.6").unwrap();
+        assert!(range.matches(&Version::parse("1.4.0").unwrap()));
+        assert!(range.matches(&Version::parse("1.5.3").unwrap()));
+        assert!(!range.matches(&Version::parse("1.3.9").unwrap()));
+        assert!(!range.matches(&Version::parse("1.6.0").unwrap()));
+
+        for val in &["1.4", "=1.4.2", "~1.4", "*", "<2"] {
+            VersionRequirement::try_from(*val).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &["", "latest", ">=1.4 <1.6", "=>1.4", "1.4.x.y"] {
+            VersionRequirement::try_from(*val).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
 /// DNSDomain represents a string that is a valid DNS domain. It stores the
 /// original string and makes it accessible through standard traits. Its purpose
 /// is input validation, for example validating the kubelet's "clusterDomain"
@@ -509,6 +687,11 @@ impl TryFrom<&str> for DNSDomain {
 
 string_impls_for!(DNSDomain, "DNSDomain");
 
+json_schema_for!(
+    DNSDomain,
+    description: "A DNS domain name, not an IP address, and not starting with '.'",
+);
+
 #[cfg(test)]
 mod test_dns_domain {
     use super::DNSDomain;
@@ -584,6 +767,12 @@ impl TryFrom<&str> for SysctlKey {
 
 string_impls_for!(SysctlKey, "SysctlKey");
 
+json_schema_for!(
+    SysctlKey,
+    description: "A sysctl key, not containing '..'",
+    pattern: Some(r"^[a-zA-Z0-9_-][a-zA-Z0-9./_-]{0,127}$"),
+);
+
 #[cfg(test)]
 mod test_sysctl_key {
     use super::SysctlKey;
@@ -670,6 +859,12 @@ impl TryFrom<&str> for Lockdown {
 
 string_impls_for!(Lockdown, "Lockdown");
 
+json_schema_for!(
+    Lockdown,
+    description: "A Linux kernel lockdown mode",
+    values: &["none", "integrity", "confidentiality"],
+);
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -701,6 +896,12 @@ impl Default for BootstrapContainerMode {
 
 string_impls_for!(BootstrapContainerMode, "BootstrapContainerMode");
 
+json_schema_for!(
+    BootstrapContainerMode,
+    description: "When to run a bootstrap container",
+    values: &["off", "once", "always"],
+);
+
 #[cfg(test)]
 mod test_valid_container_mode {
     use super::BootstrapContainerMode;
@@ -767,6 +968,12 @@ impl Default for PemCertificateString {
 
 string_impls_for!(PemCertificateString, "PemCertificateString");
 
+json_schema_for!(
+    PemCertificateString,
+    description: "A base64-encoded bundle of PEM certificates, or empty to remove the bundle",
+    pattern: Some(r"^(\s*|[A-Za-z0-9+/]*={0,2})$"),
+);
+
 #[cfg(test)]
 mod test_valid_pem_certificate_string {
     use super::PemCertificateString;
@@ -798,3 +1005,942 @@ mod test_valid_pem_certificate_string {
         .is_err())
     }
 }
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// FileMode represents a string that is a valid file mode in octal, like "0600" or "644".  It
+/// stores the original string and makes it accessible through standard traits.  The mode's bits
+/// are available through `bits`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct FileMode {
+    inner: String,
+}
+
+impl TryFrom<&str> for FileMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        ensure!(
+            (3..=4).contains(&input.len()) && input.chars().all(|c| matches!(c, '0'..='7')),
+            error::InvalidFileMode { input }
+        );
+        Ok(FileMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+impl FileMode {
+    /// Returns the mode's permission bits.
+    pub fn bits(&self) -> u32 {
+        // We checked that it's 3 or 4 octal digits, so it fits.
+        u32::from_str_radix(&self.inner, 8).expect("file mode is octal")
+    }
+}
+
+string_impls_for!(FileMode, "FileMode");
+
+json_schema_for!(
+    FileMode,
+    description: "A file mode in octal, like \"0600\"",
+    pattern: Some(r"^[0-7]{3,4}$"),
+);
+
+#[cfg(test)]
+mod test_file_mode {
+    use super::FileMode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_file_mode() {
+        for (ok, bits) in &[
+            ("0600", 0o600),
+            ("644", 0o644),
+            ("4755", 0o4755),
+            ("000", 0),
+        ] {
+            assert_eq!(FileMode::try_from(*ok).unwrap().bits(), *bits);
+        }
+    }
+
+    #[test]
+    fn invalid_file_mode() {
+        for err in &["", "60", "06444", "0800", "rw-", "-644", "0x1a"] {
+            FileMode::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// CustomFileMode represents a string that is a valid mode for a custom configuration file in
+/// octal, like "0640".  Unlike FileMode, it can't have the setuid, setgid, or sticky bits, so a
+/// custom file can't be a setuid program.  It stores the original string and makes it accessible
+/// through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CustomFileMode {
+    inner: String,
+}
+
+impl TryFrom<&str> for CustomFileMode {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        let bits = FileMode::try_from(input)
+            .ok()
+            .map(|mode| mode.bits())
+            .unwrap_or(u32::MAX);
+        ensure!(bits <= 0o777, error::InvalidCustomFileMode { input });
+        Ok(CustomFileMode {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(CustomFileMode, "CustomFileMode");
+
+json_schema_for!(
+    CustomFileMode,
+    description: "A custom file's mode in octal, like \"0640\", without setuid, setgid, or sticky bits",
+    pattern: Some(r"^0?[0-7]{3}$"),
+);
+
+#[cfg(test)]
+mod test_custom_file_mode {
+    use super::CustomFileMode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_custom_file_mode() {
+        for ok in &["0640", "644", "0777", "000"] {
+            CustomFileMode::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_custom_file_mode() {
+        for err in &["", "4755", "2755", "1777", "7777", "0800", "06444", "rw-"] {
+            CustomFileMode::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// The directory under which custom configuration files can be written.
+pub const CUSTOM_FILE_DIR: &str = "/etc/custom";
+
+/// CustomFilePath represents a string that is the absolute path of a file under CUSTOM_FILE_DIR,
+/// with no empty, "." or ".." components, so it can't name a file outside of that directory.  It
+/// stores the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CustomFilePath {
+    inner: String,
+}
+
+impl TryFrom<&str> for CustomFilePath {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        let relative = input
+            .strip_prefix(CUSTOM_FILE_DIR)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or_default();
+        ensure!(
+            !relative.is_empty()
+                && !relative.contains(|c: char| c.is_control())
+                && relative
+                    .split('/')
+                    .all(|component| !matches!(component, "" | "." | "..")),
+            error::InvalidCustomFilePath {
+                input,
+                dir: CUSTOM_FILE_DIR
+            }
+        );
+        Ok(CustomFilePath {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(CustomFilePath, "CustomFilePath");
+
+json_schema_for!(
+    CustomFilePath,
+    description: "The path of a file under /etc/custom",
+    pattern: Some(r"^/etc/custom(/[^/\x00-\x1f\x7f]+)+$"),
+);
+
+#[cfg(test)]
+mod test_custom_file_path {
+    use super::CustomFilePath;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_custom_file_path() {
+        for ok in &[
+            "/etc/custom/app.conf",
+            "/etc/custom/app/app.conf",
+            "/etc/custom/.hidden",
+            "/etc/custom/a..b",
+        ] {
+            CustomFilePath::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_custom_file_path() {
+        for err in &[
+            "",
+            "/etc/custom",
+            "/etc/custom/",
+            "/etc/customs/app.conf",
+            "etc/custom/app.conf",
+            "/etc/custom/app/",
+            "/etc/custom//app.conf",
+            "/etc/custom/./app.conf",
+            "/etc/custom/../passwd",
+            "/etc/custom/app/../../passwd",
+            "/etc/custom/app\nconf",
+            "/etc/passwd",
+        ] {
+            CustomFilePath::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// ServiceAction represents a string that is a valid action to take when a service's settings
+/// change.  It's one of:
+/// * "restart-unit <unit>" to restart a systemd unit, if it's running
+/// * "reload-unit <unit>" to reload a systemd unit, or restart it if it can't be reloaded, if
+///   it's running
+/// * "signal-unit <unit> <signal>" to send a signal like SIGHUP to a systemd unit, if it's running
+/// * "run <path> [args]" to run a program, given by absolute path, with optional arguments
+///
+/// It stores the original string and makes it accessible through standard traits.  The parsed
+/// action is available through `kind`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ServiceAction {
+    inner: String,
+}
+
+/// The parsed form of a ServiceAction.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ServiceActionKind<'a> {
+    RestartUnit { unit: &'a str },
+    ReloadUnit { unit: &'a str },
+    SignalUnit { unit: &'a str, signal: &'a str },
+    Run { path: &'a str, args: Vec<&'a str> },
+}
+
+lazy_static! {
+    pub(crate) static ref SIGNAL_NAME: Regex = Regex::new(r"^SIG[A-Z0-9+-]+$").unwrap();
+}
+
+fn parse_service_action(input: &str) -> Result<ServiceActionKind<'_>, String> {
+    let mut words = input.split_whitespace();
+    let action = words.next().ok_or("no action given")?;
+    let words: Vec<&str> = words.collect();
+    let kind = match (action, words.as_slice()) {
+        ("restart-unit", [unit]) => ServiceActionKind::RestartUnit { unit },
+        ("reload-unit", [unit]) => ServiceActionKind::ReloadUnit { unit },
+        ("signal-unit", [unit, signal]) => {
+            if !SIGNAL_NAME.is_match(signal) {
+                return Err(format!("'{}' isn't a signal name like SIGHUP", signal));
+            }
+            ServiceActionKind::SignalUnit { unit, signal }
+        }
+        ("run", [path, args @ ..]) => {
+            if !path.starts_with('/') {
+                return Err(format!("'{}' isn't an absolute path", path));
+            }
+            ServiceActionKind::Run {
+                path,
+                args: args.to_vec(),
+            }
+        }
+        ("restart-unit", _) | ("reload-unit", _) => {
+            return Err(format!("expected '{} <unit>'", action))
+        }
+        ("signal-unit", _) => return Err("expected 'signal-unit <unit> <signal>'".to_string()),
+        ("run", _) => return Err("expected 'run <path> [args]'".to_string()),
+        _ => return Err(format!("unknown action '{}'", action)),
+    };
+    Ok(kind)
+}
+
+impl TryFrom<&str> for ServiceAction {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        if let Err(msg) = parse_service_action(input) {
+            return error::InvalidRestartAction { input, msg }.fail();
+        }
+        Ok(ServiceAction {
+            inner: input.to_string(),
+        })
+    }
+}
+
+impl ServiceAction {
+    /// Returns the parsed action.
+    pub fn kind(&self) -> ServiceActionKind<'_> {
+        // We checked that it parses when we created it.
+        parse_service_action(&self.inner).expect("service action is valid")
+    }
+}
+
+string_impls_for!(ServiceAction, "ServiceAction");
+
+json_schema_for!(
+    ServiceAction,
+    description: "An action to take when a service's settings change, like \"restart-unit kubelet.service\" or \"run /usr/bin/example --flag\"",
+    pattern: Some(r"^(restart-unit|reload-unit|signal-unit|run)\s+\S.*$"),
+);
+
+#[cfg(test)]
+mod test_service_action {
+    use super::{ServiceAction, ServiceActionKind};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_service_action() {
+        let cases = vec![
+            (
+                "restart-unit kubelet.service",
+                ServiceActionKind::RestartUnit {
+                    unit: "kubelet.service",
+                },
+            ),
+            (
+                "reload-unit  ecs.service",
+                ServiceActionKind::ReloadUnit {
+                    unit: "ecs.service",
+                },
+            ),
+            (
+                "signal-unit chronyd.service SIGHUP",
+                ServiceActionKind::SignalUnit {
+                    unit: "chronyd.service",
+                    signal: "SIGHUP",
+                },
+            ),
+            (
+                "run /usr/bin/static-pods",
+                ServiceActionKind::Run {
+                    path: "/usr/bin/static-pods",
+                    args: vec![],
+                },
+            ),
+            (
+                "run /bin/example --flag value",
+                ServiceActionKind::Run {
+                    path: "/bin/example",
+                    args: vec!["--flag", "value"],
+                },
+            ),
+        ];
+        for (input, expected) in cases {
+            let action = ServiceAction::try_from(input).unwrap();
+            assert_eq!(action.kind(), expected);
+        }
+    }
+
+    #[test]
+    fn invalid_service_action() {
+        for err in &[
+            "",
+            "restart-unit",
+            "restart-unit a.service b.service",
+            "signal-unit chronyd.service",
+            "signal-unit chronyd.service hup",
+            "run",
+            "run usr/bin/example",
+            "/bin/systemctl try-restart kubelet.service",
+            "stop-unit kubelet.service",
+        ] {
+            ServiceAction::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// The systemd unit templates whose instances custom files can restart, reload, or signal.
+/// Anyone who can write settings can already start and stop host containers, so acting on their
+/// units grants nothing new.
+pub const CUSTOM_FILE_UNIT_TEMPLATES: &[&str] = &["host-containers@"];
+
+/// CustomFileAction represents a string that is a valid restart action for a custom configuration
+/// file.  It's a ServiceAction that acts on an instance of one of CUSTOM_FILE_UNIT_TEMPLATES, like
+/// "restart-unit host-containers@app.service"; custom files can't run programs, or act on other
+/// units.  It stores the original string and makes it accessible through standard traits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CustomFileAction {
+    inner: String,
+}
+
+/// Returns whether custom files can act on the given unit.
+fn custom_file_unit_allowed(unit: &str) -> bool {
+    CUSTOM_FILE_UNIT_TEMPLATES.iter().any(|template| {
+        match unit
+            .strip_prefix(template)
+            .and_then(|rest| rest.strip_suffix(".service"))
+        {
+            Some(instance) => {
+                !instance.is_empty()
+                    && instance
+                        .chars()
+                        .all(|c| (c.is_ascii() && c.is_alphanumeric()) || c == '-')
+            }
+            None => false,
+        }
+    })
+}
+
+impl TryFrom<&str> for CustomFileAction {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, error::Error> {
+        let unit = match parse_service_action(input) {
+            Ok(ServiceActionKind::RestartUnit { unit })
+            | Ok(ServiceActionKind::ReloadUnit { unit })
+            | Ok(ServiceActionKind::SignalUnit { unit, .. }) => unit,
+            Ok(ServiceActionKind::Run { .. }) => {
+                let msg = "custom files can't run programs".to_string();
+                return error::InvalidCustomFileAction { input, msg }.fail();
+            }
+            Err(msg) => return error::InvalidCustomFileAction { input, msg }.fail(),
+        };
+        if !custom_file_unit_allowed(unit) {
+            let msg = format!(
+                "custom files can only act on instances of {}",
+                CUSTOM_FILE_UNIT_TEMPLATES.join(", ")
+            );
+            return error::InvalidCustomFileAction { input, msg }.fail();
+        }
+        Ok(CustomFileAction {
+            inner: input.to_string(),
+        })
+    }
+}
+
+string_impls_for!(CustomFileAction, "CustomFileAction");
+
+json_schema_for!(
+    CustomFileAction,
+    description: "An action to take when a custom file changes, like \"restart-unit host-containers@app.service\"",
+    pattern: Some(r"^(restart-unit|reload-unit|signal-unit)\s+host-containers@[a-zA-Z0-9-]+\.service(\s+\S+)?$"),
+);
+
+#[cfg(test)]
+mod test_custom_file_action {
+    use super::CustomFileAction;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn valid_custom_file_action() {
+        for ok in &[
+            "restart-unit host-containers@app.service",
+            "reload-unit host-containers@admin.service",
+            "signal-unit host-containers@my-app.service SIGHUP",
+        ] {
+            CustomFileAction::try_from(*ok).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_custom_file_action() {
+        for err in &[
+            "",
+            "run /bin/sh -c reboot",
+            "run /usr/bin/apiclient",
+            "restart-unit kubelet.service",
+            "signal-unit systemd-journald.service SIGKILL",
+            "restart-unit host-containers@.service",
+            "restart-unit host-containers@app.socket",
+            "restart-unit host-containers@a@b.service",
+            "restart-unit host-containers@app.service extra",
+            "stop-unit host-containers@app.service",
+        ] {
+            CustomFileAction::try_from(*err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// CustomSettingValue represents the value of a custom setting, which can be a bool, a number, or
+/// a string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum CustomSettingValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+#[cfg(test)]
+mod test_custom_setting_value {
+    use super::CustomSettingValue;
+
+    #[test]
+    fn custom_setting_values() {
+        for (input, expected) in &[
+            ("true", CustomSettingValue::Bool(true)),
+            ("42", CustomSettingValue::Integer(42)),
+            ("-1", CustomSettingValue::Integer(-1)),
+            ("2.5", CustomSettingValue::Float(2.5)),
+            (r#""hi""#, CustomSettingValue::String("hi".to_string())),
+            (r#""42""#, CustomSettingValue::String("42".to_string())),
+        ] {
+            let value: CustomSettingValue = serde_json::from_str(input).unwrap();
+            assert_eq!(&value, expected);
+            assert_eq!(serde_json::to_string(&value).unwrap(), *input);
+        }
+    }
+
+    #[test]
+    fn invalid_custom_setting_values() {
+        for err in &["null", "[1]", r#"{"a": 1}"#] {
+            serde_json::from_str::<CustomSettingValue>(err).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// CustomSettingType represents a string that names the type of a custom setting: "string",
+/// "number", or "bool".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CustomSettingType {
+    inner: String,
+    kind: ValidCustomSettingType,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ValidCustomSettingType {
+    String,
+    Number,
+    Bool,
+}
+
+impl TryFrom<&str> for CustomSettingType {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let kind = serde_plain::from_str::<ValidCustomSettingType>(input).context(
+            error::InvalidPlainValue {
+                field: "custom-schema",
+            },
+        )?;
+        Ok(CustomSettingType {
+            inner: input.to_string(),
+            kind,
+        })
+    }
+}
+
+impl CustomSettingType {
+    /// Returns whether the given value is of this type.
+    pub fn accepts(&self, value: &CustomSettingValue) -> bool {
+        matches!(
+            (self.kind, value),
+            (
+                ValidCustomSettingType::String,
+                CustomSettingValue::String(_)
+            ) | (
+                ValidCustomSettingType::Number,
+                CustomSettingValue::Integer(_)
+            ) | (ValidCustomSettingType::Number, CustomSettingValue::Float(_))
+                | (ValidCustomSettingType::Bool, CustomSettingValue::Bool(_))
+        )
+    }
+}
+
+string_impls_for!(CustomSettingType, "CustomSettingType");
+
+json_schema_for!(
+    CustomSettingType,
+    description: "The type of a custom setting",
+    values: &["string", "number", "bool"],
+);
+
+#[cfg(test)]
+mod test_custom_setting_type {
+    use super::{CustomSettingType, CustomSettingValue};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_vals() {
+        for val in &["string", "number", "bool"] {
+            CustomSettingType::try_from(*val).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &["", "String", "boolean", "integer", " "] {
+            CustomSettingType::try_from(*val).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn accepts() {
+        let string = CustomSettingType::try_from("string").unwrap();
+        let number = CustomSettingType::try_from("number").unwrap();
+        let boolean = CustomSettingType::try_from("bool").unwrap();
+
+        let s = CustomSettingValue::String("1".to_string());
+        let i = CustomSettingValue::Integer(1);
+        let f = CustomSettingValue::Float(1.5);
+        let b = CustomSettingValue::Bool(true);
+
+        assert!(string.accepts(&s));
+        assert!(!string.accepts(&i));
+        assert!(number.accepts(&i));
+        assert!(number.accepts(&f));
+        assert!(!number.accepts(&s));
+        assert!(boolean.accepts(&b));
+        assert!(!boolean.accepts(&s));
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// UpdatePolicy represents a string that says how the host is updated: "managed", where it's only
+/// updated through API requests, for example from an orchestrator; "automatic", where it updates
+/// itself during its maintenance windows; or "disabled", where it isn't updated at all.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UpdatePolicy {
+    inner: String,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ValidUpdatePolicy {
+    Managed,
+    Automatic,
+    Disabled,
+}
+
+impl TryFrom<&str> for UpdatePolicy {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        serde_plain::from_str::<ValidUpdatePolicy>(input).context(error::InvalidPlainValue {
+            field: "updates.policy",
+        })?;
+        Ok(UpdatePolicy {
+            inner: input.to_string(),
+        })
+    }
+}
+
+impl UpdatePolicy {
+    fn policy(&self) -> ValidUpdatePolicy {
+        // The string was validated on creation.
+        serde_plain::from_str(&self.inner).expect("UpdatePolicy contains an invalid policy")
+    }
+
+    /// Returns whether the host should update itself during its maintenance windows.
+    pub fn is_automatic(&self) -> bool {
+        self.policy() == ValidUpdatePolicy::Automatic
+    }
+
+    /// Returns whether the host shouldn't be updated at all.
+    pub fn is_disabled(&self) -> bool {
+        self.policy() == ValidUpdatePolicy::Disabled
+    }
+}
+
+string_impls_for!(UpdatePolicy, "UpdatePolicy");
+
+json_schema_for!(
+    UpdatePolicy,
+    description: "How the host is updated",
+    values: &["managed", "automatic", "disabled"],
+);
+
+#[cfg(test)]
+mod test_update_policy {
+    use super::UpdatePolicy;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_vals() {
+        for val in &["managed", "automatic", "disabled"] {
+            UpdatePolicy::try_from(*val).unwrap();
+        }
+        assert!(UpdatePolicy::try_from("automatic").unwrap().is_automatic());
+        assert!(!UpdatePolicy::try_from("managed").unwrap().is_automatic());
+        assert!(UpdatePolicy::try_from("disabled").unwrap().is_disabled());
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &["", "Automatic", "auto", "off", " "] {
+            UpdatePolicy::try_from(*val).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// The names of the days of the week accepted by MaintenanceDays, starting with Monday.
+const DAY_NAMES: &[&str] = &["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// MaintenanceDays represents a string that selects days of the week, like the day-of-week field
+/// of a crontab: "*" for every day, or a comma-separated list of days ("mon" through "sun") and
+/// ranges of days, like "sat,sun" or "mon-fri".  A range can wrap around the end of the week, like
+/// "fri-mon".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MaintenanceDays {
+    inner: String,
+    /// A bit for each selected day, with Monday as the lowest bit.
+    days: u8,
+}
+
+impl TryFrom<&str> for MaintenanceDays {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let day_index = |name: &str| {
+            DAY_NAMES
+                .iter()
+                .position(|day| *day == name)
+                .context(error::InvalidMaintenanceDays { input })
+        };
+
+        let mut days = 0;
+        if input == "*" {
+            days = 0b111_1111;
+        } else {
+            for part in input.split(',') {
+                let (first, last) = match part.split_once('-') {
+                    Some((first, last)) => (day_index(first)?, day_index(last)?),
+                    None => (day_index(part)?, day_index(part)?),
+                };
+                let mut day = first;
+                loop {
+                    days |= 1 << day;
+                    if day == last {
+                        break;
+                    }
+                    day = (day + 1) % DAY_NAMES.len();
+                }
+            }
+        }
+
+        Ok(MaintenanceDays {
+            inner: input.to_string(),
+            days,
+        })
+    }
+}
+
+impl MaintenanceDays {
+    /// Returns whether the given day of the week is selected.
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.days & (1 << day.num_days_from_monday()) != 0
+    }
+}
+
+string_impls_for!(MaintenanceDays, "MaintenanceDays");
+
+json_schema_for!(
+    MaintenanceDays,
+    description: "'*' for every day, or a comma-separated list of days and ranges of days",
+    pattern: Some(
+        r"^(\*|(mon|tue|wed|thu|fri|sat|sun)(-(mon|tue|wed|thu|fri|sat|sun))?(,(mon|tue|wed|thu|fri|sat|sun)(-(mon|tue|wed|thu|fri|sat|sun))?)*)$"
+    ),
+);
+
+#[cfg(test)]
+mod test_maintenance_days {
+    use super::MaintenanceDays;
+    use chrono::Weekday;
+    use std::convert::TryFrom;
+
+    fn days(input: &str) -> Vec<Weekday> {
+        let days = MaintenanceDays::try_from(input).unwrap();
+        let week = [
+            Weekday::Mon,
+            Weekday::Tue,
+            Weekday::Wed,
+            Weekday::Thu,
+            Weekday::Fri,
+            Weekday::Sat,
+            Weekday::Sun,
+        ];
+        week.iter().copied().filter(|d| days.contains(*d)).collect()
+    }
+
+    #[test]
+    fn good_vals() {
+        use Weekday::*;
+        assert_eq!(days("*"), vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun]);
+        assert_eq!(days("wed"), vec![Wed]);
+        assert_eq!(days("sat,sun"), vec![Sat, Sun]);
+        assert_eq!(days("mon-fri"), vec![Mon, Tue, Wed, Thu, Fri]);
+        assert_eq!(days("fri-mon"), vec![Mon, Fri, Sat, Sun]);
+        assert_eq!(days("mon,wed-thu"), vec![Mon, Wed, Thu]);
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &[
+            "",
+            "Mon",
+            "monday",
+            "mon-",
+            "-fri",
+            "mon,",
+            "mon-tue-wed",
+            "**",
+            " mon",
+        ] {
+            MaintenanceDays::try_from(*val).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+lazy_static! {
+    pub(crate) static ref TIME_OF_DAY: Regex =
+        Regex::new(r"^([01][0-9]|2[0-3]):[0-5][0-9]$").unwrap();
+}
+
+/// TimeOfDay represents a string that contains a 24-hour time of day, like "02:30".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct TimeOfDay {
+    inner: String,
+    time: NaiveTime,
+}
+
+impl TryFrom<&str> for TimeOfDay {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        ensure!(
+            TIME_OF_DAY.is_match(input),
+            error::Pattern {
+                thing: "Time of day",
+                pattern: TIME_OF_DAY.clone(),
+                input
+            }
+        );
+        // The pattern ensures these parse and are in range.
+        let hour = input[0..2]
+            .parse()
+            .expect("TimeOfDay hour must be a number");
+        let minute = input[3..5]
+            .parse()
+            .expect("TimeOfDay minute must be a number");
+        Ok(TimeOfDay {
+            inner: input.to_string(),
+            time: NaiveTime::from_hms(hour, minute, 0),
+        })
+    }
+}
+
+impl TimeOfDay {
+    pub fn time(&self) -> NaiveTime {
+        self.time
+    }
+}
+
+string_impls_for!(TimeOfDay, "TimeOfDay");
+
+json_schema_for!(
+    TimeOfDay,
+    description: "A 24-hour time of day, like '02:30'",
+    pattern: Some(r"^([01][0-9]|2[0-3]):[0-5][0-9]$"),
+);
+
+#[cfg(test)]
+mod test_time_of_day {
+    use super::TimeOfDay;
+    use chrono::NaiveTime;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_vals() {
+        for (val, hour, minute) in &[("00:00", 0, 0), ("02:30", 2, 30), ("23:59", 23, 59)] {
+            assert_eq!(
+                TimeOfDay::try_from(*val).unwrap().time(),
+                NaiveTime::from_hms(*hour, *minute, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &["", "2:30", "24:00", "12:60", "12:00:00", "noon", " 12:00"] {
+            TimeOfDay::try_from(*val).unwrap_err();
+        }
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Timezone represents a string that contains the name of a timezone from the IANA timezone
+/// database, like "America/New_York" or "UTC".
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Timezone {
+    inner: String,
+    tz: Tz,
+}
+
+impl TryFrom<&str> for Timezone {
+    type Error = error::Error;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let tz = input
+            .parse()
+            .ok()
+            .context(error::InvalidTimezone { input })?;
+        Ok(Timezone {
+            inner: input.to_string(),
+            tz,
+        })
+    }
+}
+
+impl Timezone {
+    pub fn tz(&self) -> Tz {
+        self.tz
+    }
+}
+
+string_impls_for!(Timezone, "Timezone");
+
+json_schema_for!(
+    Timezone,
+    description: "A timezone name from the IANA timezone database",
+);
+
+#[cfg(test)]
+mod test_timezone {
+    use super::Timezone;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn good_vals() {
+        for val in &["UTC", "America/New_York", "Europe/Berlin", "Asia/Kolkata"] {
+            Timezone::try_from(*val).unwrap();
+        }
+    }
+
+    #[test]
+    fn bad_vals() {
+        for val in &["", "utc ", "America/Springfield", "+05:00", "EST5EDT/x"] {
+            Timezone::try_from(*val).unwrap_err();
+        }
+    }
+}
@@ -0,0 +1,663 @@
+//! This module describes the settings of the current variant with a [JSON Schema], and checks
+//! settings against such a schema.
+//!
+//! The schema is generated from the `Settings` structure, so it can't drift from the model.  The
+//! constraints checked by modeled types are included as patterns, lengths, and lists of allowed
+//! values, and integer fields include the range of their type.  Some modeled types make checks
+//! that can't be expressed in a schema, so settings that pass the schema can still be rejected by
+//! the API, but settings that fail the schema would be.
+//!
+//! `validate` implements the parts of JSON Schema (draft 7) used by our generated schemas, so
+//! settings can be checked without an API server, for example to check user data in CI.
+//!
+//! [JSON Schema]: https://json-schema.org/
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{
+    ArrayValidation, InstanceType, ObjectValidation, RootSchema, Schema, SchemaObject,
+    SubschemaValidation,
+};
+use schemars::visit::{visit_schema_object, Visitor};
+use schemars::JsonSchema;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{RegistryMirror, Settings};
+
+/// Returns the JSON Schema for the settings of the current variant.
+pub fn settings_schema() -> RootSchema {
+    SchemaSettings::draft07()
+        .with(|s| {
+            // Settings are optional, but they can't be null.
+            s.option_add_null_type = false;
+        })
+        .with_visitor(IntegerRanges)
+        .into_generator()
+        .into_root_schema_for::<Settings>()
+}
+
+/// The schema generated for a Rust integer only names its size, like "int32"; this adds the range
+/// of the type so that validators can check it.
+#[derive(Debug, Clone)]
+struct IntegerRanges;
+
+impl Visitor for IntegerRanges {
+    fn visit_schema_object(&mut self, schema: &mut SchemaObject) {
+        let range = match schema.format.as_deref() {
+            Some("int8") => Some((i8::MIN as f64, i8::MAX as f64)),
+            Some("int16") => Some((i16::MIN as f64, i16::MAX as f64)),
+            Some("int32") => Some((i32::MIN as f64, i32::MAX as f64)),
+            Some("int64") => Some((i64::MIN as f64, i64::MAX as f64)),
+            Some("uint8") => Some((0.0, u8::MAX as f64)),
+            Some("uint16") => Some((0.0, u16::MAX as f64)),
+            Some("uint32") => Some((0.0, u32::MAX as f64)),
+            Some("uint64") => Some((0.0, u64::MAX as f64)),
+            _ => None,
+        };
+        if let Some((minimum, maximum)) = range {
+            let number = schema.number();
+            number.minimum.get_or_insert(minimum);
+            number.maximum.get_or_insert(maximum);
+        }
+        visit_schema_object(self, schema)
+    }
+}
+
+/// Describes a map in the schema, including the keys, which the schema for `HashMap` doesn't.
+/// `#[model]` uses this for `HashMap` fields; it's not meant to be used as a real type.
+pub struct MapSchema<K, V> {
+    _types: PhantomData<(K, V)>,
+}
+
+impl<K: JsonSchema, V: JsonSchema> JsonSchema for MapSchema<K, V> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        format!("Map_of_{}_to_{}", K::schema_name(), V::schema_name())
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            object: Some(Box::new(ObjectValidation {
+                additional_properties: Some(Box::new(gen.subschema_for::<V>())),
+                property_names: Some(Box::new(gen.subschema_for::<K>())),
+                ..Default::default()
+            })),
+            ..Default::default()
+        })
+    }
+}
+
+/// Describes the forms accepted for registry mirrors: a list of mirrors, or, for backward
+/// compatibility, a map of registry to endpoints.
+pub struct MirrorsSchema;
+
+impl JsonSchema for MirrorsSchema {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "Mirrors".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let list = Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::Array.into()),
+            array: Some(Box::new(ArrayValidation {
+                items: Some(gen.subschema_for::<RegistryMirror>().into()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        });
+        let map = <MapSchema<crate::modeled_types::SingleLineString, Vec<crate::modeled_types::Url>>>::json_schema(gen);
+        Schema::Object(SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![list, map]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        })
+    }
+}
+
+/// A way in which a value doesn't match a schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// Where the problem is, like "settings.kubernetes.max-pods".
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Checks the given settings against a settings schema, like the one from `settings_schema`,
+/// returning each problem found.  The settings are the contents of the "settings" object, e.g.
+/// `{"motd": "hi"}`.  If the returned list is empty, the settings match the schema.
+///
+/// Unsupported schema keywords are ignored, so this only checks what our generated schemas use:
+/// type, properties, additionalProperties, propertyNames, items, enum, pattern, minLength,
+/// maxLength, minimum, maximum, format "ip", allOf, anyOf, oneOf, and "#/definitions/" references.
+pub fn validate(schema: &Value, settings: &Value) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    Validator { root: schema }.check(schema, settings, "settings", &mut errors);
+    errors
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&self, schema: &Value, value: &Value, path: &str, errors: &mut Vec<ValidationError>) {
+        let schema = match schema {
+            // `true` accepts anything and `false` accepts nothing.
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                return errors.push(error(path, "is not allowed"));
+            }
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(target) => self.check(target, value, path, errors),
+                None => errors.push(error(
+                    path,
+                    format!("schema reference '{}' not found", reference),
+                )),
+            }
+        }
+
+        if let Some(types) = schema.get("type") {
+            if !type_matches(types, value) {
+                // Other checks on a value of the wrong type would only add noise.
+                return errors.push(error(
+                    path,
+                    format!("expected {}, found {}", type_names(types), describe(value)),
+                ));
+            }
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+                errors.push(error(
+                    path,
+                    format!("{} is not one of: {}", value, allowed.join(", ")),
+                ));
+            }
+        }
+
+        match value {
+            Value::String(s) => self.check_string(schema, s, path, errors),
+            Value::Number(n) => {
+                if let Some(n) = n.as_f64() {
+                    check_range(schema, n, path, errors)
+                }
+            }
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        self.check(item_schema, item, &format!("{}[{}]", path, i), errors);
+                    }
+                }
+            }
+            Value::Object(fields) => self.check_object(schema, fields, path, errors),
+            Value::Bool(_) | Value::Null => {}
+        }
+
+        self.check_subschemas(schema, value, path, errors);
+    }
+
+    fn check_string(
+        &self,
+        schema: &Map<String, Value>,
+        s: &str,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        // JSON Schema lengths are in characters, not bytes.
+        let length = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+            if length < min {
+                errors.push(error(
+                    path,
+                    format!("must be at least {} characters long", min),
+                ));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+            if length > max {
+                errors.push(error(
+                    path,
+                    format!("must be at most {} characters long", max),
+                ));
+            }
+        }
+
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            match regex::Regex::new(pattern) {
+                Ok(re) => {
+                    if !re.is_match(s) {
+                        let description = schema
+                            .get("description")
+                            .and_then(Value::as_str)
+                            .map(|d| format!(" ({})", d))
+                            .unwrap_or_default();
+                        errors.push(error(
+                            path,
+                            format!(
+                                "'{}' does not match pattern '{}'{}",
+                                s, pattern, description
+                            ),
+                        ));
+                    }
+                }
+                Err(e) => errors.push(error(
+                    path,
+                    format!("schema pattern '{}' is invalid: {}", pattern, e),
+                )),
+            }
+        }
+
+        let valid_format = match schema.get("format").and_then(Value::as_str) {
+            Some("ip") => s.parse::<IpAddr>().is_ok(),
+            Some("ipv4") => s.parse::<Ipv4Addr>().is_ok(),
+            Some("ipv6") => s.parse::<Ipv6Addr>().is_ok(),
+            // Formats are only annotations unless a validator chooses to check them.
+            _ => true,
+        };
+        if !valid_format {
+            errors.push(error(path, format!("'{}' is not a valid IP address", s)));
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        fields: &Map<String, Value>,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        let property_names = schema.get("propertyNames");
+
+        for (name, field) in fields {
+            let field_path = format!("{}.{}", path, quote_segment(name));
+
+            if let Some(name_schema) = property_names {
+                let mut name_errors = Vec::new();
+                self.check(
+                    name_schema,
+                    &Value::String(name.clone()),
+                    &field_path,
+                    &mut name_errors,
+                );
+                for name_error in name_errors {
+                    errors.push(error(
+                        &name_error.path,
+                        format!("invalid name: {}", name_error.message),
+                    ));
+                }
+            }
+
+            match properties.and_then(|p| p.get(name)) {
+                Some(field_schema) => self.check(field_schema, field, &field_path, errors),
+                None => match additional {
+                    Some(Value::Bool(false)) => {
+                        errors.push(error(&field_path, "is not a known setting"))
+                    }
+                    Some(additional) => self.check(additional, field, &field_path, errors),
+                    None => {}
+                },
+            }
+        }
+
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !fields.contains_key(name) {
+                    errors.push(error(
+                        &format!("{}.{}", path, quote_segment(name)),
+                        "is required",
+                    ));
+                }
+            }
+        }
+    }
+
+    fn check_subschemas(
+        &self,
+        schema: &Map<String, Value>,
+        value: &Value,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        if let Some(all_of) = schema.get("allOf").and_then(Value::as_array) {
+            for subschema in all_of {
+                self.check(subschema, value, path, errors);
+            }
+        }
+
+        if let Some(any_of) = schema.get("anyOf").and_then(Value::as_array) {
+            if !any_of.is_empty() && self.matching(any_of, value, path) == 0 {
+                errors.push(error(path, "does not match any of the allowed forms"));
+            }
+        }
+
+        if let Some(one_of) = schema.get("oneOf").and_then(Value::as_array) {
+            let matching = self.matching(one_of, value, path);
+            if matching != 1 {
+                errors.push(error(
+                    path,
+                    format!(
+                        "must match exactly one of the allowed forms, but matches {}",
+                        matching
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// Returns how many of the given schemas the value matches.
+    fn matching(&self, schemas: &[Value], value: &Value, path: &str) -> usize {
+        schemas
+            .iter()
+            .filter(|subschema| {
+                let mut sub_errors = Vec::new();
+                self.check(subschema, value, path, &mut sub_errors);
+                sub_errors.is_empty()
+            })
+            .count()
+    }
+
+    /// Finds the schema for a reference like "#/definitions/Identifier".
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+}
+
+fn error<S: Into<String>>(path: &str, message: S) -> ValidationError {
+    ValidationError {
+        path: path.to_string(),
+        message: message.into(),
+    }
+}
+
+fn check_range(schema: &Map<String, Value>, n: f64, path: &str, errors: &mut Vec<ValidationError>) {
+    if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+        if n < min {
+            errors.push(error(
+                path,
+                format!("{} is less than the minimum {}", n, min),
+            ));
+        }
+    }
+    if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+        if n > max {
+            errors.push(error(
+                path,
+                format!("{} is greater than the maximum {}", n, max),
+            ));
+        }
+    }
+}
+
+/// Checks whether the value is of the schema type, or one of the schema types if given a list.
+fn type_matches(types: &Value, value: &Value) -> bool {
+    match types {
+        Value::String(name) => is_type(name, value),
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| is_type(name, value)),
+        _ => true,
+    }
+}
+
+fn is_type(name: &str, value: &Value) -> bool {
+    match (name, value) {
+        ("null", Value::Null) => true,
+        ("boolean", Value::Bool(_)) => true,
+        ("string", Value::String(_)) => true,
+        ("array", Value::Array(_)) => true,
+        ("object", Value::Object(_)) => true,
+        ("number", Value::Number(_)) => true,
+        ("integer", Value::Number(n)) => {
+            n.is_i64() || n.is_u64() || matches!(n.as_f64(), Some(f) if f.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn type_names(types: &Value) -> String {
+    match types {
+        Value::Array(names) => {
+            let names: Vec<&str> = names.iter().filter_map(Value::as_str).collect();
+            names.join(" or ")
+        }
+        Value::String(name) => name.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Describes a value for an error message.
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => format!("boolean {}", value),
+        Value::Number(_) => format!("number {}", value),
+        Value::String(_) => format!("string {}", value),
+        Value::Array(_) => "array".to_string(),
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+/// Quotes a name that contains dots, the way the data store does in key names.
+fn quote_segment(name: &str) -> String {
+    if name.contains('.') {
+        format!("\"{}\"", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    fn schema() -> Value {
+        serde_json::to_value(settings_schema()).unwrap()
+    }
+
+    fn messages(settings: Value) -> Vec<String> {
+        validate(&schema(), &settings)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let defaults_dir =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/variant/current/defaults.d");
+        let schema = schema();
+        for entry in fs::read_dir(defaults_dir).unwrap() {
+            let path = entry.unwrap().path();
+            let defaults: toml::Value =
+                toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            if let Some(settings) = defaults.get("settings") {
+                let settings = serde_json::to_value(settings).unwrap();
+                let errors = validate(&schema, &settings);
+                assert!(errors.is_empty(), "{}: {:?}", path.display(), errors);
+            }
+        }
+    }
+
+    #[test]
+    fn valid_settings() {
+        assert!(messages(json!({"motd": "hi", "kernel": {"lockdown": "none"}})).is_empty());
+        assert!(messages(json!({"kernel": {"sysctl": {"net.ipv4.ip_forward": "1"}}})).is_empty());
+        assert!(messages(json!({"ntp": {"time-servers": ["pool.ntp.org"]}})).is_empty());
+        assert!(messages(json!({"host-containers": {"admin": {"enabled": true}}})).is_empty());
+    }
+
+    #[test]
+    fn unknown_setting() {
+        assert_eq!(
+            messages(json!({"motdd": "hi"})),
+            vec!["settings.motdd: is not a known setting"]
+        );
+    }
+
+    #[test]
+    fn wrong_type() {
+        assert_eq!(
+            messages(json!({"motd": 1})),
+            vec!["settings.motd: expected string, found number 1"]
+        );
+    }
+
+    #[test]
+    fn not_allowed_value() {
+        assert_eq!(
+            messages(json!({"kernel": {"lockdown": "locked"}})),
+            vec![
+                r#"settings.kernel.lockdown: "locked" is not one of: "none", "integrity", "confidentiality""#
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_map_key() {
+        let errors = messages(json!({"host-containers": {"ad min": {"enabled": true}}}));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("settings.host-containers.ad min: invalid name:"));
+    }
+
+    #[test]
+    fn invalid_ip() {
+        let schema = json!({"type": "string", "format": "ip"});
+        assert!(validate(&schema, &json!("10.0.0.1")).is_empty());
+        assert!(validate(&schema, &json!("fd00::1")).is_empty());
+        assert_eq!(
+            validate(&schema, &json!("10.0.0.256")),
+            vec![error("settings", "'10.0.0.256' is not a valid IP address")]
+        );
+    }
+
+    #[test]
+    fn any_of() {
+        let schema = json!({"anyOf": [{"type": "string"}, {"type": "array"}]});
+        assert!(validate(&schema, &json!("a")).is_empty());
+        assert!(validate(&schema, &json!(["a"])).is_empty());
+        assert_eq!(
+            validate(&schema, &json!(1)),
+            vec![error("settings", "does not match any of the allowed forms")]
+        );
+    }
+
+    #[test]
+    fn integer_range() {
+        assert_eq!(
+            messages(json!({"updates": {"seed": -1}})),
+            vec!["settings.updates.seed: -1 is less than the minimum 0"]
+        );
+        assert_eq!(
+            messages(json!({"updates": {"seed": 4294967296u64}})),
+            vec!["settings.updates.seed: 4294967296 is greater than the maximum 4294967295"]
+        );
+    }
+
+    #[test]
+    fn pattern_mismatch() {
+        let errors = messages(json!({"network": {"hostname": "-bad"}}));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("settings.network.hostname: '-bad' does not match pattern"));
+    }
+
+    /// Checks that the schema for a modeled type accepts every sample the type accepts, and
+    /// rejects the given samples that it should.
+    fn check_modeled_type<T>(accepted_or_not: &[&str], rejected: &[&str])
+    where
+        T: JsonSchema + for<'a> std::convert::TryFrom<&'a str>,
+    {
+        let schema = serde_json::to_value(schemars::schema_for!(T)).unwrap();
+        for sample in accepted_or_not {
+            if T::try_from(*sample).is_ok() {
+                let errors = validate(&schema, &json!(sample));
+                assert!(errors.is_empty(), "{}: {:?}", sample, errors);
+            }
+        }
+        for sample in rejected {
+            assert!(
+                T::try_from(*sample).is_err(),
+                "{} should be invalid",
+                sample
+            );
+            assert!(!validate(&schema, &json!(sample)).is_empty(), "{}", sample);
+        }
+    }
+
+    #[test]
+    fn modeled_type_schemas() {
+        use crate::modeled_types::*;
+
+        check_modeled_type::<KubernetesLabelKey>(
+            &[
+                "a",
+                "example.com/a",
+                "a.b-c_d",
+                "kubernetes.io/os",
+                "A/b",
+                "-a",
+                "a/b/c",
+            ],
+            &["", "-a", "a b", "a/", "/a"],
+        );
+        check_modeled_type::<KubernetesLabelValue>(&["", "a", "a.b-c_d", "-a"], &["-a", "a b"]);
+        check_modeled_type::<ValidBase64>(&["", "aGk=", "aGk", "aG=k"], &["a b", "&&"]);
+        check_modeled_type::<SysctlKey>(
+            &["net.ipv4.ip_forward", "vm/max_map_count", "a", "a..b", ""],
+            &["", "a b"],
+        );
+        check_modeled_type::<SingleLineString>(&["", "a b"], &["a\nb", "a\rb", "a\u{2028}b"]);
+        check_modeled_type::<ValidLinuxHostname>(
+            &["a", "a-b.c", "-a", "a..b", "A"],
+            &["-a", "a_b"],
+        );
+        check_modeled_type::<Identifier>(&["", "a-b", "a b"], &["a b", "a.b"]);
+        check_modeled_type::<Lockdown>(&["none", "integrity", "bad"], &["bad"]);
+        check_modeled_type::<KubernetesQuantityValue>(
+            &["1", "1.5", "100Mi", "1e3", "2k", "1Q", ""],
+            &["1Q", ""],
+        );
+        check_modeled_type::<KubernetesThresholdValue>(
+            &["10%", "1.5%", "100Mi", "1", "a%", ""],
+            &["a%", ""],
+        );
+        check_modeled_type::<KubernetesDurationValue>(
+            &["1h", "1h30m", "1.5s", "10ms", "1", ""],
+            &["1", ""],
+        );
+        check_modeled_type::<FriendlyVersion>(
+            &["latest", "1.2.3", "v1.2.3", "1.2.3-rc1", "1.2", "x"],
+            &["1.2", "x"],
+        );
+    }
+}
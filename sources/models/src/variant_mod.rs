@@ -18,5 +18,7 @@ struct Model {
     settings: Settings,
     services: Services,
     configuration_files: ConfigurationFiles,
+    // The OS release isn't a setting, so it isn't described by the settings schema.
+    #[schemars(skip)]
     os: BottlerocketRelease,
 }
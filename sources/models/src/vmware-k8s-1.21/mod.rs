@@ -2,9 +2,9 @@ use model_derive::model;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use crate::modeled_types::Identifier;
+use crate::modeled_types::{CustomSettingType, CustomSettingValue, Identifier};
 use crate::{
-    BootstrapContainer, HostContainer, KernelSettings, KubernetesSettings, MetricsSettings,
+    BootstrapContainer, CustomFile, HostContainer, KernelSettings, KubernetesSettings, MetricsSettings,
     NetworkSettings, NtpSettings, PemCertificate, RegistrySettings, UpdatesSettings,
 };
 
@@ -23,4 +23,7 @@ struct Settings {
     metrics: MetricsSettings,
     pki: HashMap<Identifier, PemCertificate>,
     container_registry: RegistrySettings,
+    custom: HashMap<Identifier, CustomSettingValue>,
+    custom_schema: HashMap<Identifier, CustomSettingType>,
+    custom_files: HashMap<Identifier, CustomFile>,
 }
@@ -96,6 +96,40 @@ pub struct Update {
     #[serde(deserialize_with = "de::deserialize_bound")]
     pub waves: BTreeMap<u32, DateTime<Utc>>,
     pub images: Images,
+    /// Patches that produce `images` from the images of earlier versions, keyed by the version
+    /// they apply to.  Hosts running one of those versions can download a patch instead of the
+    /// full images.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub deltas: BTreeMap<Version, Delta>,
+}
+
+/// A set of patches that produce an update's images from the images of one earlier version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delta {
+    pub format: DeltaFormat,
+    pub boot: DeltaImage,
+    pub root: DeltaImage,
+    pub hash: DeltaImage,
+}
+
+/// A patch that produces one of an update's images.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaImage {
+    /// The name of the target containing the patch.
+    pub target: String,
+    /// The size of the earlier version's image, which is the part of its partition the patch is
+    /// applied to.
+    pub source_size: u64,
+    /// The hex-encoded SHA-256 digest of the full image, used to verify the patched partition.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeltaFormat {
+    /// A zstd frame compressed with the earlier image as a prefix, as made by
+    /// `zstd --patch-from`.
+    ZstdPatchFrom,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -171,6 +205,7 @@ impl Manifest {
             max_version: max_version.clone(),
             images,
             waves: BTreeMap::new(),
+            deltas: BTreeMap::new(),
         };
         self.update_max_version(
             &update.max_version,
@@ -237,6 +272,24 @@ impl Manifest {
             .collect()
     }
 
+    /// Adds a delta from `from_version` to the matching updates, replacing any existing delta from
+    /// that version, and returns the number of matching updates.
+    pub fn add_delta(
+        &mut self,
+        variant: String,
+        arch: String,
+        image_version: Version,
+        from_version: &Version,
+        delta: &Delta,
+    ) -> usize {
+        let matching = self.get_matching_updates(variant, arch, image_version);
+        let num_matching = matching.len();
+        for update in matching {
+            update.deltas.insert(from_version.clone(), delta.clone());
+        }
+        num_matching
+    }
+
     /// Adds a vec of waves to update, returns number of matching updates for wave
     // Wave format in `manifest.json` is slightly different from the wave structs
     // provided to this function. For example, if two `UpdateWave` structs are
@@ -475,6 +528,7 @@ mod tests {
                 root: String::from("root"),
                 hash: String::from("hash"),
             },
+            deltas: BTreeMap::new(),
         }
     }
 
@@ -640,6 +694,7 @@ mod tests {
                 root: String::from("root"),
                 hash: String::from("hash"),
             },
+            deltas: BTreeMap::new(),
         };
         let seed = 1024;
         // Construct a DateTime object for 1/1/2000 00:00:00
@@ -691,4 +746,41 @@ mod tests {
         assert!(i.next().unwrap() == "migration_1.1.0_b");
         assert!(i.next().unwrap() == "migration_1.1.0_a");
     }
+
+    #[test]
+    fn test_deltas() {
+        // A manifest with an update that has a delta from 1.4.0.
+        let path = "./tests/data/deltas.json";
+        let mut manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let from = Version::parse("1.4.0").unwrap();
+        let delta = &manifest.updates[0].deltas[&from];
+        assert_eq!(delta.format, DeltaFormat::ZstdPatchFrom);
+        assert_eq!(delta.root.source_size, 943_718_400);
+        let delta = delta.clone();
+
+        // Adding a delta only changes the matching update.
+        let from = Version::parse("1.3.0").unwrap();
+        let matching = manifest.add_delta(
+            "bottlerocket-aws-eks".to_string(),
+            "x86_64".to_string(),
+            Version::parse("1.5.0").unwrap(),
+            &from,
+            &delta,
+        );
+        assert_eq!(matching, 1);
+        assert_eq!(manifest.updates[0].deltas.len(), 2);
+        let matching = manifest.add_delta(
+            "bottlerocket-aws-eks".to_string(),
+            "aarch64".to_string(),
+            Version::parse("1.5.0").unwrap(),
+            &from,
+            &delta,
+        );
+        assert_eq!(matching, 0);
+
+        // Updates without deltas don't mention them.
+        manifest.updates[0].deltas.clear();
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(!json.contains("deltas"));
+    }
 }
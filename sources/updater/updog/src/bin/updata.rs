@@ -10,12 +10,14 @@ extern crate log;
 use crate::error::Result;
 use chrono::{DateTime, Utc};
 use semver::Version;
+use sha2::{Digest, Sha256};
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use snafu::{ErrorCompat, OptionExt, ResultExt};
-use std::fs;
-use std::path::PathBuf;
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
-use update_metadata::{Images, Manifest, Release, UpdateWaves};
+use update_metadata::{Delta, DeltaFormat, DeltaImage, Images, Manifest, Release, UpdateWaves};
 
 #[derive(Debug, StructOpt)]
 struct GeneralArgs {
@@ -76,6 +78,143 @@ impl AddUpdateArgs {
     }
 }
 
+#[derive(Debug, StructOpt)]
+struct AddDeltaArgs {
+    // metadata file to modify
+    file: PathBuf,
+
+    // image 'variant', eg. 'aws-ecs-1'
+    #[structopt(short = "f", long = "variant")]
+    variant: String,
+
+    // image version
+    #[structopt(short = "v", long = "version")]
+    image_version: Version,
+
+    // architecture image is built for
+    #[structopt(short = "a", long = "arch")]
+    arch: String,
+
+    /// Version whose images the patches apply to
+    #[structopt(long = "from-version")]
+    from_version: Version,
+
+    /// Uncompressed root image of the update
+    #[structopt(long = "root")]
+    root: PathBuf,
+
+    /// Uncompressed boot image of the update
+    #[structopt(long = "boot")]
+    boot: PathBuf,
+
+    /// Uncompressed verity "hash" image of the update
+    #[structopt(long = "hash")]
+    hash: PathBuf,
+
+    /// Uncompressed root image of --from-version
+    #[structopt(long = "from-root")]
+    from_root: PathBuf,
+
+    /// Uncompressed boot image of --from-version
+    #[structopt(long = "from-boot")]
+    from_boot: PathBuf,
+
+    /// Uncompressed verity "hash" image of --from-version
+    #[structopt(long = "from-hash")]
+    from_hash: PathBuf,
+
+    /// Directory to write the patches to; they need to be added to the repository as targets
+    #[structopt(long = "output-dir")]
+    output_dir: PathBuf,
+}
+
+impl AddDeltaArgs {
+    fn run(self) -> Result<()> {
+        let mut manifest: Manifest = update_metadata::load_file(&self.file)?;
+        let delta = Delta {
+            format: DeltaFormat::ZstdPatchFrom,
+            root: self.create_patch(&self.from_root, &self.root)?,
+            boot: self.create_patch(&self.from_boot, &self.boot)?,
+            hash: self.create_patch(&self.from_hash, &self.hash)?,
+        };
+        let num_matching = manifest.add_delta(
+            self.variant.clone(),
+            self.arch.clone(),
+            self.image_version.clone(),
+            &self.from_version,
+            &delta,
+        );
+        ensure!(
+            num_matching > 0,
+            error::DeltaUpdateNotFound {
+                arch: self.arch,
+                variant: self.variant,
+                version: self.image_version,
+            }
+        );
+        update_metadata::write_file(&self.file, &manifest)?;
+        Ok(())
+    }
+
+    /// Writes a zstd patch that produces `image` from `from_image` to the output directory, and
+    /// returns its description for the manifest.
+    fn create_patch(&self, from_image: &Path, image: &Path) -> Result<DeltaImage> {
+        let source = fs::read(from_image).context(error::ImageRead { path: from_image })?;
+        let image_file = File::open(image).context(error::ImageRead { path: image })?;
+        let image_size = image_file
+            .metadata()
+            .context(error::ImageRead { path: image })?
+            .len();
+
+        let file_name = image
+            .file_name()
+            .unwrap_or(image.as_os_str())
+            .to_string_lossy();
+        let target = format!("{}.from-{}.zst", file_name, self.from_version);
+        let path = self.output_dir.join(&target);
+        let patch_file = File::create(&path).context(error::DeltaCreate { path: &path })?;
+
+        // The window has to cover the source image and the image being produced, so everything in
+        // the source image can be referred to.
+        let window_log = 64 - (source.len() as u64 + image_size).leading_zeros();
+        let mut encoder = zstd::stream::write::Encoder::with_dictionary(patch_file, 19, &source)
+            .context(error::DeltaCreate { path: &path })?;
+        encoder
+            .long_distance_matching(true)
+            .context(error::DeltaCreate { path: &path })?;
+        encoder
+            .window_log(window_log.clamp(10, 31))
+            .context(error::DeltaCreate { path: &path })?;
+
+        // Keep a digest of the image as we compress it, so updog can verify what it produces.
+        let mut digest = Sha256::new();
+        let mut reader = BufReader::new(image_file);
+        let mut buf = vec![0; 1024 * 1024];
+        loop {
+            let n = reader
+                .read(&mut buf)
+                .context(error::ImageRead { path: image })?;
+            if n == 0 {
+                break;
+            }
+            digest.update(&buf[..n]);
+            encoder
+                .write_all(&buf[..n])
+                .context(error::DeltaCreate { path: &path })?;
+        }
+        encoder
+            .finish()
+            .context(error::DeltaCreate { path: &path })?;
+
+        info!("Wrote patch {}", path.display());
+        Ok(DeltaImage {
+            target,
+            source_size: source.len() as u64,
+            sha256: hex::encode(digest.finalize()),
+        })
+    }
+}
+
 #[derive(Debug, StructOpt)]
 struct RemoveUpdateArgs {
     // metadata file to create/modify
@@ -238,6 +377,8 @@ enum Command {
     Init(GeneralArgs),
     /// Add a new update to the manifest, not including wave information
     AddUpdate(AddUpdateArgs),
+    /// Add a delta to an update, writing patches from the images of an earlier version
+    AddDelta(AddDeltaArgs),
     /// Set waves for an update
     SetWaves(WaveArgs),
     /// Set the global maximum image version
@@ -262,6 +403,7 @@ fn main_inner() -> Result<()> {
             }
         }
         Command::AddUpdate(args) => args.run(),
+        Command::AddDelta(args) => args.run(),
         Command::SetWaves(args) => args.set(),
         Command::SetMaxVersion(args) => args.run(),
         Command::RemoveUpdate(args) => args.run(),
@@ -417,4 +559,87 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn add_delta() -> Result<()> {
+        let dir = tempfile::tempdir().context(error::TmpFileCreate)?;
+        let manifest_path = dir.path().join("manifest.json");
+        update_metadata::write_file(&manifest_path, &Manifest::default()).unwrap();
+        AddUpdateArgs {
+            file: manifest_path.clone(),
+            variant: String::from("yum"),
+            arch: String::from("x86_64"),
+            image_version: Version::parse("1.2.4").unwrap(),
+            max_version: None,
+            boot: String::from("boot"),
+            root: String::from("root"),
+            hash: String::from("hash"),
+        }
+        .run()
+        .unwrap();
+
+        // Each new image is a slightly changed copy of the old one.
+        let mut images = Vec::new();
+        for name in &["root", "boot", "hash"] {
+            let old: Vec<u8> = (0..10_000u32).flat_map(u32::to_le_bytes).collect();
+            let mut new = old.clone();
+            new[100..110].copy_from_slice(&name.repeat(4).as_bytes()[..10]);
+            let old_path = dir.path().join(format!("{}-1.2.3.img", name));
+            let new_path = dir.path().join(format!("{}-1.2.4.img", name));
+            fs::write(&old_path, &old).unwrap();
+            fs::write(&new_path, &new).unwrap();
+            images.push((old_path, new_path, old, new));
+        }
+        AddDeltaArgs {
+            file: manifest_path.clone(),
+            variant: String::from("yum"),
+            arch: String::from("x86_64"),
+            image_version: Version::parse("1.2.4").unwrap(),
+            from_version: Version::parse("1.2.3").unwrap(),
+            root: images[0].1.clone(),
+            boot: images[1].1.clone(),
+            hash: images[2].1.clone(),
+            from_root: images[0].0.clone(),
+            from_boot: images[1].0.clone(),
+            from_hash: images[2].0.clone(),
+            output_dir: dir.path().to_path_buf(),
+        }
+        .run()
+        .unwrap();
+
+        let m: Manifest = update_metadata::load_file(&manifest_path)?;
+        let delta = &m.updates[0].deltas[&Version::parse("1.2.3").unwrap()];
+        assert_eq!(delta.format, DeltaFormat::ZstdPatchFrom);
+        let delta_images = [&delta.root, &delta.boot, &delta.hash];
+        for (delta_image, (_, _, old, new)) in delta_images.iter().zip(&images) {
+            assert_eq!(delta_image.source_size, old.len() as u64);
+            assert_eq!(delta_image.sha256, hex::encode(Sha256::digest(new)));
+            let patch = File::open(dir.path().join(&delta_image.target)).unwrap();
+            let mut patched = Vec::new();
+            zstd::stream::read::Decoder::with_dictionary(BufReader::new(patch), old)
+                .unwrap()
+                .read_to_end(&mut patched)
+                .unwrap();
+            assert_eq!(&patched, new);
+        }
+
+        // There's no update for other architectures to add the delta to.
+        assert!(AddDeltaArgs {
+            file: manifest_path,
+            variant: String::from("yum"),
+            arch: String::from("aarch64"),
+            image_version: Version::parse("1.2.4").unwrap(),
+            from_version: Version::parse("1.2.3").unwrap(),
+            root: images[0].1.clone(),
+            boot: images[1].1.clone(),
+            hash: images[2].1.clone(),
+            from_root: images[0].0.clone(),
+            from_boot: images[1].0.clone(),
+            from_hash: images[2].0.clone(),
+            output_dir: dir.path().to_path_buf(),
+        }
+        .run()
+        .is_err());
+        Ok(())
+    }
 }
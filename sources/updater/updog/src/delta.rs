@@ -0,0 +1,262 @@
+//! Delta updates: patches that produce an update's images from the images of the running version,
+//! which are read from the active partition set.
+
+use crate::error::{self, Result};
+use sha2::{Digest, Sha256};
+use signpost::PartitionSet;
+use snafu::{ensure, OptionExt, ResultExt};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use tough::Repository;
+use update_metadata::{Delta, DeltaFormat, DeltaImage};
+
+/// The largest zstd window we accept.  A patch's window covers its whole source image, so this is
+/// the largest zstd allows on 64-bit hosts, and also the largest source image we can patch.
+const ZSTD_WINDOW_LOG_MAX: u32 = 31;
+
+/// The smallest window zstd will decode with.
+const ZSTD_WINDOW_LOG_MIN: u32 = 10;
+
+const MEMINFO_PATH: &str = "/proc/meminfo";
+
+/// Writes the images produced by the patches in `delta` to the `inactive` partition set, applying
+/// them to the images in the `active` partition set.  Each written image is verified against the
+/// digest of the full image, so an error means the inactive partitions must not be used.
+pub(crate) fn write_delta_images(
+    repository: &Repository,
+    delta: &Delta,
+    active: &PartitionSet,
+    inactive: &PartitionSet,
+) -> Result<()> {
+    write_delta_image(
+        repository,
+        delta.format,
+        &delta.root,
+        &active.root,
+        &inactive.root,
+    )?;
+    write_delta_image(
+        repository,
+        delta.format,
+        &delta.boot,
+        &active.boot,
+        &inactive.boot,
+    )?;
+    write_delta_image(
+        repository,
+        delta.format,
+        &delta.hash,
+        &active.hash,
+        &inactive.hash,
+    )?;
+    Ok(())
+}
+
+fn write_delta_image(
+    repository: &Repository,
+    format: DeltaFormat,
+    image: &DeltaImage,
+    source_path: &Path,
+    disk_path: &Path,
+) -> Result<()> {
+    let target = image.target.as_str();
+    let window_log_max = window_log_max(image.source_size, available_memory()?)
+        .context(error::DeltaMemory { target })?;
+
+    // Patches may refer to any part of the source image, so we read all of it.  The image is only
+    // the start of the partition; the rest is padding that wasn't part of the patch.
+    let mut source = Vec::new();
+    File::open(source_path)
+        .context(error::OpenPartition { path: source_path })?
+        .take(image.source_size)
+        .read_to_end(&mut source)
+        .context(error::ImageRead { path: source_path })?;
+    ensure!(
+        source.len() as u64 == image.source_size,
+        error::DeltaSourceSize {
+            path: source_path,
+            expected: image.source_size,
+            found: source.len() as u64,
+        }
+    );
+
+    let target = target.try_into().context(error::TargetName { target })?;
+    let patch = repository
+        .read_target(&target)
+        .context(error::Metadata)?
+        .context(error::TargetNotFound {
+            target: target.raw(),
+        })?;
+    let f = OpenOptions::new()
+        .write(true)
+        .open(disk_path)
+        .context(error::OpenPartition { path: disk_path })?;
+
+    let digest = apply_patch(format, patch, &source, window_log_max, f, target.raw())?;
+    ensure!(
+        digest == image.sha256.to_lowercase(),
+        error::DeltaVerify {
+            target: target.raw(),
+            expected: &image.sha256,
+            found: digest,
+        }
+    );
+    Ok(())
+}
+
+/// Returns the largest zstd window log we can decode a patch with while keeping the source image
+/// in memory, or None if there isn't room to apply a patch to this source at all.  The decoder
+/// keeps its own copy of the source image alongside ours, and a window of the patched image;
+/// zstd refuses frames that need a larger window than we allow, so a patch that doesn't fit is
+/// an error rather than something that gets us killed.
+fn window_log_max(source_size: u64, available: u64) -> Option<u32> {
+    if source_size > 1 << ZSTD_WINDOW_LOG_MAX {
+        return None;
+    }
+    let window = available.checked_sub(source_size * 2)?;
+    if window == 0 {
+        return None;
+    }
+    // The largest power of two that fits.
+    let window_log = 63 - window.leading_zeros();
+    if window_log < ZSTD_WINDOW_LOG_MIN {
+        return None;
+    }
+    Some(window_log.min(ZSTD_WINDOW_LOG_MAX))
+}
+
+/// Returns the memory available for new allocations without swapping, in bytes, as estimated by
+/// the kernel.
+fn available_memory() -> Result<u64> {
+    let meminfo = fs::read_to_string(MEMINFO_PATH).context(error::MemInfo)?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .map(|kib| kib * 1024)
+        .context(error::MemInfoParse)
+}
+
+/// Applies the patch read from `patch` to `source`, writing the result to `output`.  The patch
+/// can't use a zstd window larger than `2^window_log_max` bytes.  Returns the hex-encoded SHA-256
+/// digest of the result.
+fn apply_patch<R, W>(
+    format: DeltaFormat,
+    patch: R,
+    source: &[u8],
+    window_log_max: u32,
+    output: W,
+    target: &str,
+) -> Result<String>
+where
+    R: Read,
+    W: Write,
+{
+    let mut output = DigestWriter {
+        inner: output,
+        digest: Sha256::new(),
+    };
+    match format {
+        DeltaFormat::ZstdPatchFrom => {
+            let mut decoder =
+                zstd::stream::read::Decoder::with_dictionary(BufReader::new(patch), source)
+                    .context(error::DeltaDecode { target })?;
+            decoder
+                .window_log_max(window_log_max)
+                .context(error::DeltaDecode { target })?;
+            io::copy(&mut decoder, &mut output).context(error::WriteUpdate)?;
+        }
+    }
+    output.inner.flush().context(error::WriteUpdate)?;
+    Ok(hex::encode(output.digest.finalize()))
+}
+
+/// Passes writes through to `inner`, keeping a digest of everything written.
+struct DigestWriter<W> {
+    inner: W,
+    digest: Sha256,
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zstd_patch_from() {
+        let source: Vec<u8> = (0..100_000u32).flat_map(u32::to_le_bytes).collect();
+        let mut image = source.clone();
+        image[1234..1300].copy_from_slice(&[7; 66]);
+        image.extend_from_slice(b"new data at the end");
+
+        let mut encoder =
+            zstd::stream::write::Encoder::with_dictionary(Vec::new(), 3, &source).unwrap();
+        encoder.long_distance_matching(true).unwrap();
+        encoder.write_all(&image).unwrap();
+        let patch = encoder.finish().unwrap();
+        assert!(patch.len() < image.len() / 10);
+
+        let mut output = Vec::new();
+        let digest = apply_patch(
+            DeltaFormat::ZstdPatchFrom,
+            patch.as_slice(),
+            &source,
+            ZSTD_WINDOW_LOG_MAX,
+            &mut output,
+            "patch",
+        )
+        .unwrap();
+        assert_eq!(output, image);
+        assert_eq!(digest, hex::encode(Sha256::digest(&image)));
+
+        // A patch applied to the wrong source doesn't produce the image.
+        let mut output = Vec::new();
+        let result = apply_patch(
+            DeltaFormat::ZstdPatchFrom,
+            patch.as_slice(),
+            &source[1..],
+            ZSTD_WINDOW_LOG_MAX,
+            &mut output,
+            "patch",
+        );
+        assert!(result.is_err() || output != image);
+
+        // A patch that needs a larger window than we allow isn't applied.
+        apply_patch(
+            DeltaFormat::ZstdPatchFrom,
+            patch.as_slice(),
+            &source,
+            ZSTD_WINDOW_LOG_MIN,
+            &mut Vec::new(),
+            "patch",
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn window_log_max_fits_memory() {
+        let gib = 1 << 30;
+        // The source image takes twice its size, and the window gets what's left.
+        assert_eq!(window_log_max(gib, 4 * gib), Some(31));
+        assert_eq!(window_log_max(gib, 3 * gib - 1), Some(29));
+        assert_eq!(window_log_max(gib, 16 * gib), Some(ZSTD_WINDOW_LOG_MAX));
+        assert_eq!(window_log_max(gib, 2 * gib), None);
+        assert_eq!(window_log_max(gib, gib), None);
+        // Sources larger than the largest window can't be patched.
+        assert_eq!(window_log_max(4 * gib, 64 * gib), None);
+    }
+}
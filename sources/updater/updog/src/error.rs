@@ -53,6 +53,132 @@ pub(crate) enum Error {
         path: PathBuf,
     },
 
+    #[snafu(display("Failed to create patch {}: {}", path.display(), source))]
+    DeltaCreate {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to apply patch {}: {}", target, source))]
+    DeltaDecode {
+        target: String,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Not enough memory to apply patch {}", target))]
+    DeltaMemory {
+        target: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Partition {} is too small for the patch's source image; expected {} bytes, found {}",
+        path.display(),
+        expected,
+        found
+    ))]
+    DeltaSourceSize {
+        path: PathBuf,
+        expected: u64,
+        found: u64,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Image from patch {} has SHA-256 digest {}, expected {}",
+        target,
+        found,
+        expected
+    ))]
+    DeltaVerify {
+        target: String,
+        expected: String,
+        found: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "No update {}-{}-{} in the manifest to add a delta to",
+        arch,
+        variant,
+        version
+    ))]
+    DeltaUpdateNotFound {
+        arch: String,
+        variant: String,
+        version: semver::Version,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to read image {}: {}", path.display(), source))]
+    ImageRead {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to clear {} for local repository: {}", path.display(), source))]
+    LocalRepoClear {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "No repository found in {}; expected the output of 'pubsys repo', with a 'targets' directory",
+        path.display()
+    ))]
+    LocalRepoLayout { path: PathBuf, backtrace: Backtrace },
+
+    #[snafu(display("Invalid local repository location '{}'", location))]
+    LocalRepoLocation {
+        location: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to run mount for repository device {}: {}", device.display(), source))]
+    LocalRepoMount {
+        device: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to mount repository device {}: {}", device.display(), stderr))]
+    LocalRepoMountFailed {
+        device: PathBuf,
+        stderr: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to open local repository {}: {}", path.display(), source))]
+    LocalRepoOpen {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to unpack repository archive {}: {}", path.display(), source))]
+    LocalRepoUnpack {
+        path: PathBuf,
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Local repository {} has no metadata for variant '{}' on {}",
+        path.display(),
+        variant,
+        arch
+    ))]
+    LocalRepoVariant {
+        path: PathBuf,
+        variant: String,
+        arch: String,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Logger setup error: {}", source))]
     Logger { source: log::SetLoggerError },
 
@@ -87,6 +213,15 @@ pub(crate) enum Error {
         backtrace: Backtrace,
     },
 
+    #[snafu(display("Failed to read available memory from /proc/meminfo: {}", source))]
+    MemInfo {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to find available memory in /proc/meminfo"))]
+    MemInfoParse { backtrace: Backtrace },
+
     #[snafu(display("Metadata error: {}", source))]
     Metadata {
         source: tough::error::Error,
@@ -168,6 +303,9 @@ pub(crate) enum Error {
     #[snafu(display("No update available"))]
     UpdateNotAvailable { backtrace: Backtrace },
 
+    #[snafu(display("Updates are disabled by the 'settings.updates.policy' setting"))]
+    UpdatesDisabled { backtrace: Backtrace },
+
     #[snafu(display("Failed to serialize update information: {}", source))]
     UpdateSerialize {
         source: serde_json::Error,
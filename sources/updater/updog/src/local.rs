@@ -0,0 +1,272 @@
+//! Opens update repositories stored on the host, for hosts that can't reach the configured
+//! repository, like those in air-gapped networks.  The repository is the output of `pubsys repo`,
+//! given as a `file://` URL or path to a directory, a tarball of that directory, or a block device
+//! holding it.  It's loaded through tough like a remote repository, so it's checked against the
+//! same trusted root.json.
+
+use crate::error::{self, Result};
+use flate2::read::GzDecoder;
+use log::{debug, warn};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use url::Url;
+
+/// This is where we unpack or mount a repository that isn't already a directory.
+const LOCAL_REPO_PATH: &str = "/var/cache/bottlerocket-local-repo";
+
+/// A repository stored on the host, and the URLs tough can load it from.  If we had to unpack or
+/// mount the repository to read it, that's undone when this is dropped.
+#[derive(Debug)]
+pub(crate) struct LocalRepository {
+    pub(crate) metadata_base_url: String,
+    pub(crate) targets_base_url: String,
+    cleanup: Option<Cleanup>,
+}
+
+/// What we have to undo after reading a repository.
+#[derive(Debug)]
+enum Cleanup {
+    Remove(PathBuf),
+    Unmount(PathBuf),
+}
+
+impl LocalRepository {
+    /// Opens the repository at `location`.  Its metadata is taken from the directory for the given
+    /// variant and architecture, like the default `metadata_base_url`.
+    pub(crate) fn open(location: &str, variant: &str, arch: &str) -> Result<Self> {
+        let path = if location.starts_with("file://") {
+            Url::parse(location)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .context(error::LocalRepoLocation { location })?
+        } else {
+            PathBuf::from(location)
+        };
+        let path = fs::canonicalize(&path).context(error::LocalRepoOpen { path: &path })?;
+        let file_type = fs::metadata(&path)
+            .context(error::LocalRepoOpen { path: &path })?
+            .file_type();
+
+        let mut repository = Self {
+            metadata_base_url: String::new(),
+            targets_base_url: String::new(),
+            cleanup: None,
+        };
+        let dir = if file_type.is_dir() {
+            path
+        } else {
+            let work_dir = PathBuf::from(LOCAL_REPO_PATH);
+            clear_work_dir(&work_dir)?;
+            if file_type.is_block_device() {
+                mount(&path, &work_dir)?;
+                repository.cleanup = Some(Cleanup::Unmount(work_dir.clone()));
+            } else {
+                repository.cleanup = Some(Cleanup::Remove(work_dir.clone()));
+                unpack(&path, &work_dir)?;
+            }
+            work_dir
+        };
+
+        let root = find_root(&dir).context(error::LocalRepoLayout { path: &dir })?;
+        let metadata_dir = root.join(variant).join(arch);
+        ensure!(
+            metadata_dir.is_dir(),
+            error::LocalRepoVariant {
+                path: &root,
+                variant,
+                arch,
+            }
+        );
+        repository.metadata_base_url = dir_url(&metadata_dir)?;
+        repository.targets_base_url = dir_url(&root.join("targets"))?;
+        debug!(
+            "Using local repository at {}, metadata from {}",
+            root.display(),
+            repository.metadata_base_url
+        );
+        Ok(repository)
+    }
+}
+
+impl Drop for LocalRepository {
+    fn drop(&mut self) {
+        match &self.cleanup {
+            Some(Cleanup::Remove(dir)) => {
+                if let Err(e) = fs::remove_dir_all(dir) {
+                    warn!(
+                        "Unable to remove unpacked repository {}: {}",
+                        dir.display(),
+                        e
+                    );
+                }
+            }
+            Some(Cleanup::Unmount(dir)) => match Command::new("umount").arg(dir).status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("Unable to unmount {}: {}", dir.display(), status),
+                Err(e) => warn!("Unable to unmount {}: {}", dir.display(), e),
+            },
+            None => {}
+        }
+    }
+}
+
+/// Returns an empty `work_dir`, undoing anything left there by an earlier run that didn't finish.
+fn clear_work_dir(work_dir: &Path) -> Result<()> {
+    if work_dir.exists() {
+        // A repository device may still be mounted here; if not, this fails harmlessly.
+        let _ = Command::new("umount").arg(work_dir).status();
+        fs::remove_dir_all(work_dir).context(error::LocalRepoClear { path: work_dir })?;
+    }
+    fs::create_dir_all(work_dir).context(error::DirCreate { path: work_dir })
+}
+
+/// Mounts the filesystem on `device` read-only at `dir`.
+fn mount(device: &Path, dir: &Path) -> Result<()> {
+    let output = Command::new("mount")
+        .args(["-o", "ro"])
+        .arg(device)
+        .arg(dir)
+        .output()
+        .context(error::LocalRepoMount { device })?;
+    ensure!(
+        output.status.success(),
+        error::LocalRepoMountFailed {
+            device,
+            stderr: String::from_utf8_lossy(&output.stderr),
+        }
+    );
+    Ok(())
+}
+
+/// Unpacks the tarball at `archive`, which may be gzip-compressed, into `dir`.
+fn unpack(archive: &Path, dir: &Path) -> Result<()> {
+    let mut reader =
+        BufReader::new(File::open(archive).context(error::LocalRepoOpen { path: archive })?);
+    // Gzip streams start with these two bytes; a tar header can't.
+    let gzipped = reader
+        .fill_buf()
+        .context(error::LocalRepoUnpack { path: archive })?
+        .starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    tar::Archive::new(reader)
+        .unpack(dir)
+        .context(error::LocalRepoUnpack { path: archive })
+}
+
+/// Finds the top of the repository in `dir`, which holds the `targets` directory written by
+/// `pubsys repo`.  Tarballs often put everything in one top-level directory, so we also look there.
+fn find_root(dir: &Path) -> Option<PathBuf> {
+    if dir.join("targets").is_dir() {
+        return Some(dir.to_path_buf());
+    }
+    let mut subdirs = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir());
+    match (subdirs.next(), subdirs.next()) {
+        (Some(subdir), None) if subdir.join("targets").is_dir() => Some(subdir),
+        _ => None,
+    }
+}
+
+/// Returns the `file://` URL of a directory, with the trailing slash tough needs to join target
+/// and metadata names onto it.
+fn dir_url(dir: &Path) -> Result<String> {
+    Url::from_directory_path(dir)
+        .ok()
+        .map(String::from)
+        .context(error::LocalRepoLocation {
+            location: dir.display().to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn make_repo(dir: &Path) {
+        fs::create_dir_all(dir.join("targets")).unwrap();
+        fs::create_dir_all(dir.join("aws-k8s-1.21").join("x86_64")).unwrap();
+    }
+
+    #[test]
+    fn directory() {
+        let tmp = TempDir::new().unwrap();
+        make_repo(tmp.path());
+        let root = fs::canonicalize(tmp.path()).unwrap();
+
+        for location in &[
+            root.display().to_string(),
+            Url::from_directory_path(&root).unwrap().to_string(),
+        ] {
+            let repository = LocalRepository::open(location, "aws-k8s-1.21", "x86_64").unwrap();
+            assert_eq!(
+                repository.metadata_base_url,
+                format!("file://{}/aws-k8s-1.21/x86_64/", root.display())
+            );
+            assert_eq!(
+                repository.targets_base_url,
+                format!("file://{}/targets/", root.display())
+            );
+        }
+    }
+
+    #[test]
+    fn nested_directory() {
+        let tmp = TempDir::new().unwrap();
+        make_repo(&tmp.path().join("repo"));
+        let root = fs::canonicalize(tmp.path()).unwrap();
+
+        let repository =
+            LocalRepository::open(&root.display().to_string(), "aws-k8s-1.21", "x86_64").unwrap();
+        assert_eq!(
+            repository.targets_base_url,
+            format!("file://{}/repo/targets/", root.display())
+        );
+    }
+
+    #[test]
+    fn gzipped_tarball() {
+        let tmp = TempDir::new().unwrap();
+        let bundle = tmp.path().join("bundle");
+        make_repo(&bundle);
+        fs::write(bundle.join("targets").join("target"), "payload").unwrap();
+        let archive_path = tmp.path().join("bundle.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&archive_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        builder.append_dir_all("repo", &bundle).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let dir = tmp.path().join("unpacked");
+        fs::create_dir(&dir).unwrap();
+        unpack(&archive_path, &dir).unwrap();
+        let root = find_root(&dir).unwrap();
+        assert_eq!(root, dir.join("repo"));
+        assert_eq!(
+            fs::read_to_string(root.join("targets").join("target")).unwrap(),
+            "payload"
+        );
+    }
+
+    #[test]
+    fn missing_variant() {
+        let tmp = TempDir::new().unwrap();
+        make_repo(tmp.path());
+        let location = tmp.path().display().to_string();
+
+        assert!(LocalRepository::open(&location, "aws-ecs-1", "x86_64").is_err());
+        assert!(LocalRepository::open(&location, "aws-k8s-1.21", "aarch64").is_err());
+    }
+}
@@ -1,26 +1,29 @@
 #![deny(rust_2018_idioms)]
 #![warn(clippy::pedantic)]
 
+mod delta;
 mod error;
+mod local;
 mod transport;
 
 use crate::error::Result;
-use crate::transport::{HttpQueryTransport, QueryParams};
+use crate::local::LocalRepository;
+use crate::transport::{DownloadSettings, HttpQueryTransport, Progress, QueryParams};
 use bottlerocket_release::BottlerocketRelease;
 use chrono::Utc;
 use log::debug;
-use model::modeled_types::FriendlyVersion;
+use model::modeled_types::{FriendlyVersion, SemanticVersion, UpdatePolicy, VersionRequirement};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use signal_hook::consts::SIGTERM;
 use signal_hook::iterator::Signals;
 use signpost::State;
 use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
-use snafu::{ErrorCompat, OptionExt, ResultExt};
+use snafu::{ensure, ErrorCompat, OptionExt, ResultExt};
 use std::convert::{TryFrom, TryInto};
 use std::fs::{self, File, OpenOptions};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 use std::thread;
@@ -42,6 +45,9 @@ const MIGRATION_PATH: &str = "/var/lib/bottlerocket-migrations";
 /// This is where we store the TUF metadata used by migrator after reboot.
 const METADATA_PATH: &str = "/var/cache/bottlerocket-metadata";
 
+/// This is where we keep partial downloads of update images, so they can be resumed.
+const DOWNLOAD_PATH: &str = "/var/cache/bottlerocket-downloads";
+
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 enum Command {
@@ -63,9 +69,45 @@ struct Config {
     ignore_waves: bool,
     https_proxy: Option<String>,
     no_proxy: Option<Vec<String>>,
-    // TODO API sourced configuration, eg.
-    // blacklist: Option<Vec<Version>>,
-    // mode: Option<{Automatic, Managed, Disabled}>
+    // If the policy is "disabled", updog refuses to write or apply updates.
+    policy: Option<UpdatePolicy>,
+    denied_versions: Option<Vec<SemanticVersion>>,
+    allowed_versions: Option<VersionRequirement>,
+    // Maximum download rate for update images, in KiB per second.
+    download_rate_limit: Option<u64>,
+}
+
+impl Config {
+    fn version_filter(&self) -> VersionFilter<'_> {
+        VersionFilter {
+            denied: self.denied_versions.as_deref().unwrap_or_default(),
+            allowed: self.allowed_versions.as_ref(),
+        }
+    }
+}
+
+/// The versions the user allows updating to, from the `denied-versions` and `allowed-versions`
+/// update settings.
+#[derive(Debug, Default)]
+struct VersionFilter<'a> {
+    denied: &'a [SemanticVersion],
+    allowed: Option<&'a VersionRequirement>,
+}
+
+impl VersionFilter<'_> {
+    /// Returns why the given version may not be updated to, or None if it may.
+    fn skip_reason(&self, version: &Version) -> Option<String> {
+        if self.denied.iter().any(|denied| denied.version() == version) {
+            return Some("it's in settings.updates.denied-versions".to_string());
+        }
+        match self.allowed {
+            Some(allowed) if !allowed.matches(version) => Some(format!(
+                "it doesn't match settings.updates.allowed-versions '{}'",
+                allowed
+            )),
+            _ => None,
+        }
+    }
 }
 
 /// Prints a more specific message before exiting through usage().
@@ -97,6 +139,7 @@ SUBCOMMANDS:
         [ -i | --image version ]      Update to a specfic image version
         [ -n | --now ]                Update immediately, ignoring wave limits
         [ -t | --timestamp time ]     The timestamp to execute an update from
+        [ --progress-file path ]      Write download progress to this file, as JSON
 
     update-apply            Update boot flags (after having called update-image)
         [ -r | --reboot ]             Reboot after updating boot flags
@@ -105,6 +148,9 @@ SUBCOMMANDS:
 
 GLOBAL OPTIONS:
     [ -j | --json ]               JSON-formatted output
+    [ --repository location ]     Use the repository at this file:// URL or path, which may be
+                                  a directory, tarball, or block device, instead of the
+                                  configured one
     [ --log-level trace|debug|info|warn|error ]  Set logging verbosity");
     std::process::exit(1)
 }
@@ -136,33 +182,79 @@ fn load_repository(transport: HttpQueryTransport, config: &Config) -> Result<Rep
     .context(error::Metadata)
 }
 
+/// Returns true if the update is for our variant and architecture and is ready for us, before the
+/// user's version filter is applied.
+fn is_candidate(update: &Update, variant: &str, ignore_waves: bool, seed: u32) -> bool {
+    update.variant == *variant
+        && update.arch == TARGET_ARCH
+        && update.version <= update.max_version
+        && (ignore_waves || update.update_ready(seed, Utc::now()))
+}
+
 fn applicable_updates<'a>(
     manifest: &'a Manifest,
     variant: &str,
     ignore_waves: bool,
     seed: u32,
+    filter: &VersionFilter<'_>,
 ) -> Vec<&'a Update> {
     let mut updates: Vec<&Update> = manifest
         .updates
         .iter()
-        .filter(|u| {
-            u.variant == *variant
-                && u.arch == TARGET_ARCH
-                && u.version <= u.max_version
-                && (ignore_waves || u.update_ready(seed, Utc::now()))
-        })
+        .filter(|u| is_candidate(u, variant, ignore_waves, seed))
+        .filter(|u| filter.skip_reason(&u.version).is_none())
         .collect();
     // sort descending
     updates.sort_unstable_by(|a, b| b.version.cmp(&a.version));
     updates
 }
 
-// TODO use config if there is api-sourced configuration that could affect this
-// TODO updog.toml may include settings that cause us to ignore/delay
-// certain/any updates;
-//  Ignore Specific Target Version
-//  Ignore Any Target
-//  ...
+/// Returns the updates that the user's version filter keeps us from choosing, with the reason for
+/// each, sorted descending.  Only updates that could otherwise have been chosen are returned: the
+/// target version, if we're given one, or else the versions newer than the running version.
+fn skipped_updates<'a>(
+    manifest: &'a Manifest,
+    version: &Version,
+    variant: &str,
+    ignore_waves: bool,
+    seed: u32,
+    target: Option<&Version>,
+    filter: &VersionFilter<'_>,
+) -> Vec<(&'a Update, String)> {
+    let mut skipped: Vec<(&Update, String)> = manifest
+        .updates
+        .iter()
+        .filter(|u| is_candidate(u, variant, ignore_waves, seed))
+        .filter(|u| match target {
+            Some(target) => u.version == *target,
+            None => u.version > *version,
+        })
+        .filter_map(|u| Some((u, filter.skip_reason(&u.version)?)))
+        .collect();
+    skipped.sort_unstable_by(|a, b| b.0.version.cmp(&a.0.version));
+    skipped
+}
+
+/// Returns the version the update settings lock us to, or None if they allow the latest version.
+fn version_lock_target(version_lock: &str) -> Result<Option<Version>> {
+    if version_lock == "latest" {
+        return Ok(None);
+    }
+    // Make sure the version string from the config is a valid version string that might be prefixed with 'v'
+    let friendly_version_lock =
+        FriendlyVersion::try_from(version_lock).context(error::BadVersionConfig {
+            version_str: version_lock,
+        })?;
+    // Convert back to semver::Version
+    let semver_version_lock = friendly_version_lock
+        .try_into()
+        .context(error::BadVersion {
+            version_str: version_lock,
+        })?;
+    Ok(Some(semver_version_lock))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn update_required<'a>(
     manifest: &'a Manifest,
     version: &Version,
@@ -170,26 +262,16 @@ fn update_required<'a>(
     ignore_waves: bool,
     seed: u32,
     version_lock: &str,
+    filter: &VersionFilter<'_>,
     force_version: Option<Version>,
 ) -> Result<Option<&'a Update>> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = applicable_updates(manifest, variant, ignore_waves, seed, filter);
 
     if let Some(forced_version) = force_version {
         return Ok(updates.into_iter().find(|u| u.version == forced_version));
     }
 
-    if version_lock != "latest" {
-        // Make sure the version string from the config is a valid version string that might be prefixed with 'v'
-        let friendly_version_lock =
-            FriendlyVersion::try_from(version_lock).context(error::BadVersionConfig {
-                version_str: version_lock,
-            })?;
-        // Convert back to semver::Version
-        let semver_version_lock = friendly_version_lock
-            .try_into()
-            .context(error::BadVersion {
-                version_str: version_lock,
-            })?;
+    if let Some(semver_version_lock) = version_lock_target(version_lock)? {
         // If the configured version-lock matches our current version, we won't update to the same version
         return if semver_version_lock == *version {
             Ok(None)
@@ -272,7 +354,24 @@ fn retrieve_migrations(
     Ok(())
 }
 
-fn update_image(update: &Update, repository: &Repository) -> Result<()> {
+/// Returns the size of the given target, as listed in the repository metadata.
+fn target_length(repository: &Repository, target: &str) -> Result<u64> {
+    let target_name = target.try_into().context(error::TargetName { target })?;
+    let target = repository
+        .targets()
+        .signed
+        .find_target(&target_name)
+        .ok()
+        .context(error::TargetNotFound { target })?;
+    Ok(target.length)
+}
+
+fn update_image(
+    update: &Update,
+    repository: &Repository,
+    current_version: &Version,
+    progress: &Progress,
+) -> Result<()> {
     let mut gpt_state = State::load().context(error::PartitionTableRead)?;
     gpt_state.clear_inactive();
     // Write out the clearing of the inactive partition immediately, because we're about to
@@ -282,10 +381,50 @@ fn update_image(update: &Update, repository: &Repository) -> Result<()> {
 
     let inactive = gpt_state.inactive_set();
 
+    // If there's a delta from our version, the update can be made from the active partitions and
+    // a patch, which is much smaller than the full images.  The patched images are verified, and
+    // if anything goes wrong, including not having the memory to apply a patch, we fall back to
+    // the full images.  Like the full images, the patches are read through the transport, which
+    // counts what it reads toward the progress.
+    let mut written = false;
+    if let Some(delta) = update.deltas.get(current_version) {
+        progress.start(
+            target_length(repository, &delta.root.target)?
+                + target_length(repository, &delta.boot.target)?
+                + target_length(repository, &delta.hash.target)?,
+        );
+        match delta::write_delta_images(repository, delta, gpt_state.active_set(), inactive) {
+            Ok(()) => written = true,
+            Err(e) => eprintln!(
+                "Unable to apply delta update from {}, using full images: {}",
+                current_version, e
+            ),
+        }
+    }
+
     // TODO Do we want to recover the inactive side on an error?
-    write_target_to_disk(repository, &update.images.root, &inactive.root)?;
-    write_target_to_disk(repository, &update.images.boot, &inactive.boot)?;
-    write_target_to_disk(repository, &update.images.hash, &inactive.hash)?;
+    if !written {
+        progress.start(
+            target_length(repository, &update.images.root)?
+                + target_length(repository, &update.images.boot)?
+                + target_length(repository, &update.images.hash)?,
+        );
+        write_target_to_disk(repository, &update.images.root, &inactive.root)?;
+        write_target_to_disk(repository, &update.images.boot, &inactive.boot)?;
+        write_target_to_disk(repository, &update.images.hash, &inactive.hash)?;
+    }
+    progress.finish();
+
+    // Anything left in the download directory is from updates we didn't finish, and won't be
+    // needed now that we have this one.
+    if Path::new(DOWNLOAD_PATH).exists() {
+        if let Err(e) = fs::remove_dir_all(DOWNLOAD_PATH) {
+            eprintln!(
+                "Unable to remove partial downloads in {}: {}",
+                DOWNLOAD_PATH, e
+            );
+        }
+    }
 
     gpt_state.mark_inactive_valid();
     gpt_state.write().context(error::PartitionTableWrite)?;
@@ -326,8 +465,9 @@ fn list_updates(
     json: bool,
     ignore_waves: bool,
     seed: u32,
+    filter: &VersionFilter<'_>,
 ) -> Result<()> {
-    let updates = applicable_updates(manifest, variant, ignore_waves, seed);
+    let updates = applicable_updates(manifest, variant, ignore_waves, seed, filter);
     if json {
         println!(
             "{}",
@@ -341,6 +481,35 @@ fn list_updates(
     Ok(())
 }
 
+/// Tells the user about any update we'd have chosen if not for their version filter.  The messages
+/// go to stderr so they don't interfere with JSON output; callers like `apiclient update check`
+/// show lines starting with "Skipping update" to the user.
+fn report_skipped_updates(
+    manifest: &Manifest,
+    version: &Version,
+    variant: &str,
+    ignore_waves: bool,
+    config: &Config,
+    force_version: Option<&Version>,
+) -> Result<()> {
+    let target = match force_version {
+        Some(forced) => Some(forced.clone()),
+        None => version_lock_target(&config.version_lock)?,
+    };
+    for (u, reason) in skipped_updates(
+        manifest,
+        version,
+        variant,
+        ignore_waves,
+        config.seed,
+        target.as_ref(),
+        &config.version_filter(),
+    ) {
+        eprintln!("Skipping update to {}: {}", u.version, reason);
+    }
+    Ok(())
+}
+
 /// Struct to hold the specified command line argument values
 struct Arguments {
     subcommand: String,
@@ -351,6 +520,8 @@ struct Arguments {
     all: bool,
     reboot: bool,
     variant: Option<String>,
+    progress_file: Option<PathBuf>,
+    repository: Option<String>,
 }
 
 /// Parse the command line arguments to get the user-specified values
@@ -363,6 +534,8 @@ fn parse_args(args: std::env::Args) -> Arguments {
     let mut all = false;
     let mut reboot = false;
     let mut variant = None;
+    let mut progress_file = None;
+    let mut repository = None;
 
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
@@ -388,6 +561,18 @@ fn parse_args(args: std::env::Args) -> Arguments {
                         .unwrap_or_else(|| usage_msg("Did not give argument to --variant")),
                 );
             }
+            "--progress-file" => {
+                progress_file =
+                    Some(PathBuf::from(iter.next().unwrap_or_else(|| {
+                        usage_msg("Did not give argument to --progress-file")
+                    })));
+            }
+            "--repository" => {
+                repository = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --repository")),
+                );
+            }
             "-n" | "--now" | "--ignore-waves" => {
                 ignore_waves = true;
             }
@@ -420,6 +605,8 @@ fn parse_args(args: std::env::Args) -> Arguments {
         all,
         reboot,
         variant,
+        progress_file,
+        repository,
     }
 }
 
@@ -498,11 +685,39 @@ fn main_inner() -> Result<()> {
     let command =
         serde_plain::from_str::<Command>(&arguments.subcommand).unwrap_or_else(|_| usage());
 
-    let config = load_config()?;
+    let mut config = load_config()?;
+    if matches!(
+        command,
+        Command::Update | Command::UpdateImage | Command::UpdateApply
+    ) {
+        let disabled = matches!(&config.policy, Some(policy) if policy.is_disabled());
+        ensure!(!disabled, error::UpdatesDisabled);
+    }
     set_https_proxy_environment_variables(&config.https_proxy, &config.no_proxy)?;
     let current_release = BottlerocketRelease::new().context(error::ReleaseVersion)?;
     let variant = arguments.variant.unwrap_or(current_release.variant_id);
-    let transport = HttpQueryTransport::new();
+    // A repository stored on the host replaces the configured one.  It has to stay open, and any
+    // archive unpacked, until we're done reading targets from it.
+    let local_repository = match &arguments.repository {
+        Some(location) => Some(LocalRepository::open(location, &variant, TARGET_ARCH)?),
+        None => None,
+    };
+    if let Some(local) = &local_repository {
+        config
+            .metadata_base_url
+            .clone_from(&local.metadata_base_url);
+        config.targets_base_url.clone_from(&local.targets_base_url);
+    }
+    let mut transport = HttpQueryTransport::new();
+    if matches!(command, Command::Update | Command::UpdateImage) {
+        transport = transport.download_settings(DownloadSettings {
+            targets_base_url: config.targets_base_url.trim_end_matches('/').to_string(),
+            checkpoint_dir: PathBuf::from(DOWNLOAD_PATH),
+            rate_limit: config.download_rate_limit.map(|kib| kib * 1024),
+            progress_file: arguments.progress_file.clone(),
+        });
+    }
+    let progress = transport.progress();
     // get a shared pointer to the transport's query_params so we can add metrics information to
     // the transport's HTTP calls.
     let mut query_params = transport.query_params();
@@ -512,6 +727,14 @@ fn main_inner() -> Result<()> {
     let ignore_waves = arguments.ignore_waves || config.ignore_waves;
     match command {
         Command::CheckUpdate | Command::Whats => {
+            report_skipped_updates(
+                &manifest,
+                &current_release.version_id,
+                &variant,
+                ignore_waves,
+                &config,
+                arguments.force_version.as_ref(),
+            )?;
             if arguments.all {
                 return list_updates(
                     &manifest,
@@ -519,6 +742,7 @@ fn main_inner() -> Result<()> {
                     arguments.json,
                     ignore_waves,
                     config.seed,
+                    &config.version_filter(),
                 );
             }
 
@@ -529,6 +753,7 @@ fn main_inner() -> Result<()> {
                 ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &config.version_filter(),
                 arguments.force_version,
             )?
             .context(error::UpdateNotAvailable)?;
@@ -536,6 +761,14 @@ fn main_inner() -> Result<()> {
             output(arguments.json, &update, &fmt_full_version(&update))?;
         }
         Command::Update | Command::UpdateImage => {
+            report_skipped_updates(
+                &manifest,
+                &current_release.version_id,
+                &variant,
+                ignore_waves,
+                &config,
+                arguments.force_version.as_ref(),
+            )?;
             if let Some(u) = update_required(
                 &manifest,
                 &current_release.version_id,
@@ -543,6 +776,7 @@ fn main_inner() -> Result<()> {
                 ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &config.version_filter(),
                 arguments.force_version,
             )? {
                 eprintln!("Starting update to {}", u.version);
@@ -554,7 +788,7 @@ fn main_inner() -> Result<()> {
                     u,
                     &current_release.version_id,
                 )?;
-                update_image(u, &repository)?;
+                update_image(u, &repository, &current_release.version_id, &progress)?;
                 if command == Command::Update {
                     update_flags()?;
                     if arguments.reboot {
@@ -673,6 +907,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            policy: None,
+            denied_versions: None,
+            allowed_versions: None,
+            download_rate_limit: None,
         };
         let version = Version::parse("1.18.0").unwrap();
         let variant = String::from("bottlerocket-aws-eks");
@@ -685,6 +923,7 @@ mod tests {
                 config.ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &config.version_filter(),
                 None
             )
             .unwrap()
@@ -706,6 +945,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            policy: None,
+            denied_versions: None,
+            allowed_versions: None,
+            download_rate_limit: None,
         };
 
         let version = Version::parse("0.1.3").unwrap();
@@ -717,6 +960,7 @@ mod tests {
             config.ignore_waves,
             config.seed,
             &config.version_lock,
+            &config.version_filter(),
             None,
         )
         .unwrap();
@@ -744,6 +988,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            policy: None,
+            denied_versions: None,
+            allowed_versions: None,
+            download_rate_limit: None,
         };
 
         let version = Version::parse("1.10.0").unwrap();
@@ -755,6 +1003,7 @@ mod tests {
             config.ignore_waves,
             config.seed,
             &config.version_lock,
+            &config.version_filter(),
             None,
         )
         .unwrap();
@@ -770,6 +1019,75 @@ mod tests {
         }
     }
 
+    #[test]
+    fn version_filters() {
+        // The same manifest as test_multiple, where 1.15.0 would be chosen without filters.
+        let path = "tests/data/multiple.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let version = Version::parse("1.10.0").unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let chosen = |denied: &[&str], allowed: Option<&str>| {
+            let denied: Vec<SemanticVersion> =
+                denied.iter().map(|v| (*v).try_into().unwrap()).collect();
+            let allowed: Option<VersionRequirement> = allowed.map(|a| a.try_into().unwrap());
+            let filter = VersionFilter {
+                denied: &denied,
+                allowed: allowed.as_ref(),
+            };
+            update_required(
+                &manifest, &version, &variant, false, 123, "latest", &filter, None,
+            )
+            .unwrap()
+            .map(|u| u.version.to_string())
+        };
+
+        assert_eq!(chosen(&[], None), Some("1.15.0".to_string()));
+        assert_eq!(chosen(&["v1.15.0"], None), Some("1.13.0".to_string()));
+        assert_eq!(
+            chosen(&[], Some(">=1.11, <1.14")),
+            Some("1.13.0".to_string())
+        );
+        assert_eq!(chosen(&["1.13.0"], Some("<1.14")), None);
+    }
+
+    #[test]
+    fn skipped_versions() {
+        // The same manifest as test_multiple, with x86_64 updates 1.13.0 and 1.15.0.
+        let path = "tests/data/multiple.json";
+        let manifest: Manifest = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        let variant = String::from("bottlerocket-aws-eks");
+        let skipped = |version: &str, target: Option<&str>, allowed: &str| {
+            let version = Version::parse(version).unwrap();
+            let target = target.map(|t| Version::parse(t).unwrap());
+            let allowed: VersionRequirement = allowed.try_into().unwrap();
+            let filter = VersionFilter {
+                denied: &[],
+                allowed: Some(&allowed),
+            };
+            skipped_updates(
+                &manifest,
+                &version,
+                &variant,
+                false,
+                123,
+                target.as_ref(),
+                &filter,
+            )
+            .into_iter()
+            .map(|(u, _)| u.version.to_string())
+            .collect::<Vec<_>>()
+        };
+
+        // Newer versions outside the allowed range are reported.
+        assert_eq!(skipped("1.10.0", None, "<1.13"), vec!["1.15.0", "1.13.0"]);
+        // Older versions aren't, since we'd never have chosen them.
+        assert_eq!(skipped("1.14.0", None, "<1.14"), vec!["1.15.0"]);
+        assert!(skipped("1.14.0", None, ">=1.14").is_empty());
+        // With a target, only the target is reported, even if it's older.
+        assert_eq!(skipped("1.14.0", Some("1.13.0"), ">=1.14"), vec!["1.13.0"]);
+        assert_eq!(skipped("1.10.0", Some("1.15.0"), "<1.15"), vec!["1.15.0"]);
+    }
+
     #[test]
     fn force_update_version() {
         // A manifest with four updates; two valid, one which exceeds the max
@@ -786,6 +1104,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            policy: None,
+            denied_versions: None,
+            allowed_versions: None,
+            download_rate_limit: None,
         };
 
         let version = Version::parse("1.10.0").unwrap();
@@ -798,6 +1120,7 @@ mod tests {
             config.ignore_waves,
             config.seed,
             &config.version_lock,
+            &config.version_filter(),
             Some(forced),
         )
         .unwrap();
@@ -856,6 +1179,7 @@ mod tests {
                 root: String::from("boot"),
                 hash: String::from("boot"),
             },
+            deltas: BTreeMap::new(),
         };
 
         let current_version = Version::parse("1.0.0").unwrap();
@@ -869,6 +1193,10 @@ mod tests {
             ignore_waves: false,
             https_proxy: None,
             no_proxy: None,
+            policy: None,
+            denied_versions: None,
+            allowed_versions: None,
+            download_rate_limit: None,
         };
 
         // Two waves; the 1st wave that starts immediately, and the final wave which starts in one hour
@@ -886,6 +1214,7 @@ mod tests {
                 config.ignore_waves,
                 config.seed,
                 &config.version_lock,
+                &config.version_filter(),
                 None,
             )
             .unwrap()
@@ -901,6 +1230,7 @@ mod tests {
                 config.ignore_waves,
                 2000,
                 &config.version_lock,
+                &config.version_filter(),
                 None,
             )
             .unwrap()
@@ -1,19 +1,78 @@
-use std::sync::{Arc, RwLock};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use log::error;
-use tough::{HttpTransport, Transport, TransportError};
+use log::{debug, error, warn};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use serde::Serialize;
+use tough::{FilesystemTransport, HttpTransport, Transport, TransportError, TransportErrorKind};
 use url::Url;
 
+/// How many times in a row we'll try to continue a target download that isn't making progress.
+const DOWNLOAD_TRIES: u32 = 5;
+/// How long we wait before trying to continue an interrupted target download.
+const DOWNLOAD_RETRY_WAIT: Duration = Duration::from_secs(5);
+/// How long one request for part of a target may take.  Downloads that take longer continue with
+/// another request, so this only bounds how long a stalled connection can go unnoticed.
+const DOWNLOAD_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the download progress file is rewritten.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
 /// A shared pointer to a list of query params that the transport will add to HTTP calls.
 #[derive(Debug, Clone, Default)]
 pub(crate) struct QueryParams(Arc<RwLock<Vec<(String, String)>>>);
 
-/// A `tough` `Transport` that allows us to add query parameters to HTTP calls.
+/// A `tough` `Transport` that allows us to add query parameters to HTTP calls.  If it's given
+/// `DownloadSettings`, targets are downloaded so that they can be resumed after an interruption,
+/// even by a later updog process, and at a limited rate.
 #[derive(Debug, Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub(crate) struct HttpQueryTransport {
     pub inner: HttpTransport,
     parameters: QueryParams,
+    downloads: Option<DownloadSettings>,
+    progress: Progress,
+}
+
+/// How the transport downloads targets.
+#[derive(Debug, Clone)]
+pub(crate) struct DownloadSettings {
+    /// URLs starting with this are targets.  Metadata is small, and fetched normally.
+    pub(crate) targets_base_url: String,
+    /// The directory where partial downloads are kept until they're complete.  Each is named for
+    /// the last segment of its URL, which includes the target's digest in a repository with
+    /// consistent snapshots.
+    pub(crate) checkpoint_dir: PathBuf,
+    /// The maximum download rate, in bytes per second.
+    pub(crate) rate_limit: Option<u64>,
+    /// The file the progress of downloads is written to.
+    pub(crate) progress_file: Option<PathBuf>,
+}
+
+/// A shared pointer to the progress of the current download, which the transport updates as it
+/// reads targets.  While a download is being tracked, its progress is written to the progress file,
+/// if there is one, so it can be shown while we're running.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Progress(Arc<Mutex<ProgressState>>);
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    path: Option<PathBuf>,
+    tracking: bool,
+    report: ProgressReport,
+    last_written: Option<Instant>,
+}
+
+/// The progress of a download, as written to the progress file.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct ProgressReport {
+    bytes_done: u64,
+    bytes_total: u64,
 }
 
 impl QueryParams {
@@ -53,18 +112,104 @@ impl QueryParams {
     }
 }
 
+impl Progress {
+    fn new(path: Option<PathBuf>) -> Self {
+        Self(Arc::new(Mutex::new(ProgressState {
+            path,
+            ..ProgressState::default()
+        })))
+    }
+
+    /// Starts tracking a download of `bytes_total` bytes, replacing any earlier one.
+    pub(crate) fn start(&self, bytes_total: u64) {
+        if let Ok(mut state) = self.0.lock() {
+            state.tracking = true;
+            state.report = ProgressReport {
+                bytes_done: 0,
+                bytes_total,
+            };
+            state.write();
+        }
+    }
+
+    /// Records that `bytes` more bytes of the download are done.
+    fn add(&self, bytes: u64) {
+        if let Ok(mut state) = self.0.lock() {
+            if !state.tracking {
+                return;
+            }
+            state.report.bytes_done += bytes;
+            let due = match state.last_written {
+                Some(last_written) => last_written.elapsed() >= PROGRESS_INTERVAL,
+                None => true,
+            };
+            if due {
+                state.write();
+            }
+        }
+    }
+
+    /// Writes the final progress of the download and stops tracking it.
+    pub(crate) fn finish(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            if state.tracking {
+                state.write();
+                state.tracking = false;
+            }
+        }
+    }
+}
+
+impl ProgressState {
+    /// Replaces the progress file, so readers never see a partial report.  Progress is only
+    /// informational, so failures are logged rather than interrupting the download.
+    fn write(&mut self) {
+        if let Some(path) = &self.path {
+            self.last_written = Some(Instant::now());
+            let result = serde_json::to_string(&self.report)
+                .map_err(io::Error::from)
+                .and_then(|report| {
+                    let tmp_path = path.with_extension("tmp");
+                    fs::write(&tmp_path, report)?;
+                    fs::rename(&tmp_path, path)
+                });
+            if let Err(e) = result {
+                warn!(
+                    "Unable to write download progress to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
 impl HttpQueryTransport {
     pub fn new() -> Self {
         Self {
             inner: HttpTransport::default(),
             parameters: QueryParams::default(),
+            downloads: None,
+            progress: Progress::default(),
         }
     }
 
+    /// Download targets according to the given settings.
+    pub fn download_settings(mut self, downloads: DownloadSettings) -> Self {
+        self.progress = Progress::new(downloads.progress_file.clone());
+        self.downloads = Some(downloads);
+        self
+    }
+
     /// Obtain a shared pointer to the query params for this transport.
     pub fn query_params(&self) -> QueryParams {
         QueryParams(Arc::clone(&self.parameters.0))
     }
+
+    /// Obtain a shared pointer to the download progress for this transport.
+    pub fn progress(&self) -> Progress {
+        Progress(Arc::clone(&self.progress.0))
+    }
 }
 
 impl Transport for HttpQueryTransport {
@@ -72,6 +217,346 @@ impl Transport for HttpQueryTransport {
         &self,
         url: Url,
     ) -> std::result::Result<Box<dyn std::io::Read + Send>, TransportError> {
-        self.inner.fetch(self.parameters.add_params_to_url(url))
+        // Repositories stored on the host are read directly; there's nothing to resume or limit.
+        if url.scheme() == "file" {
+            return Ok(Box::new(ProgressRead {
+                inner: FilesystemTransport.fetch(url)?,
+                progress: self.progress(),
+            }));
+        }
+        let downloads = match &self.downloads {
+            Some(downloads) if url.as_str().starts_with(&downloads.targets_base_url) => downloads,
+            _ => return self.inner.fetch(self.parameters.add_params_to_url(url)),
+        };
+        let name = url
+            .path_segments()
+            .and_then(Iterator::last)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| TransportError::new(TransportErrorKind::Other, &url))?;
+        let url = self.parameters.add_params_to_url(url);
+        let checkpoint_path = downloads.checkpoint_dir.join(name);
+        let reader = ResumableRead::new(url, downloads, &checkpoint_path, self.progress())?;
+        Ok(Box::new(reader))
+    }
+}
+
+// =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
+
+/// Reads a file from a repository on the host, counting what's read toward the download progress.
+struct ProgressRead {
+    inner: Box<dyn Read + Send>,
+    progress: Progress,
+}
+
+impl Read for ProgressRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add(n as u64);
+        Ok(n)
+    }
+}
+
+/// Reads a target, first from what was saved of an earlier, interrupted download, and then from
+/// the server, starting where the saved part ends.  Everything read from the server is saved, so
+/// the download can be continued if it's interrupted again.  When the whole target has been read,
+/// the saved copy is removed; tough verifies what we read, so the saved copy never needs to be
+/// trusted.
+struct ResumableRead {
+    url: Url,
+    client: Client,
+    checkpoint_path: PathBuf,
+    /// The saved part of the target, while we're reading it.
+    saved: Option<File>,
+    /// The file new data is saved to.
+    checkpoint: File,
+    /// The current response from the server, if we've connected.
+    response: Option<Response>,
+    /// How many bytes of the target we've read.
+    offset: u64,
+    /// How many times in a row we've failed to read from the server.
+    failures: u32,
+    rate_limiter: Option<RateLimiter>,
+    progress: Progress,
+}
+
+impl ResumableRead {
+    fn new(
+        url: Url,
+        downloads: &DownloadSettings,
+        checkpoint_path: &Path,
+        progress: Progress,
+    ) -> std::result::Result<Self, TransportError> {
+        let open_checkpoint = || -> io::Result<(File, File)> {
+            fs::create_dir_all(&downloads.checkpoint_dir)?;
+            let checkpoint = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(checkpoint_path)?;
+            Ok((checkpoint, File::open(checkpoint_path)?))
+        };
+        let (checkpoint, saved) = open_checkpoint()
+            .map_err(|e| TransportError::new_with_cause(TransportErrorKind::Other, &url, e))?;
+        if checkpoint_path.metadata().map_or(0, |m| m.len()) > 0 {
+            debug!("Resuming download of {}", url);
+        }
+        let client = Client::builder()
+            .timeout(DOWNLOAD_REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| TransportError::new_with_cause(TransportErrorKind::Other, &url, e))?;
+        Ok(Self {
+            url,
+            client,
+            checkpoint_path: checkpoint_path.to_path_buf(),
+            saved: Some(saved),
+            checkpoint,
+            response: None,
+            offset: 0,
+            failures: 0,
+            rate_limiter: downloads.rate_limit.map(RateLimiter::new),
+            progress,
+        })
+    }
+
+    /// Requests the rest of the target, starting at our offset.  Returns None if there's nothing
+    /// left to read.
+    fn connect(&mut self) -> io::Result<Option<Response>> {
+        let mut request = self.client.get(self.url.clone());
+        if self.offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", self.offset));
+        }
+        // Failing to reach the server, or an unexpected response, ends this attempt; the caller
+        // may try again.
+        let response = request
+            .send()
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionAborted, e))?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => Ok(Some(response)),
+            // The server is sending the whole target, so skip what we already have.
+            StatusCode::OK => {
+                let mut response = response;
+                let skipped = io::copy(&mut (&mut response).take(self.offset), &mut io::sink())?;
+                if skipped < self.offset {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is shorter than what was already downloaded", self.url),
+                    ));
+                }
+                Ok(Some(response))
+            }
+            // We already have all of it.
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(None),
+            StatusCode::NOT_FOUND => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", self.url),
+            )),
+            status => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("Request for {} failed with {}", self.url, status),
+            )),
+        }
+    }
+
+    /// Reads from the server, connecting if needed, and saves what we read.
+    fn read_remote(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.response.is_none() {
+            self.response = self.connect()?;
+        }
+        let n = match &mut self.response {
+            Some(response) => response.read(buf)?,
+            None => return Ok(0),
+        };
+        self.checkpoint.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    /// Removes the saved copy of the target, which is no longer needed once it's all been read.
+    fn remove_checkpoint(&self) {
+        if let Err(e) = fs::remove_file(&self.checkpoint_path) {
+            warn!(
+                "Unable to remove completed download {}: {}",
+                self.checkpoint_path.display(),
+                e
+            );
+        }
+    }
+}
+
+impl Read for ResumableRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(saved) = &mut self.saved {
+            let n = saved.read(buf)?;
+            if n > 0 {
+                self.offset += n as u64;
+                self.progress.add(n as u64);
+                return Ok(n);
+            }
+            self.saved = None;
+        }
+
+        loop {
+            match self.read_remote(buf) {
+                Ok(0) => {
+                    self.remove_checkpoint();
+                    return Ok(0);
+                }
+                Ok(n) => {
+                    self.offset += n as u64;
+                    self.failures = 0;
+                    self.progress.add(n as u64);
+                    if let Some(rate_limiter) = &mut self.rate_limiter {
+                        rate_limiter.wait(n as u64);
+                    }
+                    return Ok(n);
+                }
+                // Nothing we retry will find a missing target.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(e),
+                Err(e) => {
+                    // Whatever we saved is still good; a later attempt can continue from it.
+                    self.response = None;
+                    self.failures += 1;
+                    if self.failures >= DOWNLOAD_TRIES {
+                        return Err(e);
+                    }
+                    debug!(
+                        "Error reading {} at byte {}, will continue: {}",
+                        self.url, self.offset, e
+                    );
+                    thread::sleep(DOWNLOAD_RETRY_WAIT);
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the average rate of a download at or under a limit by sleeping after reads that get
+/// ahead of it.
+struct RateLimiter {
+    bytes_per_second: u64,
+    start: Instant,
+    bytes: u64,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Records that `bytes` bytes were read, and sleeps until reading them was within the limit.
+    fn wait(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        let allowed = Duration::from_millis(self.bytes * 1000 / self.bytes_per_second);
+        if let Some(early) = allowed.checked_sub(self.start.elapsed()) {
+            thread::sleep(early);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit() {
+        let mut rate_limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.wait(50);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// Serves `content` once over HTTP from a local port, honoring a Range header, and returns the
+    /// URL along with a handle that gives the Range header the server saw.
+    fn serve_once(content: Vec<u8>) -> (Url, thread::JoinHandle<Option<String>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/targets/abc.image",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            let range = request
+                .lines()
+                .find(|line| line.to_lowercase().starts_with("range:"))
+                .map(|line| line["range:".len()..].trim().to_string());
+            let start: usize = match &range {
+                Some(range) => range["bytes=".len()..range.len() - 1].parse().unwrap(),
+                None => 0,
+            };
+            let status = if range.is_some() {
+                "206 Partial Content"
+            } else {
+                "200 OK"
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                content.len() - start
+            )
+            .unwrap();
+            stream.write_all(&content[start..]).unwrap();
+            range
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn resume_download() {
+        let content: Vec<u8> = (0..100_000u32).flat_map(u32::to_le_bytes).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = dir.path().join("abc.image");
+        fs::write(&checkpoint_path, &content[..150_000]).unwrap();
+
+        let (url, server) = serve_once(content.clone());
+        let downloads = DownloadSettings {
+            targets_base_url: url.as_str().trim_end_matches("/abc.image").to_string(),
+            checkpoint_dir: dir.path().to_path_buf(),
+            rate_limit: None,
+            progress_file: None,
+        };
+        let mut reader =
+            ResumableRead::new(url, &downloads, &checkpoint_path, Progress::default()).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+
+        // Only the part we didn't have was requested, and the finished download was removed.
+        assert_eq!(server.join().unwrap(), Some("bytes=150000-".to_string()));
+        assert!(output == content);
+        assert!(!checkpoint_path.exists());
+    }
+
+    #[test]
+    fn progress_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("progress.json");
+        let progress = Progress::new(Some(path.clone()));
+
+        // Nothing is written until a download is tracked.
+        progress.add(10);
+        assert!(!path.exists());
+
+        progress.start(100);
+        progress.add(10);
+        progress.add(20);
+        progress.finish();
+        let report: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report["bytes_done"], 30);
+        assert_eq!(report["bytes_total"], 100);
     }
 }